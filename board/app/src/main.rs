@@ -37,6 +37,7 @@ fn main() -> ! {
     board.watchdog.feed(); // make sure we leave enough time for the panic handler
     loop {
         board.run_loop_iteration();
+        datalogger::publish_free_heap(prelude::free_heap());
         datalogger.run_loop_iteration(&mut board);
     }
 }
@@ -22,6 +22,10 @@ fn alloc_heap() {
     }
 }
 
+pub(crate) fn free_heap() -> usize {
+    HEAP.free()
+}
+
 #[alloc_error_handler]
 fn oom(_: Layout) -> ! {
     loop {}
@@ -5,4 +5,11 @@ pub enum GpioMode {
     PullDownInput,
     PushPullOutput,
     OpenDrainOutput
-}
\ No newline at end of file
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum GpioInterruptEdge {
+    Rising,
+    Falling,
+    RisingFalling
+}
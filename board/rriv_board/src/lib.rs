@@ -7,11 +7,13 @@ use core::fmt;
 pub mod gpio;
 pub mod hardware_error;
 
-use crate::{gpio::GpioMode, hardware_error::HardwareError};
+use crate::{gpio::{GpioInterruptEdge, GpioMode}, hardware_error::HardwareError};
 
-pub const EEPROM_DATALOGGER_SETTINGS_SIZE: usize = 64;
+pub const EEPROM_DATALOGGER_SETTINGS_SIZE: usize = 112; // was 64, the datalogger migrates settings stored by older firmware
 pub const EEPROM_SENSOR_SETTINGS_SIZE: usize = 64;
 pub const EEPROM_SERIAL_NUMBER_SIZE: usize = 5;
+pub const EEPROM_ONE_WIRE_ROMS_SIZE: usize = 128; // rom codes of up to 16 one wire probes
+pub const USART_BAUD_RATE: u32 = 115200; // the usart's baud rate when no sensor has switched it
 
 
 #[cfg(feature = "24LC08")]
@@ -31,6 +33,12 @@ pub enum SerialRxPeripheral{
     SerialPeripheral2
 }
 
+#[derive(Clone, Copy, PartialEq)]
+pub enum PowerRail {
+    Switched3v,
+    Switched5v
+}
+
 
 pub trait RRIVBoard: Send {
 
@@ -45,6 +53,8 @@ pub trait RRIVBoard: Send {
     fn retrieve_datalogger_settings(&mut self, buffer: &mut [u8;EEPROM_DATALOGGER_SETTINGS_SIZE]);
     fn store_sensor_settings(&mut self, slot: u8, bytes: &[u8; EEPROM_SENSOR_SETTINGS_SIZE] );
     fn retrieve_sensor_settings(&mut self, buffer: &mut [u8; EEPROM_SENSOR_SETTINGS_SIZE * EEPROM_TOTAL_SENSOR_SLOTS]);
+    fn store_one_wire_roms(&mut self, bytes: &[u8; EEPROM_ONE_WIRE_ROMS_SIZE]);
+    fn retrieve_one_wire_roms(&mut self, buffer: &mut [u8; EEPROM_ONE_WIRE_ROMS_SIZE]);
 
     // Modes
     fn set_debug(&mut self, debug: bool);
@@ -52,6 +62,7 @@ pub trait RRIVBoard: Send {
     // Data Logging
     fn write_log_file(&mut self, args: fmt::Arguments);
     fn flush_log_file(&mut self);
+    fn create_log_file(&mut self); // flush the current log file and start writing to a new one
 
 
     // Time
@@ -64,6 +75,7 @@ pub trait RRIVBoard: Send {
                 // TODO: give his a more unique name specifying that it's used to talk with the serial rrivctl interface
                 // maybe rrivctl_send
     fn usart_send(&mut self, bytes: &[u8]);
+    fn set_usart_baud_rate(&mut self, baud_rate: u32); // sensors switch the usart to their baud rate while they hold it, and back to USART_BAUD_RATE
     fn serial_debug(&mut self, args: fmt::Arguments);
     fn delay_ms(&mut self, ms: u16);
     fn delay_us(&mut self, us: u16);
@@ -74,6 +86,7 @@ pub trait RRIVBoard: Send {
     fn get_battery_level(&mut self) -> i16;
 
     fn sleep(&mut self);
+    fn sleep_until(&mut self, epoch: i64); // low power sleep until the given UTC epoch
 
     // low level board functionality
     // for debugging and basic operation
@@ -81,6 +94,8 @@ pub trait RRIVBoard: Send {
     fn get_uid(&mut self) -> [u8; 12];
     fn set_serial_number(&mut self, serial_number: [u8;5]) -> bool;
     fn get_serial_number(&mut self) -> [u8;5];
+    fn get_boot_count(&mut self) -> u32; // number of times the board has started, kept in eeprom
+    fn get_sd_free_space_kb(&mut self) -> Option<u32>; // None when there is no card
     
     // fn subsystem(&mut self, ...)  //TODO: custom commands to the board subsystems, use a tokenized rather than json format
 
@@ -125,18 +140,42 @@ pub trait RRIVBoard: Send {
 
     fn enable_interrupt(&self);
     fn disable_interrupt(&self);
+    fn set_power_rail(&mut self, rail: PowerRail, enabled: bool); // switch a sensor power rail, the 5v rail also powers the external adc and stays on during sleep while enabled
+    fn enable_gpio_interrupt(&mut self, pin: u8, edge: GpioInterruptEdge, wake: bool) -> Result<(), ()>; // edge interrupt on a dynamic gpio, keeps firing during sleep and ends the sleep if wake is set
+    fn disable_gpio_interrupt(&mut self, pin: u8);
     fn get_current_time(&self) -> u32;
+    fn get_cycles_per_microsecond(&self) -> u32; // for the cycle counts passed to gpio interrupt functions
+    fn store_backup_value(&mut self, index: usize, value: u32); // kept through resets and sleep, but not when the board loses all power
+    fn retrieve_backup_value(&mut self, index: usize) -> u32; // 0 until a value is stored
 
 }
 
 
-pub static mut GPIO_INTERRUPT_FUNCTION: Option< Box<dyn Fn(u32, bool)> > = None;
+pub const GPIO_INTERRUPT_PINS: usize = 8;
+pub const GPIO_INTERRUPT_CAPABLE_PINS: [u8; 5] = [2, 5, 6, 7, 8]; // the dynamic gpios with an EXTI line of their own
+pub const BACKUP_VALUES: usize = 16;
+
+// interrupt functions for the dynamic gpios, indexed by pin - 1
+// called with the cpu cycle count at the interrupt and the state of the pin
+pub static mut GPIO_INTERRUPT_FUNCTIONS: [Option< Box<dyn Fn(u32, bool)> >; GPIO_INTERRUPT_PINS] = [None, None, None, None, None, None, None, None];
 
-pub fn configure_gpio_interrupt_function<T: Fn(u32, bool) + 'static>(function: T ) {
-    // unmask the correct EXTI interrupt for SDI-12 or whatever
-    // store the function we actionally want to call
+pub fn configure_gpio_interrupt_function<T: Fn(u32, bool) + 'static>(pin: u8, function: T ) {
+    // store the function we actually want to call when the pin's EXTI interrupt fires
+    // the board unmasks the interrupt separately
+    if pin < 1 || pin as usize > GPIO_INTERRUPT_PINS {
+        return;
+    }
+    unsafe {
+        GPIO_INTERRUPT_FUNCTIONS[pin as usize - 1] = Some(Box::new(function));
+    }
+}
+
+pub fn remove_gpio_interrupt_function(pin: u8) {
+    if pin < 1 || pin as usize > GPIO_INTERRUPT_PINS {
+        return;
+    }
     unsafe {
-        GPIO_INTERRUPT_FUNCTION = Some(Box::new(function));
+        GPIO_INTERRUPT_FUNCTIONS[pin as usize - 1] = None;
     }
 }
 
@@ -13,9 +13,11 @@ const _EEPROM_RESET_VALUE: u16 = 255; // max value of a byte
 
 
 const EEPROM_DATALOGGER_SETTINGS_START: u8 = 16;
-const _EEPROM_SENSOR_SETTINGS_START: u8 = 80;
+const EEPROM_ONE_WIRE_ROMS_START: u8 = 128; // after the datalogger settings, to the end of block 0
+// sensor settings start in block 1
 
 const EEPROM_SERIAL_NUMBER_START: u8 = 0;
+const EEPROM_BOOT_COUNT_START: u8 = 8; // in the gap between the serial number and the datalogger settings
 
 
 pub fn write_bytes_to_eeprom(board: &mut crate::Board, block: u8, start_address: u8, bytes: &[u8]) {
@@ -92,6 +94,20 @@ pub fn read_serial_number_from_eeprom(
     return serial_number;
 }
 
+pub fn write_boot_count_to_eeprom(board: &mut Board, boot_count: u32) {
+    write_bytes_to_eeprom(board, 0, EEPROM_BOOT_COUNT_START, &boot_count.to_le_bytes());
+}
+
+pub fn read_boot_count_from_eeprom(board: &mut Board) -> u32 {
+    let mut bytes: [u8; 4] = [0; 4];
+    read_bytes_from_eeprom(board, 0, EEPROM_BOOT_COUNT_START, &mut bytes);
+    let boot_count = u32::from_le_bytes(bytes);
+    if boot_count == u32::MAX {
+        return 0; // never written
+    }
+    boot_count
+}
+
 pub fn write_datalogger_settings_to_eeprom(
     board: &mut Board,
     bytes: &[u8; rriv_board::EEPROM_DATALOGGER_SETTINGS_SIZE],
@@ -103,6 +119,17 @@ pub fn read_datalogger_settings_from_eeprom(board: &mut Board, buffer: &mut [u8]
     read_bytes_from_eeprom(board, 0, EEPROM_DATALOGGER_SETTINGS_START, buffer);
 }
 
+pub fn write_one_wire_roms_to_eeprom(
+    board: &mut Board,
+    bytes: &[u8; rriv_board::EEPROM_ONE_WIRE_ROMS_SIZE],
+) {
+    write_bytes_to_eeprom(board, 0, EEPROM_ONE_WIRE_ROMS_START, bytes);
+}
+
+pub fn read_one_wire_roms_from_eeprom(board: &mut Board, buffer: &mut [u8]) {
+    read_bytes_from_eeprom(board, 0, EEPROM_ONE_WIRE_ROMS_START, buffer);
+}
+
 struct MemoryPosition {
     pub block: u8,
     #[allow(unused)]
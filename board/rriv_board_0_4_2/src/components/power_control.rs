@@ -25,6 +25,29 @@ impl PowerControl {
     delay.delay_ms(250_u32);
   }
 
+  pub fn disable_3v(&mut self) {
+    self.pins.enable_3v.set_low();
+  }
+
+  pub fn enable_3v(&mut self, delay: &mut impl embedded_hal::blocking::delay::DelayMs<u32> ) {
+    self.pins.enable_3v.set_high();
+    delay.delay_ms(250_u32);
+  }
+
+  pub fn is_5v_enabled(&self) -> bool {
+    self.pins.enable_5v.is_set_high()
+  }
+
+  // the 5v rail powers the sensors and the external adc
+  pub fn disable_5v(&mut self) {
+    self.pins.enable_5v.set_low();
+  }
+
+  pub fn enable_5v(&mut self, delay: &mut impl embedded_hal::blocking::delay::DelayMs<u32> ) {
+    self.pins.enable_5v.set_high();
+    delay.delay_ms(250_u32);
+  }
+
   pub fn cycle_all(&mut self, delay: &mut impl embedded_hal::blocking::delay::DelayMs<u32> ) {
     self.pins.enable_5v.set_low();
     self.pins.enable_3v.set_low();
@@ -132,6 +132,8 @@ pub struct Storage {
     root_dir: Directory,
     cache: [u8; CACHE_SIZE],
     next_position: usize,
+    card_bytes: u64,
+    used_bytes: u64,
 }
 
 pub enum CardError {
@@ -245,6 +247,8 @@ impl Storage {
             root_dir: root_dir,
             cache: [b'\0'; CACHE_SIZE],
             next_position: 0,
+            card_bytes: size,
+            used_bytes: bytes,
         };
         Ok(storage)
     }
@@ -307,6 +311,20 @@ impl Storage {
 
     }
 
+    pub fn start_new_file(&mut self, timestamp: i64) {
+        self.flush();
+
+        if let Some(file) = self.file {
+            match self.volume_manager.close_file(file) {
+                Ok(_) => {}
+                Err(err) => defmt::println!("Err: {:?}", defmt::Debug2Format(&err)),
+            }
+            self.file = None;
+        }
+
+        self.create_file(timestamp);
+    }
+
     pub fn flush(&mut self) {
         if self.file == None {
             self.reopen_file();
@@ -329,6 +347,11 @@ impl Storage {
         }
     }
 
+    // estimated from the files found at startup plus everything written since
+    pub fn free_bytes(&self) -> u64 {
+        self.card_bytes.saturating_sub(self.used_bytes)
+    }
+
     pub fn write(&mut self, data: &[u8], timestamp: i64) {
         //-> Result<Ok, Error<Error>>{
 
@@ -340,6 +363,7 @@ impl Storage {
         }
 
         let mut write_start = 0;
+        self.used_bytes = self.used_bytes + data.len() as u64;
 
         if self.next_position + data.len() > CACHE_SIZE {
             // flush cache
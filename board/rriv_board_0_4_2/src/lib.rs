@@ -16,6 +16,7 @@ use stm32f1xx_hal::timer::{Ch, Channel, CounterUs, PwmHz, Tim4NoRemap};
 
 use core::fmt::{self};
 use core::mem;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use core::{
     cell::RefCell,
     default::Default,
@@ -38,6 +39,7 @@ use stm32f1xx_hal::serial::StopBits;
 use stm32f1xx_hal::spi::Spi;
 use stm32f1xx_hal::{
     afio::MAPR,
+    backup_domain::BackupDomain,
     gpio::Dynamic,
     pac::TIM3,
     watchdog::IndependentWatchdog,
@@ -59,8 +61,9 @@ use usb_device::{bus::UsbBusAllocator, prelude::*};
 use usbd_serial::{SerialPort, USB_CLASS_CDC};
 
 use rriv_board::{
-    EEPROM_TOTAL_SENSOR_SLOTS, GPIO_INTERRUPT_FUNCTION, RRIVBoard, RXProcessor, SerialRxPeripheral
+    EEPROM_TOTAL_SENSOR_SLOTS, GPIO_INTERRUPT_FUNCTIONS, PowerRail, RRIVBoard, RXProcessor, SerialRxPeripheral
 };
+use rriv_board::gpio::GpioInterruptEdge;
 
 use ds323x::{DateTimeAccess, Ds323x};
 use stm32f1xx_hal::rtc::Rtc;
@@ -84,6 +87,12 @@ pub const HSE_MHZ: u32 = 8;
 pub const SYSCLK_MHZ: u32 = 48;
 pub const PCLK_MHZ: u32 = 24;
 
+const WATCHDOG_SECONDS: u32 = 6;
+const SLEEP_WATCHDOG_SECONDS: u32 = 24; // the longest period the independent watchdog supports is about 26s
+// the watchdog periods assume a 40kHz LSI, but the LSI can run as fast as 60kHz,
+// which expires the sleep watchdog after 16s, so wake up well before that to feed it
+const MAX_STOP_SECONDS: u32 = 10;
+
 #[allow(dead_code)]
 static WAKE_LED: Mutex<RefCell<Option<RedLed>>> = Mutex::new(RefCell::new(None));
 
@@ -100,6 +109,12 @@ static USART2_RX_PROCESSOR: Mutex<RefCell<Option<Box<&mut dyn RXProcessor>>>> =
 static UART5_RX_PROCESSOR: Mutex<RefCell<Option<Box<&mut dyn RXProcessor>>>> =
     Mutex::new(RefCell::new(None));
 
+// EXTI lines enabled with enable_gpio_interrupt, these stay enabled during sleep
+// edges on the wake lines also end the sleep
+static GPIO_INTERRUPT_LINES: AtomicU32 = AtomicU32::new(0);
+static GPIO_WAKE_LINES: AtomicU32 = AtomicU32::new(0);
+static GPIO_WOKE: AtomicBool = AtomicBool::new(false);
+
 
 #[repr(C)]
 pub struct Usart {
@@ -114,7 +129,7 @@ pub struct Board {
     pub uid: [u8; 12],
     pub delay: DelayUs<TIM3>,
     pub precise_delay: PreciseDelayUs,
-    // // pub power_control: PowerControl,
+    pub power_control: PowerControl,
     pub gpio: DynamicGpioPins,
     pub gpio_cr: GpioCr,
     pub internal_adc: InternalAdc,
@@ -128,6 +143,7 @@ pub struct Board {
     pub storage: Option<Storage>,
     pub debug: bool,
     pub file_epoch: i64,
+    pub boot_count: u32,
     pub one_wire_bus: Option<OneWire<OneWirePin<Pin<'C', 0, Dynamic>>>>,
     one_wire_search_state: Option<SearchState>,
     pub watchdog: IndependentWatchdog,
@@ -135,6 +151,8 @@ pub struct Board {
     pub hardware_errors: [HardwareError; 5],
     pub clocks: Clocks,
     pub pwm: Option<PwmHz<TIM4, Tim4NoRemap, Ch<2>, Pin<'B', 8, gpio::Alternate<PushPull>>>>,
+    rail_5v_held: bool, // switched on with set_power_rail, stays on during sleep
+    backup_domain: BackupDomain,
 }
 
 impl Board {
@@ -147,6 +165,10 @@ impl Board {
             storage.create_file(timestamp);
         }
 
+        self.boot_count = eeprom::read_boot_count_from_eeprom(self).wrapping_add(1);
+        eeprom::write_boot_count_to_eeprom(self, self.boot_count);
+        defmt::println!("boot count {}", self.boot_count);
+
         // setting the pin for receiving telemetry on UART5
         // this crashes the mcu hard, maybe only if something isn't plugged in
         // defmt::println!("set up pin 2"); rriv_board::RRIVBoard::delay_ms(self, 1000);
@@ -159,63 +181,127 @@ impl Board {
 
     }
 
-    pub fn sleep_mcu(&mut self) {
-        // TODO: sleep mode won't work with independent watch dog, unless we can stop it.
-        // EDIT: there is not alternative source for indep watchdog, it's always HSI
-        // EDIT: therefore field mode must restart with indep watchdog disabled
-
-        self.internal_rtc.set_alarm(5000); // 5 seconds?
+    // sleep in STOP mode until the internal RTC alarm fires after the given number of seconds
+    // the independent watchdog can't be stopped and keeps running from the LSI during STOP,
+    // so callers must keep seconds below the watchdog period
+    pub fn sleep_mcu(&mut self, seconds: u32) {
+        let wake_time = self.internal_rtc.current_time() + seconds;
+        self.internal_rtc.set_alarm(wake_time); // also clears the alarm flag
         self.internal_rtc.listen_alarm();
-        defmt::println!("will sleep");
 
-        // disable interrupts
+        // disable interrupts that should not wake us up
+        // gpio interrupts enabled with enable_gpio_interrupt stay on so they can wake us or keep counting
+        let mask_exti2 = NVIC::is_enabled(pac::Interrupt::EXTI2)
+            && GPIO_INTERRUPT_LINES.load(Ordering::Relaxed) & (1 << 2) == 0;
         NVIC::mask(pac::Interrupt::USB_HP_CAN_TX);
         NVIC::mask(pac::Interrupt::USB_LP_CAN_RX0);
         NVIC::mask(pac::Interrupt::USART2);
+        if mask_exti2 {
+            NVIC::mask(pac::Interrupt::EXTI2);
+        }
 
         unsafe { NVIC::unmask(pac::Interrupt::RTCALARM) };
-        cortex_m::asm::dsb();
 
         let mut core_peripherals: pac::CorePeripherals = unsafe { cortex_m::Peripherals::steal() };
         core_peripherals.SYST.disable_interrupt();
 
-        cortex_m::asm::wfi();
+        self.watchdog.feed();
+        self.enter_stop_mode();
+        self.watchdog.feed();
 
         core_peripherals.SYST.enable_interrupt();
 
-        cortex_m::asm::isb();
+        NVIC::mask(pac::Interrupt::RTCALARM);
+        self.internal_rtc.unlisten_alarm();
+        self.internal_rtc.clear_alarm_flag();
 
         // re-enable interrupts
         unsafe { NVIC::unmask(pac::Interrupt::USB_HP_CAN_TX) };
         unsafe { NVIC::unmask(pac::Interrupt::USB_LP_CAN_RX0) };
         unsafe { NVIC::unmask(pac::Interrupt::USART2) };
-
-        defmt::println!("woke from sleep");
+        if mask_exti2 {
+            unsafe { NVIC::unmask(pac::Interrupt::EXTI2) };
+        }
     }
 
     pub fn enter_stop_mode(&mut self) {
-        //           debug("setting up EXTI");
-        //   *bb_perip(&EXTI_BASE->IMR, EXTI_RTC_ALARM_BIT) = 1;
-        // 	*bb_perip(&EXTI_BASE->RTSR, EXTI_RTC_ALARM_BIT) = 1;
+        let device_peripherals: pac::Peripherals = unsafe { pac::Peripherals::steal() };
+        let mut core_peripherals: pac::CorePeripherals = unsafe { cortex_m::Peripherals::steal() };
 
         // in addition to the RTCALARM interrupt, the rtc must route through EXTI to wake the MCU up from stop mode.
-        let device_peripherals: pac::Peripherals = unsafe { pac::Peripherals::steal() };
-        device_peripherals.EXTI.imr.write(
-            |w| w.mr17().set_bit(), // interrupt mask bit 17 enables RTC EXTI
+        device_peripherals.EXTI.imr.modify(
+            |_, w| w.mr17().set_bit(), // interrupt mask bit 17 enables RTC EXTI
         );
-        device_peripherals.EXTI.rtsr.write(
-            |w| w.tr17().set_bit(), // rising trigger bit 17 enables RTC EXTI
+        device_peripherals.EXTI.rtsr.modify(
+            |_, w| w.tr17().set_bit(), // rising trigger bit 17 enables RTC EXTI
         );
+        device_peripherals.EXTI.pr.write(|w| w.pr17().set_bit());
+
+        // run from the HSI so that the HSE oscillator can be powered down
+        let rcc = &device_peripherals.RCC;
+        rcc.cr.modify(|_, w| w.hsion().set_bit());
+        while rcc.cr.read().hsirdy().bit_is_clear() {}
+        rcc.cfgr.modify(|_, w| w.sw().hsi());
+        while !rcc.cfgr.read().sws().is_hsi() {}
+        rcc.cr.modify(|_, w| w.pllon().clear_bit());
+        rcc.cr.modify(|_, w| w.hseon().clear_bit());
+        self.oscillator_control.disable_hse();
+
+        // STOP mode with the voltage regulator in low power mode
+        device_peripherals.PWR.cr.modify(|_, w| w.pdds().clear_bit().lpds().set_bit().cwuf().set_bit());
+        core_peripherals.SCB.set_sleepdeep();
+
+        // with interrupts disabled the wake up interrupt stays pending through wfi,
+        // and its handler runs once the clocks are restored and the cycle counter is caught up
+        cortex_m::interrupt::free(|_cs| {
+            let sleep_start = rtc_ticks();
+
+            cortex_m::asm::dsb();
+            cortex_m::asm::wfi();
+            cortex_m::asm::isb();
+
+            core_peripherals.SCB.clear_sleepdeep();
+
+            // the MCU wakes up running from the HSI, restore the clocks configured by the BoardBuilder
+            // the PLL multiplier and bus prescalers in CFGR are retained through STOP mode
+            self.oscillator_control.enable_hse();
+            rcc.cr.modify(|_, w| w.hseon().set_bit());
+            while rcc.cr.read().hserdy().bit_is_clear() {}
+            rcc.cr.modify(|_, w| w.pllon().set_bit());
+            while rcc.cr.read().pllrdy().bit_is_clear() {}
+            rcc.cfgr.modify(|_, w| w.sw().pll());
+            while !rcc.cfgr.read().sws().is_pll() {}
+
+            // the rtc registers read stale values after STOP until they resynchronize
+            device_peripherals.RTC.crl.modify(|_, w| w.rsf().clear_bit());
+            while device_peripherals.RTC.crl.read().rsf().bit_is_clear() {}
+
+            // the cycle counter stops in STOP mode, add the time slept so gpio interrupt times stay real time
+            let slept_cycles = rtc_ticks().saturating_sub(sleep_start) * SYSCLK_MHZ as u64 * 1_000_000 / RTC_TICKS_PER_SECOND;
+            core_peripherals.DWT.set_cycle_count(
+                cortex_m::peripheral::DWT::cycle_count().wrapping_add(slept_cycles as u32),
+            );
+        });
+    }
 
-        // clocks
-        // steal and use raw the same function sin cfgr to switch to hsi and wait for stabilization
-        // and the same thing to switch back.
-        // let clocks = cfgr
-        //     .use_hse(8.MHz())
-        //     .sysclk(48.MHz())
-        //     .pclk1(24.MHz())
-        //     .adcclk(14.MHz())
-        //     .freeze(flash_acr);
+    // power down the peripherals that aren't needed while sleeping
+    fn power_down_peripherals(&mut self) {
+        if let Some(ref mut storage) = &mut self.storage {
+            storage.flush();
+        }
+        self.external_adc.disable(&mut self.delay);
+        self.power_control.disable_5v();
+    }
+
+    fn power_up_peripherals(&mut self) {
+        // external adc and i2c stability require these steps, same as during setup
+        self.power_control.enable_5v(&mut self.delay);
+        self.external_adc.enable(&mut self.delay);
+        self.external_adc.reset(&mut self.delay);
+        if let Some(mut i2c1) = self.i2c1.take() {
+            self.external_adc.configure(&mut i2c1);
+            self.i2c1 = Some(i2c1);
+        }
     }
 
     fn disable_interrupts(&self) {
@@ -311,6 +397,23 @@ impl RRIVBoard for Board {
         
     }
 
+    fn set_usart_baud_rate(&mut self, baud_rate: u32) {
+        if baud_rate == 0 {
+            return;
+        }
+        let device_peripherals: pac::Peripherals = unsafe { pac::Peripherals::steal() };
+        let usart = &device_peripherals.USART2;
+
+        // let the last byte go out at the old rate
+        while usart.sr.read().tc().bit_is_clear() {}
+
+        // USART2 is clocked from APB1, the same divider the HAL computes in setup_serial
+        let brr = (self.clocks.pclk1().raw() + baud_rate / 2) / baud_rate;
+        usart.cr1.modify(|_, w| w.ue().clear_bit());
+        usart.brr.write(|w| unsafe { w.bits(brr) });
+        usart.cr1.modify(|_, w| w.ue().set_bit());
+    }
+
     fn usb_serial_send(&mut self, arg: fmt::Arguments) { // TODO: ok so the formatter doesn't below in the board level, it can go into a util in the datalogger app level
         let mut buf = [0u8; 500];
         match format_no_std::show(
@@ -362,6 +465,14 @@ impl RRIVBoard for Board {
         eeprom::read_datalogger_settings_from_eeprom(self, buffer);
     }
 
+    fn store_one_wire_roms(&mut self, bytes: &[u8; rriv_board::EEPROM_ONE_WIRE_ROMS_SIZE]) {
+        eeprom::write_one_wire_roms_to_eeprom(self, bytes);
+    }
+
+    fn retrieve_one_wire_roms(&mut self, buffer: &mut [u8; rriv_board::EEPROM_ONE_WIRE_ROMS_SIZE]) {
+        eeprom::read_one_wire_roms_from_eeprom(self, buffer);
+    }
+
     fn retrieve_sensor_settings(
         // retrieve_all_sensor_configurations
         &mut self,
@@ -457,6 +568,47 @@ impl RRIVBoard for Board {
         self.watchdog.feed();
     }
 
+    fn sleep_until(&mut self, epoch: i64) {
+        let now = self.epoch_timestamp();
+        if epoch <= now {
+            return;
+        }
+        let seconds = (epoch - now) as u32;
+        defmt::println!("sleeping for {}s", seconds);
+
+        // sensors may have switched the 5v rail off already,
+        // and sensors holding it with set_power_rail, such as pulse counters, need it to stay on
+        let rail_5v_enabled = self.power_control.is_5v_enabled() && !self.rail_5v_held;
+        if rail_5v_enabled {
+            self.power_down_peripherals();
+        } else if let Some(ref mut storage) = &mut self.storage {
+            storage.flush();
+        }
+
+        // the independent watchdog can't be stopped, so lengthen it while sleeping
+        // and wake up in time to feed it
+        self.watchdog.start(MilliSeconds::secs(SLEEP_WATCHDOG_SECONDS));
+        let wake_time = self.internal_rtc.current_time() + seconds;
+        loop {
+            let now = self.internal_rtc.current_time();
+            if now >= wake_time {
+                break;
+            }
+            if GPIO_WOKE.swap(false, Ordering::Relaxed) {
+                // woken early by a gpio interrupt
+                break;
+            }
+            let remaining = wake_time - now;
+            self.sleep_mcu(if remaining > MAX_STOP_SECONDS { MAX_STOP_SECONDS } else { remaining });
+        }
+        self.watchdog.start(MilliSeconds::secs(WATCHDOG_SECONDS));
+
+        if rail_5v_enabled {
+            self.power_up_peripherals();
+        }
+        defmt::println!("woke from sleep");
+    }
+
     fn set_debug(&mut self, debug: bool) {
         self.debug = debug;
     }
@@ -486,6 +638,13 @@ impl RRIVBoard for Board {
         }
     }
 
+    fn create_log_file(&mut self) {
+        let timestamp: i64 = self.epoch_timestamp();
+        if let Some(ref mut storage) = &mut self.storage {
+            storage.start_new_file(timestamp);
+        }
+    }
+
     fn dump_eeprom(&mut self) {
         let mut buffer: [u8; EEPROM_TOTAL_SENSOR_SLOTS * rriv_board::EEPROM_SENSOR_SETTINGS_SIZE] =
             [0; EEPROM_TOTAL_SENSOR_SLOTS * rriv_board::EEPROM_SENSOR_SETTINGS_SIZE];
@@ -523,6 +682,17 @@ impl RRIVBoard for Board {
         eeprom::read_serial_number_from_eeprom(self)
     }
 
+    fn get_boot_count(&mut self) -> u32 {
+        self.boot_count
+    }
+
+    fn get_sd_free_space_kb(&mut self) -> Option<u32> {
+        match &self.storage {
+            Some(storage) => Some((storage.free_bytes() / 1024) as u32),
+            None => None,
+        }
+    }
+
     fn query_internal_adc(&mut self, channel: u8) -> u16 {
         match self.internal_adc.read(channel) {
             Ok(value) => return value,
@@ -916,10 +1086,131 @@ impl RRIVBoard for Board {
         NVIC::mask(pac::Interrupt::EXTI2);
     }
 
+    fn set_power_rail(&mut self, rail: PowerRail, enabled: bool) {
+        match rail {
+            PowerRail::Switched3v => {
+                if enabled {
+                    self.power_control.enable_3v(&mut self.delay);
+                } else {
+                    self.power_control.disable_3v();
+                }
+            }
+            PowerRail::Switched5v => {
+                self.rail_5v_held = enabled;
+                if enabled {
+                    if !self.power_control.is_5v_enabled() {
+                        // brings the external adc back up as well
+                        self.power_up_peripherals();
+                    }
+                } else {
+                    self.external_adc.disable(&mut self.delay);
+                    self.power_control.disable_5v();
+                }
+            }
+        }
+    }
+
+    fn enable_gpio_interrupt(&mut self, pin: u8, edge: GpioInterruptEdge, wake: bool) -> Result<(), ()> {
+        let (line, port) = match gpio_exti_line(pin) {
+            Some(line) => line,
+            None => return Err(()),
+        };
+
+        // EXTI2 is already unmasked when the sdi12 data pin is listening on it
+        if line == 2
+            && NVIC::is_enabled(pac::Interrupt::EXTI2)
+            && GPIO_INTERRUPT_LINES.load(Ordering::Relaxed) & (1 << 2) == 0
+        {
+            return Err(());
+        }
+
+        // route the pin's port to its EXTI line, the AFIO parts are consumed during setup so write the registers directly
+        let device_peripherals: pac::Peripherals = unsafe { pac::Peripherals::steal() };
+        let afio = &device_peripherals.AFIO;
+        let shift = (line % 4) * 4;
+        let route = |bits: u32| (bits & !(0xf << shift)) | (port << shift);
+        match line / 4 {
+            0 => afio.exticr1.modify(|r, w| unsafe { w.bits(route(r.bits())) }),
+            1 => afio.exticr2.modify(|r, w| unsafe { w.bits(route(r.bits())) }),
+            2 => afio.exticr3.modify(|r, w| unsafe { w.bits(route(r.bits())) }),
+            _ => afio.exticr4.modify(|r, w| unsafe { w.bits(route(r.bits())) }),
+        }
+
+        let exti = &device_peripherals.EXTI;
+        let mask = 1 << line;
+        let (rising, falling) = match edge {
+            GpioInterruptEdge::Rising => (true, false),
+            GpioInterruptEdge::Falling => (false, true),
+            GpioInterruptEdge::RisingFalling => (true, true),
+        };
+        exti.rtsr.modify(|r, w| unsafe { w.bits(if rising { r.bits() | mask } else { r.bits() & !mask }) });
+        exti.ftsr.modify(|r, w| unsafe { w.bits(if falling { r.bits() | mask } else { r.bits() & !mask }) });
+        exti.pr.write(|w| unsafe { w.bits(mask) });
+        exti.imr.modify(|r, w| unsafe { w.bits(r.bits() | mask) });
+
+        GPIO_INTERRUPT_LINES.fetch_or(mask, Ordering::Relaxed);
+        if wake {
+            GPIO_WAKE_LINES.fetch_or(mask, Ordering::Relaxed);
+        } else {
+            GPIO_WAKE_LINES.fetch_and(!mask, Ordering::Relaxed);
+        }
+        unsafe { NVIC::unmask(gpio_exti_interrupt(line)) };
+        Ok(())
+    }
+
+    fn disable_gpio_interrupt(&mut self, pin: u8) {
+        let (line, _) = match gpio_exti_line(pin) {
+            Some(line) => line,
+            None => return,
+        };
+
+        let device_peripherals: pac::Peripherals = unsafe { pac::Peripherals::steal() };
+        let exti = &device_peripherals.EXTI;
+        let mask = 1 << line;
+        exti.imr.modify(|r, w| unsafe { w.bits(r.bits() & !mask) });
+        exti.rtsr.modify(|r, w| unsafe { w.bits(r.bits() & !mask) });
+        exti.ftsr.modify(|r, w| unsafe { w.bits(r.bits() & !mask) });
+        exti.pr.write(|w| unsafe { w.bits(mask) });
+        GPIO_INTERRUPT_LINES.fetch_and(!mask, Ordering::Relaxed);
+        GPIO_WAKE_LINES.fetch_and(!mask, Ordering::Relaxed);
+
+        // the EXTI9_5 and EXTI15_10 interrupts are shared by several lines
+        let shared_lines: u32 = match gpio_exti_interrupt(line) {
+            pac::Interrupt::EXTI9_5 => 0x03e0,
+            pac::Interrupt::EXTI15_10 => 0xfc00,
+            _ => mask,
+        };
+        if exti.imr.read().bits() & shared_lines == 0 {
+            NVIC::mask(gpio_exti_interrupt(line));
+        }
+    }
+
     fn get_current_time(&self) -> u32 {
         cortex_m::peripheral::DWT::cycle_count() / SYSCLK_MHZ
     }
 
+    fn get_cycles_per_microsecond(&self) -> u32 {
+        SYSCLK_MHZ
+    }
+
+    // each value takes two of the 16 bit data registers DR11 to DR42
+    fn store_backup_value(&mut self, index: usize, value: u32) {
+        if index >= rriv_board::BACKUP_VALUES {
+            return;
+        }
+        self.backup_domain.write_data_register_high(index * 2, (value >> 16) as u16);
+        self.backup_domain.write_data_register_high(index * 2 + 1, value as u16);
+    }
+
+    fn retrieve_backup_value(&mut self, index: usize) -> u32 {
+        if index >= rriv_board::BACKUP_VALUES {
+            return 0;
+        }
+        let high = self.backup_domain.read_data_register_high(index * 2) as u32;
+        let low = self.backup_domain.read_data_register_high(index * 2 + 1) as u32;
+        high << 16 | low
+    }
+
 
 }
 
@@ -963,6 +1254,66 @@ fn USB_LP_CAN_RX0() {
     });
 }
 
+#[interrupt]
+fn RTCALARM() {
+    // wakes the MCU from STOP mode, the alarm flag is cleared by the sleeping code once clocks are restored
+    let exti = unsafe { &*pac::EXTI::ptr() };
+    exti.pr.write(|w| w.pr17().set_bit());
+}
+
+const RTC_TICKS_PER_SECOND: u64 = 32768; // the internal rtc runs from the LSE with the prescaler counting down each second
+
+// internal rtc time in LSE ticks, from the seconds counter and the prescaler divider
+fn rtc_ticks() -> u64 {
+    let rtc = unsafe { &*pac::RTC::ptr() };
+    loop {
+        let seconds = (rtc.cnth.read().bits() << 16) | rtc.cntl.read().bits();
+        let divider = (rtc.divh.read().bits() << 16) | rtc.divl.read().bits();
+        // read again in case the second rolled over between the reads
+        if seconds == ((rtc.cnth.read().bits() << 16) | rtc.cntl.read().bits()) {
+            return seconds as u64 * RTC_TICKS_PER_SECOND + (RTC_TICKS_PER_SECOND - 1 - divider as u64);
+        }
+    }
+}
+
+// EXTI line and AFIO port code (PB = 1, PC = 2, PD = 3) of the dynamic gpios that can raise interrupts
+// gpio1 is used for PWM, and gpio3 and gpio4 are mapped wrongly on the hardware
+// the same pins as rriv_board::GPIO_INTERRUPT_CAPABLE_PINS
+fn gpio_exti_line(pin: u8) -> Option<(u32, u32)> {
+    match pin {
+        2 => Some((5, 1)),  // PB5
+        5 => Some((2, 3)),  // PD2
+        6 => Some((12, 2)), // PC12
+        7 => Some((11, 2)), // PC11
+        8 => Some((10, 2)), // PC10
+        _ => None,
+    }
+}
+
+fn gpio_exti_interrupt(line: u32) -> pac::Interrupt {
+    match line {
+        2 => pac::Interrupt::EXTI2,
+        5..=9 => pac::Interrupt::EXTI9_5,
+        _ => pac::Interrupt::EXTI15_10,
+    }
+}
+
+fn gpio_interrupt(pin: u8, line: u32, now: u32, gpio_state: bool) {
+    if GPIO_WAKE_LINES.load(Ordering::Relaxed) & (1 << line) != 0 {
+        GPIO_WOKE.store(true, Ordering::Relaxed);
+    }
+
+    cortex_m::interrupt::free(|_cs| {
+        unsafe {
+            #[allow(static_mut_refs)]
+            if let Some(gpio_interrupt_function) = &mut GPIO_INTERRUPT_FUNCTIONS[pin as usize - 1] {
+                gpio_interrupt_function(now, gpio_state);
+            }
+        }
+    });
+    // defmt::println!("GPIO interrupt at {}", now);
+}
+
 #[interrupt]
 fn EXTI2() {
     let exti = unsafe { &*pac::EXTI::ptr() };
@@ -971,16 +1322,37 @@ fn EXTI2() {
         let now = cortex_m::peripheral::DWT::cycle_count();
         let is_low = unsafe {(*pac::GPIOD::ptr()).idr.read().bits() & (1 << 2) == 0 };
         let gpio_state = if is_low { false } else { true };
-        let now = now / SYSCLK_MHZ; // convert to microseconds
-        cortex_m::interrupt::free(|_cs| {
-            unsafe {
-                #[allow(static_mut_refs)]
-                if let Some(gpio_interrupt_function) = &mut GPIO_INTERRUPT_FUNCTION {
-                    gpio_interrupt_function(now, gpio_state);
-                }
-            }
-        });
-        // defmt::println!("GPIO interrupt at {}", now);
+        gpio_interrupt(5, 2, now, gpio_state);
+    }
+}
+
+#[interrupt]
+fn EXTI9_5() {
+    let exti = unsafe { &*pac::EXTI::ptr() };
+    if exti.pr.read().pr5().bit_is_set() {
+        exti.pr.write(|w| w.pr5().set_bit());
+        let now = cortex_m::peripheral::DWT::cycle_count();
+        let gpio_state = unsafe { (*pac::GPIOB::ptr()).idr.read().bits() & (1 << 5) != 0 };
+        gpio_interrupt(2, 5, now, gpio_state);
+    }
+}
+
+#[interrupt]
+fn EXTI15_10() {
+    let exti = unsafe { &*pac::EXTI::ptr() };
+    let now = cortex_m::peripheral::DWT::cycle_count();
+    let idr = unsafe { (*pac::GPIOC::ptr()).idr.read().bits() };
+    if exti.pr.read().pr10().bit_is_set() {
+        exti.pr.write(|w| w.pr10().set_bit());
+        gpio_interrupt(8, 10, now, idr & (1 << 10) != 0);
+    }
+    if exti.pr.read().pr11().bit_is_set() {
+        exti.pr.write(|w| w.pr11().set_bit());
+        gpio_interrupt(7, 11, now, idr & (1 << 11) != 0);
+    }
+    if exti.pr.read().pr12().bit_is_set() {
+        exti.pr.write(|w| w.pr12().set_bit());
+        gpio_interrupt(6, 12, now, idr & (1 << 12) != 0);
     }
 }
 
@@ -1040,6 +1412,7 @@ pub struct BoardBuilder {
     pub i2c1: Option<BoardI2c1>,
     pub i2c2: Option<BoardI2c2>,
     pub internal_rtc: Option<Rtc>,
+    pub backup_domain: Option<BackupDomain>,
     pub storage: Option<Storage>,
     pub watchdog: Option<IndependentWatchdog>,
     pub counter: Option<CounterUs<TIM5>>,
@@ -1065,6 +1438,7 @@ impl BoardBuilder {
             rgb_led: None,
             oscillator_control: None,
             internal_rtc: None,
+            backup_domain: None,
             storage: None,
             watchdog: None,
             counter: None,
@@ -1104,7 +1478,7 @@ impl BoardBuilder {
             precise_delay: self.precise_delay.unwrap(),
             gpio: self.gpio.unwrap(),
             gpio_cr: gpio_cr,
-            // // power_control: self.power_control.unwrap(),
+            power_control: self.power_control.unwrap(),
             internal_adc: internal_adc,
             external_adc: self.external_adc.unwrap(),
             battery_level: self.battery_level.unwrap(),
@@ -1114,6 +1488,7 @@ impl BoardBuilder {
             storage: self.storage,
             debug: false,
             file_epoch: 0,
+            boot_count: 0,
             one_wire_bus: one_wire,
             one_wire_search_state: None,
             watchdog: watchdog,
@@ -1121,6 +1496,8 @@ impl BoardBuilder {
             hardware_errors: self.hardware_errors,
             clocks: self.clocks.unwrap(),
             pwm: Some(self.pwm.unwrap()),
+            rail_5v_held: false,
+            backup_domain: self.backup_domain.unwrap(),
         }
     }
 
@@ -1161,7 +1538,7 @@ impl BoardBuilder {
             mapr,
             // Config::default().baudrate(38400.bps()).wordlength_8bits().parity_none().stopbits(StopBits::STOP1), // this worked for the nox sensor
             Config::default()
-                .baudrate(115200.bps())// this appears to be right for the RAK 3172
+                .baudrate(rriv_board::USART_BAUD_RATE.bps())// this appears to be right for the RAK 3172
                 // .baudrate(38400.bps()) // going slower for uart5 and rs485 for now
                 .wordlength_8bits()
                 .parity_none()
@@ -1330,7 +1707,7 @@ impl BoardBuilder {
         let mut watchdog = IndependentWatchdog::new(device_peripherals.IWDG);
         watchdog.stop_on_debug(&device_peripherals.DBGMCU, true);
 
-        watchdog.start(MilliSeconds::secs(6));
+        watchdog.start(MilliSeconds::secs(WATCHDOG_SECONDS));
         watchdog.feed();
 
         // mcu device registers
@@ -1457,6 +1834,7 @@ impl BoardBuilder {
         );
 
         self.internal_rtc = Some(Rtc::new(device_peripherals.RTC, &mut backup_domain)); // TODO: make sure LSE on and running?
+        self.backup_domain = Some(backup_domain);
 
 
         BoardBuilder::setup_usb(usb_pins, &mut gpio_cr, device_peripherals.USB, &clocks);
@@ -1468,7 +1846,7 @@ impl BoardBuilder {
         let storage: Option<Storage> = {
             watchdog.start(MilliSeconds::secs(24));
             let result = storage::build(spi2_pins, device_peripherals.SPI2, clocks, delay2);
-            watchdog.start(MilliSeconds::secs(6));
+            watchdog.start(MilliSeconds::secs(WATCHDOG_SECONDS));
             match result {
                 Ok(storage) => Some(storage),
                 Err(hardware_error) => {
@@ -1645,6 +2023,7 @@ impl BoardBuilder {
         // build the power control
         let mut power_control = Some(PowerControl::new(power_pins)).unwrap();
         power_control.cycle_5v(&mut delay);
+        self.power_control = Some(power_control);
 
         // build the internal adc
         let internal_adc_configuration =
@@ -108,7 +108,8 @@ pub fn build_driver(
             Some(id) => id,
             None => [0;6],
         };
-        let general_settings = SensorDriverGeneralConfiguration::new(sensor_id, sensor_type_id);
+        let mut general_settings = SensorDriverGeneralConfiguration::new(sensor_id, sensor_type_id);
+        update_general_configuration(&mut general_settings, payload_values);
 
         match functions.0(general_settings, raw_payload_values) {
             Err(message) => {
@@ -123,6 +124,65 @@ pub fn build_driver(
     Err("build fn missing") 
 }
 
+// apply the general settings of a sensor set command
+pub fn update_general_configuration(general_config: &mut SensorDriverGeneralConfiguration, payload_values: &SensorSetPayloadValues) {
+    if let Some(warmup) = payload_values.warmup {
+        general_config.warmup = warmup;
+    }
+    if let Some(readings_per_burst) = payload_values.readings_per_burst {
+        general_config.readings_per_burst = readings_per_burst;
+    }
+    if let Some(sampling_interval) = payload_values.sampling_interval {
+        general_config.sampling_interval = sampling_interval;
+    }
+    if let Some(adaptive_parameter) = payload_values.adaptive_parameter {
+        general_config.adaptive_parameter = adaptive_parameter;
+    }
+    if let Some(adaptive_change) = payload_values.adaptive_change {
+        general_config.adaptive_change = adaptive_change;
+    }
+    if let Some(adaptive_level) = payload_values.adaptive_level {
+        match adaptive_level {
+            Some(level) => {
+                general_config.adaptive_level = level;
+                general_config.adaptive_level_enabled = 1;
+            }
+            None => general_config.adaptive_level_enabled = 0,
+        }
+    }
+    if let Some(adaptive_interval) = payload_values.adaptive_interval {
+        general_config.adaptive_interval = adaptive_interval;
+    }
+    if let Some(adaptive_duration) = payload_values.adaptive_duration {
+        general_config.adaptive_duration = adaptive_duration;
+    }
+    if let Some(power_domain) = payload_values.power_domain {
+        general_config.power_domain = power_domain;
+    }
+}
+
+// driver configuration json with the general configuration shared by all drivers added in
+pub fn sensor_configuration_json(driver: &mut Box<dyn SensorDriver>) -> Value {
+    let mut json = driver.get_configuration_json();
+    let general_config = driver.get_general_configuration();
+    if let Value::Object(ref mut map) = json {
+        map.insert("warmup".to_string(), json!(general_config.warmup));
+        map.insert("readings_per_burst".to_string(), json!(general_config.readings_per_burst));
+        map.insert("sampling_interval".to_string(), json!(general_config.sampling_interval));
+        map.insert("adaptive_parameter".to_string(), json!(general_config.adaptive_parameter));
+        map.insert("adaptive_change".to_string(), json!(general_config.adaptive_change));
+        if general_config.adaptive_level_enabled == 1 {
+            map.insert("adaptive_level".to_string(), json!(general_config.adaptive_level));
+        } else {
+            map.insert("adaptive_level".to_string(), json!(false));
+        }
+        map.insert("adaptive_interval".to_string(), json!(general_config.adaptive_interval));
+        map.insert("adaptive_duration".to_string(), json!(general_config.adaptive_duration));
+        map.insert("power".to_string(), json!(driver.get_requested_power().text()));
+    }
+    json
+}
+
 pub fn find_empty_slot(
         drivers: &mut [Option<Box<dyn SensorDriver>>; rriv_board::EEPROM_TOTAL_SENSOR_SLOTS]
 ) -> Option<usize> {
@@ -4,4 +4,5 @@ pub mod modes;
 pub mod bytes;
 pub mod helper;
 pub mod payloads;
-pub mod error;
\ No newline at end of file
+pub mod error;
+pub mod schedule;
\ No newline at end of file
@@ -17,9 +17,18 @@ pub struct DataloggerSettingsValues {
     pub start_up_delay: Option<u16>,
     pub delay_between_bursts: Option<u16>,
     pub bursts_per_measurement_cycle: Option<u8>,
+    pub readings_per_burst: Option<u8>,
+    pub clock_aligned: Option<bool>,
+    pub window_start: Option<u16>,
+    pub window_end: Option<u16>,
+    pub window_interval: Option<u16>,
+    pub hibernate_until: Option<u32>,
+    pub trigger_pin: Option<u8>,
+    pub trigger_edge: Option<u8>,
     pub mode: Option<u8>,
     pub enable_lorawan_telemetry: Option<bool>,
     pub enable_modbus_rtu: Option<bool>,
+    pub modbus_slave_id: Option<u8>,
     pub interactive_logging: Option<bool>,
     pub enable_sdi12: Option<bool>,
     pub lock_mode: Option<bool>
@@ -35,10 +44,20 @@ pub struct DataloggerSetPayload {
     pub interactive_logging_interval: Option<u16>,
     pub sleep_interval: Option<u16>,
     pub bursts_per_cycle: Option<u8>,
+    pub readings_per_burst: Option<u8>,
     pub start_up_delay: Option<u16>,
+    pub delay_between_bursts: Option<u16>,
+    pub clock_aligned: Option<bool>,
+    pub window_start: Option<Value>,
+    pub window_end: Option<Value>,
+    pub window_interval: Option<u16>,
+    pub hibernate_until: Option<u32>,
+    pub trigger_pin: Option<u8>,
+    pub trigger_edge: Option<Value>,
     pub mode: Option<Value>,
     pub enable_lorawan_telemetry: Option<bool>,
     pub enable_modbus_rtu: Option<bool>,
+    pub modbus_slave_id: Option<u8>,
     pub interactive_logging: Option<bool>,
     pub enable_sdi12: Option<bool>,
     pub lock_mode: Option<bool>
@@ -47,7 +66,7 @@ pub struct DataloggerSetPayload {
 }
 
 impl DataloggerSetPayload {
-    pub fn values(self) -> DataloggerSettingsValues {
+    pub fn values(self) -> Result<DataloggerSettingsValues, &'static str> {
         let mut datalogger_settings_values = DataloggerSettingsValues::default();
 
         if let Some(value) = self.logger_name {
@@ -102,10 +121,18 @@ impl DataloggerSetPayload {
             datalogger_settings_values.bursts_per_measurement_cycle = Some(bursts_per_cycle);
         }
 
+        if let Some(readings_per_burst) = self.readings_per_burst {
+            datalogger_settings_values.readings_per_burst = Some(readings_per_burst);
+        }
+
         if let Some(start_up_delay) = self.start_up_delay {
             datalogger_settings_values.start_up_delay = Some(start_up_delay);
         }
 
+        if let Some(delay_between_bursts) = self.delay_between_bursts {
+            datalogger_settings_values.delay_between_bursts = Some(delay_between_bursts);
+        }
+
         if let Some(enable_lorawan_telemetry) = self.enable_lorawan_telemetry {
             datalogger_settings_values.enable_lorawan_telemetry = Some(enable_lorawan_telemetry);
         }
@@ -118,6 +145,13 @@ impl DataloggerSetPayload {
             datalogger_settings_values.enable_modbus_rtu = Some(enable_modbus_rtu);
         }
 
+        if let Some(modbus_slave_id) = self.modbus_slave_id {
+            if !(1..=127).contains(&modbus_slave_id) {
+                return Err("modbus_slave_id must be between 1 and 127");
+            }
+            datalogger_settings_values.modbus_slave_id = Some(modbus_slave_id);
+        }
+
         if let Some(enable_sdi12) = self.enable_sdi12 {
             datalogger_settings_values.enable_sdi12 = Some(enable_sdi12);
         }
@@ -126,7 +160,55 @@ impl DataloggerSetPayload {
             datalogger_settings_values.lock_mode = Some(lock_mode);
         }
 
-        datalogger_settings_values
+        if let Some(clock_aligned) = self.clock_aligned {
+            datalogger_settings_values.clock_aligned = Some(clock_aligned);
+        }
+
+        if let Some(value) = self.window_start {
+            datalogger_settings_values.window_start = Some(time_of_day_value(value)?);
+        }
+
+        if let Some(value) = self.window_end {
+            datalogger_settings_values.window_end = Some(time_of_day_value(value)?);
+        }
+
+        if let Some(window_interval) = self.window_interval {
+            if window_interval > 60 * 4 {
+                return Err("window_interval must be 240 minutes or less");
+            }
+            datalogger_settings_values.window_interval = Some(window_interval);
+        }
+
+        if let Some(hibernate_until) = self.hibernate_until {
+            datalogger_settings_values.hibernate_until = Some(hibernate_until);
+        }
+
+        if let Some(trigger_pin) = self.trigger_pin {
+            if trigger_pin > 8 {
+                return Err("trigger_pin must be between 0 and 8");
+            }
+            datalogger_settings_values.trigger_pin = Some(trigger_pin);
+        }
+
+        if let Some(value) = self.trigger_edge {
+            let edge = match value {
+                serde_json::Value::String(value) => crate::services::trigger_service::edge_from_str(&value),
+                _ => None,
+            };
+            match edge {
+                Some(edge) => datalogger_settings_values.trigger_edge = Some(edge),
+                None => return Err("trigger_edge must be rising, falling or both"),
+            }
+        }
+
+        Ok(datalogger_settings_values)
+    }
+}
+
+fn time_of_day_value(value: Value) -> Result<u16, &'static str> {
+    match value {
+        serde_json::Value::String(value) => crate::datalogger::schedule::parse_time_of_day(&value),
+        _ => Err("time of day must be HH:MM"),
     }
 }
 
@@ -150,11 +232,29 @@ pub struct SensorSetPayload {
     pub action: Value,
     pub id: Option<Value>, // option
     pub r#type: Option<Value>,     
+    pub warmup: Option<u16>,
+    pub readings_per_burst: Option<u8>,
+    pub sampling_interval: Option<u16>,
+    pub adaptive_parameter: Option<u8>,
+    pub adaptive_change: Option<f32>,
+    pub adaptive_level: Option<Value>,
+    pub adaptive_interval: Option<u16>,
+    pub adaptive_duration: Option<u16>,
+    pub power: Option<Value>,
 }
 
 pub struct SensorSetPayloadValues {
     pub sensor_type_id: Option<u16>,
     pub sensor_id: Option<[u8; 6]>,
+    pub warmup: Option<u16>,
+    pub readings_per_burst: Option<u8>,
+    pub sampling_interval: Option<u16>,
+    pub adaptive_parameter: Option<u8>,
+    pub adaptive_change: Option<f32>,
+    pub adaptive_level: Option<Option<f32>>, // Some(None) switches the level off
+    pub adaptive_interval: Option<u16>,
+    pub adaptive_duration: Option<u16>,
+    pub power_domain: Option<u8>,
 }
 
 impl SensorSetPayload {
@@ -205,9 +305,75 @@ impl SensorSetPayload {
 
 
         
+        if let Some(readings_per_burst) = self.readings_per_burst {
+            if readings_per_burst == 0 || readings_per_burst > 100 {
+                return Err("readings_per_burst must be between 1 and 100");
+            }
+        }
+
+        if let Some(warmup) = self.warmup {
+            if warmup > 600 {
+                return Err("warmup must be 600 seconds or less");
+            }
+        }
+
+        if let Some(adaptive_change) = self.adaptive_change {
+            if adaptive_change < 0.0 {
+                return Err("adaptive_change must not be negative");
+            }
+        }
+
+        let mut adaptive_level = None;
+        if let Some(value) = &self.adaptive_level {
+            match value {
+                serde_json::Value::Number(level) => {
+                    adaptive_level = Some(Some(level.as_f64().unwrap_or_default() as f32));
+                }
+                serde_json::Value::Bool(false) => {
+                    adaptive_level = Some(None);
+                }
+                _ => {
+                    return Err("adaptive_level must be a number or false");
+                }
+            }
+        }
+
+        if let Some(adaptive_interval) = self.adaptive_interval {
+            if adaptive_interval > 3600 {
+                return Err("adaptive_interval must be 3600 seconds or less");
+            }
+        }
+
+        if let Some(adaptive_duration) = self.adaptive_duration {
+            if adaptive_duration > 24 * 60 {
+                return Err("adaptive_duration must be 1440 minutes or less");
+            }
+        }
+
+        let mut power_domain = None;
+        if let Some(value) = &self.power {
+            let domain = match value {
+                serde_json::Value::String(value) => crate::drivers::resources::power::PowerDomain::from_str(value),
+                _ => None,
+            };
+            match domain {
+                Some(domain) => power_domain = Some(domain.to_u8()),
+                None => return Err("power must be always_on, 3v, 5v, gpio1, gpio2 or gpio5 to gpio8"),
+            }
+        }
+
         let values = SensorSetPayloadValues {
             sensor_id: sensor_id,
-            sensor_type_id: sensor_type_id
+            sensor_type_id: sensor_type_id,
+            warmup: self.warmup,
+            readings_per_burst: self.readings_per_burst,
+            sampling_interval: self.sampling_interval,
+            adaptive_parameter: self.adaptive_parameter,
+            adaptive_change: self.adaptive_change,
+            adaptive_level,
+            adaptive_interval: self.adaptive_interval,
+            adaptive_duration: self.adaptive_duration,
+            power_domain,
         };
         Ok(values)
     }
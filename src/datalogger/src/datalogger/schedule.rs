@@ -0,0 +1,222 @@
+// Wall clock aligned measurement schedules
+//
+// Cycles start on multiples of the interval counted from the unix epoch, so every logger
+// using the same interval samples at the same UTC times (:00, :15, :30, :45 for 15 minutes).
+// An optional daily window (UTC) uses its own interval, e.g. every 5 minutes between 06:00 and 20:00.
+
+use super::settings::DataloggerSettings;
+
+pub const MINUTES_PER_DAY: u16 = 24 * 60;
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+fn minute_of_day(epoch: i64) -> u16 {
+    (epoch.rem_euclid(SECONDS_PER_DAY) / 60) as u16
+}
+
+fn in_window(settings: &DataloggerSettings, minute: u16) -> bool {
+    let start = settings.window_start;
+    let end = settings.window_end;
+    if start <= end {
+        minute >= start && minute < end
+    } else {
+        // the window wraps past midnight
+        minute >= start || minute < end
+    }
+}
+
+fn window_enabled(settings: &DataloggerSettings) -> bool {
+    settings.window_interval > 0 && settings.window_start != settings.window_end
+}
+
+// interval in seconds that applies at the given epoch
+fn interval_at(settings: &DataloggerSettings, epoch: i64) -> i64 {
+    let minutes = if window_enabled(settings) && in_window(settings, minute_of_day(epoch)) {
+        settings.window_interval
+    } else {
+        settings.sleep_interval
+    };
+
+    if minutes == 0 {
+        return 60;
+    }
+    minutes as i64 * 60
+}
+
+// the longest time between measurement cycles in seconds, the sleep_interval or the window_interval
+fn longest_cycle_interval(settings: &DataloggerSettings) -> i64 {
+    let mut minutes = settings.sleep_interval;
+    if window_enabled(settings) && settings.window_interval > minutes {
+        minutes = settings.window_interval;
+    }
+
+    if minutes == 0 {
+        return 60;
+    }
+    minutes as i64 * 60
+}
+
+// sensors are measured at most once per measurement cycle, so a sampling interval can only make a sensor skip cycles
+// one shorter than a cycle would silently sample at the cycle rate instead
+pub fn sampling_interval_allowed(settings: &DataloggerSettings, sampling_interval: u16) -> bool {
+    sampling_interval == 0 || sampling_interval as i64 >= longest_cycle_interval(settings)
+}
+
+// the next time a boundary of the window is crossed after the given epoch
+fn next_window_boundary(settings: &DataloggerSettings, epoch: i64) -> i64 {
+    let day_start = epoch - epoch.rem_euclid(SECONDS_PER_DAY);
+    let mut next = i64::MAX;
+    for minute in [settings.window_start, settings.window_end] {
+        let mut boundary = day_start + minute as i64 * 60;
+        if boundary <= epoch {
+            boundary += SECONDS_PER_DAY;
+        }
+        if boundary < next {
+            next = boundary;
+        }
+    }
+    next
+}
+
+// epoch at which the next measurement cycle should start
+pub fn next_cycle_epoch(settings: &DataloggerSettings, now: i64) -> i64 {
+    let interval = interval_at(settings, now);
+    let next = (now.div_euclid(interval) + 1) * interval;
+
+    if window_enabled(settings) {
+        // entering or leaving the window changes the interval, so start on the boundary
+        let boundary = next_window_boundary(settings, now);
+        if boundary < next {
+            return boundary;
+        }
+    }
+
+    next
+}
+
+// parse a "HH:MM" time of day into minutes after midnight
+pub fn parse_time_of_day(value: &str) -> Result<u16, &'static str> {
+    let mut parts = value.split(':');
+    let hours = parts.next().and_then(|hours| hours.parse::<u16>().ok());
+    let minutes = parts.next().and_then(|minutes| minutes.parse::<u16>().ok());
+    if parts.next().is_some() {
+        return Err("time of day must be HH:MM");
+    }
+
+    match (hours, minutes) {
+        (Some(hours), Some(minutes)) if hours < 24 && minutes < 60 => Ok(hours * 60 + minutes),
+        _ => Err("time of day must be HH:MM"),
+    }
+}
+
+pub fn format_time_of_day(minutes: u16, buf: &mut [u8; 5]) -> &str {
+    let hours = minutes / 60;
+    let minutes = minutes % 60;
+    buf[0] = b'0' + (hours / 10) as u8;
+    buf[1] = b'0' + (hours % 10) as u8;
+    buf[2] = b':';
+    buf[3] = b'0' + (minutes / 10) as u8;
+    buf[4] = b'0' + (minutes % 10) as u8;
+    core::str::from_utf8(buf).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: i64 = 1792368000; // 2026-10-19 00:00 UTC
+
+    fn at(hours: i64, minutes: i64) -> i64 {
+        DAY + hours * 3600 + minutes * 60
+    }
+
+    #[test]
+    fn test_next_cycle_is_aligned_to_the_interval() {
+        let mut settings = DataloggerSettings::new();
+        settings.sleep_interval = 15;
+        assert_eq!(at(0, 15), next_cycle_epoch(&settings, at(0, 10) + 1));
+        assert_eq!(at(0, 30), next_cycle_epoch(&settings, at(0, 15)));
+        assert_eq!(at(24, 0), next_cycle_epoch(&settings, at(23, 59)));
+
+        settings.sleep_interval = 0;
+        assert_eq!(at(0, 11), next_cycle_epoch(&settings, at(0, 10) + 1));
+    }
+
+    #[test]
+    fn test_next_cycle_starts_on_window_boundaries() {
+        let mut settings = DataloggerSettings::new();
+        settings.sleep_interval = 120;
+        settings.window_start = 7 * 60;
+        settings.window_end = 20 * 60 + 2;
+        settings.window_interval = 5;
+
+        assert_eq!(at(6, 0), next_cycle_epoch(&settings, at(5, 0)));
+        assert_eq!(at(7, 0), next_cycle_epoch(&settings, at(6, 0)));
+        assert_eq!(at(7, 5), next_cycle_epoch(&settings, at(7, 0)));
+        assert_eq!(at(20, 0), next_cycle_epoch(&settings, at(19, 58)));
+        assert_eq!(at(20, 2), next_cycle_epoch(&settings, at(20, 0)));
+        assert_eq!(at(22, 0), next_cycle_epoch(&settings, at(20, 2)));
+
+        // a window with no interval, or no length, is off
+        settings.window_interval = 0;
+        assert_eq!(at(8, 0), next_cycle_epoch(&settings, at(7, 0)));
+        settings.window_interval = 5;
+        settings.window_end = settings.window_start;
+        assert_eq!(at(8, 0), next_cycle_epoch(&settings, at(7, 0)));
+    }
+
+    #[test]
+    fn test_next_cycle_in_a_window_past_midnight() {
+        let mut settings = DataloggerSettings::new();
+        settings.sleep_interval = 60;
+        settings.window_start = 22 * 60;
+        settings.window_end = 2 * 60;
+        settings.window_interval = 10;
+
+        assert_eq!(at(22, 0), next_cycle_epoch(&settings, at(21, 30)));
+        assert_eq!(at(24, 0), next_cycle_epoch(&settings, at(23, 55)));
+        assert_eq!(at(0, 10), next_cycle_epoch(&settings, at(0, 0)));
+        assert_eq!(at(2, 0), next_cycle_epoch(&settings, at(1, 55)));
+        assert_eq!(at(3, 0), next_cycle_epoch(&settings, at(2, 0)));
+    }
+
+    #[test]
+    fn test_sampling_interval_is_at_least_a_cycle() {
+        let mut settings = DataloggerSettings::new();
+        settings.sleep_interval = 15;
+        assert!(sampling_interval_allowed(&settings, 0));
+        assert!(sampling_interval_allowed(&settings, 15 * 60));
+        assert!(sampling_interval_allowed(&settings, 3600));
+        assert!(!sampling_interval_allowed(&settings, 60));
+
+        // outside of a faster window the cycles are sleep_interval apart
+        settings.window_start = 7 * 60;
+        settings.window_end = 20 * 60;
+        settings.window_interval = 5;
+        assert!(!sampling_interval_allowed(&settings, 10 * 60));
+        settings.window_interval = 30;
+        assert!(!sampling_interval_allowed(&settings, 15 * 60));
+        assert!(sampling_interval_allowed(&settings, 30 * 60));
+    }
+
+    #[test]
+    fn test_parse_time_of_day() {
+        assert_eq!(Ok(390), parse_time_of_day("06:30"));
+        assert_eq!(Ok(365), parse_time_of_day("6:5"));
+        assert_eq!(Ok(0), parse_time_of_day("00:00"));
+        assert_eq!(Ok(1439), parse_time_of_day("23:59"));
+        assert!(parse_time_of_day("24:00").is_err());
+        assert!(parse_time_of_day("12:60").is_err());
+        assert!(parse_time_of_day("06").is_err());
+        assert!(parse_time_of_day("06:30:00").is_err());
+        assert!(parse_time_of_day("ab:cd").is_err());
+        assert!(parse_time_of_day("").is_err());
+    }
+
+    #[test]
+    fn test_format_time_of_day() {
+        let mut buf = [0u8; 5];
+        assert_eq!("06:30", format_time_of_day(390, &mut buf));
+        assert_eq!("00:00", format_time_of_day(0, &mut buf));
+        assert_eq!("23:59", format_time_of_day(1439, &mut buf));
+    }
+}
@@ -3,9 +3,11 @@ use rriv_board::EEPROM_DATALOGGER_SETTINGS_SIZE;
 use util::{any_as_u8_slice, check_alphanumeric};
 
 use crate::datalogger::payloads::DataloggerSettingsValues;
+use crate::datalogger::schedule::MINUTES_PER_DAY;
 
 
-const DATALOGGER_SETTINGS_UNUSED_BYTES: usize = 13;
+const DATALOGGER_SETTINGS_UNUSED_BYTES: usize = 47;
+const DATALOGGER_SETTINGS_LAYOUT_VERSION: u8 = 1;
 #[bitfield(u8)]
 #[derive(PartialEq)]
 pub struct DataloggerSettingsBitField {
@@ -16,7 +18,7 @@ pub struct DataloggerSettingsBitField {
     pub enable_lorawan_telemetry: bool,
 
     #[bits(1, default = false)]
-    pub enable_modbus_rtu: bool,
+    pub enable_modbus_rtu: bool, // the logger stays awake to answer requests, so it doesn't sleep between cycles
 
     #[bits(1, default = false)]
     pub lock_mode: bool,
@@ -34,9 +36,35 @@ pub struct DataloggerSettingsBitField {
     pub enable_sdi12: bool,
 }
 
+#[bitfield(u8)]
+#[derive(PartialEq)]
+pub struct TriggerSettingsBitField {
+    #[bits(4, default = 0)]
+    pub pin: u8, // dynamic gpio that triggers measurements, 0 means no trigger
+
+    #[bits(2, default = 0)]
+    pub edge: u8, // 0 rising, 1 falling, 2 both
+
+    #[bits(2)]
+    __: u8,
+}
 
+#[bitfield(u8)]
+#[derive(PartialEq)]
+pub struct OptionsBitField {
+    #[bits(1, default = false)]
+    pub clock_aligned: bool, // cycles start on multiples of the interval in UTC
+
+    #[bits(7, default = 0)]
+    pub modbus_slave_id: u8, // address as a modbus rtu slave, 0 means the default address
+}
 
+
+
+// stored in eeprom, so fields keep their offsets and new fields are taken from the reserved bytes
+// firmware before layout_version stored 64 bytes ending with toggles, see migrate
 #[derive(Clone, Copy, PartialEq)]
+#[repr(C)]
 pub struct DataloggerSettings {
     pub deployment_identifier: [u8; 16],
     pub logger_name: [u8; 8],
@@ -44,14 +72,24 @@ pub struct DataloggerSettings {
     pub deployment_timestamp: u64,
     pub interactive_logging_interval: u16, //seconds
     pub sleep_interval: u16, // minutes
-    pub start_up_delay: u16,
-    pub delay_between_bursts: u16,
+    pub start_up_delay: u16, // seconds
+    pub delay_between_bursts: u16, // seconds
     pub bursts_per_measurement_cycle: u8,
     pub mode: u8,
     pub toggles: DataloggerSettingsBitField,
+    layout_version: u8,
+    pub readings_per_burst: u8, // 0 means use the largest readings_per_burst of the configured sensors
+    pub options: OptionsBitField,
+    pub window_start: u16, // minutes after UTC midnight
+    pub window_end: u16, // minutes after UTC midnight
+    pub window_interval: u16, // minutes, 0 means no window
+    pub hibernate_until: u32, // UTC epoch at which hibernate mode switches to field mode
+    pub trigger: TriggerSettingsBitField,
     reserved: [u8; DATALOGGER_SETTINGS_UNUSED_BYTES],
 }
 
+const _: () = assert!(core::mem::size_of::<DataloggerSettings>() == EEPROM_DATALOGGER_SETTINGS_SIZE);
+
 impl DataloggerSettings {
     pub fn new() -> Self {
         DataloggerSettings {
@@ -66,13 +104,44 @@ impl DataloggerSettings {
             delay_between_bursts: 0,
             mode: b'i',
             toggles: DataloggerSettingsBitField::new(),
+            layout_version: DATALOGGER_SETTINGS_LAYOUT_VERSION,
+            readings_per_burst: 0,
+            options: OptionsBitField::new(),
+            window_start: 0,
+            window_end: 0,
+            window_interval: 0,
+            hibernate_until: 0,
+            trigger: TriggerSettingsBitField::new(),
             reserved: [b'\0'; DATALOGGER_SETTINGS_UNUSED_BYTES],
         }
     }
 
     pub fn new_from_bytes(bytes: [u8; EEPROM_DATALOGGER_SETTINGS_SIZE]) -> DataloggerSettings {
+        // the byte array isn't aligned for deployment_timestamp
         let settings = bytes.as_ptr().cast::<DataloggerSettings>();
-        unsafe { *settings }
+        unsafe { settings.read_unaligned() }
+    }
+
+    // settings from before layout_version keep the fields up to toggles,
+    // the bytes after them were reserved or beyond the old partition and may hold anything
+    pub fn migrate(self) -> DataloggerSettings {
+        if self.layout_version == DATALOGGER_SETTINGS_LAYOUT_VERSION {
+            return self;
+        }
+
+        let mut settings = DataloggerSettings::new();
+        settings.deployment_identifier = self.deployment_identifier;
+        settings.logger_name = self.logger_name;
+        settings.site_name = self.site_name;
+        settings.deployment_timestamp = self.deployment_timestamp;
+        settings.interactive_logging_interval = self.interactive_logging_interval;
+        settings.sleep_interval = self.sleep_interval;
+        settings.start_up_delay = self.start_up_delay;
+        settings.delay_between_bursts = self.delay_between_bursts;
+        settings.bursts_per_measurement_cycle = self.bursts_per_measurement_cycle;
+        settings.mode = self.mode;
+        settings.toggles = self.toggles;
+        settings
     }
 
     pub fn get_bytes(&mut self) -> [u8; EEPROM_DATALOGGER_SETTINGS_SIZE] {
@@ -91,6 +160,10 @@ impl DataloggerSettings {
             settings.bursts_per_measurement_cycle = 1;
         }
 
+        if self.readings_per_burst > 100 {
+            settings.readings_per_burst = 0;
+        }
+
         if self.delay_between_bursts > 300_u16 {
             settings.delay_between_bursts = 0_u16
         }
@@ -103,6 +176,22 @@ impl DataloggerSettings {
             settings.sleep_interval = 15_u16;
         }
 
+        if self.window_start >= MINUTES_PER_DAY || self.window_end >= MINUTES_PER_DAY {
+            settings.window_start = 0;
+            settings.window_end = 0;
+        }
+
+        if self.window_interval > 60_u16 * 4 {
+            settings.window_interval = 0;
+        }
+
+        if self.trigger.pin() > 8 {
+            settings.trigger = TriggerSettingsBitField::new();
+        }
+
+        if self.trigger.edge() > 2 {
+            settings.trigger.set_edge(0);
+        }
 
         if self.start_up_delay > 60_u16 {
             settings.start_up_delay = 0;
@@ -138,6 +227,15 @@ impl DataloggerSettings {
         settings.bursts_per_measurement_cycle = values.bursts_per_measurement_cycle.unwrap_or(self.bursts_per_measurement_cycle);
         settings.start_up_delay = values.start_up_delay.unwrap_or(self.start_up_delay);
         settings.delay_between_bursts = values.delay_between_bursts.unwrap_or(self.delay_between_bursts);
+        settings.readings_per_burst = values.readings_per_burst.unwrap_or(self.readings_per_burst);
+        settings.options.set_clock_aligned(values.clock_aligned.unwrap_or(self.options.clock_aligned()));
+        settings.options.set_modbus_slave_id(values.modbus_slave_id.unwrap_or(self.options.modbus_slave_id()));
+        settings.window_start = values.window_start.unwrap_or(self.window_start);
+        settings.window_end = values.window_end.unwrap_or(self.window_end);
+        settings.window_interval = values.window_interval.unwrap_or(self.window_interval);
+        settings.hibernate_until = values.hibernate_until.unwrap_or(self.hibernate_until);
+        settings.trigger.set_pin(values.trigger_pin.unwrap_or(self.trigger.pin()));
+        settings.trigger.set_edge(values.trigger_edge.unwrap_or(self.trigger.edge()));
         settings.mode = values.mode.unwrap_or(self.mode);
         settings.toggles.set_enable_lorawan_telemetry(values.enable_lorawan_telemetry.unwrap_or(self.toggles.enable_lorawan_telemetry()));
         settings.toggles.set_enable_modbus_rtu(values.enable_modbus_rtu.unwrap_or(self.toggles.enable_modbus_rtu()));
@@ -151,3 +249,71 @@ impl DataloggerSettings {
 }
 
 
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use core::mem::offset_of;
+
+    #[test]
+    fn test_settings_keep_stored_offsets() {
+        assert_eq!(0, offset_of!(DataloggerSettings, deployment_identifier));
+        assert_eq!(16, offset_of!(DataloggerSettings, logger_name));
+        assert_eq!(24, offset_of!(DataloggerSettings, site_name));
+        assert_eq!(32, offset_of!(DataloggerSettings, deployment_timestamp));
+        assert_eq!(40, offset_of!(DataloggerSettings, interactive_logging_interval));
+        assert_eq!(42, offset_of!(DataloggerSettings, sleep_interval));
+        assert_eq!(44, offset_of!(DataloggerSettings, start_up_delay));
+        assert_eq!(46, offset_of!(DataloggerSettings, delay_between_bursts));
+        assert_eq!(48, offset_of!(DataloggerSettings, bursts_per_measurement_cycle));
+        assert_eq!(49, offset_of!(DataloggerSettings, mode));
+        assert_eq!(50, offset_of!(DataloggerSettings, toggles));
+        assert_eq!(51, offset_of!(DataloggerSettings, layout_version));
+        assert_eq!(52, offset_of!(DataloggerSettings, readings_per_burst));
+        assert_eq!(53, offset_of!(DataloggerSettings, options));
+        assert_eq!(54, offset_of!(DataloggerSettings, window_start));
+        assert_eq!(56, offset_of!(DataloggerSettings, window_end));
+        assert_eq!(58, offset_of!(DataloggerSettings, window_interval));
+        assert_eq!(60, offset_of!(DataloggerSettings, hibernate_until));
+        assert_eq!(64, offset_of!(DataloggerSettings, trigger));
+    }
+
+    #[test]
+    fn test_settings_round_trip() {
+        let mut settings = DataloggerSettings::new();
+        settings.window_start = 120;
+        settings.hibernate_until = 1_700_000_000;
+        let bytes = settings.get_bytes();
+
+        let restored = DataloggerSettings::new_from_bytes(bytes).migrate();
+        assert!(restored == settings);
+    }
+
+    #[test]
+    fn test_settings_from_earlier_firmware() {
+        // 64 bytes ending with toggles and reserved bytes, then whatever followed in the eeprom
+        let mut bytes = [0xFFu8; EEPROM_DATALOGGER_SETTINGS_SIZE];
+        bytes[0..51].fill(0);
+        bytes[0..4].copy_from_slice(b"dep1");
+        bytes[40..42].copy_from_slice(&5u16.to_ne_bytes());
+        bytes[42..44].copy_from_slice(&30u16.to_ne_bytes());
+        bytes[48] = 3;
+        bytes[49] = b'f';
+        bytes[50] = 0b0000_0010;
+        bytes[51..64].fill(0);
+
+        let settings = DataloggerSettings::new_from_bytes(bytes).migrate();
+        assert_eq!(b"dep1", &settings.deployment_identifier[0..4]);
+        assert_eq!(5, settings.interactive_logging_interval);
+        assert_eq!(30, settings.sleep_interval);
+        assert_eq!(3, settings.bursts_per_measurement_cycle);
+        assert_eq!(b'f', settings.mode);
+        assert!(settings.toggles.enable_lorawan_telemetry());
+        assert_eq!(0, settings.readings_per_burst);
+        assert_eq!(0, settings.window_interval);
+        assert_eq!(0, settings.hibernate_until);
+        assert_eq!(0, settings.trigger.pin());
+        assert_eq!(DATALOGGER_SETTINGS_LAYOUT_VERSION, settings.layout_version);
+    }
+}
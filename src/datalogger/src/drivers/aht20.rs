@@ -42,7 +42,8 @@ pub struct AHT20 {
     special_config: AHT20SpecialConfiguration,
     humidity: f64,
     temperature: f64,
-    enabled: bool
+    enabled: bool,
+    measurement_requested: bool,
 }
 
 
@@ -79,6 +80,44 @@ impl AHT20 {
         }
     }
 
+    fn request_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        let cmd: [u8; 3] = [AHTX0_CMD_TRIGGER, 0x33, 0];
+        match board.ic2_write(AHTX0_I2CADDR_DEFAULT, &cmd) {
+            Ok(_) => {},
+            Err(err) => {
+                defmt::println!("Failed write to AHT20 {:?}", err);
+                self.humidity = MAX;
+                self.temperature = MAX
+            },
+        }
+    }
+
+    fn read_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        let mut data = [0_u8; 6];
+        match board.ic2_read(AHTX0_I2CADDR_DEFAULT, &mut data){
+            Ok(_) => {},
+            Err(err) => {
+                defmt::println!("Failed write to AHT20 {:?}", err);
+                self.humidity = MAX;
+                self.temperature = MAX
+            },
+        }
+
+        let mut h: Wrapping<u32> = Wrapping(data[1] as u32);
+        h = h << 8;
+        h = h | Wrapping(data[2] as u32);
+        h = h << 4;
+        h = h | (Wrapping(data[3] as u32) >> 4);
+        self.humidity = (h.0 as f64 * 100.0) / 0x100000 as f64;
+   
+        let mut t: u32 = (data[3] & 0x0F) as u32;
+        t = t << 8;
+        t = t | data[4] as u32;
+        t = t << 8;
+        t = t | data [5] as u32;
+        self.temperature = t as f64 * 200.0 / (0x100000 as f64) - 50.0;
+    }
+
     fn self_calibrate(board: &mut dyn rriv_board::RRIVBoard){
         let cmd = [AHTX0_CMD_CALIBRATE, 0x08, 0x00];
         let _ = board.ic2_write(AHTX0_I2CADDR_DEFAULT, &cmd);
@@ -143,48 +182,34 @@ impl SensorDriver for AHT20 {
 
     getters!();
 
-    fn take_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+    fn start_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
         if !self.enabled {
             return;
         }
+        self.request_measurement(board);
+        self.measurement_requested = true;
+    }
 
-
-        let cmd: [u8; 3] = [AHTX0_CMD_TRIGGER, 0x33, 0];
-        match board.ic2_write(AHTX0_I2CADDR_DEFAULT, &cmd) {
-            Ok(_) => {},
-            Err(err) => {
-                defmt::println!("Failed write to AHT20 {:?}", err);
-                self.humidity = MAX;
-                self.temperature = MAX
-            },
+    fn poll_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) -> bool {
+        if !self.measurement_requested {
+            return true;
         }
-
-        AHT20::loop_until_ready(board);
-
-        let mut data = [0_u8; 6];
-        match board.ic2_read(AHTX0_I2CADDR_DEFAULT, &mut data){
-            Ok(_) => {},
-            Err(err) => {
-                defmt::println!("Failed write to AHT20 {:?}", err);
-                self.humidity = MAX;
-                self.temperature = MAX
-            },
+        if (AHT20::get_status(board) & AHTX0_STATUS_BUSY) != 0 {
+            return false;
         }
+        self.measurement_requested = false;
+        self.read_measurement(board);
+        true
+    }
 
-        let mut h: Wrapping<u32> = Wrapping(data[1] as u32);
-        h = h << 8;
-        h = h | Wrapping(data[2] as u32);
-        h = h << 4;
-        h = h | (Wrapping(data[3] as u32) >> 4);
-        self.humidity = (h.0 as f64 * 100.0) / 0x100000 as f64;
-   
-        let mut t: u32 = (data[3] & 0x0F) as u32;
-        t = t << 8;
-        t = t | data[4] as u32;
-        t = t << 8;
-        t = t | data [5] as u32;
-        self.temperature = t as f64 * 200.0 / (0x100000 as f64) - 50.0;
+    fn take_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        if !self.enabled {
+            return;
+        }
 
+        self.request_measurement(board);
+        AHT20::loop_until_ready(board);
+        self.read_measurement(board);
     }
 
     fn get_measured_parameter_count(&mut self) -> usize {
@@ -241,7 +266,8 @@ impl AHT20 {
             special_config,
             humidity: MAX,
             temperature: MAX,
-            enabled: true
+            enabled: true,
+            measurement_requested: false,
         }
     }
 }
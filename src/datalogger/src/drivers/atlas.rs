@@ -0,0 +1,747 @@
+// Atlas Scientific OEM circuits, EC, pH, ORP, DO and RTD, as one driver over the registers of each circuit
+// the circuits read continuously while active and raise DATA_AVAILABLE when a new reading is ready
+// values are 32 bit big endian registers holding the value times a fixed multiplier
+// the circuits keep their own calibration, sensor calibrate fit sends the reference values of the stored points
+// as the circuit's calibration requests and sensor calibrate clear clears the circuit's calibration
+
+use core::marker::PhantomData;
+
+use serde_json::json;
+
+use crate::sensor_name_from_type_id;
+use crate::services::barometer_service;
+
+use super::types::*;
+
+// Constants for OEM register data
+// pub const DEVICE_TYPE: u8 = 0x00;
+// pub const FIRMWARE_VERSION: u8 = 0x01;
+// pub const ADDR_LOCK: u8 = 0x02;
+// pub const NEW_ADDR_REGISTER: u8 = 0x03;
+// pub const INT_CTRL: u8 = 0x04;
+pub const LED_CTRL: u8 = 0x05;
+pub const SLEEP_CTRL: u8 = 0x06;
+pub const DATA_AVAILABLE: u8 = 0x07;
+
+// take_measurement polls this often until a new reading is available, covering the slowest reading interval
+pub const POLL_DELAY_MS: u16 = 10;
+pub const POLL_ATTEMPTS: u32 = 200;
+
+const COMPENSATION_MULTIPLIER: f64 = 100.0; // all compensation registers hold the value times 100
+
+const COMPENSATE_TEMPERATURE: u8 = 0b001;
+const COMPENSATE_SALINITY: u8 = 0b010;
+const COMPENSATE_PRESSURE: u8 = 0b100;
+const COMPENSATE_PRESSURE_FROM_BAROMETER: u8 = 0b1000; // use the pressure published by a barometer driver
+
+// the registers that differ between the circuits
+pub struct AtlasRegisters {
+    pub default_address: u8,
+    pub calibration_value: Option<u8>, // None when calibration points don't take a value
+    pub calibration_multiplier: f64,
+    pub calibration_request: u8,
+    pub temperature_compensation: Option<u8>,
+    pub salinity_compensation: Option<u8>,
+    pub pressure_compensation: Option<u8>,
+}
+
+// stored as the Atlas driver's special configuration
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct AtlasOemConfiguration {
+    temperature_compensation: f32, // degrees C
+    salinity_compensation: f32,    // conductivity in uS/cm
+    pressure_compensation: f32,    // kPa
+    address: u8,                   // 0 means the circuit's default address
+    compensations: u8,             // bits for the compensations that have been set
+}
+
+impl AtlasOemConfiguration {
+    pub fn new() -> AtlasOemConfiguration {
+        AtlasOemConfiguration {
+            temperature_compensation: 0.0,
+            salinity_compensation: 0.0,
+            pressure_compensation: 0.0,
+            address: 0,
+            compensations: 0,
+        }
+    }
+
+    pub fn address(&self, registers: &AtlasRegisters) -> u8 {
+        if self.address == 0 { registers.default_address } else { self.address }
+    }
+
+    pub fn update_from_values(&mut self, values: &serde_json::Value, registers: &AtlasRegisters) -> Result<(), &'static str> {
+        match &values["address"] {
+            serde_json::Value::Number(number) => {
+                match number.as_u64() {
+                    Some(number) if (0x08..=0x77).contains(&number) => self.address = number as u8,
+                    _ => return Err("invalid address"),
+                }
+            }
+            serde_json::Value::Null => {}
+            _ => return Err("invalid address"),
+        }
+
+        update_compensation(
+            &values["temperature_compensation"],
+            registers.temperature_compensation.is_some(),
+            COMPENSATE_TEMPERATURE,
+            &mut self.temperature_compensation,
+            &mut self.compensations,
+            "temperature compensation is not supported",
+        )?;
+        update_compensation(
+            &values["salinity_compensation"],
+            registers.salinity_compensation.is_some(),
+            COMPENSATE_SALINITY,
+            &mut self.salinity_compensation,
+            &mut self.compensations,
+            "salinity compensation is not supported",
+        )?;
+        // pressure can also follow the pressure published by a barometer driver
+        if values["pressure_compensation"] == "barometer" {
+            if registers.pressure_compensation.is_none() {
+                return Err("pressure compensation is not supported");
+            }
+            self.compensations = (self.compensations & !COMPENSATE_PRESSURE) | COMPENSATE_PRESSURE_FROM_BAROMETER;
+            return Ok(());
+        }
+        if !values["pressure_compensation"].is_null() {
+            self.compensations &= !COMPENSATE_PRESSURE_FROM_BAROMETER;
+        }
+        update_compensation(
+            &values["pressure_compensation"],
+            registers.pressure_compensation.is_some(),
+            COMPENSATE_PRESSURE,
+            &mut self.pressure_compensation,
+            &mut self.compensations,
+            "pressure compensation is not supported",
+        )?;
+
+        Ok(())
+    }
+
+    // adds the address and compensation settings to a driver's configuration json
+    pub fn add_configuration_json(&self, json: &mut serde_json::Value, registers: &AtlasRegisters) {
+        json["address"] = self.address(registers).into();
+        if registers.temperature_compensation.is_some() {
+            json["temperature_compensation"] = compensation_json(self.temperature_compensation, self.compensations & COMPENSATE_TEMPERATURE != 0);
+        }
+        if registers.salinity_compensation.is_some() {
+            json["salinity_compensation"] = compensation_json(self.salinity_compensation, self.compensations & COMPENSATE_SALINITY != 0);
+        }
+        if registers.pressure_compensation.is_some() && self.compensations & COMPENSATE_PRESSURE_FROM_BAROMETER != 0 {
+            json["pressure_compensation"] = "barometer".into();
+        } else if registers.pressure_compensation.is_some() {
+            json["pressure_compensation"] = compensation_json(self.pressure_compensation, self.compensations & COMPENSATE_PRESSURE != 0);
+        }
+    }
+}
+
+// a number sets the compensation, false stops sending it so the circuit uses its own default
+fn update_compensation(
+    value: &serde_json::Value,
+    supported: bool,
+    bit: u8,
+    compensation: &mut f32,
+    compensations: &mut u8,
+    unsupported_message: &'static str,
+) -> Result<(), &'static str> {
+    match value {
+        serde_json::Value::Null => return Ok(()),
+        _ if !supported => return Err(unsupported_message),
+        serde_json::Value::Number(number) => {
+            match number.as_f64() {
+                Some(number) if number >= 0.0 => {
+                    *compensation = number as f32;
+                    *compensations |= bit;
+                }
+                _ => return Err("compensation values must be positive numbers"),
+            }
+        }
+        serde_json::Value::Bool(false) => *compensations &= !bit,
+        _ => return Err("compensation values must be positive numbers"),
+    }
+    Ok(())
+}
+
+fn compensation_json(value: f32, enabled: bool) -> serde_json::Value {
+    if enabled { value.into() } else { serde_json::Value::Null }
+}
+
+const CALIBRATION_CLEAR: u8 = 1; // the clear request is the same on every circuit
+const MAX_CALIBRATION_REQUESTS: usize = 4; // clear and up to three points
+
+#[derive(Copy, Clone)]
+pub struct AtlasCalibration {
+    request: u8,
+    value: f64,
+}
+
+// calibration requests waiting to be sent to the circuit, in the order the circuit needs them
+pub struct AtlasCalibrationQueue {
+    calibrations: [AtlasCalibration; MAX_CALIBRATION_REQUESTS],
+    count: usize,
+}
+
+impl AtlasCalibrationQueue {
+    fn new() -> AtlasCalibrationQueue {
+        AtlasCalibrationQueue {
+            calibrations: [AtlasCalibration { request: 0, value: 0.0 }; MAX_CALIBRATION_REQUESTS],
+            count: 0,
+        }
+    }
+
+    pub fn push(&mut self, request: u8, value: f64) -> Result<(), ()> {
+        if self.count == MAX_CALIBRATION_REQUESTS {
+            return Err(());
+        }
+        self.calibrations[self.count] = AtlasCalibration { request, value };
+        self.count += 1;
+        Ok(())
+    }
+
+    fn pop_front(&mut self) -> Option<AtlasCalibration> {
+        if self.count == 0 {
+            return None;
+        }
+        let calibration = self.calibrations[0];
+        self.calibrations.copy_within(1..self.count, 0);
+        self.count -= 1;
+        Some(calibration)
+    }
+
+    fn clear(&mut self) {
+        self.count = 0;
+    }
+}
+
+pub struct AtlasOem {
+    address: u8,
+}
+
+impl AtlasOem {
+    pub fn new(configuration: &AtlasOemConfiguration, registers: &AtlasRegisters) -> AtlasOem {
+        AtlasOem {
+            address: configuration.address(registers),
+        }
+    }
+
+    // wake the circuit, turn on the led and send the compensations
+    pub fn setup(&mut self, board: &mut dyn rriv_board::RRIVBoard, configuration: &AtlasOemConfiguration, registers: &AtlasRegisters) {
+        self.address = configuration.address(registers);
+        self.wake(board);
+        self.write_register(board, &[LED_CTRL, 1]);
+        self.write_compensations(board, configuration, registers);
+    }
+
+    pub fn wake(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        self.write_register(board, &[SLEEP_CTRL, 1]);
+    }
+
+    pub fn write_compensations(&mut self, board: &mut dyn rriv_board::RRIVBoard, configuration: &AtlasOemConfiguration, registers: &AtlasRegisters) {
+        let compensations = [
+            (registers.temperature_compensation, COMPENSATE_TEMPERATURE, configuration.temperature_compensation),
+            (registers.salinity_compensation, COMPENSATE_SALINITY, configuration.salinity_compensation),
+            (registers.pressure_compensation, COMPENSATE_PRESSURE, configuration.pressure_compensation),
+        ];
+        for (register, bit, value) in compensations {
+            if let Some(register) = register {
+                if configuration.compensations & bit != 0 {
+                    self.write_value(board, register, value as f64 * COMPENSATION_MULTIPLIER);
+                }
+            }
+        }
+
+        if let Some(register) = registers.pressure_compensation {
+            if configuration.compensations & COMPENSATE_PRESSURE_FROM_BAROMETER != 0 {
+                if let Some(pressure) = barometer_service::barometric_pressure() {
+                    self.write_value(board, register, pressure as f64 * COMPENSATION_MULTIPLIER);
+                }
+            }
+        }
+    }
+
+    // the circuit sets the calibration request register back to 0 when it has finished a calibration
+    pub fn calibration_busy(&mut self, board: &mut dyn rriv_board::RRIVBoard, registers: &AtlasRegisters) -> bool {
+        let mut request = [0u8];
+        match board.ic2_write_read(self.address, &[registers.calibration_request], &mut request) {
+            Ok(_) => request[0] != 0,
+            Err(err) => {
+                defmt::println!("{}", err);
+                true
+            }
+        }
+    }
+
+    pub fn calibrate(&mut self, board: &mut dyn rriv_board::RRIVBoard, calibration: AtlasCalibration, registers: &AtlasRegisters) {
+        if let Some(register) = registers.calibration_value {
+            self.write_value(board, register, calibration.value * registers.calibration_multiplier);
+        }
+        self.write_register(board, &[registers.calibration_request, calibration.request]);
+        defmt::println!("atlas 0x{:x} calibration request {}", self.address, calibration.request);
+    }
+
+    // discard the current reading so the next one is measured after the measurement started
+    pub fn clear_data_available(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        self.write_register(board, &[DATA_AVAILABLE, 0]);
+    }
+
+    pub fn data_available(&mut self, board: &mut dyn rriv_board::RRIVBoard) -> bool {
+        let mut available = [0u8];
+        match board.ic2_write_read(self.address, &[DATA_AVAILABLE], &mut available) {
+            Ok(_) => available[0] == 1,
+            Err(err) => {
+                defmt::println!("{}", err);
+                false
+            }
+        }
+    }
+
+    // read a value register and divide out its multiplier
+    pub fn read_value(&mut self, board: &mut dyn rriv_board::RRIVBoard, register: u8, multiplier: f64) -> Option<f64> {
+        let mut bytes = [0u8; 4];
+        match board.ic2_write_read(self.address, &[register], &mut bytes) {
+            Ok(_) => Some(i32::from_be_bytes(bytes) as f64 / multiplier),
+            Err(err) => {
+                defmt::println!("{}", err);
+                None
+            }
+        }
+    }
+
+    fn write_value(&mut self, board: &mut dyn rriv_board::RRIVBoard, register: u8, value: f64) {
+        let rounded = if value < 0.0 { value - 0.5 } else { value + 0.5 };
+        let bytes = (rounded as i32).to_be_bytes();
+        self.write_register(board, &[register, bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+
+    fn write_register(&mut self, board: &mut dyn rriv_board::RRIVBoard, message: &[u8]) {
+        match board.ic2_write(self.address, message) {
+            Ok(_) => {}
+            Err(err) => defmt::println!("{}", err),
+        }
+    }
+}
+
+pub struct AtlasReading {
+    pub register: u8,
+    pub multiplier: f64,
+    pub identifier: &'static str,
+}
+
+const MAX_READINGS: usize = 2;
+
+// what differs between the circuits, the registers, the readings and how calibration points map to requests
+pub trait AtlasProbe {
+    const REGISTERS: AtlasRegisters;
+    const READINGS: &'static [AtlasReading];
+    const CALIBRATION_POINTS: usize; // the most calibration points the circuit takes
+
+    // queue the calibration requests for the reference values of the calibration points, newest first
+    fn queue_calibration(references: &[f64], queue: &mut AtlasCalibrationQueue) -> Result<(), ()>;
+}
+
+pub struct AtlasSpecialConfiguration<P: AtlasProbe> {
+    oem: AtlasOemConfiguration,
+    probe: PhantomData<P>,
+}
+
+impl<P: AtlasProbe> Clone for AtlasSpecialConfiguration<P> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<P: AtlasProbe> Copy for AtlasSpecialConfiguration<P> {}
+
+impl<P: AtlasProbe> AtlasSpecialConfiguration<P> {
+    pub fn new_from_bytes(
+        bytes: [u8; SENSOR_SETTINGS_PARTITION_SIZE],
+    ) -> AtlasSpecialConfiguration<P> {
+        let oem = unsafe { core::ptr::read_unaligned(bytes.as_ptr().cast::<AtlasOemConfiguration>()) };
+        Self { oem, probe: PhantomData }
+    }
+
+    pub fn parse_from_values(value: serde_json::Value) -> Result<AtlasSpecialConfiguration<P>, &'static str> {
+        let mut oem = AtlasOemConfiguration::new();
+        oem.update_from_values(&value, &P::REGISTERS)?;
+        Ok(Self { oem, probe: PhantomData })
+    }
+}
+
+pub struct Atlas<P: AtlasProbe> {
+    general_config: SensorDriverGeneralConfiguration,
+    special_config: AtlasSpecialConfiguration<P>,
+    oem: AtlasOem,
+    calibrations: AtlasCalibrationQueue,
+    values: [Option<f64>; MAX_READINGS],
+}
+
+impl<P: AtlasProbe> Atlas<P> {
+    pub fn new(
+        general_config: SensorDriverGeneralConfiguration,
+        special_config: AtlasSpecialConfiguration<P>,
+    ) -> Self {
+        Atlas {
+            general_config,
+            special_config,
+            oem: AtlasOem::new(&special_config.oem, &P::REGISTERS),
+            calibrations: AtlasCalibrationQueue::new(),
+            values: [None; MAX_READINGS],
+        }
+    }
+}
+
+impl<P: AtlasProbe> SensorDriver for Atlas<P> {
+    getters!();
+
+    fn get_configuration_json(&mut self) -> serde_json::Value {
+        let mut sensor_id = self.get_id();
+        let sensor_id = util::str_from_utf8(&mut sensor_id).unwrap_or("Invalid");
+
+        let mut sensor_name = sensor_name_from_type_id(self.get_type_id().into());
+        let sensor_name = util::str_from_utf8(&mut sensor_name).unwrap_or("Invalid");
+
+        let mut json = json!({
+            "id": sensor_id,
+            "type": sensor_name,
+        });
+        self.special_config.oem.add_configuration_json(&mut json, &P::REGISTERS);
+        json
+    }
+
+    fn setup(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        self.oem.setup(board, &self.special_config.oem, &P::REGISTERS);
+    }
+
+    fn begin_warmup(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        self.oem.wake(board);
+    }
+
+    fn get_measured_parameter_count(&mut self) -> usize {
+        P::READINGS.len()
+    }
+
+    fn get_measured_parameter_value(&mut self, index: usize) -> Result<f64, ()> {
+        if index >= P::READINGS.len() {
+            return Err(());
+        }
+        self.values[index].ok_or(())
+    }
+
+    fn get_measured_parameter_identifier(&mut self, index: usize) -> [u8; 16] {
+        let identifier = match P::READINGS.get(index) {
+            Some(reading) => reading.identifier,
+            None => "invalid",
+        };
+        let mut buf = [0u8; 16];
+        buf[..identifier.len()].copy_from_slice(identifier.as_bytes());
+        buf
+    }
+
+    fn take_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        self.start_measurement(board);
+        for _ in 0..POLL_ATTEMPTS {
+            if self.poll_measurement(board) {
+                return;
+            }
+            board.delay_ms(POLL_DELAY_MS);
+        }
+    }
+
+    fn start_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        self.values = [None; MAX_READINGS];
+        self.oem.write_compensations(board, &self.special_config.oem, &P::REGISTERS);
+        self.oem.clear_data_available(board);
+    }
+
+    fn poll_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) -> bool {
+        if !self.oem.data_available(board) {
+            return false;
+        }
+        for (i, reading) in P::READINGS.iter().enumerate() {
+            self.values[i] = self.oem.read_value(board, reading.register, reading.multiplier);
+        }
+        self.oem.clear_data_available(board);
+        true
+    }
+
+    // one calibration request at a time, each once the circuit has finished the one before
+    fn update_actuators(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        if self.calibrations.count == 0 || self.oem.calibration_busy(board, &P::REGISTERS) {
+            return;
+        }
+        if let Some(calibration) = self.calibrations.pop_front() {
+            self.oem.calibrate(board, calibration, &P::REGISTERS);
+        }
+    }
+
+    fn get_required_calibration_point_count(&self) -> usize {
+        P::CALIBRATION_POINTS
+    }
+
+    fn fit(&mut self, pairs: &[CalibrationPair]) -> Result<(), ()> {
+        let mut references = [0.0; MAX_CALIBRATION_REQUESTS];
+        if pairs.is_empty() || pairs.len() > P::CALIBRATION_POINTS || pairs.len() > references.len() {
+            self.calibrations.clear(); // a failed fit leaves the circuit's calibration as it was
+            return Err(());
+        }
+        for (i, pair) in pairs.iter().enumerate() {
+            references[i] = pair.point;
+        }
+
+        if P::queue_calibration(&references[..pairs.len()], &mut self.calibrations).is_err() {
+            self.calibrations.clear();
+            return Err(());
+        }
+        Ok(())
+    }
+
+    fn clear_calibration(&mut self) {
+        self.calibrations.clear();
+        let _ = self.calibrations.push(CALIBRATION_CLEAR, 0.0);
+    }
+
+    fn update(&mut self, values: serde_json::Value) -> Result<(), &'static str> {
+        self.special_config.oem.update_from_values(&values, &P::REGISTERS)
+    }
+}
+
+// EC, readings in uS/cm
+// a reference of 0 is the dry calibration, then a single point, or low and high points
+pub struct Ec;
+
+impl AtlasProbe for Ec {
+    const REGISTERS: AtlasRegisters = AtlasRegisters {
+        default_address: 0x64,
+        calibration_value: Some(0x0A),
+        calibration_multiplier: 100.0,
+        calibration_request: 0x0E,
+        temperature_compensation: Some(0x10),
+        salinity_compensation: None,
+        pressure_compensation: None,
+    };
+    const READINGS: &'static [AtlasReading] = &[AtlasReading { register: 0x18, multiplier: 100.0, identifier: "eC" }];
+    const CALIBRATION_POINTS: usize = 3;
+
+    fn queue_calibration(references: &[f64], queue: &mut AtlasCalibrationQueue) -> Result<(), ()> {
+        const DRY: u8 = 2;
+        const SINGLE: u8 = 3;
+        const LOW: u8 = 4;
+        const HIGH: u8 = 5;
+
+        let dry = references.iter().filter(|reference| **reference == 0.0).count();
+        if dry > 1 {
+            return Err(());
+        }
+        if dry == 1 {
+            queue.push(DRY, 0.0)?;
+        }
+
+        let mut standards = references.iter().copied().filter(|reference| *reference != 0.0);
+        match (standards.next(), standards.next()) {
+            (None, _) => Ok(()),
+            (Some(single), None) => queue.push(SINGLE, single),
+            (Some(first), Some(second)) if first != second => {
+                queue.push(LOW, first.min(second))?;
+                queue.push(HIGH, first.max(second))
+            }
+            _ => Err(()),
+        }
+    }
+}
+
+// pH, the point nearest pH 7 is the mid point, which goes first as it clears the low and high points, then low and high
+pub struct Ph;
+
+impl AtlasProbe for Ph {
+    const REGISTERS: AtlasRegisters = AtlasRegisters {
+        default_address: 0x65,
+        calibration_value: Some(0x08),
+        calibration_multiplier: 1000.0,
+        calibration_request: 0x0C,
+        temperature_compensation: Some(0x0E),
+        salinity_compensation: None,
+        pressure_compensation: None,
+    };
+    const READINGS: &'static [AtlasReading] = &[AtlasReading { register: 0x16, multiplier: 1000.0, identifier: "pH" }];
+    const CALIBRATION_POINTS: usize = 3;
+
+    fn queue_calibration(references: &[f64], queue: &mut AtlasCalibrationQueue) -> Result<(), ()> {
+        const LOW: u8 = 2;
+        const MID: u8 = 3;
+        const HIGH: u8 = 4;
+
+        let mut mid = 0;
+        for (i, reference) in references.iter().enumerate() {
+            if (reference - 7.0).abs() < (references[mid] - 7.0).abs() {
+                mid = i;
+            }
+        }
+        queue.push(MID, references[mid])?;
+
+        let (mut low, mut high) = (None, None);
+        for (i, reference) in references.iter().enumerate() {
+            if i == mid {
+                continue;
+            }
+            if *reference < references[mid] && low.is_none() {
+                low = Some(*reference);
+            } else if *reference > references[mid] && high.is_none() {
+                high = Some(*reference);
+            } else {
+                return Err(()); // two points on the same side of the mid point
+            }
+        }
+        if let Some(low) = low {
+            queue.push(LOW, low)?;
+        }
+        if let Some(high) = high {
+            queue.push(HIGH, high)?;
+        }
+        Ok(())
+    }
+}
+
+// ORP, readings in mV, a single point in the calibration solution's mV
+pub struct Orp;
+
+impl AtlasProbe for Orp {
+    const REGISTERS: AtlasRegisters = AtlasRegisters {
+        default_address: 0x66,
+        calibration_value: Some(0x08),
+        calibration_multiplier: 10.0,
+        calibration_request: 0x0C,
+        temperature_compensation: None,
+        salinity_compensation: None,
+        pressure_compensation: None,
+    };
+    const READINGS: &'static [AtlasReading] = &[AtlasReading { register: 0x0E, multiplier: 10.0, identifier: "orp" }];
+    const CALIBRATION_POINTS: usize = 1;
+
+    fn queue_calibration(references: &[f64], queue: &mut AtlasCalibrationQueue) -> Result<(), ()> {
+        const SINGLE: u8 = 2;
+        queue.push(SINGLE, references[0])
+    }
+}
+
+// dissolved oxygen, readings in mg/L and percent saturation
+// a reference of 0 is the zero oxygen solution, any other reference is the probe in air, which goes first
+pub struct DissolvedOxygen;
+
+impl AtlasProbe for DissolvedOxygen {
+    const REGISTERS: AtlasRegisters = AtlasRegisters {
+        default_address: 0x67,
+        calibration_value: None,
+        calibration_multiplier: 1.0,
+        calibration_request: 0x08,
+        temperature_compensation: Some(0x12),
+        salinity_compensation: Some(0x0A),
+        pressure_compensation: Some(0x0E),
+    };
+    const READINGS: &'static [AtlasReading] = &[
+        AtlasReading { register: 0x22, multiplier: 100.0, identifier: "do_mg_l" },
+        AtlasReading { register: 0x26, multiplier: 100.0, identifier: "do_saturation" },
+    ];
+    const CALIBRATION_POINTS: usize = 2;
+
+    fn queue_calibration(references: &[f64], queue: &mut AtlasCalibrationQueue) -> Result<(), ()> {
+        const ATMOSPHERIC: u8 = 2;
+        const ZERO: u8 = 3;
+
+        let zero = references.iter().filter(|reference| **reference == 0.0).count();
+        let atmospheric = references.len() - zero;
+        if zero > 1 || atmospheric > 1 {
+            return Err(());
+        }
+        if atmospheric == 1 {
+            queue.push(ATMOSPHERIC, 0.0)?;
+        }
+        if zero == 1 {
+            queue.push(ZERO, 0.0)?;
+        }
+        Ok(())
+    }
+}
+
+// RTD, readings in degrees C, a single point at the reference temperature
+pub struct Rtd;
+
+impl AtlasProbe for Rtd {
+    const REGISTERS: AtlasRegisters = AtlasRegisters {
+        default_address: 0x68,
+        calibration_value: Some(0x08),
+        calibration_multiplier: 1000.0,
+        calibration_request: 0x0C,
+        temperature_compensation: None,
+        salinity_compensation: None,
+        pressure_compensation: None,
+    };
+    const READINGS: &'static [AtlasReading] = &[AtlasReading { register: 0x0E, multiplier: 1000.0, identifier: "temperature" }];
+    const CALIBRATION_POINTS: usize = 1;
+
+    fn queue_calibration(references: &[f64], queue: &mut AtlasCalibrationQueue) -> Result<(), ()> {
+        const SINGLE: u8 = 2;
+        queue.push(SINGLE, references[0])
+    }
+}
+
+pub type AtlasEC = Atlas<Ec>;
+pub type AtlasECSpecialConfiguration = AtlasSpecialConfiguration<Ec>;
+pub type AtlasPH = Atlas<Ph>;
+pub type AtlasPHSpecialConfiguration = AtlasSpecialConfiguration<Ph>;
+pub type AtlasORP = Atlas<Orp>;
+pub type AtlasORPSpecialConfiguration = AtlasSpecialConfiguration<Orp>;
+pub type AtlasDO = Atlas<DissolvedOxygen>;
+pub type AtlasDOSpecialConfiguration = AtlasSpecialConfiguration<DissolvedOxygen>;
+pub type AtlasRTD = Atlas<Rtd>;
+pub type AtlasRTDSpecialConfiguration = AtlasSpecialConfiguration<Rtd>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn requests(queue: &AtlasCalibrationQueue) -> ([u8; MAX_CALIBRATION_REQUESTS], [f64; MAX_CALIBRATION_REQUESTS]) {
+        let mut requests = [0; MAX_CALIBRATION_REQUESTS];
+        let mut values = [0.0; MAX_CALIBRATION_REQUESTS];
+        for i in 0..queue.count {
+            requests[i] = queue.calibrations[i].request;
+            values[i] = queue.calibrations[i].value;
+        }
+        (requests, values)
+    }
+
+    #[test]
+    fn test_ph_mid_point_goes_first() {
+        let mut queue = AtlasCalibrationQueue::new();
+        Ph::queue_calibration(&[10.0, 4.0, 7.0], &mut queue).unwrap();
+        assert_eq!(([3, 2, 4, 0], [7.0, 4.0, 10.0, 0.0]), requests(&queue));
+
+        let mut queue = AtlasCalibrationQueue::new();
+        assert!(Ph::queue_calibration(&[4.0, 7.0, 3.0], &mut queue).is_err());
+    }
+
+    #[test]
+    fn test_ec_dry_then_low_and_high() {
+        let mut queue = AtlasCalibrationQueue::new();
+        Ec::queue_calibration(&[80000.0, 0.0, 12880.0], &mut queue).unwrap();
+        assert_eq!(([2, 4, 5, 0], [0.0, 12880.0, 80000.0, 0.0]), requests(&queue));
+
+        let mut queue = AtlasCalibrationQueue::new();
+        Ec::queue_calibration(&[1413.0], &mut queue).unwrap();
+        assert_eq!(([3, 0, 0, 0], [1413.0, 0.0, 0.0, 0.0]), requests(&queue));
+    }
+
+    #[test]
+    fn test_calibration_queue_order() {
+        let mut queue = AtlasCalibrationQueue::new();
+        queue.push(CALIBRATION_CLEAR, 0.0).unwrap();
+        DissolvedOxygen::queue_calibration(&[0.0, 100.0], &mut queue).unwrap();
+        assert_eq!(Some(CALIBRATION_CLEAR), queue.pop_front().map(|calibration| calibration.request));
+        assert_eq!(Some(2), queue.pop_front().map(|calibration| calibration.request));
+        assert_eq!(Some(3), queue.pop_front().map(|calibration| calibration.request));
+        assert!(queue.pop_front().is_none());
+    }
+}
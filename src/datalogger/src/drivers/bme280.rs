@@ -0,0 +1,685 @@
+use serde_json::json;
+
+use crate::sensor_name_from_type_id;
+use crate::services::barometer_service;
+
+use super::types::*;
+
+// Bosch BME280 and BMP3xx (BMP388, BMP390) barometric pressure sensors on i2c
+// measurements run in forced mode, and the pressure is published for drivers that compensate for air pressure
+// pressure is in kPa, temperature in degrees C, humidity in %RH and altitude in m above the sea level reference
+
+const CHIP_BME280: u8 = 0;
+const CHIP_BMP3XX: u8 = 1;
+
+const DEFAULT_ADDRESS: u8 = 0x77; // 0x76 when SDO is pulled low
+const DEFAULT_SEA_LEVEL_PRESSURE: f32 = 101.325; // kPa
+
+// BME280 registers
+const BME280_CHIP_ID: u8 = 0x60;
+const BME280_REG_CHIP_ID: u8 = 0xD0;
+const BME280_REG_CALIBRATION_TP: u8 = 0x88; // 26 bytes, dig_T1 to dig_H1
+const BME280_REG_CALIBRATION_H: u8 = 0xE1; // 7 bytes, dig_H2 to dig_H6
+const BME280_REG_CTRL_HUM: u8 = 0xF2;
+const BME280_REG_STATUS: u8 = 0xF3;
+const BME280_REG_CTRL_MEAS: u8 = 0xF4;
+const BME280_REG_CONFIG: u8 = 0xF5;
+const BME280_REG_DATA: u8 = 0xF7; // 8 bytes, pressure, temperature, humidity
+const BME280_STATUS_MEASURING: u8 = 0x08;
+const BME280_MODE_FORCED: u8 = 0b01;
+
+// BMP3xx registers
+const BMP388_CHIP_ID: u8 = 0x50;
+const BMP390_CHIP_ID: u8 = 0x60;
+const BMP3_REG_CHIP_ID: u8 = 0x00;
+const BMP3_REG_STATUS: u8 = 0x03;
+const BMP3_REG_DATA: u8 = 0x04; // 6 bytes, pressure, temperature
+const BMP3_REG_PWR_CTRL: u8 = 0x1B;
+const BMP3_REG_OSR: u8 = 0x1C;
+const BMP3_REG_CONFIG: u8 = 0x1F;
+const BMP3_REG_CALIBRATION: u8 = 0x31; // 21 bytes
+const BMP3_STATUS_DATA_READY: u8 = 0x60; // pressure and temperature ready
+const BMP3_PWR_FORCED: u8 = 0b0001_0011; // forced mode with pressure and temperature enabled
+
+fn chip_from_str(value: &str) -> Option<u8> {
+    match value {
+        "bme280" => Some(CHIP_BME280),
+        "bmp3xx" => Some(CHIP_BMP3XX),
+        _ => None,
+    }
+}
+
+fn chip_text(chip: u8) -> &'static str {
+    match chip {
+        CHIP_BMP3XX => "bmp3xx",
+        _ => "bme280",
+    }
+}
+
+// register code for an oversampling rate, the BME280 codes start at 1 for x1 since 0 skips the measurement
+fn oversampling_code(chip: u8, oversampling: u8) -> Option<u8> {
+    let code = match oversampling {
+        1 => 0,
+        2 => 1,
+        4 => 2,
+        8 => 3,
+        16 => 4,
+        32 if chip == CHIP_BMP3XX => 5,
+        _ => return None,
+    };
+    if chip == CHIP_BME280 { Some(code + 1) } else { Some(code) }
+}
+
+fn iir_filter_code(chip: u8, coefficient: u8) -> Option<u8> {
+    match (chip, coefficient) {
+        (CHIP_BME280, 0) => Some(0),
+        (CHIP_BME280, 2) => Some(1),
+        (CHIP_BME280, 4) => Some(2),
+        (CHIP_BME280, 8) => Some(3),
+        (CHIP_BME280, 16) => Some(4),
+        (CHIP_BMP3XX, 0) => Some(0),
+        (CHIP_BMP3XX, 1) => Some(1),
+        (CHIP_BMP3XX, 3) => Some(2),
+        (CHIP_BMP3XX, 7) => Some(3),
+        (CHIP_BMP3XX, 15) => Some(4),
+        (CHIP_BMP3XX, 31) => Some(5),
+        (CHIP_BMP3XX, 63) => Some(6),
+        (CHIP_BMP3XX, 127) => Some(7),
+        _ => None,
+    }
+}
+
+// altitude from the international barometric formula
+fn altitude(pressure: f64, sea_level_pressure: f64) -> f64 {
+    44330.0 * (1.0 - util::exp(util::ln(pressure / sea_level_pressure) / 5.255))
+}
+
+#[derive(Copy, Clone)]
+pub struct BME280SpecialConfiguration {
+    sea_level_pressure: f32, // kPa, reference for the altitude
+    chip: u8,
+    address: u8,
+    pressure_oversampling: u8,
+    temperature_oversampling: u8,
+    humidity_oversampling: u8, // BME280 only
+    iir_filter: u8,            // filter coefficient, 0 is off
+    publish_pressure: u8,      // 1 publishes the pressure for other drivers
+    _empty: [u8; 21],
+}
+
+impl BME280SpecialConfiguration {
+    pub fn new_from_bytes(
+        bytes: [u8; SENSOR_SETTINGS_PARTITION_SIZE],
+    ) -> BME280SpecialConfiguration {
+        let settings = bytes.as_ptr().cast::<BME280SpecialConfiguration>();
+        unsafe { *settings }
+    }
+
+    pub fn parse_from_values(value: serde_json::Value) -> Result<BME280SpecialConfiguration, &'static str> {
+        let chip = match &value["chip"] {
+            serde_json::Value::String(chip) => match chip_from_str(chip.as_str()) {
+                Some(chip) => chip,
+                None => return Err("chip must be bme280 or bmp3xx"),
+            },
+            serde_json::Value::Null => CHIP_BME280,
+            _ => return Err("chip must be bme280 or bmp3xx"),
+        };
+
+        let mut special_config = Self {
+            sea_level_pressure: DEFAULT_SEA_LEVEL_PRESSURE,
+            chip,
+            address: DEFAULT_ADDRESS,
+            pressure_oversampling: if chip == CHIP_BME280 { 1 } else { 8 },
+            temperature_oversampling: 1,
+            humidity_oversampling: 1,
+            iir_filter: 0,
+            publish_pressure: 1,
+            _empty: [b'\0'; 21],
+        };
+        special_config.update_from_values(value)?;
+        Ok(special_config)
+    }
+
+    pub fn update_from_values(&mut self, values: serde_json::Value) -> Result<(), &'static str> {
+        match &values["chip"] {
+            serde_json::Value::String(chip) if chip_from_str(chip.as_str()) == Some(self.chip) => {}
+            serde_json::Value::Null => {}
+            _ => return Err("the chip can't be changed, remove and add the sensor"),
+        }
+
+        match &values["address"] {
+            serde_json::Value::Number(number) => {
+                match number.as_u64() {
+                    Some(number) if number == 0x76 || number == 0x77 => self.address = number as u8,
+                    _ => return Err("address must be 0x76 or 0x77"),
+                }
+            }
+            serde_json::Value::Null => {}
+            _ => return Err("address must be 0x76 or 0x77"),
+        }
+
+        let oversamplings = [
+            ("pressure_oversampling", &mut self.pressure_oversampling),
+            ("temperature_oversampling", &mut self.temperature_oversampling),
+            ("humidity_oversampling", &mut self.humidity_oversampling),
+        ];
+        for (key, oversampling) in oversamplings {
+            match &values[key] {
+                serde_json::Value::Number(number) => {
+                    match number.as_u64() {
+                        Some(number) if number <= 32 && oversampling_code(self.chip, number as u8).is_some() => {
+                            *oversampling = number as u8
+                        }
+                        _ => return Err("oversampling must be 1, 2, 4, 8 or 16, or 32 on the bmp3xx"),
+                    }
+                }
+                serde_json::Value::Null => {}
+                _ => return Err("oversampling must be 1, 2, 4, 8 or 16, or 32 on the bmp3xx"),
+            }
+        }
+
+        match &values["iir_filter"] {
+            serde_json::Value::Number(number) => {
+                match number.as_u64() {
+                    Some(number) if number <= 127 && iir_filter_code(self.chip, number as u8).is_some() => {
+                        self.iir_filter = number as u8
+                    }
+                    _ => return Err("iir_filter must be 0, 2, 4, 8 or 16 on the bme280, 0, 1, 3, 7, 15, 31, 63 or 127 on the bmp3xx"),
+                }
+            }
+            serde_json::Value::Null => {}
+            _ => return Err("iir_filter must be 0, 2, 4, 8 or 16 on the bme280, 0, 1, 3, 7, 15, 31, 63 or 127 on the bmp3xx"),
+        }
+
+        match &values["sea_level_pressure"] {
+            serde_json::Value::Number(number) => {
+                match number.as_f64() {
+                    Some(number) if number > 0.0 => self.sea_level_pressure = number as f32,
+                    _ => return Err("sea_level_pressure must be a positive number of kPa"),
+                }
+            }
+            serde_json::Value::Null => {}
+            _ => return Err("sea_level_pressure must be a positive number of kPa"),
+        }
+
+        match &values["publish_pressure"] {
+            serde_json::Value::Bool(publish) => self.publish_pressure = *publish as u8,
+            serde_json::Value::Null => {}
+            _ => return Err("publish_pressure must be true or false"),
+        }
+
+        Ok(())
+    }
+}
+
+// compensation parameters read from the sensor's nvm, as in the Bosch datasheets' floating point formulas
+#[derive(Copy, Clone)]
+struct Calibration {
+    t: [f64; 3],
+    p: [f64; 11],
+    h: [f64; 6],
+}
+
+fn bme280_calibration(tp: &[u8; 26], h: &[u8; 7]) -> Calibration {
+    let unsigned = |i: usize| u16::from_le_bytes([tp[i], tp[i + 1]]) as f64;
+    let signed = |i: usize| i16::from_le_bytes([tp[i], tp[i + 1]]) as f64;
+
+    Calibration {
+        t: [unsigned(0), signed(2), signed(4)],
+        p: [
+            unsigned(6),
+            signed(8),
+            signed(10),
+            signed(12),
+            signed(14),
+            signed(16),
+            signed(18),
+            signed(20),
+            signed(22),
+            0.0,
+            0.0,
+        ],
+        h: [
+            tp[25] as f64,
+            i16::from_le_bytes([h[0], h[1]]) as f64,
+            h[2] as f64,
+            (((h[3] as i8 as i16) << 4) | (h[4] & 0x0F) as i16) as f64,
+            (((h[5] as i8 as i16) << 4) | (h[4] >> 4) as i16) as f64,
+            h[6] as i8 as f64,
+        ],
+    }
+}
+
+fn bmp3_calibration(nvm: &[u8; 21]) -> Calibration {
+    let unsigned = |i: usize| u16::from_le_bytes([nvm[i], nvm[i + 1]]) as f64;
+    let signed = |i: usize| i16::from_le_bytes([nvm[i], nvm[i + 1]]) as f64;
+    let byte = |i: usize| nvm[i] as i8 as f64;
+    let power = |exponent: i64| f64::from_bits(((exponent + 1023) as u64) << 52); // 2^exponent
+
+    Calibration {
+        t: [
+            unsigned(0) * power(8),
+            unsigned(2) / power(30),
+            byte(4) / power(48),
+        ],
+        p: [
+            (signed(5) - power(14)) / power(20),
+            (signed(7) - power(14)) / power(29),
+            byte(9) / power(32),
+            byte(10) / power(37),
+            unsigned(11) * power(3),
+            unsigned(13) / power(6),
+            byte(15) / power(8),
+            byte(16) / power(15),
+            signed(17) / power(48),
+            byte(19) / power(48),
+            byte(20) / power(65),
+        ],
+        h: [0.0; 6],
+    }
+}
+
+// temperature in C, pressure in kPa and humidity in %RH from the 8 data bytes
+fn compensate_bme280(data: &[u8; 8], calibration: &Calibration) -> (f64, Option<f64>, f64) {
+    let adc_p = ((data[0] as u32) << 12 | (data[1] as u32) << 4 | (data[2] as u32) >> 4) as f64;
+    let adc_t = ((data[3] as u32) << 12 | (data[4] as u32) << 4 | (data[5] as u32) >> 4) as f64;
+    let adc_h = ((data[6] as u32) << 8 | data[7] as u32) as f64;
+
+    let t = &calibration.t;
+    let var1 = (adc_t / 16384.0 - t[0] / 1024.0) * t[1];
+    let var2 = (adc_t / 131072.0 - t[0] / 8192.0) * (adc_t / 131072.0 - t[0] / 8192.0) * t[2];
+    let t_fine = var1 + var2;
+    let temperature = t_fine / 5120.0;
+
+    let p = &calibration.p;
+    let mut var1 = t_fine / 2.0 - 64000.0;
+    let mut var2 = var1 * var1 * p[5] / 32768.0;
+    var2 += var1 * p[4] * 2.0;
+    var2 = var2 / 4.0 + p[3] * 65536.0;
+    var1 = (p[2] * var1 * var1 / 524288.0 + p[1] * var1) / 524288.0;
+    var1 = (1.0 + var1 / 32768.0) * p[0];
+    let pressure = if var1 == 0.0 {
+        None
+    } else {
+        let mut pressure = 1048576.0 - adc_p;
+        pressure = (pressure - var2 / 4096.0) * 6250.0 / var1;
+        let var1 = p[8] * pressure * pressure / 2147483648.0;
+        let var2 = pressure * p[7] / 32768.0;
+        pressure += (var1 + var2 + p[6]) / 16.0;
+        Some(pressure / 1000.0)
+    };
+
+    let h = &calibration.h;
+    let mut humidity = t_fine - 76800.0;
+    humidity = (adc_h - (h[3] * 64.0 + h[4] / 16384.0 * humidity))
+        * (h[1] / 65536.0 * (1.0 + h[5] / 67108864.0 * humidity * (1.0 + h[2] / 67108864.0 * humidity)));
+    humidity *= 1.0 - h[0] * humidity / 524288.0;
+    (temperature, pressure, humidity.clamp(0.0, 100.0))
+}
+
+// temperature in C and pressure in kPa from the 6 data bytes
+fn compensate_bmp3(data: &[u8; 6], calibration: &Calibration) -> (f64, f64) {
+    let adc_p = ((data[2] as u32) << 16 | (data[1] as u32) << 8 | data[0] as u32) as f64;
+    let adc_t = ((data[5] as u32) << 16 | (data[4] as u32) << 8 | data[3] as u32) as f64;
+
+    let t = &calibration.t;
+    let partial = adc_t - t[0];
+    let temperature = partial * t[1] + partial * partial * t[2];
+
+    let p = &calibration.p;
+    let t2 = temperature * temperature;
+    let t3 = t2 * temperature;
+    let out1 = p[4] + p[5] * temperature + p[6] * t2 + p[7] * t3;
+    let out2 = adc_p * (p[0] + p[1] * temperature + p[2] * t2 + p[3] * t3);
+    let out3 = adc_p * adc_p * (p[8] + p[9] * temperature) + adc_p * adc_p * adc_p * p[10];
+    (temperature, (out1 + out2 + out3) / 1000.0)
+}
+
+pub struct BME280 {
+    general_config: SensorDriverGeneralConfiguration,
+    special_config: BME280SpecialConfiguration,
+    calibration: Option<Calibration>,
+    measurement_requested: bool,
+    measurement_start: u32,
+    pressure: Option<f64>,
+    temperature: Option<f64>,
+    humidity: Option<f64>,
+}
+
+impl BME280 {
+    pub fn new(
+        general_config: SensorDriverGeneralConfiguration,
+        special_config: BME280SpecialConfiguration,
+    ) -> Self {
+        BME280 {
+            general_config,
+            special_config,
+            calibration: None,
+            measurement_requested: false,
+            measurement_start: 0,
+            pressure: None,
+            temperature: None,
+            humidity: None,
+        }
+    }
+
+    fn write_register(&mut self, board: &mut dyn rriv_board::RRIVBoard, register: u8, value: u8) -> Result<(), ()> {
+        board.ic2_write(self.special_config.address, &[register, value])
+    }
+
+    fn read_registers(&mut self, board: &mut dyn rriv_board::RRIVBoard, register: u8, buffer: &mut [u8]) -> Result<(), ()> {
+        board.ic2_write_read(self.special_config.address, &[register], buffer)
+    }
+
+    fn configure(&mut self, board: &mut dyn rriv_board::RRIVBoard) -> Result<Calibration, ()> {
+        let chip = self.special_config.chip;
+        let pressure_code = oversampling_code(chip, self.special_config.pressure_oversampling).unwrap_or(0);
+        let temperature_code = oversampling_code(chip, self.special_config.temperature_oversampling).unwrap_or(0);
+        let filter_code = iir_filter_code(chip, self.special_config.iir_filter).unwrap_or(0);
+
+        let mut chip_id = [0u8];
+        if chip == CHIP_BME280 {
+            self.read_registers(board, BME280_REG_CHIP_ID, &mut chip_id)?;
+            if chip_id[0] != BME280_CHIP_ID {
+                defmt::println!("BME280 not found, chip id 0x{:x}", chip_id[0]);
+                return Err(());
+            }
+            let humidity_code = oversampling_code(chip, self.special_config.humidity_oversampling).unwrap_or(0);
+            self.write_register(board, BME280_REG_CONFIG, filter_code << 2)?;
+            // ctrl_hum only takes effect after ctrl_meas is written
+            self.write_register(board, BME280_REG_CTRL_HUM, humidity_code)?;
+            self.write_register(board, BME280_REG_CTRL_MEAS, (temperature_code << 5) | (pressure_code << 2))?;
+            let mut tp = [0u8; 26];
+            self.read_registers(board, BME280_REG_CALIBRATION_TP, &mut tp)?;
+            let mut h = [0u8; 7];
+            self.read_registers(board, BME280_REG_CALIBRATION_H, &mut h)?;
+            Ok(bme280_calibration(&tp, &h))
+        } else {
+            self.read_registers(board, BMP3_REG_CHIP_ID, &mut chip_id)?;
+            if chip_id[0] != BMP388_CHIP_ID && chip_id[0] != BMP390_CHIP_ID {
+                defmt::println!("BMP3xx not found, chip id 0x{:x}", chip_id[0]);
+                return Err(());
+            }
+            self.write_register(board, BMP3_REG_CONFIG, filter_code << 1)?;
+            self.write_register(board, BMP3_REG_OSR, (temperature_code << 3) | pressure_code)?;
+            let mut nvm = [0u8; 21];
+            self.read_registers(board, BMP3_REG_CALIBRATION, &mut nvm)?;
+            Ok(bmp3_calibration(&nvm))
+        }
+    }
+
+    // upper bound of the forced mode conversion time from the datasheets
+    fn measurement_time_ms(&self) -> u32 {
+        let pressure = self.special_config.pressure_oversampling as u32;
+        let temperature = self.special_config.temperature_oversampling as u32;
+        if self.special_config.chip == CHIP_BME280 {
+            let humidity = self.special_config.humidity_oversampling as u32;
+            // 1.25 + 2.3 T + 2.3 P + 0.575 + 2.3 H + 0.575 ms
+            (2400 + 2300 * (temperature + pressure + humidity)) / 1000 + 1
+        } else {
+            // 234 + 392 + 2020 P + 163 + 2020 T us
+            (789 + 2020 * (pressure + temperature)) / 1000 + 1
+        }
+    }
+
+    fn read_bme280(&mut self, board: &mut dyn rriv_board::RRIVBoard, calibration: &Calibration) -> Result<(), ()> {
+        let mut data = [0u8; 8];
+        self.read_registers(board, BME280_REG_DATA, &mut data)?;
+        let (temperature, pressure, humidity) = compensate_bme280(&data, calibration);
+        self.temperature = Some(temperature);
+        self.pressure = pressure;
+        self.humidity = Some(humidity);
+        Ok(())
+    }
+
+    fn read_bmp3(&mut self, board: &mut dyn rriv_board::RRIVBoard, calibration: &Calibration) -> Result<(), ()> {
+        let mut data = [0u8; 6];
+        self.read_registers(board, BMP3_REG_DATA, &mut data)?;
+        let (temperature, pressure) = compensate_bmp3(&data, calibration);
+        self.temperature = Some(temperature);
+        self.pressure = Some(pressure);
+        Ok(())
+    }
+}
+
+impl SensorDriver for BME280 {
+    getters!();
+
+    fn get_configuration_json(&mut self) -> serde_json::Value {
+        let mut sensor_id = self.get_id();
+        let sensor_id = util::str_from_utf8(&mut sensor_id).unwrap_or("Invalid");
+
+        let mut sensor_name = sensor_name_from_type_id(self.get_type_id().into());
+        let sensor_name = util::str_from_utf8(&mut sensor_name).unwrap_or("Invalid");
+
+        let mut json = json!({
+            "id": sensor_id,
+            "type": sensor_name,
+            "chip": chip_text(self.special_config.chip),
+            "address": self.special_config.address,
+            "pressure_oversampling": self.special_config.pressure_oversampling,
+            "temperature_oversampling": self.special_config.temperature_oversampling,
+            "iir_filter": self.special_config.iir_filter,
+            "sea_level_pressure": self.special_config.sea_level_pressure,
+            "publish_pressure": self.special_config.publish_pressure == 1,
+        });
+        if self.special_config.chip == CHIP_BME280 {
+            json["humidity_oversampling"] = self.special_config.humidity_oversampling.into();
+        }
+        json
+    }
+
+    fn setup(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        self.calibration = match self.configure(board) {
+            Ok(calibration) => Some(calibration),
+            Err(_) => {
+                defmt::println!("failed to set up {} at 0x{:x}", chip_text(self.special_config.chip), self.special_config.address);
+                None
+            }
+        };
+    }
+
+    fn teardown(&mut self, _board: &mut dyn rriv_board::RRIVBoard) {
+        if self.special_config.publish_pressure == 1 {
+            barometer_service::clear_pressure();
+        }
+    }
+
+    fn get_measured_parameter_count(&mut self) -> usize {
+        if self.special_config.chip == CHIP_BME280 { 4 } else { 3 }
+    }
+
+    fn get_measured_parameter_value(&mut self, index: usize) -> Result<f64, ()> {
+        let altitude = self
+            .pressure
+            .map(|pressure| altitude(pressure, self.special_config.sea_level_pressure as f64));
+        let value = match (index, self.special_config.chip) {
+            (0, _) => self.pressure,
+            (1, _) => self.temperature,
+            (2, _) => altitude,
+            (3, CHIP_BME280) => self.humidity,
+            _ => None,
+        };
+        value.ok_or(())
+    }
+
+    fn get_measured_parameter_identifier(&mut self, index: usize) -> [u8; 16] {
+        let identifier = match (index, self.special_config.chip) {
+            (0, _) => "pressure",
+            (1, _) => "temperature",
+            (2, _) => "altitude",
+            (3, CHIP_BME280) => "humidity",
+            _ => "invalid",
+        };
+        let mut buf = [0u8; 16];
+        buf[..identifier.len()].copy_from_slice(identifier.as_bytes());
+        buf
+    }
+
+    fn take_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        self.start_measurement(board);
+        board.delay_ms(self.measurement_time_ms() as u16);
+        for _ in 0..10 {
+            if self.poll_measurement(board) {
+                return;
+            }
+            board.delay_ms(5);
+        }
+    }
+
+    fn start_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        self.pressure = None;
+        self.temperature = None;
+        self.humidity = None;
+        if self.calibration.is_none() {
+            // the sensor may have been connected or powered since setup
+            self.setup(board);
+            if self.calibration.is_none() {
+                return;
+            }
+        }
+
+        let result = if self.special_config.chip == CHIP_BME280 {
+            let temperature_code = oversampling_code(CHIP_BME280, self.special_config.temperature_oversampling).unwrap_or(0);
+            let pressure_code = oversampling_code(CHIP_BME280, self.special_config.pressure_oversampling).unwrap_or(0);
+            self.write_register(board, BME280_REG_CTRL_MEAS, (temperature_code << 5) | (pressure_code << 2) | BME280_MODE_FORCED)
+        } else {
+            self.write_register(board, BMP3_REG_PWR_CTRL, BMP3_PWR_FORCED)
+        };
+
+        match result {
+            Ok(_) => {
+                self.measurement_requested = true;
+                self.measurement_start = board.millis();
+            }
+            Err(_) => defmt::println!("failed to start {} measurement", chip_text(self.special_config.chip)),
+        }
+    }
+
+    fn poll_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) -> bool {
+        if !self.measurement_requested {
+            return true;
+        }
+        if board.millis().wrapping_sub(self.measurement_start) < self.measurement_time_ms() {
+            return false;
+        }
+
+        let calibration = match self.calibration {
+            Some(calibration) => calibration,
+            None => return true,
+        };
+
+        let mut status = [0u8];
+        let result = if self.special_config.chip == CHIP_BME280 {
+            match self.read_registers(board, BME280_REG_STATUS, &mut status) {
+                Ok(_) if status[0] & BME280_STATUS_MEASURING != 0 => return false,
+                Ok(_) => self.read_bme280(board, &calibration),
+                Err(_) => Err(()),
+            }
+        } else {
+            match self.read_registers(board, BMP3_REG_STATUS, &mut status) {
+                Ok(_) if status[0] & BMP3_STATUS_DATA_READY != BMP3_STATUS_DATA_READY => return false,
+                Ok(_) => self.read_bmp3(board, &calibration),
+                Err(_) => Err(()),
+            }
+        };
+        self.measurement_requested = false;
+
+        if result.is_err() {
+            defmt::println!("failed to read {}", chip_text(self.special_config.chip));
+            return true;
+        }
+
+        if let Some(pressure) = self.pressure {
+            if self.special_config.publish_pressure == 1 {
+                barometer_service::publish_pressure(pressure as f32);
+            }
+        }
+        true
+    }
+
+    fn update(&mut self, values: serde_json::Value) -> Result<(), &'static str> {
+        let publishing = self.special_config.publish_pressure == 1;
+        self.special_config.update_from_values(values)?;
+        if publishing && self.special_config.publish_pressure == 0 {
+            barometer_service::clear_pressure();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // dig_T1 to dig_P9 from the example in the BME280 datasheet, dig_H1 in the last byte
+    fn bme280_nvm() -> [u8; 26] {
+        let mut tp = [0u8; 26];
+        tp[0..2].copy_from_slice(&27504u16.to_le_bytes());
+        for (i, value) in [26435i16, -1000].iter().enumerate() {
+            tp[2 + i * 2..4 + i * 2].copy_from_slice(&value.to_le_bytes());
+        }
+        tp[6..8].copy_from_slice(&36477u16.to_le_bytes());
+        for (i, value) in [-10685i16, 3024, 2855, 140, -7, 15500, -14600, 6000].iter().enumerate() {
+            tp[8 + i * 2..10 + i * 2].copy_from_slice(&value.to_le_bytes());
+        }
+        tp[25] = 75;
+        tp
+    }
+
+    #[test]
+    fn test_bme280_calibration() {
+        // dig_H4 = -300 and dig_H5 = 50 share the nibbles of h[4]
+        let calibration = bme280_calibration(&bme280_nvm(), &[0x72, 0x01, 0x00, 0xED, 0x24, 0x03, 0x1E]);
+        assert_eq!([27504.0, 26435.0, -1000.0], calibration.t);
+        assert_eq!(36477.0, calibration.p[0]);
+        assert_eq!(-10685.0, calibration.p[1]);
+        assert_eq!(6000.0, calibration.p[8]);
+        assert_eq!([75.0, 370.0, 0.0, -300.0, 50.0, 30.0], calibration.h);
+    }
+
+    #[test]
+    fn test_bme280_compensation() {
+        let calibration = bme280_calibration(&bme280_nvm(), &[0x72, 0x01, 0x00, 0x13, 0x21, 0x03, 0x1E]);
+        assert_eq!(305.0, calibration.h[3]);
+
+        // adc_T 519888 and adc_P 415148 from the datasheet, adc_H 27000
+        let data = [0x65, 0x5A, 0xC0, 0x7E, 0xED, 0x00, 0x69, 0x78];
+        let (temperature, pressure, humidity) = compensate_bme280(&data, &calibration);
+        assert!((temperature - 25.08).abs() < 0.01);
+        assert!((pressure.unwrap() - 100.653).abs() < 0.001);
+        assert!((humidity - 42.04).abs() < 0.01);
+
+        // humidity is clamped to the physical range
+        let data = [0x65, 0x5A, 0xC0, 0x7E, 0xED, 0x00, 0xFF, 0xFF];
+        assert_eq!(100.0, compensate_bme280(&data, &calibration).2);
+
+        // an unprogrammed nvm has no pressure
+        let mut tp = bme280_nvm();
+        tp[6..8].copy_from_slice(&[0, 0]);
+        assert_eq!(None, compensate_bme280(&data, &bme280_calibration(&tp, &[0; 7])).1);
+    }
+
+    #[test]
+    fn test_bmp3_compensation() {
+        // par_t1 to par_p11 of a BMP388
+        let nvm = [145, 106, 237, 74, 249, 239, 244, 113, 231, 34, 0, 159, 97, 164, 118, 3, 250, 231, 10, 7, 196];
+        let calibration = bmp3_calibration(&nvm);
+        assert_eq!(27281.0 * 256.0, calibration.t[0]);
+        assert_eq!(19181.0 / 1073741824.0, calibration.t[1]);
+        assert_eq!((-2833.0 - 16384.0) / 1048576.0, calibration.p[0]);
+        assert_eq!(24991.0 * 8.0, calibration.p[4]);
+
+        // adc_T 8400000 and adc_P 6000000
+        let data = [0x80, 0x8D, 0x5B, 0x80, 0x2C, 0x80];
+        let (temperature, pressure) = compensate_bmp3(&data, &calibration);
+        assert!((temperature - 25.246).abs() < 0.001);
+        assert!((pressure - 95.615).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_altitude() {
+        assert_eq!(0.0, altitude(101.325, 101.325));
+        assert!((altitude(89.875, 101.325) - 1000.0).abs() < 1.0);
+    }
+}
@@ -1,13 +1,15 @@
 pub const CONVERT_TEMP: u8 = 0x44;
-pub const _WRITE_SCRATCHPAD: u8 = 0x4E;
+pub const WRITE_SCRATCHPAD: u8 = 0x4E;
 pub const READ_SCRATCHPAD: u8 = 0xBE;
-pub const _COPY_SCRATCHPAD: u8 = 0x48;
+pub const COPY_SCRATCHPAD: u8 = 0x48;
 pub const _RECALL_EEPROM: u8 = 0xB8;
 
+pub const FAMILY_CODE: u8 = 0x28;
+
 //use embedded_hal::blocking::delay::DelayMs;
 
 #[repr(u8)]
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq)]
 pub enum Resolution {
     Bits9 = 0b00011111,
     Bits10 = 0b00111111,
@@ -41,58 +43,202 @@ impl Resolution {
         }
     }
 
-    #[allow(unused)]
     pub(crate) fn to_config_register(&self) -> u8 {
         *self as u8
     }
+
+    pub(crate) fn from_bits(bits: u8) -> Option<Resolution> {
+        match bits {
+            9 => Some(Resolution::Bits9),
+            10 => Some(Resolution::Bits10),
+            11 => Some(Resolution::Bits11),
+            12 => Some(Resolution::Bits12),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn bits(&self) -> u8 {
+        match self {
+            Resolution::Bits9 => 9,
+            Resolution::Bits10 => 10,
+            Resolution::Bits11 => 11,
+            Resolution::Bits12 => 12,
+        }
+    }
 }
 
 
+use one_wire_bus::crc::crc8;
 use serde_json::json;
 
 use crate::registry::sensor_name_from_type_id;
 
 use super::types::*;
 
-pub const NUMBER_OF_MEASURED_PARAMETERS: usize = 2;
+// DS18B20 probes on the one wire bus, either a single probe addressed with skip rom,
+// or a chain of probes each addressed by its rom code
+// the rom codes don't fit in the sensor slot, so they are kept in the board's one wire rom table
+// and only one ds18b20 sensor can have a chain of probes, the check byte catches a table written by another sensor
+// each probe reports a raw temperature and a calibrated temperature with the probe's offset added,
+// named by the probe's position in the probes list, T1_raw, T1_cal, T2_raw, ...
+
+pub const MAX_PROBES: usize = rriv_board::EEPROM_ONE_WIRE_ROMS_SIZE / 8;
+const MAX_DISCOVERED: usize = 20;
+const OFFSET_UNITS_PER_DEGREE: f64 = 32.0; // offsets are stored in 1/32 C
+const POWER_ON_RAW: i16 = 0x0550; // 85 C, read before any conversion has been done
+
+// rom codes are written family code first, as most tools print them, e.g. 28FF641E8316034B
+fn rom_text(rom: u64, buffer: &mut [u8; 16]) -> &str {
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+    for (i, byte) in rom.to_le_bytes().iter().enumerate() {
+        buffer[i * 2] = HEX[(byte >> 4) as usize];
+        buffer[i * 2 + 1] = HEX[(byte & 0x0F) as usize];
+    }
+    core::str::from_utf8(buffer).unwrap_or_default()
+}
+
+fn rom_from_text(text: &str) -> Option<u64> {
+    if text.len() != 16 {
+        return None;
+    }
+    let mut bytes = [0u8; 8];
+    for i in 0..8 {
+        bytes[i] = u8::from_str_radix(text.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    if bytes[0] != FAMILY_CODE || crc8(&bytes) != 0 {
+        return None;
+    }
+    Some(u64::from_le_bytes(bytes))
+}
 
+fn rom_table(roms: &[u64; MAX_PROBES]) -> [u8; rriv_board::EEPROM_ONE_WIRE_ROMS_SIZE] {
+    let mut table = [0u8; rriv_board::EEPROM_ONE_WIRE_ROMS_SIZE];
+    for (i, rom) in roms.iter().enumerate() {
+        table[i * 8..i * 8 + 8].copy_from_slice(&rom.to_le_bytes());
+    }
+    table
+}
+
+#[repr(C)]
 #[derive(Copy, Clone)]
 pub struct Ds18b20SpecialConfiguration {
-    m: f32, // 4
-    b: f32, // 4
-    // calibrate data?
-    // power mode
-    // resolution mode
+    offsets: [i8; MAX_PROBES], // 1/32 C
+    probe_count: u8,           // 0 for a single probe addressed with skip rom
+    resolution: u8,            // bits, 0 for the 12 bit default
+    roms_check: u8,            // crc of the one wire rom table written for this sensor
+    _empty: [u8; 13],
+    // the sensor slot holds the fields above, the rom codes go to the one wire rom table in setup
+    roms: [u64; MAX_PROBES],
+    roms_changed: bool,
 }
 
+const _: () = assert!(core::mem::offset_of!(Ds18b20SpecialConfiguration, roms) == SENSOR_SETTINGS_PARTITION_SIZE);
+
 impl Ds18b20SpecialConfiguration {
+    fn empty() -> Ds18b20SpecialConfiguration {
+        Self {
+            offsets: [0; MAX_PROBES],
+            probe_count: 0,
+            resolution: 0,
+            roms_check: 0,
+            _empty: [b'\0'; 13],
+            roms: [0; MAX_PROBES],
+            roms_changed: false,
+        }
+    }
+
     pub fn parse_from_values(
         value: serde_json::Value,
     ) -> Result<Ds18b20SpecialConfiguration, &'static str> {
-        let _ = value;
-        Ok(Self {
-            m: 0_f32,
-            b: 0_f32,
-        } )
+        let mut special_config = Self::empty();
+        special_config.update_from_values(value)?;
+        Ok(special_config)
     }
 
     pub fn new_from_bytes(
-        _bytes: [u8; SENSOR_SETTINGS_PARTITION_SIZE],
+        bytes: [u8; SENSOR_SETTINGS_PARTITION_SIZE],
     ) -> Ds18b20SpecialConfiguration {
-        Self {
-            m: 0_f32,
-            b: 0_f32,
+        let mut special_config = Self::empty();
+        for i in 0..MAX_PROBES {
+            special_config.offsets[i] = bytes[i] as i8;
         }
+        special_config.probe_count = bytes[MAX_PROBES];
+        special_config.resolution = bytes[MAX_PROBES + 1];
+        special_config.roms_check = bytes[MAX_PROBES + 2];
+        special_config
     }
+
+    pub fn update_from_values(&mut self, values: serde_json::Value) -> Result<(), &'static str> {
+        match &values["resolution"] {
+            serde_json::Value::Number(number) => match number.as_u64().and_then(|bits| Resolution::from_bits(bits as u8)) {
+                Some(resolution) => self.resolution = resolution.bits(),
+                None => return Err("resolution must be 9, 10, 11 or 12 bits"),
+            },
+            serde_json::Value::Null => {}
+            _ => return Err("resolution must be 9, 10, 11 or 12 bits"),
+        }
+
+        match &values["probes"] {
+            serde_json::Value::Array(probes) => {
+                if probes.len() > MAX_PROBES {
+                    return Err("probes can have up to 16 entries");
+                }
+
+                let mut roms = [0; MAX_PROBES];
+                let mut offsets = [0; MAX_PROBES];
+                for (i, probe) in probes.iter().enumerate() {
+                    roms[i] = match probe["rom"].as_str().and_then(rom_from_text) {
+                        Some(rom) => rom,
+                        None => return Err("rom must be a DS18B20 rom code of 16 hex digits, such as 28FF641E8316034B"),
+                    };
+                    if roms[..i].contains(&roms[i]) {
+                        return Err("probes must have different rom codes");
+                    }
+
+                    offsets[i] = match &probe["offset"] {
+                        serde_json::Value::Number(number) => match offset_from_degrees(number.as_f64().unwrap_or(f64::MAX)) {
+                            Some(offset) => offset,
+                            None => return Err("probe offset must be within 3.9 C"),
+                        },
+                        serde_json::Value::Null => 0,
+                        _ => return Err("probe offset must be within 3.9 C"),
+                    };
+                }
+
+                self.roms = roms;
+                self.roms_changed = true;
+                self.offsets = offsets;
+                self.probe_count = probes.len() as u8;
+            }
+            serde_json::Value::Null => {}
+            _ => return Err("probes must be a list"),
+        }
+
+        Ok(())
+    }
+
+    fn resolution(&self) -> Resolution {
+        Resolution::from_bits(self.resolution).unwrap_or(Resolution::Bits12)
+    }
+}
+
+fn offset_from_degrees(degrees: f64) -> Option<i8> {
+    let units = degrees * OFFSET_UNITS_PER_DEGREE;
+    let units = if units < 0.0 { units - 0.5 } else { units + 0.5 };
+    if units > i8::MAX as f64 || units < i8::MIN as f64 {
+        return None;
+    }
+    Some(units as i8)
 }
 
 pub struct Ds18b20 {
     general_config: SensorDriverGeneralConfiguration,
-    #[allow(unused)]
     special_config: Ds18b20SpecialConfiguration,
-    measured_parameter_values: [f64; NUMBER_OF_MEASURED_PARAMETERS],
-    m: f64,
-    b: f64,
+    temperatures: [Option<f64>; MAX_PROBES],
+    roms_valid: bool, // false when the rom table was written for another sensor
+    discovered: [u64; MAX_DISCOVERED],
+    discovered_count: usize,
+    conversion_start: Option<u32>, // board millis when the pending conversion was started
 }
 
 impl Ds18b20 {
@@ -103,142 +249,253 @@ impl Ds18b20 {
         Ds18b20 {
             general_config,
             special_config,
-            measured_parameter_values: [0.0; NUMBER_OF_MEASURED_PARAMETERS],
-            m: 0_f64,
-            b: 0_f64,
+            temperatures: [None; MAX_PROBES],
+            roms_valid: false,
+            discovered: [0; MAX_DISCOVERED],
+            discovered_count: 0,
+            conversion_start: None,
+        }
+    }
+
+    fn probe_count(&self) -> usize {
+        let count = self.special_config.probe_count as usize;
+        if count > MAX_PROBES { MAX_PROBES } else { count }
+    }
+
+    // a single probe with skip rom counts as one probe
+    fn reading_count(&self) -> usize {
+        if self.probe_count() == 0 { 1 } else { self.probe_count() }
+    }
+
+    // write the rom codes set by update, or read back the ones stored for this sensor
+    fn load_roms(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        if self.special_config.roms_changed {
+            let table = rom_table(&self.special_config.roms);
+            board.store_one_wire_roms(&table);
+            self.special_config.roms_check = crc8(&table);
+            self.special_config.roms_changed = false;
+            self.roms_valid = true;
+            return;
+        }
+
+        let mut table = [0u8; rriv_board::EEPROM_ONE_WIRE_ROMS_SIZE];
+        board.retrieve_one_wire_roms(&mut table);
+        self.roms_valid = crc8(&table) == self.special_config.roms_check;
+        if !self.roms_valid {
+            defmt::println!("ds18b20 rom codes were replaced by another sensor, set the probes again");
+            return;
+        }
+        for i in 0..MAX_PROBES {
+            let mut rom = [0u8; 8];
+            rom.copy_from_slice(&table[i * 8..i * 8 + 8]);
+            self.special_config.roms[i] = u64::from_le_bytes(rom);
+        }
+    }
+
+    // search the bus for the rom codes of the probes, reported in the configuration
+    fn discover(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        self.discovered_count = 0;
+        board.one_wire_bus_start_search();
+        while let Some(rom) = board.one_wire_bus_search() {
+            if rom.to_le_bytes()[0] != FAMILY_CODE {
+                continue; // other devices on the bus
+            }
+            if self.discovered_count < MAX_DISCOVERED {
+                self.discovered[self.discovered_count] = rom;
+                self.discovered_count = self.discovered_count + 1;
+            }
+        }
+
+        for i in 0..self.probe_count() {
+            if !self.discovered[..self.discovered_count].contains(&self.special_config.roms[i]) {
+                defmt::println!("ds18b20 probe T{} not found", i + 1);
+            }
+        }
+    }
+
+    // None addresses a single probe with skip rom
+    fn probe_rom(&self, index: usize) -> Option<Option<u64>> {
+        if self.probe_count() == 0 {
+            return Some(None);
+        }
+        if !self.roms_valid {
+            return None;
+        }
+        Some(Some(self.special_config.roms[index]))
+    }
+
+    fn address(&mut self, board: &mut dyn rriv_board::RRIVBoard, rom: Option<u64>) {
+        board.one_wire_reset();
+        match rom {
+            Some(rom) => board.one_wire_match_address(rom),
+            None => board.one_wire_skip_address(),
+        }
+    }
+
+    fn read_scratchpad(&mut self, board: &mut dyn rriv_board::RRIVBoard, rom: Option<u64>) -> Option<[u8; 9]> {
+        self.address(board, rom);
+        board.one_wire_write_byte(READ_SCRATCHPAD);
+        let mut scratchpad = [0; 9];
+        if board.one_wire_read_bytes(&mut scratchpad).is_err() {
+            return None;
+        }
+        // a shorted bus reads zeros, which pass the crc
+        if crc8(&scratchpad) != 0 || scratchpad.iter().all(|byte| *byte == 0) {
+            return None;
         }
+        Some(scratchpad)
+    }
+
+    // set the resolution in the probe's eeprom, only when it differs so the eeprom isn't worn
+    fn configure_resolution(&mut self, board: &mut dyn rriv_board::RRIVBoard, rom: Option<u64>) {
+        let resolution = self.special_config.resolution();
+        let scratchpad = match self.read_scratchpad(board, rom) {
+            Some(scratchpad) => scratchpad,
+            None => return,
+        };
+        if Resolution::from_config_register(scratchpad[4]) == Some(resolution) {
+            return;
+        }
+
+        self.address(board, rom);
+        board.one_wire_write_byte(WRITE_SCRATCHPAD);
+        board.one_wire_write_byte(scratchpad[2]); // alarm high, unchanged
+        board.one_wire_write_byte(scratchpad[3]); // alarm low, unchanged
+        board.one_wire_write_byte(resolution.to_config_register());
+        self.address(board, rom);
+        board.one_wire_write_byte(COPY_SCRATCHPAD);
+        board.delay_ms(10);
+    }
+
+    fn start_conversion(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        // all probes on the bus convert at once
+        board.one_wire_reset();
+        board.one_wire_skip_address();
+        board.one_wire_write_byte(CONVERT_TEMP);
+    }
+
+    fn read_temperatures(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        self.temperatures = [None; MAX_PROBES];
+
+        for i in 0..self.reading_count() {
+            let rom = match self.probe_rom(i) {
+                Some(rom) => rom,
+                None => continue,
+            };
+
+            let scratchpad = match self.read_scratchpad(board, rom) {
+                Some(scratchpad) => scratchpad,
+                None => {
+                    defmt::println!("Problem reading temperature");
+                    continue;
+                }
+            };
+
+            // lower resolutions leave the low bits undefined, the value is in 1/16 C at every resolution
+            let resolution = Resolution::from_config_register(scratchpad[4]).unwrap_or(Resolution::Bits12);
+            let undefined_bits = 12 - resolution.bits();
+            let raw_temp = i16::from_le_bytes([scratchpad[0], scratchpad[1]]) >> undefined_bits << undefined_bits;
+            if raw_temp == POWER_ON_RAW {
+                defmt::println!("ds18b20 reading before conversion");
+                continue;
+            }
+            let temperature = raw_temp as f64 / 16.0;
+            defmt::println!("Temp C: {}", temperature);
+            self.temperatures[i] = Some(temperature);
+        }
+    }
+
+    fn offset(&self, index: usize) -> f64 {
+        self.special_config.offsets[index] as f64 / OFFSET_UNITS_PER_DEGREE
     }
 }
 
 impl SensorDriver for Ds18b20 {
     
-    #[allow(unused)]
     fn setup(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
-        
+        if self.probe_count() == 0 {
+            self.configure_resolution(board, None);
+            return;
+        }
+
+        self.load_roms(board);
+        self.discover(board);
+        for i in 0..self.probe_count() {
+            if let Some(rom) = self.probe_rom(i) {
+                self.configure_resolution(board, rom);
+            }
+        }
     }
 
     getters!();
 
+    // a raw and a calibrated temperature for each probe
     fn get_measured_parameter_count(&mut self) -> usize {
-        return NUMBER_OF_MEASURED_PARAMETERS;
+        self.reading_count() * 2
     }
 
     fn get_measured_parameter_value(&mut self, index: usize) -> Result<f64, ()> {
-        if index < NUMBER_OF_MEASURED_PARAMETERS {
-            Ok(self.measured_parameter_values[index])
-        } else {
+        let probe = index / 2;
+        if probe >= self.reading_count() {
             return Err(());
-            // return -1.0_f64;
+        }
+        let temperature = self.temperatures[probe].ok_or(())?;
+        if index % 2 == 0 {
+            Ok(temperature)
+        } else {
+            Ok(temperature + self.offset(probe))
         }
     }
 
     fn get_measured_parameter_identifier(&mut self, index: usize) -> [u8; 16] {
-        let identifiers: [&str; NUMBER_OF_MEASURED_PARAMETERS] = ["T_raw", "T_cal"];
         let mut buf: [u8; 16] = [0_u8; 16];
+        let probe = index / 2;
+        let suffix = if index % 2 == 0 { "raw" } else { "cal" };
 
-        let mut identifier: &str = "invalid";
-        if index <= NUMBER_OF_MEASURED_PARAMETERS {
-            identifier = identifiers[index];
+        if probe >= self.reading_count() {
+            buf[..7].copy_from_slice(b"invalid");
+            return buf;
         }
-        for i in 0..identifier.len() {
-            buf[i] = identifier.as_bytes()[i];
+        if self.probe_count() == 0 {
+            buf[..2].copy_from_slice(b"T_");
+            buf[2..5].copy_from_slice(suffix.as_bytes());
+            return buf;
         }
-        return buf;
-    }
 
-    fn take_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
-        defmt::println!("starting take measurement");
-        board.one_wire_reset();
-        board.one_wire_skip_address();
-        board.one_wire_write_byte(CONVERT_TEMP);
-        //Resolution::Bits12.delay_for_measurement_time(delay));
-        let delay_ms = Resolution::Bits12.max_measurement_time_millis();
-        board.delay_ms(delay_ms);
+        let mut identifier = [0u8; 16];
+        if let Ok(identifier) = format_no_std::show(
+            &mut identifier,
+            format_args!("T{}_{}", probe + 1, suffix),
+        ) {
+            buf[..identifier.len()].copy_from_slice(identifier.as_bytes());
+        }
+        buf
+    }
 
-        // init measure parameter values
-        self.measured_parameter_values[0] = core::f64::MAX;
-        self.measured_parameter_values[1] = core::f64::MAX;
+    fn start_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        self.start_conversion(board);
+        self.conversion_start = Some(board.millis());
+    }
 
-        // This code just reads from a single one wire device
-        board.one_wire_reset();
-        board.one_wire_skip_address();
-        board.one_wire_write_byte(READ_SCRATCHPAD);
-        let mut scratchpad = [0; 9];
-        match board.one_wire_read_bytes(&mut scratchpad) {
-            Ok(_) => {
-                let resolution =
-                    if let Some(resolution) = Resolution::from_config_register(scratchpad[4]) {
-                        resolution
-                    } else {
-                        //    return Err(OneWireError::CrcMismatch);
-                        defmt::println!("Problem reading resolution from scratchpad");
-                        return;
-                    };
-                let raw_temp = u16::from_le_bytes([scratchpad[0], scratchpad[1]]);
-                let temperature = match resolution {
-                    Resolution::Bits12 => (raw_temp as f32) / 16.0,
-                    Resolution::Bits11 => (raw_temp as f32) / 8.0,
-                    Resolution::Bits10 => (raw_temp as f32) / 4.0,
-                    Resolution::Bits9 => (raw_temp as f32) / 2.0,
-                };
-                defmt::println!("Temp C: {}", temperature);
-                let value = temperature as f64;
-                self.measured_parameter_values[0] = value as f64;
-                self.measured_parameter_values[1] = self.m * value as f64 + self.b;
-            }
-            Err(_) => {
-                defmt::println!("Problem reading temperature");
+    fn poll_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) -> bool {
+        if let Some(conversion_start) = self.conversion_start {
+            let conversion_time = self.special_config.resolution().max_measurement_time_millis() as u32;
+            if board.millis().wrapping_sub(conversion_start) < conversion_time {
+                return false;
             }
+            self.conversion_start = None;
+            self.read_temperatures(board);
         }
+        true
+    }
 
-        // This code iterates over all the attached one wire devices
-        // defmt::println!("start bus search take measurement");
-        // board.one_wire_bus_start_search();
-        // defmt::println!("iterate");
-        // loop {
-        //     if let Some(device_address) = board.one_wire_bus_search() {
-        //         defmt::println!("found a device");
-        //         // todo: fix this so that you can check family code
-        //         // if device_address.family_code() != ds18b20::FAMILY_CODE {
-        //         //     // skip other devices
-        //         //     continue;
-        //         // }
-        //         // You will generally create the sensor once, and save it for later
-        //         // let sensor = Ds18b20::new(device_address)?;
-
-        //         // // contains the read temperature, as well as config info such as the resolution used
-        //         // let sensor_data = sensor.read_data(one_wire_bus, delay)?;
-        //         // writeln!(tx, "Device at {:?} is {}°C", device_address, sensor_data.temperature);
-
-        //         board.one_wire_reset();
-        //         board.one_wire_match_address(device_address);
-        //         board.one_wire_write_byte(READ_SCRATCHPAD);
-
-        //         let mut scratchpad = [0; 9];
-        //         board.one_wire_read_bytes(&mut scratchpad);
-
-        //         let resolution =
-        //             if let Some(resolution) = Resolution::from_config_register(scratchpad[4]) {
-        //                 resolution
-        //             } else {
-        //                 //    return Err(OneWireError::CrcMismatch);
-        //                 defmt::println!("Problem reading resolution from scratchpad");
-        //                 return;
-        //             };
-        //         let raw_temp = u16::from_le_bytes([scratchpad[0], scratchpad[1]]);
-        //         let temperature = match resolution {
-        //             Resolution::Bits12 => (raw_temp as f32) / 16.0,
-        //             Resolution::Bits11 => (raw_temp as f32) / 8.0,
-        //             Resolution::Bits10 => (raw_temp as f32) / 4.0,
-        //             Resolution::Bits9 => (raw_temp as f32) / 2.0,
-        //         };
-        //         defmt::println!("Temp C: {}", temperature);
-        //     } else {
-        //         break;
-        //     }
-        // }
+    fn take_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        defmt::println!("starting take measurement");
+        self.start_conversion(board);
+        let delay_ms = self.special_config.resolution().max_measurement_time_millis();
+        board.delay_ms(delay_ms);
+        self.read_temperatures(board);
     }
-    
 
     fn get_configuration_json(&mut self) -> serde_json::Value {
         let mut sensor_id = self.get_id();
@@ -253,45 +510,73 @@ impl SensorDriver for Ds18b20 {
             Err(_) => "Invalid",
         };
 
-        json!({
+        let mut probes = alloc::vec::Vec::new();
+        for i in 0..self.probe_count() {
+            let mut buffer = [0u8; 16];
+            let rom = match self.probe_rom(i) {
+                Some(Some(rom)) => serde_json::Value::from(rom_text(rom, &mut buffer)),
+                _ => serde_json::Value::from("unknown"),
+            };
+            let found = self.discovered[..self.discovered_count].contains(&self.special_config.roms[i]);
+            probes.push(json!({
+                "rom": rom,
+                "offset": self.offset(i),
+                "found": found,
+            }));
+        }
+
+        // rom codes found by the last search, for setting up probes
+        let mut discovered = alloc::vec::Vec::new();
+        for rom in &self.discovered[..self.discovered_count] {
+            let mut buffer = [0u8; 16];
+            discovered.push(serde_json::Value::from(rom_text(*rom, &mut buffer)));
+        }
+
+        let mut json = json!({
            "id" : sensor_id,
-           "type" : sensor_name
-        })
+           "type" : sensor_name,
+           "resolution": self.special_config.resolution().bits(),
+           "discovered": discovered,
+        });
+        if self.probe_count() > 0 {
+            json["probes"] = serde_json::Value::Array(probes);
+        } else {
+            json["offset"] = self.offset(0).into();
+        }
+        json
     }
 
+    // each probe's offset is set from its reading at the calibration point, such as an ice bath
     fn fit(&mut self, pairs: &[CalibrationPair]) -> Result<(), ()> {
-        for i in 0..pairs.len() {
-            let pair = &pairs[i];
-            defmt::println!("calib pair{:?} {} {}", i, pair.point, pair.values[0]);
-        }
-
-        if pairs.len() != 2 {
+        if pairs.len() != 1 {
             return Err(());
         }
 
-        let cal1 = &pairs[0];
-        let cal2 = &pairs[1];
-
-        self.m = (cal2.point - cal1.point) / (cal2.values[0] - cal1.values[0]);
-        self.b = cal1.point - self.m * cal1.values[0];
-        defmt::println!("calibration: {} {}", self.m, self.b);
-        self.special_config.m = self.m as f32;
-        self.special_config.b = self.b as f32;
-        defmt::println!(
-            "calibration: {} {}",
-            self.special_config.m,
-            self.special_config.b
-        );
+        let pair = &pairs[0];
+        let mut offsets = [0; MAX_PROBES];
+        for i in 0..self.reading_count() {
+            if i >= pair.values.len() {
+                return Err(());
+            }
+            offsets[i] = match offset_from_degrees(pair.point - pair.values[i]) {
+                Some(offset) => offset,
+                None => {
+                    defmt::println!("ds18b20 probe {} offset out of range", i);
+                    return Err(());
+                }
+            };
+        }
+        self.special_config.offsets = offsets;
 
         Ok(())
     }
 
     fn clear_calibration(&mut self) {
-        defmt::println!("clear calibration not implemented");
+        self.special_config.offsets = [0; MAX_PROBES];
     }
 
-    #[allow(unused_variables)]
     fn update(&mut self, values: serde_json::Value) -> Result<(),&'static str> {
-        Err("update not implemented")
+        self.special_config.update_from_values(values)?;
+        Ok(())
     }
 }
@@ -10,13 +10,15 @@ pub struct GenericAnalog {
     measured_parameter_values: [f64; 2],
     m: f64,
     b: f64,
+    conversion_requested: bool, // ads1x15 single shot conversion in progress
 }
 
 impl SensorDriver for GenericAnalog {
     fn get_configuration_json(&mut self) -> serde_json::Value {
         let adc_select = match self.special_config.settings.adc_select() {
-            0 => "internal",
-            1 => "external",
+            ADC_SELECT_INTERNAL => "internal",
+            ADC_SELECT_EXTERNAL => "external",
+            ADC_SELECT_ADS1X15 => "ads1x15",
             _ => "invalid",
         };
 
@@ -32,14 +34,23 @@ impl SensorDriver for GenericAnalog {
             Err(_) => "Invalid",
         };
 
-        json!({
+        let mut json = json!({
            "id" : sensor_id,
            "type" : sensor_name,
            "m": self.m, // return the converted value
            "b" : self.b, // return the converted value
            "sensor_port": self.special_config.sensor_port,
            "adc_select": adc_select
-        })
+        });
+        if self.special_config.settings.adc_select() == ADC_SELECT_ADS1X15 {
+            let ads = &self.special_config.ads;
+            json["ads_chip"] = if ads.chip == ADS1015 { "ads1015" } else { "ads1115" }.into();
+            json["ads_address"] = ads.address.into();
+            json["ads_input"] = ADS_INPUTS[ads.input as usize & 0x07].into();
+            json["ads_full_scale_mv"] = ADS_FULL_SCALE_MV[ads.gain as usize % ADS_FULL_SCALE_MV.len()].into();
+            json["ads_data_rate"] = ads.data_rates()[ads.data_rate as usize & 0x07].into();
+        }
+        json
     }
 
     #[allow(unused)]
@@ -64,18 +75,71 @@ impl SensorDriver for GenericAnalog {
     }
 
     fn take_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        self.start_measurement(board);
+        for _ in 0..ADS_POLL_ATTEMPTS {
+            if self.poll_measurement(board) {
+                return;
+            }
+            board.delay_ms(ADS_POLL_DELAY_MS);
+        }
+    }
+
+    fn start_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
         let value = 
             match self.special_config.settings.adc_select() {
-                0 => {
+                ADC_SELECT_INTERNAL => {
                     board.query_internal_adc(self.special_config.sensor_port)
                 }
-                1 => {
+                ADC_SELECT_EXTERNAL => {
                     board.query_external_adc(self.special_config.sensor_port)
                 }
-                2_usize.. => todo!("other adcs not implemented"),
+                ADC_SELECT_ADS1X15 => {
+                    self.measured_parameter_values = [f64::MAX; 2];
+                    let ads = self.special_config.ads;
+                    let config = ads.config_register() | ADS_CONFIG_START;
+                    let bytes = config.to_be_bytes();
+                    match board.ic2_write(ads.address, &[ADS_REGISTER_CONFIG, bytes[0], bytes[1]]) {
+                        Ok(_) => self.conversion_requested = true,
+                        Err(_) => defmt::println!("failed to start ads1x15 conversion at 0x{:x}", ads.address),
+                    }
+                    return;
+                }
+                _ => {
+                    defmt::println!("invalid adc select");
+                    return;
+                }
             };
-        self.measured_parameter_values[0] = value.into();
-        self.measured_parameter_values[1] = self.m * value as f64 + self.b;
+        self.set_value(value as f64);
+    }
+
+    fn poll_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) -> bool {
+        if !self.conversion_requested {
+            return true;
+        }
+
+        let ads = self.special_config.ads;
+        let mut config = [0u8; 2];
+        match board.ic2_write_read(ads.address, &[ADS_REGISTER_CONFIG], &mut config) {
+            Ok(_) if u16::from_be_bytes(config) & ADS_CONFIG_START == 0 => return false, // still converting
+            Ok(_) => {}
+            Err(_) => {
+                self.conversion_requested = false;
+                return true;
+            }
+        }
+
+        let mut conversion = [0u8; 2];
+        self.conversion_requested = false;
+        if board.ic2_write_read(ads.address, &[ADS_REGISTER_CONVERSION], &mut conversion).is_err() {
+            defmt::println!("failed to read ads1x15 at 0x{:x}", ads.address);
+            return true;
+        }
+
+        // the ADS1015's 12 bit result is left aligned, so both chips scale from 16 bits
+        let raw = i16::from_be_bytes(conversion) as f64;
+        let full_scale = ADS_FULL_SCALE_MV[ads.gain as usize % ADS_FULL_SCALE_MV.len()] as f64 / 1000.0;
+        self.set_value(raw * full_scale / 32768.0);
+        true
     }
 
     fn clear_calibration(&mut self) {
@@ -85,12 +149,11 @@ impl SensorDriver for GenericAnalog {
         self.special_config.b = 0_f32;
     }
 
-    fn fit(&mut self, pairs: &[CalibrationPair]) -> Result<(), ()> {
-        for i in 0..pairs.len() {
-            let pair = &pairs[i];
-            defmt::println!("calib pair{:?} {} {}", i, pair.point, pair.values[0]);
-        }
+    fn get_required_calibration_point_count(&self) -> usize {
+        2
+    }
 
+    fn fit(&mut self, pairs: &[CalibrationPair]) -> Result<(), ()> {
         if pairs.len() != 2 {
             return Err(());
         }
@@ -100,14 +163,8 @@ impl SensorDriver for GenericAnalog {
 
         self.m = (cal2.point - cal1.point) / (cal2.values[0] - cal1.values[0]);
         self.b = cal1.point - self.m * cal1.values[0];
-        defmt::println!("calibration: {} {}", self.m, self.b);
         self.special_config.m = self.m as f32;
         self.special_config.b = self.b as f32;
-        defmt::println!(
-            "calibration: {} {}",
-            self.special_config.m,
-            self.special_config.b
-        );
 
         Ok(())
     }
@@ -139,8 +196,126 @@ impl GenericAnalog {
             measured_parameter_values: [0.0; 2],
             m: 0_f64,
             b: 0_f64,
+            conversion_requested: false,
         }
     }
+
+    // the raw value is adc counts, or volts from an ads1x15
+    fn set_value(&mut self, value: f64) {
+        self.measured_parameter_values[0] = value;
+        self.measured_parameter_values[1] = self.m * value + self.b;
+    }
+}
+
+const ADC_SELECT_INTERNAL: usize = 0;
+const ADC_SELECT_EXTERNAL: usize = 1; // the board adc at 0x2F
+const ADC_SELECT_ADS1X15: usize = 2;
+
+// ADS1115 (16 bit) and ADS1015 (12 bit) adcs, single shot conversions
+const ADS1115: u8 = 0;
+const ADS1015: u8 = 1;
+const ADS_DEFAULT_ADDRESS: u8 = 0x48; // ADDR tied to ground, 0x49 to 0x4B for VDD, SDA and SCL
+const ADS_REGISTER_CONVERSION: u8 = 0x00;
+const ADS_REGISTER_CONFIG: u8 = 0x01;
+const ADS_CONFIG_START: u16 = 0x8000; // starts a conversion when written, set when reading once the conversion is done
+const ADS_CONFIG_SINGLE_SHOT: u16 = 0x0100;
+const ADS_CONFIG_COMPARATOR_OFF: u16 = 0x0003;
+const ADS_POLL_DELAY_MS: u16 = 5;
+const ADS_POLL_ATTEMPTS: u32 = 40; // longer than a conversion at 8 samples per second
+
+// mux codes, differential pairs first
+const ADS_INPUTS: [&str; 8] = ["a0-a1", "a0-a3", "a1-a3", "a2-a3", "a0", "a1", "a2", "a3"];
+// pga codes
+const ADS_FULL_SCALE_MV: [u16; 6] = [6144, 4096, 2048, 1024, 512, 256];
+// data rate codes, samples per second
+const ADS1115_DATA_RATES: [u16; 8] = [8, 16, 32, 64, 128, 250, 475, 860];
+const ADS1015_DATA_RATES: [u16; 8] = [128, 250, 490, 920, 1600, 2400, 3300, 3300];
+
+#[derive(Copy, Clone)]
+struct Ads1x15Configuration {
+    chip: u8,
+    address: u8,
+    input: u8,     // mux code
+    gain: u8,      // pga code
+    data_rate: u8, // data rate code
+}
+
+impl Ads1x15Configuration {
+    fn new() -> Ads1x15Configuration {
+        Ads1x15Configuration {
+            chip: ADS1115,
+            address: ADS_DEFAULT_ADDRESS,
+            input: 4,     // a0 to ground
+            gain: 2,      // 2.048 V
+            data_rate: 4, // 128 samples per second on the ADS1115
+        }
+    }
+
+    fn parse_from_values(value: &serde_json::Value) -> Result<Ads1x15Configuration, &'static str> {
+        let mut ads = Ads1x15Configuration::new();
+
+        match &value["ads_chip"] {
+            serde_json::Value::String(chip) => match chip.as_str() {
+                "ads1115" => ads.chip = ADS1115,
+                "ads1015" => {
+                    ads.chip = ADS1015;
+                    ads.data_rate = 4; // 1600 samples per second
+                }
+                _ => return Err("ads_chip must be ads1115 or ads1015"),
+            },
+            serde_json::Value::Null => {}
+            _ => return Err("ads_chip must be ads1115 or ads1015"),
+        }
+
+        match &value["ads_address"] {
+            serde_json::Value::Number(number) => match number.as_u64() {
+                Some(number) if (0x48..=0x4B).contains(&number) => ads.address = number as u8,
+                _ => return Err("ads_address must be 0x48 to 0x4B"),
+            },
+            serde_json::Value::Null => {}
+            _ => return Err("ads_address must be 0x48 to 0x4B"),
+        }
+
+        match &value["ads_input"] {
+            serde_json::Value::String(input) => match ADS_INPUTS.iter().position(|name| *name == input.as_str()) {
+                Some(code) => ads.input = code as u8,
+                None => return Err("ads_input must be a0 to a3, or a differential pair a0-a1, a0-a3, a1-a3 or a2-a3"),
+            },
+            _ => return Err("ads_input must be a0 to a3, or a differential pair a0-a1, a0-a3, a1-a3 or a2-a3"),
+        }
+
+        match &value["ads_full_scale_mv"] {
+            serde_json::Value::Number(number) => match ADS_FULL_SCALE_MV.iter().position(|mv| Some(*mv as u64) == number.as_u64()) {
+                Some(code) => ads.gain = code as u8,
+                None => return Err("ads_full_scale_mv must be 6144, 4096, 2048, 1024, 512 or 256"),
+            },
+            serde_json::Value::Null => {}
+            _ => return Err("ads_full_scale_mv must be 6144, 4096, 2048, 1024, 512 or 256"),
+        }
+
+        match &value["ads_data_rate"] {
+            serde_json::Value::Number(number) => match ads.data_rates().iter().position(|rate| Some(*rate as u64) == number.as_u64()) {
+                Some(code) => ads.data_rate = code as u8,
+                None => return Err("ads_data_rate must be one of the chip's samples per second rates"),
+            },
+            serde_json::Value::Null => {}
+            _ => return Err("ads_data_rate must be one of the chip's samples per second rates"),
+        }
+
+        Ok(ads)
+    }
+
+    fn data_rates(&self) -> &'static [u16; 8] {
+        if self.chip == ADS1015 { &ADS1015_DATA_RATES } else { &ADS1115_DATA_RATES }
+    }
+
+    fn config_register(&self) -> u16 {
+        ((self.input as u16 & 0x07) << 12)
+            | ((self.gain as u16 & 0x07) << 9)
+            | ADS_CONFIG_SINGLE_SHOT
+            | ((self.data_rate as u16 & 0x07) << 5)
+            | ADS_CONFIG_COMPARATOR_OFF
+    }
 }
 
 #[bitfield(u8)]
@@ -158,12 +333,14 @@ pub struct GenericAnalogSpecialConfiguration {
     b: f32,                                // 4
     sensor_port: u8,                       // 1
     settings: GenericAnalogDriverBitfield, // 1
+    ads: Ads1x15Configuration,             // 5
 }
 
 impl GenericAnalogSpecialConfiguration {
     pub fn parse_from_values(value: serde_json::Value) -> Result<GenericAnalogSpecialConfiguration, &'static str> {
         // should we return a Result object here? because we are parsing?  parse_from_values?
         let mut sensor_port: u8 = 0;
+        let ads_selected = value["adc_select"] == "ads1x15"; // the ads1x15 inputs are set with ads_input
         match &value["sensor_port"] {
             serde_json::Value::Number(number) => {
                 if let Some(number) = number.as_u64() {
@@ -176,6 +353,7 @@ impl GenericAnalogSpecialConfiguration {
                     }
                 }
             }
+            _ if ads_selected => {}
             _ => {
                 return Err("missing sensor port")
             }
@@ -185,10 +363,13 @@ impl GenericAnalogSpecialConfiguration {
         match &value["adc_select"] {
             serde_json::Value::String(string) => match string.as_str() {
                 "internal" => {
-                    bitfield.set_adc_select(0);
+                    bitfield.set_adc_select(ADC_SELECT_INTERNAL);
                 }
                 "external" => {
-                    bitfield.set_adc_select(1);
+                    bitfield.set_adc_select(ADC_SELECT_EXTERNAL);
+                }
+                "ads1x15" => {
+                    bitfield.set_adc_select(ADC_SELECT_ADS1X15);
                 }
                 _ => {
                     return Err("bad adc select string");
@@ -199,11 +380,18 @@ impl GenericAnalogSpecialConfiguration {
             }
         }
 
+        let ads = if ads_selected {
+            Ads1x15Configuration::parse_from_values(&value)?
+        } else {
+            Ads1x15Configuration::new()
+        };
+
         Ok(Self {
             m: 0_f32,
             b: 0_f32,
             sensor_port: sensor_port,
-            settings: bitfield
+            settings: bitfield,
+            ads,
         })
     }
 
@@ -0,0 +1,590 @@
+use serde_json::json;
+
+use crate::sensor_name_from_type_id;
+
+use super::types::*;
+
+// an i2c sensor described by its configuration, for trying out a new breakout without a firmware release
+// setup writes the init bytes, a measurement writes the trigger bytes, waits wait_ms and reads up to 8 bytes,
+// from register if one is set, then each parameter takes length bytes from start, shifts, masks and scales them
+// value = ((raw >> shift) & mask) * scale + offset, sign extended from the mask width when signed
+// scale and offset are stored as half precision floats to fit four parameters in the settings partition,
+// so they keep about 3 significant digits
+
+const MAX_PARAMETERS: usize = 4;
+const MAX_READ_LENGTH: usize = 8;
+const MAX_COMMAND_LENGTH: usize = 2;
+const WAIT_UNIT_MS: u32 = 10;
+
+// layout bits
+const LAYOUT_INIT_LENGTH_MASK: u8 = 0x03;
+const LAYOUT_TRIGGER_LENGTH_SHIFT: u8 = 2;
+const LAYOUT_PARAMETER_COUNT_SHIFT: u8 = 4; // count - 1
+const LAYOUT_READ_REGISTER: u8 = 0x40; // write the register before reading
+
+// parameter format bits
+const FORMAT_START_MASK: u8 = 0x07;
+const FORMAT_LENGTH_SHIFT: u8 = 3; // length - 1
+const FORMAT_LITTLE_ENDIAN: u8 = 0x20;
+const FORMAT_SIGNED: u8 = 0x40;
+
+// parameter field bits, shift in the high nibble and mask width in the low nibble, 0 for no mask
+const FIELD_MAX_SHIFT: u64 = 15;
+const FIELD_MAX_MASK_WIDTH: u32 = 15;
+
+// f32 to IEEE half precision, None if out of range
+fn f32_to_f16(value: f32) -> Option<u16> {
+    if !value.is_finite() {
+        return None;
+    }
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xFF) as i32 - 127 + 15;
+    let mantissa = bits & 0x007F_FFFF;
+
+    if value == 0.0 || exponent < -10 {
+        return Some(sign); // too small for a subnormal
+    }
+    if exponent <= 0 {
+        // subnormal, with the implicit bit made explicit
+        let mantissa = mantissa | 0x0080_0000;
+        let shift = (14 - exponent) as u32;
+        let mut half = (mantissa >> shift) as u16;
+        if (mantissa >> (shift - 1)) & 1 != 0 {
+            half += 1;
+        }
+        return Some(sign | half);
+    }
+    if exponent >= 31 {
+        return None;
+    }
+
+    let mut half = sign as u32 | ((exponent as u32) << 10) | (mantissa >> 13);
+    if mantissa & 0x1000 != 0 {
+        half += 1; // a carry into the exponent is still correct
+    }
+    if (half >> 10) & 0x1F == 0x1F {
+        return None;
+    }
+    Some(half as u16)
+}
+
+fn f16_to_f32(half: u16) -> f32 {
+    let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((half >> 10) & 0x1F) as u32;
+    let mantissa = (half & 0x03FF) as u32;
+    match exponent {
+        0 => sign * mantissa as f32 * 5.960_464_5e-8, // 2^-24
+        0x1F => sign * f32::INFINITY,
+        _ => f32::from_bits(((half as u32 & 0x8000) << 16) | ((exponent + 127 - 15) << 23) | (mantissa << 13)),
+    }
+}
+
+fn parse_bytes(value: &serde_json::Value, bytes: &mut [u8; MAX_COMMAND_LENGTH], message: &'static str) -> Result<Option<u8>, &'static str> {
+    match value {
+        serde_json::Value::Array(values) => {
+            if values.len() > MAX_COMMAND_LENGTH {
+                return Err(message);
+            }
+            let mut parsed = [0u8; MAX_COMMAND_LENGTH];
+            for (i, value) in values.iter().enumerate() {
+                match value.as_u64() {
+                    Some(byte) if byte <= u8::MAX as u64 => parsed[i] = byte as u8,
+                    _ => return Err(message),
+                }
+            }
+            *bytes = parsed;
+            Ok(Some(values.len() as u8))
+        }
+        serde_json::Value::Null => Ok(None),
+        _ => Err(message),
+    }
+}
+
+fn bytes_json(bytes: &[u8]) -> serde_json::Value {
+    serde_json::Value::Array(bytes.iter().map(|byte| (*byte).into()).collect())
+}
+
+#[derive(Copy, Clone)]
+pub struct GenericI2CSpecialConfiguration {
+    address: u8,
+    layout: u8,
+    init: [u8; MAX_COMMAND_LENGTH],
+    trigger: [u8; MAX_COMMAND_LENGTH],
+    wait: u8, // 10 ms units
+    register: u8,
+    formats: [u8; MAX_PARAMETERS],
+    fields: [u8; MAX_PARAMETERS],
+    scales: [u16; MAX_PARAMETERS],  // f16
+    offsets: [u16; MAX_PARAMETERS], // f16
+}
+
+impl GenericI2CSpecialConfiguration {
+    pub fn new_from_bytes(
+        bytes: [u8; SENSOR_SETTINGS_PARTITION_SIZE],
+    ) -> GenericI2CSpecialConfiguration {
+        let settings = bytes.as_ptr().cast::<GenericI2CSpecialConfiguration>();
+        unsafe { *settings }
+    }
+
+    pub fn parse_from_values(value: serde_json::Value) -> Result<GenericI2CSpecialConfiguration, &'static str> {
+        if !value["address"].is_number() {
+            return Err("address is required");
+        }
+        if !value["parameters"].is_array() {
+            return Err("parameters are required");
+        }
+
+        let mut special_config = Self {
+            address: 0,
+            layout: 0,
+            init: [0; MAX_COMMAND_LENGTH],
+            trigger: [0; MAX_COMMAND_LENGTH],
+            wait: 0,
+            register: 0,
+            formats: [0; MAX_PARAMETERS],
+            fields: [0; MAX_PARAMETERS],
+            scales: [0; MAX_PARAMETERS],
+            offsets: [0; MAX_PARAMETERS],
+        };
+        special_config.update_from_values(value)?;
+        Ok(special_config)
+    }
+
+    pub fn update_from_values(&mut self, values: serde_json::Value) -> Result<(), &'static str> {
+        match &values["address"] {
+            serde_json::Value::Number(number) => match number.as_u64() {
+                Some(number) if (0x08..=0x77).contains(&number) => self.address = number as u8,
+                _ => return Err("address must be 0x08 to 0x77"),
+            },
+            serde_json::Value::Null => {}
+            _ => return Err("address must be 0x08 to 0x77"),
+        }
+
+        if let Some(length) = parse_bytes(&values["init"], &mut self.init, "init must be a list of up to 2 bytes")? {
+            self.layout = (self.layout & !LAYOUT_INIT_LENGTH_MASK) | length;
+        }
+
+        if let Some(length) = parse_bytes(&values["trigger"], &mut self.trigger, "trigger must be a list of up to 2 bytes")? {
+            self.layout = (self.layout & !(0x03 << LAYOUT_TRIGGER_LENGTH_SHIFT)) | (length << LAYOUT_TRIGGER_LENGTH_SHIFT);
+        }
+
+        match &values["wait_ms"] {
+            serde_json::Value::Number(number) => match number.as_u64() {
+                Some(number) if number <= 255 * WAIT_UNIT_MS as u64 => {
+                    self.wait = number.div_ceil(WAIT_UNIT_MS as u64) as u8
+                }
+                _ => return Err("wait_ms must be 0 to 2550"),
+            },
+            serde_json::Value::Null => {}
+            _ => return Err("wait_ms must be 0 to 2550"),
+        }
+
+        // false reads without writing a register first
+        match &values["register"] {
+            serde_json::Value::Number(number) => match number.as_u64() {
+                Some(number) if number <= u8::MAX as u64 => {
+                    self.register = number as u8;
+                    self.layout |= LAYOUT_READ_REGISTER;
+                }
+                _ => return Err("register must be 0 to 255, or false"),
+            },
+            serde_json::Value::Bool(false) => self.layout &= !LAYOUT_READ_REGISTER,
+            serde_json::Value::Null => {}
+            _ => return Err("register must be 0 to 255, or false"),
+        }
+
+        match &values["parameters"] {
+            serde_json::Value::Array(parameters) => {
+                if parameters.is_empty() || parameters.len() > MAX_PARAMETERS {
+                    return Err("parameters must have 1 to 4 entries");
+                }
+
+                let mut formats = [0; MAX_PARAMETERS];
+                let mut fields = [0; MAX_PARAMETERS];
+                let mut scales = [0; MAX_PARAMETERS];
+                let mut offsets = [0; MAX_PARAMETERS];
+                let mut next_start = 0;
+                for (i, parameter) in parameters.iter().enumerate() {
+                    let length = match &parameter["length"] {
+                        serde_json::Value::Number(number) => match number.as_u64() {
+                            Some(length) if (1..=4).contains(&length) => length,
+                            _ => return Err("length must be 1 to 4 bytes"),
+                        },
+                        serde_json::Value::Null => 2,
+                        _ => return Err("length must be 1 to 4 bytes"),
+                    };
+
+                    // parameters follow each other in the read unless a start byte is given
+                    let start = match &parameter["start"] {
+                        serde_json::Value::Number(number) => match number.as_u64() {
+                            Some(start) => start,
+                            None => return Err("invalid start"),
+                        },
+                        serde_json::Value::Null => next_start,
+                        _ => return Err("invalid start"),
+                    };
+                    if start + length > MAX_READ_LENGTH as u64 {
+                        return Err("parameters must be within the first 8 bytes read");
+                    }
+                    next_start = start + length;
+
+                    let mut format = start as u8 | ((length as u8 - 1) << FORMAT_LENGTH_SHIFT);
+
+                    match &parameter["byte_order"] {
+                        serde_json::Value::String(byte_order) => match byte_order.as_str() {
+                            "big" => {}
+                            "little" => format |= FORMAT_LITTLE_ENDIAN,
+                            _ => return Err("byte_order must be big or little"),
+                        },
+                        serde_json::Value::Null => {}
+                        _ => return Err("byte_order must be big or little"),
+                    }
+
+                    match &parameter["signed"] {
+                        serde_json::Value::Bool(true) => format |= FORMAT_SIGNED,
+                        serde_json::Value::Bool(false) | serde_json::Value::Null => {}
+                        _ => return Err("signed must be true or false"),
+                    }
+
+                    let shift = match &parameter["shift"] {
+                        serde_json::Value::Number(number) => match number.as_u64() {
+                            Some(shift) if shift <= FIELD_MAX_SHIFT => shift as u8,
+                            _ => return Err("shift must be 0 to 15 bits"),
+                        },
+                        serde_json::Value::Null => 0,
+                        _ => return Err("shift must be 0 to 15 bits"),
+                    };
+
+                    // masks are applied after the shift, and must be contiguous low bits such as 0x0FFF
+                    let mask_width = match &parameter["mask"] {
+                        serde_json::Value::Number(number) => match number.as_u64() {
+                            Some(mask) if mask > 0 && (mask + 1) & mask == 0 && (mask + 1).trailing_zeros() <= FIELD_MAX_MASK_WIDTH => {
+                                (mask + 1).trailing_zeros() as u8
+                            }
+                            _ => return Err("mask must be low bits, 0x1 to 0x7FFF"),
+                        },
+                        serde_json::Value::Null => 0,
+                        _ => return Err("mask must be low bits, 0x1 to 0x7FFF"),
+                    };
+
+                    let scale = match &parameter["scale"] {
+                        serde_json::Value::Number(number) => number.as_f64().map(|scale| scale as f32),
+                        serde_json::Value::Null => Some(1.0),
+                        _ => None,
+                    };
+                    let offset = match &parameter["offset"] {
+                        serde_json::Value::Number(number) => number.as_f64().map(|offset| offset as f32),
+                        serde_json::Value::Null => Some(0.0),
+                        _ => None,
+                    };
+
+                    formats[i] = format;
+                    fields[i] = (shift << 4) | mask_width;
+                    scales[i] = match scale.and_then(f32_to_f16) {
+                        Some(scale) => scale,
+                        None => return Err("scale must be a number within 65504"),
+                    };
+                    offsets[i] = match offset.and_then(f32_to_f16) {
+                        Some(offset) => offset,
+                        None => return Err("offset must be a number within 65504"),
+                    };
+                }
+
+                self.formats = formats;
+                self.fields = fields;
+                self.scales = scales;
+                self.offsets = offsets;
+                self.layout = (self.layout & !(0x03 << LAYOUT_PARAMETER_COUNT_SHIFT))
+                    | ((parameters.len() as u8 - 1) << LAYOUT_PARAMETER_COUNT_SHIFT);
+            }
+            serde_json::Value::Null => {}
+            _ => return Err("parameters must be a list"),
+        }
+
+        Ok(())
+    }
+
+    fn init_length(&self) -> usize {
+        (self.layout & LAYOUT_INIT_LENGTH_MASK) as usize
+    }
+
+    fn trigger_length(&self) -> usize {
+        ((self.layout >> LAYOUT_TRIGGER_LENGTH_SHIFT) & 0x03) as usize
+    }
+
+    fn parameter_count(&self) -> usize {
+        ((self.layout >> LAYOUT_PARAMETER_COUNT_SHIFT) & 0x03) as usize + 1
+    }
+
+    fn parameter_start(&self, index: usize) -> usize {
+        (self.formats[index] & FORMAT_START_MASK) as usize
+    }
+
+    fn parameter_length(&self, index: usize) -> usize {
+        ((self.formats[index] >> FORMAT_LENGTH_SHIFT) & 0x03) as usize + 1
+    }
+
+    fn read_length(&self) -> usize {
+        (0..self.parameter_count())
+            .map(|i| self.parameter_start(i) + self.parameter_length(i))
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn parameter_value(&self, index: usize, data: &[u8; MAX_READ_LENGTH]) -> f64 {
+        let format = self.formats[index];
+        let start = self.parameter_start(index);
+        let length = self.parameter_length(index);
+
+        let mut raw: u32 = 0;
+        for i in 0..length {
+            let byte = if format & FORMAT_LITTLE_ENDIAN != 0 {
+                data[start + length - 1 - i]
+            } else {
+                data[start + i]
+            };
+            raw = (raw << 8) | byte as u32;
+        }
+
+        let shift = (self.fields[index] >> 4) as u32;
+        let mask_width = (self.fields[index] & 0x0F) as u32;
+        let width = if mask_width > 0 { mask_width } else { length as u32 * 8 };
+        let raw = raw >> shift;
+        let raw = if width < 32 { raw & ((1 << width) - 1) } else { raw };
+
+        let value = if format & FORMAT_SIGNED != 0 {
+            // sign extend from the field width
+            ((raw << (32 - width)) as i32 >> (32 - width)) as f64
+        } else {
+            raw as f64
+        };
+
+        value * f16_to_f32(self.scales[index]) as f64 + f16_to_f32(self.offsets[index]) as f64
+    }
+}
+
+pub struct GenericI2C {
+    general_config: SensorDriverGeneralConfiguration,
+    special_config: GenericI2CSpecialConfiguration,
+    values: [Option<f64>; MAX_PARAMETERS],
+    measuring: bool,
+    trigger_time: u32,
+}
+
+impl GenericI2C {
+    pub fn new(
+        general_config: SensorDriverGeneralConfiguration,
+        special_config: GenericI2CSpecialConfiguration,
+    ) -> Self {
+        GenericI2C {
+            general_config,
+            special_config,
+            values: [None; MAX_PARAMETERS],
+            measuring: false,
+            trigger_time: 0,
+        }
+    }
+}
+
+impl SensorDriver for GenericI2C {
+    getters!();
+
+    fn get_configuration_json(&mut self) -> serde_json::Value {
+        let mut sensor_id = self.get_id();
+        let sensor_id = util::str_from_utf8(&mut sensor_id).unwrap_or("Invalid");
+
+        let mut sensor_name = sensor_name_from_type_id(self.get_type_id().into());
+        let sensor_name = util::str_from_utf8(&mut sensor_name).unwrap_or("Invalid");
+
+        let config = &self.special_config;
+        let mut parameters = serde_json::Value::Array(alloc::vec::Vec::new());
+        if let serde_json::Value::Array(parameters) = &mut parameters {
+            for i in 0..config.parameter_count() {
+                let format = config.formats[i];
+                let mask_width = config.fields[i] & 0x0F;
+                let mut parameter = json!({
+                    "start": config.parameter_start(i),
+                    "length": config.parameter_length(i),
+                    "byte_order": if format & FORMAT_LITTLE_ENDIAN != 0 { "little" } else { "big" },
+                    "signed": format & FORMAT_SIGNED != 0,
+                    "shift": config.fields[i] >> 4,
+                    "scale": f16_to_f32(config.scales[i]),
+                    "offset": f16_to_f32(config.offsets[i]),
+                });
+                if mask_width > 0 {
+                    parameter["mask"] = ((1u32 << mask_width) - 1).into();
+                }
+                parameters.push(parameter);
+            }
+        }
+
+        json!({
+            "id": sensor_id,
+            "type": sensor_name,
+            "address": config.address,
+            "init": bytes_json(&config.init[..config.init_length()]),
+            "trigger": bytes_json(&config.trigger[..config.trigger_length()]),
+            "wait_ms": config.wait as u32 * WAIT_UNIT_MS,
+            "register": if config.layout & LAYOUT_READ_REGISTER != 0 { config.register.into() } else { serde_json::Value::Bool(false) },
+            "parameters": parameters,
+        })
+    }
+
+    fn setup(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        let length = self.special_config.init_length();
+        if length > 0
+            && board.ic2_write(self.special_config.address, &self.special_config.init[..length]).is_err()
+        {
+            defmt::println!("generic i2c init failed at 0x{:x}", self.special_config.address);
+        }
+    }
+
+    fn get_measured_parameter_count(&mut self) -> usize {
+        self.special_config.parameter_count()
+    }
+
+    fn get_measured_parameter_value(&mut self, index: usize) -> Result<f64, ()> {
+        if index >= self.special_config.parameter_count() {
+            return Err(());
+        }
+        self.values[index].ok_or(())
+    }
+
+    fn get_measured_parameter_identifier(&mut self, index: usize) -> [u8; 16] {
+        let mut buf = [0u8; 16];
+        if index >= self.special_config.parameter_count() {
+            buf[..7].copy_from_slice(b"invalid");
+            return buf;
+        }
+
+        let mut identifier = [0u8; 16];
+        if let Ok(identifier) = format_no_std::show(&mut identifier, format_args!("value_{}", index)) {
+            buf[..identifier.len()].copy_from_slice(identifier.as_bytes());
+        }
+        buf
+    }
+
+    fn take_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        self.start_measurement(board);
+        board.delay_ms((self.special_config.wait as u32 * WAIT_UNIT_MS) as u16);
+        for _ in 0..10 {
+            if self.poll_measurement(board) {
+                return;
+            }
+            board.delay_ms(WAIT_UNIT_MS as u16);
+        }
+        self.measuring = false;
+    }
+
+    fn start_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        self.values = [None; MAX_PARAMETERS];
+        self.measuring = true;
+        self.trigger_time = board.millis();
+
+        let length = self.special_config.trigger_length();
+        if length > 0
+            && board.ic2_write(self.special_config.address, &self.special_config.trigger[..length]).is_err()
+        {
+            defmt::println!("generic i2c trigger failed at 0x{:x}", self.special_config.address);
+            self.measuring = false;
+        }
+    }
+
+    fn poll_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) -> bool {
+        if !self.measuring {
+            return true;
+        }
+        if board.millis().wrapping_sub(self.trigger_time) < self.special_config.wait as u32 * WAIT_UNIT_MS {
+            return false;
+        }
+        self.measuring = false;
+
+        let config = self.special_config;
+        let mut data = [0u8; MAX_READ_LENGTH];
+        let length = config.read_length();
+        let result = if config.layout & LAYOUT_READ_REGISTER != 0 {
+            board.ic2_write_read(config.address, &[config.register], &mut data[..length])
+        } else {
+            board.ic2_read(config.address, &mut data[..length])
+        };
+        if result.is_err() {
+            defmt::println!("generic i2c read failed at 0x{:x}", config.address);
+            return true;
+        }
+
+        for i in 0..config.parameter_count() {
+            self.values[i] = Some(config.parameter_value(i, &data));
+        }
+        true
+    }
+
+    fn update(&mut self, values: serde_json::Value) -> Result<(), &'static str> {
+        self.special_config.update_from_values(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_half_precision() {
+        assert_eq!(Some(0x3C00), f32_to_f16(1.0));
+        assert_eq!(Some(0xC000), f32_to_f16(-2.0));
+        assert_eq!(Some(0x7BFF), f32_to_f16(65504.0));
+        assert_eq!(None, f32_to_f16(65520.0));
+        assert_eq!(None, f32_to_f16(f32::NAN));
+        assert_eq!(Some(0x0001), f32_to_f16(5.960_464_5e-8)); // smallest subnormal
+
+        for value in [0.0, 1.0, -40.0, 0.1, 0.0025, 175.0, 1e-6] {
+            let half = f32_to_f16(value).unwrap();
+            assert!((f16_to_f32(half) - value).abs() <= value.abs() / 1024.0 + 6e-8);
+        }
+    }
+
+    #[test]
+    fn test_parameter_decoding() {
+        let config = GenericI2CSpecialConfiguration::parse_from_values(serde_json::json!({
+            "address": 0x38,
+            "init": [0x01],
+            "trigger": [0xAC, 0x33],
+            "wait_ms": 75,
+            "register": 0,
+            "parameters": [
+                { "scale": 0.5, "offset": -40 },
+                { "byte_order": "little", "signed": true, "scale": 0.25 },
+                { "start": 4, "shift": 4, "mask": 0xFFF, "signed": true },
+                { "length": 1 },
+            ],
+        }))
+        .unwrap();
+
+        assert_eq!(1, config.init_length());
+        assert_eq!(2, config.trigger_length());
+        assert_eq!(8, config.wait);
+        assert_ne!(0, config.layout & LAYOUT_READ_REGISTER);
+        assert_eq!(4, config.parameter_count());
+        assert_eq!(7, config.read_length());
+
+        let data = [0x01, 0x00, 0xFE, 0xFF, 0x80, 0x10, 200, 0];
+        assert_eq!(88.0, config.parameter_value(0, &data));
+        assert_eq!(-0.5, config.parameter_value(1, &data));
+        assert_eq!(-2047.0, config.parameter_value(2, &data));
+        assert_eq!(200.0, config.parameter_value(3, &data));
+    }
+
+    #[test]
+    fn test_invalid_parameters() {
+        let parse = |parameters: serde_json::Value| {
+            GenericI2CSpecialConfiguration::parse_from_values(serde_json::json!({ "address": 0x38, "parameters": parameters }))
+        };
+        assert!(parse(serde_json::json!([{}])).is_ok());
+        assert!(parse(serde_json::json!([])).is_err());
+        assert!(parse(serde_json::json!([{}, {}, {}, {}, {}])).is_err());
+        assert!(parse(serde_json::json!([{ "start": 6, "length": 4 }])).is_err());
+        assert!(parse(serde_json::json!([{ "mask": 0x5 }])).is_err());
+        assert!(parse(serde_json::json!([{ "scale": 70000 }])).is_err());
+        assert!(GenericI2CSpecialConfiguration::parse_from_values(serde_json::json!({ "address": 0x80, "parameters": [{}] })).is_err());
+    }
+}
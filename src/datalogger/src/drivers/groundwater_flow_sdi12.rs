@@ -108,7 +108,7 @@ impl SensorDriver for GroundwaterFlowSDI12 {
     fn setup(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
         board.set_gpio_pin_mode(5, rriv_board::gpio::GpioMode::PullDownInput);
         let mut sdi12_service = sdi12_service::Sdi12TxProcessor::new(self.special_config.gpio, self.special_config.sensor_address);
-        sdi12_service.setup();
+        sdi12_service.setup(board);
     }
 
     fn get_measured_parameter_count(&mut self) -> usize {
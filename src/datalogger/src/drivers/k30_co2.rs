@@ -5,12 +5,23 @@ use crate::sensor_name_from_type_id;
 use super::types::*;
 use serde_json::json;
 
+const I2C_ADDRESS: u8 = 0x68;
+const READ_RAM_COMMAND: u8 = 0x2;
+const NUMBER_OF_BYTES: u8 = 2;
+const CO2_VALUE_ADDRESS: u8 = 0x08;
+
+const READ_COMPLETE_STATUS: u8 = 0x21;
+//    const READ_INCOMPLETE_STATUS: u8 = 0x20;
+
+const RESPONSE_DELAY_MS: u32 = 40; // 20ms is 'typical' in the datasheet.
+
 pub struct K30CO2 {
     general_config: SensorDriverGeneralConfiguration,
     special_config: K30CO2SpecialConfiguration,
     measured_parameter_values: [f64; 2],
     m: f64,
     b: f64,
+    request_time: Option<u32>, // board millis when the pending read was requested
 }
 
 impl SensorDriver for K30CO2 {
@@ -40,6 +51,14 @@ impl SensorDriver for K30CO2 {
         self.m = self.special_config.m as f64;
         self.b = self.special_config.b as f64;
     }
+
+    // the K30 has no enable input, it is switched on through its power domain before the warmup
+    // and its last reading is stale by the time the warmup ends
+    #[allow(unused)]
+    fn begin_warmup(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        self.request_time = None;
+        self.measured_parameter_values[0] = -1_f64;
+    }
  
     getters!();
 
@@ -66,17 +85,83 @@ impl SensorDriver for K30CO2 {
         identifier
     }
 
+    fn start_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        if self.request_value(board) {
+            self.request_time = Some(board.millis());
+        }
+    }
+
+    fn poll_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) -> bool {
+        if let Some(request_time) = self.request_time {
+            if board.millis().wrapping_sub(request_time) < RESPONSE_DELAY_MS {
+                return false;
+            }
+            self.request_time = None;
+            self.read_value(board);
+        }
+        true
+    }
+
     fn take_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
-       // send the i2c command
-       const I2C_ADDRESS: u8 = 0x68;
-       const READ_RAM_COMMAND: u8 = 0x2;
-       const NUMBER_OF_BYTES: u8 = 2;
-       const CO2_VALUE_ADDRESS: u8 = 0x08;
+        if !self.request_value(board) {
+            return;
+        }
 
-       const READ_COMPLETE_STATUS: u8 = 0x21;
-        //    const READ_INCOMPLETE_STATUS: u8 = 0x20;
-       
+        board.delay_ms(RESPONSE_DELAY_MS as u16);
+        self.read_value(board);
+    }
+
+    fn clear_calibration(&mut self) {
+        self.m = 0_f64;
+        self.b = 0_f64;
+        self.special_config.m = 0_f32;
+        self.special_config.b = 0_f32;
+    }
+
+    fn get_required_calibration_point_count(&self) -> usize {
+        2
+    }
+
+    fn fit(&mut self, pairs: &[CalibrationPair]) -> Result<(), ()> {
+        if pairs.len() != 2 {
+            return Err(());
+        }
 
+        let cal1 = &pairs[0];
+        let cal2 = &pairs[1];
+
+        self.m = (cal2.point - cal1.point) / (cal2.values[0] - cal1.values[0]);
+        self.b = cal1.point - self.m * cal1.values[0];
+        self.special_config.m = self.m as f32;
+        self.special_config.b = self.b as f32;
+
+        Ok(())
+    }
+
+    #[allow(unused_variables)]
+    fn update(&mut self, values: serde_json::Value) -> Result<(),&'static str> {
+        Err("update not implemented")
+    }
+
+}
+
+impl K30CO2 {
+    pub fn new(
+        general_config: SensorDriverGeneralConfiguration,
+        special_config: K30CO2SpecialConfiguration,
+    ) -> Self {
+        K30CO2 {
+            general_config,
+            special_config,
+            measured_parameter_values: [0.0; 2],
+            m: 0_f64,
+            b: 0_f64,
+            request_time: None,
+        }
+    }
+
+    // send the i2c command, returns false if the sensor didn't accept it
+    fn request_value(&mut self, board: &mut dyn rriv_board::RRIVBoard) -> bool {
         let mut command: [u8; 4] = [0; 4];
         command[0] = (READ_RAM_COMMAND << 4) + NUMBER_OF_BYTES;
         command[1] = 0; // MSB of RAM address
@@ -85,21 +170,23 @@ impl SensorDriver for K30CO2 {
         command[3] = checksum.0;
         defmt::println!("command {:X}", command);
 
-        let mut response: [u8; 4] = [0; 4];
         self.measured_parameter_values[0] = -1 as f64;
 
         match board.ic2_write(I2C_ADDRESS, &command) {
-            Ok(_) => defmt::println!("request sent ok"),
+            Ok(_) => {
+                defmt::println!("request sent ok");
+                true
+            }
             Err(err) => {
                 defmt::println!("i2c err: {:?}", err);
                 self.measured_parameter_values[0] = -1 as f64;
-                return;
+                false
             }
-,
         }
+    }
 
-        board.delay_ms(40); // 20ms is 'typical' in the datasheet.
-        
+    fn read_value(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        let mut response: [u8; 4] = [0; 4];
         match board.ic2_read(I2C_ADDRESS, &mut response){
             Ok(_) => {
                 defmt::println!("response {:X}", response);
@@ -131,62 +218,6 @@ impl SensorDriver for K30CO2 {
             },
         }
     }
-
-
-    fn clear_calibration(&mut self) {
-        self.m = 0_f64;
-        self.b = 0_f64;
-        self.special_config.m = 0_f32;
-        self.special_config.b = 0_f32;
-    }
-
-    fn fit(&mut self, pairs: &[CalibrationPair]) -> Result<(), ()> {
-        for i in 0..pairs.len() {
-            let pair = &pairs[i];
-            defmt::println!("calib pair{:?} {} {}", i, pair.point, pair.values[0]);
-        }
-
-        if pairs.len() != 2 {
-            return Err(());
-        }
-
-        let cal1 = &pairs[0];
-        let cal2 = &pairs[1];
-
-        self.m = (cal2.point - cal1.point) / (cal2.values[0] - cal1.values[0]);
-        self.b = cal1.point - self.m * cal1.values[0];
-        defmt::println!("calibration: {} {}", self.m, self.b);
-        self.special_config.m = self.m as f32;
-        self.special_config.b = self.b as f32;
-        defmt::println!(
-            "calibration: {} {}",
-            self.special_config.m,
-            self.special_config.b
-        );
-
-        Ok(())
-    }
-
-    #[allow(unused_variables)]
-    fn update(&mut self, values: serde_json::Value) -> Result<(),&'static str> {
-        Err("update not implemented")
-    }
-
-}
-
-impl K30CO2 {
-    pub fn new(
-        general_config: SensorDriverGeneralConfiguration,
-        special_config: K30CO2SpecialConfiguration,
-    ) -> Self {
-        K30CO2 {
-            general_config,
-            special_config,
-            measured_parameter_values: [0.0; 2],
-            m: 0_f64,
-            b: 0_f64,
-        }
-    }
 }
 
 #[derive(Copy, Clone)]
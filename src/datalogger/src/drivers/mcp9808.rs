@@ -175,7 +175,6 @@ impl SensorDriver for MCP9808TemperatureDriver {
 
     fn fit(&mut self, pairs: &[CalibrationPair]) -> Result<(), ()> {
        // validation
-       defmt::println!("pairs len {:?}", pairs.len());
        if pairs.len() != 1 {
         return Err(());
        }
@@ -165,25 +165,14 @@ impl SensorDriver for MHZ9041ADriver {
     #[allow(unused)]
     fn setup(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
         self.calibration_offset = (self.special_config.calibration_offset as f64) / 1000_f64;
+        self.connect(board);
+    }
 
-        // If no address was specified, try known addresses to find the sensor
-        if self.address == ADDRESS_AUTO_DETECT {
-            for &candidate in &AUTO_DETECT_ADDRESSES {
-                // Attempt to read the vendor ID register as a presence check
-                let reg = [REG_VID_H];
-                if board.ic2_write(candidate, &reg).is_ok() {
-                    let mut buf: [u8; 1] = [0];
-                    if board.ic2_read(candidate, &mut buf).is_ok() {
-                        self.address = candidate;
-                        break;
-                    }
-                }
-            }
-        }
-
-        // Set sensor to passive (polling) mode
-        let set_mode_cmd = [REG_MODE, MODE_PASSIVE];
-        let _ = board.ic2_write(self.address, &set_mode_cmd);
+    // the sensor has no enable input, it is switched on through its power domain before the warmup
+    // it may have been unpowered during setup, so look for it again
+    fn begin_warmup(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        self.measured_parameter_values = [f64::MAX; NUMBER_OF_MEASURED_PARAMETERS];
+        self.connect(board);
     }
 
     getters!();
@@ -322,4 +311,25 @@ impl MHZ9041ADriver {
         }
     }
 
+    fn connect(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        // If no address was specified, try known addresses to find the sensor
+        if self.address == ADDRESS_AUTO_DETECT {
+            for &candidate in &AUTO_DETECT_ADDRESSES {
+                // Attempt to read the vendor ID register as a presence check
+                let reg = [REG_VID_H];
+                if board.ic2_write(candidate, &reg).is_ok() {
+                    let mut buf: [u8; 1] = [0];
+                    if board.ic2_read(candidate, &mut buf).is_ok() {
+                        self.address = candidate;
+                        break;
+                    }
+                }
+            }
+        }
+
+        // Set sensor to passive (polling) mode
+        let set_mode_cmd = [REG_MODE, MODE_PASSIVE];
+        let _ = board.ic2_write(self.address, &set_mode_cmd);
+    }
+
 }
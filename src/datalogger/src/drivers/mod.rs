@@ -12,10 +12,20 @@ pub mod ring_w_mux_sim;
 pub mod timed_switch_2;
 pub mod ds18b20;
 pub mod k30_co2;
-pub mod atlas_ec;
+pub mod atlas;
 pub mod aht20;
 pub mod adc_temperature;
 pub mod resources;
 pub mod groundwater_flow_sdi12;
 pub mod mhz9041a;
-
+pub mod pulse_counter;
+pub mod modbus_rtu;
+pub mod sdi12_sensor;
+pub mod bme280;
+pub mod sht;
+pub mod serial_level;
+pub mod nmea_gps;
+pub mod generic_i2c;
+pub mod thermistor;
+pub mod system_health;
+pub mod scd;
@@ -0,0 +1,534 @@
+use modbus_core::rtu::{client, Header, RequestAdu};
+use modbus_core::{Request, RequestPdu, Response, ResponsePdu};
+use rriv_board::gpio::GpioMode;
+use serde_json::json;
+
+use crate::sensor_name_from_type_id;
+use crate::services::usart_service;
+
+use super::types::*;
+
+// modbus rtu master reading holding or input registers from a slave on the usart, through an rs485 transceiver
+// the usart runs at the board's fixed baud rate
+
+const MAX_REGISTERS: usize = 4; // register readings that fit in the special configuration
+const RESPONSE_TIMEOUT_MS: u32 = 250;
+const MAX_SLAVE_ID: u64 = 247;
+
+// register format bits
+const FORMAT_DATA_TYPE_MASK: u8 = 0x07;
+const FORMAT_WORD_SWAP: u8 = 0x08; // low word first for 32 bit values
+const FORMAT_INPUT_REGISTER: u8 = 0x10; // read input registers instead of holding registers
+
+const DATA_TYPE_U16: u8 = 0;
+const DATA_TYPE_I16: u8 = 1;
+const DATA_TYPE_U32: u8 = 2;
+const DATA_TYPE_I32: u8 = 3;
+const DATA_TYPE_F32: u8 = 4;
+
+fn data_type_from_str(value: &str) -> Option<u8> {
+    match value {
+        "u16" => Some(DATA_TYPE_U16),
+        "i16" => Some(DATA_TYPE_I16),
+        "u32" => Some(DATA_TYPE_U32),
+        "i32" => Some(DATA_TYPE_I32),
+        "f32" => Some(DATA_TYPE_F32),
+        _ => None,
+    }
+}
+
+fn data_type_text(data_type: u8) -> &'static str {
+    match data_type {
+        DATA_TYPE_I16 => "i16",
+        DATA_TYPE_U32 => "u32",
+        DATA_TYPE_I32 => "i32",
+        DATA_TYPE_F32 => "f32",
+        _ => "u16",
+    }
+}
+
+fn register_quantity(format: u8) -> u16 {
+    match format & FORMAT_DATA_TYPE_MASK {
+        DATA_TYPE_U32 | DATA_TYPE_I32 | DATA_TYPE_F32 => 2,
+        _ => 1,
+    }
+}
+
+fn register_value(format: u8, words: [u16; 2]) -> Option<f64> {
+    let (high, low) = if format & FORMAT_WORD_SWAP != 0 {
+        (words[1], words[0])
+    } else {
+        (words[0], words[1])
+    };
+    let bits = ((high as u32) << 16) | low as u32;
+
+    match format & FORMAT_DATA_TYPE_MASK {
+        DATA_TYPE_U16 => Some(words[0] as f64),
+        DATA_TYPE_I16 => Some(words[0] as i16 as f64),
+        DATA_TYPE_U32 => Some(bits as f64),
+        DATA_TYPE_I32 => Some(bits as i32 as f64),
+        DATA_TYPE_F32 => {
+            let value = f32::from_bits(bits);
+            if value.is_finite() {
+                Some(value as f64)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct ModbusRtuSpecialConfiguration {
+    scales: [f32; MAX_REGISTERS],
+    addresses: [u16; MAX_REGISTERS],
+    formats: [u8; MAX_REGISTERS],
+    slave_id: u8,
+    direction_gpio: u8, // drives the transceiver's driver enable while sending, 0 for auto direction transceivers
+    register_count: u8,
+    _empty: [u8; 1],
+}
+
+impl ModbusRtuSpecialConfiguration {
+    pub fn new_from_bytes(
+        bytes: [u8; SENSOR_SETTINGS_PARTITION_SIZE],
+    ) -> ModbusRtuSpecialConfiguration {
+        let settings = bytes.as_ptr().cast::<ModbusRtuSpecialConfiguration>();
+        unsafe { *settings }
+    }
+
+    pub fn parse_from_values(value: serde_json::Value) -> Result<ModbusRtuSpecialConfiguration, &'static str> {
+        if !value["slave_id"].is_number() {
+            return Err("slave_id is required");
+        }
+        if !value["registers"].is_array() {
+            return Err("registers are required");
+        }
+
+        let mut special_config = Self {
+            scales: [1.0; MAX_REGISTERS],
+            addresses: [0; MAX_REGISTERS],
+            formats: [0; MAX_REGISTERS],
+            slave_id: 1,
+            direction_gpio: 0,
+            register_count: 0,
+            _empty: [b'\0'; 1],
+        };
+        special_config.update_from_values(value)?;
+        Ok(special_config)
+    }
+
+    pub fn update_from_values(&mut self, values: serde_json::Value) -> Result<(), &'static str> {
+        match &values["slave_id"] {
+            serde_json::Value::Number(number) => {
+                match number.as_u64() {
+                    Some(number) if (1..=MAX_SLAVE_ID).contains(&number) => self.slave_id = number as u8,
+                    _ => return Err("slave_id must be 1 to 247"),
+                }
+            }
+            serde_json::Value::Null => {}
+            _ => return Err("slave_id must be 1 to 247"),
+        }
+
+        match &values["direction_gpio"] {
+            serde_json::Value::Number(number) => {
+                match number.as_u64() {
+                    Some(number) if number <= 8 => self.direction_gpio = number as u8,
+                    _ => return Err("invalid direction gpio"),
+                }
+            }
+            serde_json::Value::Null => {}
+            _ => return Err("invalid direction gpio"),
+        }
+
+        match &values["registers"] {
+            serde_json::Value::Array(registers) => {
+                if registers.is_empty() || registers.len() > MAX_REGISTERS {
+                    return Err("registers must have 1 to 4 entries");
+                }
+
+                let mut scales = [1.0; MAX_REGISTERS];
+                let mut addresses = [0; MAX_REGISTERS];
+                let mut formats = [0; MAX_REGISTERS];
+                for (i, register) in registers.iter().enumerate() {
+                    addresses[i] = match register["address"].as_u64() {
+                        Some(address) if address <= u16::MAX as u64 => address as u16,
+                        _ => return Err("register address must be 0 to 65535"),
+                    };
+
+                    let mut format = match &register["data_type"] {
+                        serde_json::Value::String(data_type) => match data_type_from_str(data_type.as_str()) {
+                            Some(data_type) => data_type,
+                            None => return Err("data_type must be u16, i16, u32, i32 or f32"),
+                        },
+                        serde_json::Value::Null => DATA_TYPE_U16,
+                        _ => return Err("data_type must be u16, i16, u32, i32 or f32"),
+                    };
+
+                    match &register["function"] {
+                        serde_json::Value::String(function) => match function.as_str() {
+                            "holding" => {}
+                            "input" => format |= FORMAT_INPUT_REGISTER,
+                            _ => return Err("function must be holding or input"),
+                        },
+                        serde_json::Value::Null => {}
+                        _ => return Err("function must be holding or input"),
+                    }
+
+                    match &register["word_order"] {
+                        serde_json::Value::String(word_order) => match word_order.as_str() {
+                            "big" => {}
+                            "little" => format |= FORMAT_WORD_SWAP,
+                            _ => return Err("word_order must be big or little"),
+                        },
+                        serde_json::Value::Null => {}
+                        _ => return Err("word_order must be big or little"),
+                    }
+
+                    match &register["scale"] {
+                        serde_json::Value::Number(scale) => match scale.as_f64() {
+                            Some(scale) => scales[i] = scale as f32,
+                            None => return Err("invalid scale"),
+                        },
+                        serde_json::Value::Null => {}
+                        _ => return Err("invalid scale"),
+                    }
+
+                    formats[i] = format;
+                }
+
+                self.scales = scales;
+                self.addresses = addresses;
+                self.formats = formats;
+                self.register_count = registers.len() as u8;
+            }
+            serde_json::Value::Null => {}
+            _ => return Err("registers must be a list"),
+        }
+
+        Ok(())
+    }
+
+    // read request for a configured register
+    fn request_adu(&self, index: usize) -> RequestAdu<'static> {
+        let format = self.formats[index];
+        let address = self.addresses[index];
+        let quantity = register_quantity(format);
+        let request = if format & FORMAT_INPUT_REGISTER != 0 {
+            Request::ReadInputRegisters(address, quantity)
+        } else {
+            Request::ReadHoldingRegisters(address, quantity)
+        };
+        RequestAdu {
+            hdr: Header { slave: self.slave_id },
+            pdu: RequestPdu(request),
+        }
+    }
+}
+
+pub struct ModbusRtu {
+    general_config: SensorDriverGeneralConfiguration,
+    special_config: ModbusRtuSpecialConfiguration,
+    values: [Option<f64>; MAX_REGISTERS],
+    reading: Option<usize>, // register being read during a measurement
+    bus_owned: bool,        // the usart is captured for our response
+    request_time: u32,
+}
+
+impl ModbusRtu {
+    pub fn new(
+        general_config: SensorDriverGeneralConfiguration,
+        special_config: ModbusRtuSpecialConfiguration,
+    ) -> Self {
+        ModbusRtu {
+            general_config,
+            special_config,
+            values: [None; MAX_REGISTERS],
+            reading: None,
+            bus_owned: false,
+            request_time: 0,
+        }
+    }
+
+    fn register_count(&self) -> usize {
+        let count = self.special_config.register_count as usize;
+        if count > MAX_REGISTERS { MAX_REGISTERS } else { count }
+    }
+
+    // send the read request for a register, returns false if another driver is using the bus
+    fn send_request(&mut self, board: &mut dyn rriv_board::RRIVBoard, index: usize) -> bool {
+        if !usart_service::start_raw_capture(board) {
+            return false;
+        }
+        self.bus_owned = true;
+
+        let mut frame = [0u8; 8];
+        match client::encode_request(self.special_config.request_adu(index), &mut frame) {
+            Ok(length) => {
+                let direction_gpio = self.special_config.direction_gpio;
+                if direction_gpio > 0 {
+                    board.write_gpio_pin(direction_gpio, true);
+                }
+                board.usart_send(&frame[..length]); // returns once the last byte is out
+                if direction_gpio > 0 {
+                    board.write_gpio_pin(direction_gpio, false);
+                }
+            }
+            Err(_) => {
+                defmt::println!("failed to encode modbus request");
+            }
+        }
+        self.request_time = board.millis();
+        true
+    }
+
+    fn release_bus(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        if self.bus_owned {
+            usart_service::stop_raw_capture(board);
+            self.bus_owned = false;
+        }
+    }
+
+    // check for the response to the current request, returns true once the register is done
+    fn receive_response(&mut self, board: &mut dyn rriv_board::RRIVBoard, index: usize) -> bool {
+        let mut buffer = [0u8; 256];
+        let length = usart_service::read_raw_bytes(&mut buffer);
+
+        let adu = match client::decode_response(&buffer[..length]) {
+            Ok(Some(adu)) => adu,
+            Ok(None) => {
+                if board.millis().wrapping_sub(self.request_time) > RESPONSE_TIMEOUT_MS {
+                    defmt::println!("modbus slave {} did not respond", self.special_config.slave_id);
+                    return true;
+                }
+                return false;
+            }
+            Err(_) => {
+                defmt::println!("invalid modbus response");
+                return true;
+            }
+        };
+
+        if adu.hdr.slave != self.special_config.slave_id {
+            // a response from another slave, keep listening
+            usart_service::clear_raw_bytes(board);
+            return false;
+        }
+
+        let data = match adu.pdu {
+            ResponsePdu(Ok(Response::ReadHoldingRegisters(data))) => data,
+            ResponsePdu(Ok(Response::ReadInputRegisters(data))) => data,
+            ResponsePdu(Ok(_)) => {
+                defmt::println!("unexpected modbus response");
+                return true;
+            }
+            ResponsePdu(Err(_)) => {
+                defmt::println!("modbus exception from slave {}", self.special_config.slave_id);
+                return true;
+            }
+        };
+
+        let format = self.special_config.formats[index];
+        if data.len() < register_quantity(format) as usize {
+            return true;
+        }
+        let words = [data.get(0).unwrap_or_default(), data.get(1).unwrap_or_default()];
+        self.values[index] = register_value(format, words)
+            .map(|value| value * self.special_config.scales[index] as f64);
+        true
+    }
+}
+
+impl SensorDriver for ModbusRtu {
+    fn setup(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        if self.special_config.direction_gpio > 0 {
+            board.set_gpio_pin_mode(self.special_config.direction_gpio, GpioMode::PushPullOutput);
+            board.write_gpio_pin(self.special_config.direction_gpio, false); // listen
+        }
+    }
+
+    fn teardown(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        self.release_bus(board);
+        self.reading = None;
+    }
+
+    fn get_requested_gpios(&self) -> super::resources::gpio::GpioRequest {
+        let mut gpio_request = super::resources::gpio::GpioRequest::none();
+        gpio_request.use_usart();
+        gpio_request.use_pin(self.special_config.direction_gpio);
+        gpio_request
+    }
+
+    getters!();
+
+    fn get_measured_parameter_count(&mut self) -> usize {
+        self.register_count()
+    }
+
+    fn get_measured_parameter_value(&mut self, index: usize) -> Result<f64, ()> {
+        if index >= self.register_count() {
+            return Err(());
+        }
+        self.values[index].ok_or(())
+    }
+
+    fn get_measured_parameter_identifier(&mut self, index: usize) -> [u8; 16] {
+        let mut buf = [0u8; 16];
+        if index >= self.register_count() {
+            buf[..7].copy_from_slice(b"invalid");
+            return buf;
+        }
+
+        let prefix = if self.special_config.formats[index] & FORMAT_INPUT_REGISTER != 0 { "ir" } else { "hr" };
+        let mut identifier = [0u8; 16];
+        if let Ok(identifier) = format_no_std::show(
+            &mut identifier,
+            format_args!("{}_{}", prefix, self.special_config.addresses[index]),
+        ) {
+            buf[..identifier.len()].copy_from_slice(identifier.as_bytes());
+        }
+        buf
+    }
+
+    fn take_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        self.start_measurement(board);
+        let attempts = (MAX_REGISTERS as u32 * RESPONSE_TIMEOUT_MS) / 5 + 10;
+        for _ in 0..attempts {
+            if self.poll_measurement(board) {
+                return;
+            }
+            board.delay_ms(5);
+        }
+        self.teardown(board);
+    }
+
+    fn start_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        self.release_bus(board);
+        self.values = [None; MAX_REGISTERS];
+        self.reading = if self.register_count() > 0 { Some(0) } else { None };
+    }
+
+    fn poll_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) -> bool {
+        let index = match self.reading {
+            Some(index) => index,
+            None => return true,
+        };
+
+        if !self.bus_owned {
+            // wait for any other modbus sensor to finish with the bus
+            self.send_request(board, index);
+            return false;
+        }
+
+        if !self.receive_response(board, index) {
+            return false;
+        }
+
+        self.release_bus(board);
+        if index + 1 < self.register_count() {
+            self.reading = Some(index + 1);
+            false
+        } else {
+            self.reading = None;
+            true
+        }
+    }
+
+    fn get_configuration_json(&mut self) -> serde_json::Value {
+        let mut sensor_id = self.get_id();
+        let sensor_id = util::str_from_utf8(&mut sensor_id).unwrap_or("Invalid");
+
+        let mut sensor_name = sensor_name_from_type_id(self.get_type_id().into());
+        let sensor_name = util::str_from_utf8(&mut sensor_name).unwrap_or("Invalid");
+
+        let mut registers = serde_json::Value::Array(alloc::vec::Vec::new());
+        if let serde_json::Value::Array(registers) = &mut registers {
+            for i in 0..self.register_count() {
+                let format = self.special_config.formats[i];
+                registers.push(json!({
+                    "address": self.special_config.addresses[i],
+                    "function": if format & FORMAT_INPUT_REGISTER != 0 { "input" } else { "holding" },
+                    "data_type": data_type_text(format & FORMAT_DATA_TYPE_MASK),
+                    "word_order": if format & FORMAT_WORD_SWAP != 0 { "little" } else { "big" },
+                    "scale": self.special_config.scales[i],
+                }));
+            }
+        }
+
+        json!({
+            "id": sensor_id,
+            "type": sensor_name,
+            "slave_id": self.special_config.slave_id,
+            "direction_gpio": self.special_config.direction_gpio,
+            "registers": registers,
+        })
+    }
+
+    fn update(&mut self, values: serde_json::Value) -> Result<(), &'static str> {
+        self.special_config.update_from_values(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_word_order() {
+        let words = [0x1234, 0x5678];
+        assert_eq!(Some(0x12345678 as f64), register_value(DATA_TYPE_U32, words));
+        assert_eq!(Some(0x56781234 as f64), register_value(DATA_TYPE_U32 | FORMAT_WORD_SWAP, words));
+        assert_eq!(Some(0x1234 as f64), register_value(DATA_TYPE_U16 | FORMAT_WORD_SWAP, words)); // single registers ignore word order
+    }
+
+    #[test]
+    fn test_signed_and_float_decoding() {
+        assert_eq!(Some(-2.0), register_value(DATA_TYPE_I16, [0xFFFE, 0]));
+        assert_eq!(Some(65534.0), register_value(DATA_TYPE_U16, [0xFFFE, 0]));
+        assert_eq!(Some(-100000.0), register_value(DATA_TYPE_I32, [0xFFFE, 0x7960]));
+        assert_eq!(Some(-100000.0), register_value(DATA_TYPE_I32 | FORMAT_WORD_SWAP, [0x7960, 0xFFFE]));
+
+        assert_eq!(Some(1.5), register_value(DATA_TYPE_F32, [0x3FC0, 0x0000]));
+        assert_eq!(Some(-20.25), register_value(DATA_TYPE_F32 | FORMAT_WORD_SWAP, [0x0000, 0xC1A2]));
+        assert_eq!(None, register_value(DATA_TYPE_F32, [0x7FC0, 0x0000])); // nan
+        assert_eq!(None, register_value(DATA_TYPE_F32, [0x7F80, 0x0000])); // infinity
+
+        assert_eq!(1, register_quantity(DATA_TYPE_I16 | FORMAT_INPUT_REGISTER));
+        assert_eq!(2, register_quantity(DATA_TYPE_F32 | FORMAT_WORD_SWAP));
+    }
+
+    #[test]
+    fn test_request_and_response_framing() {
+        let config = ModbusRtuSpecialConfiguration::parse_from_values(serde_json::json!({
+            "slave_id": 1,
+            "registers": [
+                { "address": 0, "data_type": "u32" },
+                { "address": 0x0102, "function": "input" },
+            ],
+        }))
+        .unwrap();
+
+        let mut frame = [0u8; 8];
+        let length = client::encode_request(config.request_adu(0), &mut frame).unwrap();
+        assert_eq!([0x01, 0x03, 0x00, 0x00, 0x00, 0x02, 0xC4, 0x0B], frame[..length]);
+
+        let length = client::encode_request(config.request_adu(1), &mut frame).unwrap();
+        assert_eq!([0x01, 0x04, 0x01, 0x02, 0x00, 0x01], frame[..length - 2]);
+
+        let response = [0x01, 0x03, 0x04, 0x12, 0x34, 0x56, 0x78, 0x81, 0x07];
+        let adu = client::decode_response(&response).unwrap().unwrap();
+        assert_eq!(1, adu.hdr.slave);
+        let data = match adu.pdu {
+            ResponsePdu(Ok(Response::ReadHoldingRegisters(data))) => data,
+            _ => panic!("expected a holding register response"),
+        };
+        let words = [data.get(0).unwrap_or_default(), data.get(1).unwrap_or_default()];
+        assert_eq!(Some(0x12345678 as f64), register_value(config.formats[0], words));
+
+        assert_eq!(None, client::decode_response(&response[..6]).unwrap()); // still receiving
+        let mut corrupted = response;
+        corrupted[4] ^= 0x01;
+        assert!(matches!(client::decode_response(&corrupted), Ok(None))); // bad crc, skipped until the timeout
+    }
+}
@@ -0,0 +1,504 @@
+use serde_json::json;
+
+use crate::sensor_name_from_type_id;
+use crate::services::usart_service;
+
+use super::types::*;
+
+// GPS receiver on the usart, reading position from NMEA RMC and GGA sentences from any talker (GP, GN, GL, ...)
+// a measurement listens until both sentences report a fix, or until fix_timeout runs out
+// with set_clock the board clock is set from the RMC time when it has drifted more than clock_threshold seconds
+// the usart is switched to the receiver's baud_rate while the receiver holds it, most receivers default to 9600
+
+const MAX_SENTENCE_LENGTH: usize = 82; // from $ to the checksum, as in the NMEA 0183 standard
+const CAPTURE_LIMIT: usize = 160; // clear the capture before it fills the usart raw buffer
+const DEFAULT_FIX_TIMEOUT: u8 = 10; // s
+const DEFAULT_CLOCK_THRESHOLD: u16 = 2; // s
+
+fn hex_digit(byte: u8) -> Option<u8> {
+    match byte {
+        b'0'..=b'9' => Some(byte - b'0'),
+        b'A'..=b'F' => Some(byte - b'A' + 10),
+        b'a'..=b'f' => Some(byte - b'a' + 10),
+        _ => None,
+    }
+}
+
+// the sentence between $ and *, if the checksum matches
+fn checked_sentence(sentence: &[u8]) -> Option<&str> {
+    let star = sentence.iter().position(|byte| *byte == b'*')?;
+    if sentence.len() < star + 3 {
+        return None;
+    }
+    let checksum = (hex_digit(sentence[star + 1])? << 4) | hex_digit(sentence[star + 2])?;
+    let body = &sentence[..star];
+    if body.iter().fold(0u8, |checksum, byte| checksum ^ byte) != checksum {
+        return None;
+    }
+    core::str::from_utf8(body).ok()
+}
+
+// ddmm.mmmm or dddmm.mmmm and a hemisphere, to signed decimal degrees
+fn parse_coordinate(value: &str, hemisphere: &str) -> Option<f64> {
+    let value = value.parse::<f64>().ok()?;
+    let degrees = (value / 100.0) as i32 as f64;
+    let degrees = degrees + (value - degrees * 100.0) / 60.0;
+    match hemisphere {
+        "N" | "E" => Some(degrees),
+        "S" | "W" => Some(-degrees),
+        _ => None,
+    }
+}
+
+fn parse_digits(value: &str) -> Option<i64> {
+    if value.is_empty() || !value.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    value.parse::<i64>().ok()
+}
+
+// hhmmss.ss and ddmmyy to seconds since the unix epoch, fractions of a second are dropped
+fn parse_epoch(time: &str, date: &str) -> Option<i64> {
+    if time.len() < 6 || date.len() != 6 {
+        return None;
+    }
+    let hours = parse_digits(time.get(0..2)?)?;
+    let minutes = parse_digits(time.get(2..4)?)?;
+    let seconds = parse_digits(time.get(4..6)?)?;
+    let day = parse_digits(date.get(0..2)?)?;
+    let month = parse_digits(date.get(2..4)?)?;
+    let year = parse_digits(date.get(4..6)?)? + 2000;
+    if hours > 23 || minutes > 59 || seconds > 60 || !(1..=31).contains(&day) || !(1..=12).contains(&month) {
+        return None;
+    }
+
+    // days from 1970-01-01 for a proleptic gregorian date
+    let (year, month) = if month <= 2 { (year - 1, month + 9) } else { (year, month - 3) };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+
+    Some(days * 86400 + hours * 3600 + minutes * 60 + seconds)
+}
+
+#[derive(Copy, Clone)]
+pub struct NmeaGpsSpecialConfiguration {
+    clock_threshold: u16, // s
+    fix_timeout: u8,      // s
+    set_clock: bool,
+    baud_rate: u8, // index into the usart baud rates
+}
+
+impl NmeaGpsSpecialConfiguration {
+    pub fn new_from_bytes(
+        bytes: [u8; SENSOR_SETTINGS_PARTITION_SIZE],
+    ) -> NmeaGpsSpecialConfiguration {
+        let settings = bytes.as_ptr().cast::<NmeaGpsSpecialConfiguration>();
+        unsafe { *settings }
+    }
+
+    pub fn parse_from_values(value: serde_json::Value) -> Result<NmeaGpsSpecialConfiguration, &'static str> {
+        let mut special_config = Self {
+            clock_threshold: DEFAULT_CLOCK_THRESHOLD,
+            fix_timeout: DEFAULT_FIX_TIMEOUT,
+            set_clock: false,
+            baud_rate: usart_service::BAUD_RATE_9600,
+        };
+        special_config.update_from_values(value)?;
+        Ok(special_config)
+    }
+
+    pub fn update_from_values(&mut self, values: serde_json::Value) -> Result<(), &'static str> {
+        match &values["set_clock"] {
+            serde_json::Value::Bool(set_clock) => self.set_clock = *set_clock,
+            serde_json::Value::Null => {}
+            _ => return Err("set_clock must be true or false"),
+        }
+
+        match &values["clock_threshold"] {
+            serde_json::Value::Number(number) => match number.as_u64() {
+                Some(number) if number >= 1 && number <= u16::MAX as u64 => self.clock_threshold = number as u16,
+                _ => return Err("clock_threshold must be 1 to 65535 seconds"),
+            },
+            serde_json::Value::Null => {}
+            _ => return Err("clock_threshold must be 1 to 65535 seconds"),
+        }
+
+        match &values["fix_timeout"] {
+            serde_json::Value::Number(number) => match number.as_u64() {
+                Some(number) if (1..=120).contains(&number) => self.fix_timeout = number as u8,
+                _ => return Err("fix_timeout must be 1 to 120 seconds"),
+            },
+            serde_json::Value::Null => {}
+            _ => return Err("fix_timeout must be 1 to 120 seconds"),
+        }
+
+        match &values["baud_rate"] {
+            serde_json::Value::Number(number) => match number.as_u64().and_then(usart_service::baud_rate_index) {
+                Some(baud_rate) => self.baud_rate = baud_rate,
+                None => return Err("baud_rate must be 9600, 19200, 38400, 57600 or 115200"),
+            },
+            serde_json::Value::Null => {}
+            _ => return Err("baud_rate must be 9600, 19200, 38400, 57600 or 115200"),
+        }
+
+        Ok(())
+    }
+}
+
+pub struct NmeaGps {
+    general_config: SensorDriverGeneralConfiguration,
+    special_config: NmeaGpsSpecialConfiguration,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    altitude: Option<f64>,
+    fix_quality: Option<u8>,
+    satellites: Option<u8>,
+    rmc_fix: bool, // a valid RMC has been received this measurement
+    gga_fix: bool, // a GGA with a fix has been received this measurement
+    measuring: bool,
+    bus_owned: bool, // the usart is captured for our sentences
+    measurement_start: u32,
+    parsed: usize,     // captured bytes already parsed
+    in_sentence: bool, // a $ has been seen, so the sentence buffer holds the sentence so far
+    sentence: [u8; MAX_SENTENCE_LENGTH],
+    sentence_length: usize,
+}
+
+impl NmeaGps {
+    pub fn new(
+        general_config: SensorDriverGeneralConfiguration,
+        special_config: NmeaGpsSpecialConfiguration,
+    ) -> Self {
+        NmeaGps {
+            general_config,
+            special_config,
+            latitude: None,
+            longitude: None,
+            altitude: None,
+            fix_quality: None,
+            satellites: None,
+            rmc_fix: false,
+            gga_fix: false,
+            measuring: false,
+            bus_owned: false,
+            measurement_start: 0,
+            parsed: 0,
+            in_sentence: false,
+            sentence: [0u8; MAX_SENTENCE_LENGTH],
+            sentence_length: 0,
+        }
+    }
+
+    fn release_bus(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        if self.bus_owned {
+            usart_service::stop_raw_capture(board);
+            board.set_usart_baud_rate(rriv_board::USART_BAUD_RATE);
+            self.bus_owned = false;
+        }
+    }
+
+    fn receive_sentences(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        let mut buffer = [0u8; 256];
+        let length = usart_service::read_raw_bytes(&mut buffer);
+
+        for &byte in &buffer[self.parsed..length] {
+            match byte {
+                b'$' => {
+                    self.in_sentence = true;
+                    self.sentence_length = 0;
+                }
+                b'\r' | b'\n' => {
+                    if self.in_sentence {
+                        let sentence = self.sentence;
+                        if let Some(sentence) = checked_sentence(&sentence[..self.sentence_length]) {
+                            self.process_sentence(board, sentence);
+                        }
+                    }
+                    self.in_sentence = false;
+                }
+                _ if self.in_sentence => {
+                    if self.sentence_length < MAX_SENTENCE_LENGTH {
+                        self.sentence[self.sentence_length] = byte;
+                        self.sentence_length += 1;
+                    } else {
+                        self.in_sentence = false;
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.parsed = length;
+
+        if length >= CAPTURE_LIMIT {
+            // bytes arriving while clearing can be lost, the checksum drops the damaged sentence
+            usart_service::clear_raw_bytes(board);
+            self.parsed = 0;
+        }
+    }
+
+    fn process_sentence(&mut self, board: &mut dyn rriv_board::RRIVBoard, sentence: &str) {
+        let mut fields = [""; 15];
+        for (i, value) in sentence.split(',').take(fields.len()).enumerate() {
+            fields[i] = value;
+        }
+
+        // the talker is the first two characters, GP, GN, GL, GA or BD
+        match fields[0].get(2..) {
+            Some("RMC") => self.process_rmc(board, &fields),
+            Some("GGA") => self.process_gga(&fields),
+            _ => {}
+        }
+    }
+
+    // $GxRMC,time,status,lat,N/S,lon,E/W,speed,course,date,...
+    fn process_rmc(&mut self, board: &mut dyn rriv_board::RRIVBoard, fields: &[&str; 15]) {
+        if fields[2] != "A" {
+            return;
+        }
+        let latitude = parse_coordinate(fields[3], fields[4]);
+        let longitude = parse_coordinate(fields[5], fields[6]);
+        if latitude.is_none() || longitude.is_none() {
+            return;
+        }
+        self.latitude = latitude;
+        self.longitude = longitude;
+
+        if !self.rmc_fix && self.special_config.set_clock {
+            if let Some(epoch) = parse_epoch(fields[1], fields[9]) {
+                let drift = board.epoch_timestamp() - epoch;
+                if drift.abs() > self.special_config.clock_threshold as i64 {
+                    defmt::println!("setting the clock from gps, drift {} s", drift);
+                    board.set_epoch(epoch);
+                }
+            }
+        }
+        self.rmc_fix = true;
+    }
+
+    // $GxGGA,time,lat,N/S,lon,E/W,quality,satellites,hdop,altitude,M,...
+    fn process_gga(&mut self, fields: &[&str; 15]) {
+        let fix_quality = fields[6].parse::<u8>().unwrap_or(0);
+        self.fix_quality = Some(fix_quality);
+        self.satellites = fields[7].parse::<u8>().ok().or(Some(0));
+        if fix_quality == 0 {
+            return;
+        }
+
+        if let (Some(latitude), Some(longitude)) = (parse_coordinate(fields[2], fields[3]), parse_coordinate(fields[4], fields[5])) {
+            self.latitude = Some(latitude);
+            self.longitude = Some(longitude);
+        }
+        self.altitude = fields[9].parse::<f64>().ok();
+        self.gga_fix = true;
+    }
+
+    fn finish_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        self.release_bus(board);
+        self.measuring = false;
+        if !self.rmc_fix && !self.gga_fix {
+            // position from sentences without a fix is not reported
+            self.latitude = None;
+            self.longitude = None;
+            self.altitude = None;
+        }
+    }
+}
+
+impl SensorDriver for NmeaGps {
+    getters!();
+
+    fn get_configuration_json(&mut self) -> serde_json::Value {
+        let mut sensor_id = self.get_id();
+        let sensor_id = util::str_from_utf8(&mut sensor_id).unwrap_or("Invalid");
+
+        let mut sensor_name = sensor_name_from_type_id(self.get_type_id().into());
+        let sensor_name = util::str_from_utf8(&mut sensor_name).unwrap_or("Invalid");
+
+        json!({
+            "id": sensor_id,
+            "type": sensor_name,
+            "set_clock": self.special_config.set_clock,
+            "clock_threshold": self.special_config.clock_threshold,
+            "fix_timeout": self.special_config.fix_timeout,
+            "baud_rate": usart_service::baud_rate(self.special_config.baud_rate),
+        })
+    }
+
+    fn setup(&mut self, _board: &mut dyn rriv_board::RRIVBoard) {}
+
+    fn teardown(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        self.release_bus(board);
+        self.measuring = false;
+    }
+
+    fn get_requested_gpios(&self) -> super::resources::gpio::GpioRequest {
+        let mut gpio_request = super::resources::gpio::GpioRequest::none();
+        gpio_request.use_usart();
+        gpio_request
+    }
+
+    fn get_measured_parameter_count(&mut self) -> usize {
+        5
+    }
+
+    fn get_measured_parameter_value(&mut self, index: usize) -> Result<f64, ()> {
+        match index {
+            0 => self.latitude.ok_or(()),
+            1 => self.longitude.ok_or(()),
+            2 => self.altitude.ok_or(()),
+            3 => self.fix_quality.map(|quality| quality as f64).ok_or(()),
+            4 => self.satellites.map(|satellites| satellites as f64).ok_or(()),
+            _ => Err(()),
+        }
+    }
+
+    fn get_measured_parameter_identifier(&mut self, index: usize) -> [u8; 16] {
+        let identifier = match index {
+            0 => "latitude",
+            1 => "longitude",
+            2 => "altitude_m",
+            3 => "fix_quality",
+            4 => "satellites",
+            _ => "invalid",
+        };
+        let mut buf = [0u8; 16];
+        buf[..identifier.len()].copy_from_slice(identifier.as_bytes());
+        buf
+    }
+
+    fn get_measurement_timeout(&self) -> Option<i64> {
+        Some(self.special_config.fix_timeout as i64 + 2)
+    }
+
+    fn take_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        self.start_measurement(board);
+        let attempts = (self.special_config.fix_timeout as u32 + 1) * 100;
+        for _ in 0..attempts {
+            if self.poll_measurement(board) {
+                return;
+            }
+            board.run_loop_iteration(); // feeds the watchdog while waiting for a fix
+            board.delay_ms(10);
+        }
+        self.teardown(board);
+    }
+
+    fn start_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        self.release_bus(board);
+        self.latitude = None;
+        self.longitude = None;
+        self.altitude = None;
+        self.fix_quality = None;
+        self.satellites = None;
+        self.rmc_fix = false;
+        self.gga_fix = false;
+        self.measuring = true;
+        self.measurement_start = board.millis();
+    }
+
+    fn poll_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) -> bool {
+        if !self.measuring {
+            return true;
+        }
+
+        if !self.bus_owned {
+            // wait for any other usart user to finish with the bus
+            if usart_service::start_raw_capture(board) {
+                board.set_usart_baud_rate(usart_service::baud_rate(self.special_config.baud_rate));
+                self.bus_owned = true;
+                self.parsed = 0;
+                self.in_sentence = false;
+            }
+        } else {
+            self.receive_sentences(board);
+        }
+
+        if self.rmc_fix && self.gga_fix {
+            self.finish_measurement(board);
+            return true;
+        }
+
+        if board.millis().wrapping_sub(self.measurement_start) > self.special_config.fix_timeout as u32 * 1000 {
+            defmt::println!("no gps fix");
+            self.finish_measurement(board);
+            return true;
+        }
+
+        false
+    }
+
+    fn update(&mut self, values: serde_json::Value) -> Result<(), &'static str> {
+        self.special_config.update_from_values(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RMC: &[u8] = b"GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,191026,003.1,W";
+
+    fn with_checksum(body: &[u8], checksum: &[u8]) -> ([u8; MAX_SENTENCE_LENGTH], usize) {
+        let mut sentence = [0u8; MAX_SENTENCE_LENGTH];
+        sentence[..body.len()].copy_from_slice(body);
+        sentence[body.len()] = b'*';
+        sentence[body.len() + 1..body.len() + 3].copy_from_slice(checksum);
+        (sentence, body.len() + 3)
+    }
+
+    #[test]
+    fn test_checked_sentence() {
+        let (sentence, length) = with_checksum(RMC, b"68");
+        assert_eq!(Some(core::str::from_utf8(RMC).unwrap()), checked_sentence(&sentence[..length]));
+
+        assert_eq!(Some(0xa), hex_digit(b'a'));
+        assert_eq!(None, hex_digit(b'g'));
+
+        let (sentence, length) = with_checksum(RMC, b"69");
+        assert_eq!(None, checked_sentence(&sentence[..length]));
+
+        // missing or truncated checksum
+        assert_eq!(None, checked_sentence(RMC));
+        assert_eq!(None, checked_sentence(&sentence[..length - 1]));
+        assert_eq!(None, checked_sentence(b"GPGGA*G1"));
+    }
+
+    #[test]
+    fn test_parse_coordinate() {
+        assert!((parse_coordinate("4807.038", "N").unwrap() - 48.1173).abs() < 1e-9);
+        assert!((parse_coordinate("01131.000", "W").unwrap() + 11.516666666).abs() < 1e-6);
+        assert!((parse_coordinate("3350.5", "S").unwrap() + 33.841666666).abs() < 1e-6);
+        assert_eq!(None, parse_coordinate("4807.038", ""));
+        assert_eq!(None, parse_coordinate("", "N"));
+    }
+
+    #[test]
+    fn test_parse_epoch() {
+        assert_eq!(Some(1792413319), parse_epoch("123519", "191026"));
+        assert_eq!(Some(1792413319), parse_epoch("123519.75", "191026"));
+        assert_eq!(Some(946684800), parse_epoch("000000", "010100"));
+        assert_eq!(Some(951782400), parse_epoch("000000", "290200")); // leap day
+        assert_eq!(None, parse_epoch("", "191026"));
+        assert_eq!(None, parse_epoch("123519", ""));
+        assert_eq!(None, parse_epoch("246000", "191026"));
+        assert_eq!(None, parse_epoch("123519", "191326"));
+        assert_eq!(None, parse_epoch("12+519", "191026"));
+    }
+
+    #[test]
+    fn test_baud_rate() {
+        let special_config = NmeaGpsSpecialConfiguration::parse_from_values(json!({})).unwrap();
+        assert_eq!(9600, usart_service::baud_rate(special_config.baud_rate));
+        let special_config = NmeaGpsSpecialConfiguration::parse_from_values(json!({ "baud_rate": 38400 })).unwrap();
+        assert_eq!(38400, usart_service::baud_rate(special_config.baud_rate));
+        assert!(NmeaGpsSpecialConfiguration::parse_from_values(json!({ "baud_rate": 4800 })).is_err());
+
+        // configurations stored before the setting existed have a zero byte there
+        let bytes = [0u8; SENSOR_SETTINGS_PARTITION_SIZE];
+        let special_config = NmeaGpsSpecialConfiguration::new_from_bytes(bytes);
+        assert_eq!(rriv_board::USART_BAUD_RATE, usart_service::baud_rate(special_config.baud_rate));
+    }
+}
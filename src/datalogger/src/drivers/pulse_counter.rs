@@ -0,0 +1,333 @@
+use core::cell::Cell;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use rriv_board::gpio::GpioMode;
+use serde_json::json;
+
+use crate::sensor_name_from_type_id;
+use crate::services::trigger_service;
+
+use super::types::*;
+
+// counts edges on a dynamic gpio, for tipping bucket rain gauges and pulse output flow meters
+// edges are counted in the gpio interrupt so counting continues while the logger sleeps
+// total counts from when the sensor was set, it is kept in the board's backup registers so it survives restarts,
+// but not a loss of all power, and restarts when the sensor is set again or removed
+
+const DEFAULT_DEBOUNCE_MS: u16 = 20; // reed switches in tipping buckets bounce for a few ms
+const MAX_DEBOUNCE_MS: u16 = 1000;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO_COUNT: AtomicU32 = AtomicU32::new(0);
+// pulses counted since the last reading, indexed by pin - 1
+static PULSE_COUNTS: [AtomicU32; rriv_board::GPIO_INTERRUPT_PINS] = [ZERO_COUNT; rriv_board::GPIO_INTERRUPT_PINS];
+
+fn rate_period_from_str(value: &str) -> Option<u8> {
+    match value {
+        "second" => Some(0),
+        "minute" => Some(1),
+        "hour" => Some(2),
+        _ => None,
+    }
+}
+
+fn rate_period_text(rate_period: u8) -> &'static str {
+    match rate_period {
+        0 => "second",
+        1 => "minute",
+        _ => "hour",
+    }
+}
+
+fn rate_period_seconds(rate_period: u8) -> f64 {
+    match rate_period {
+        0 => 1.0,
+        1 => 60.0,
+        _ => 3600.0,
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct PulseCounterSpecialConfiguration {
+    multiplier: f32, // units per pulse, e.g. 0.2 mm per tip or liters per pulse
+    debounce_ms: u16,
+    gpio_pin: u8,
+    edge: u8,        // rising, falling or both as stored by the trigger service
+    rate_period: u8, // rate is reported per second, minute or hour
+    _empty: [u8; 23],
+}
+
+impl PulseCounterSpecialConfiguration {
+    pub fn new_from_bytes(
+        bytes: [u8; SENSOR_SETTINGS_PARTITION_SIZE],
+    ) -> PulseCounterSpecialConfiguration {
+        let settings = bytes.as_ptr().cast::<PulseCounterSpecialConfiguration>();
+        unsafe { *settings }
+    }
+
+    pub fn parse_from_values(value: serde_json::Value) -> Result<PulseCounterSpecialConfiguration, &'static str> {
+        if !value["gpio_pin"].is_number() {
+            return Err("gpio pin is required");
+        }
+
+        let mut special_config = Self {
+            multiplier: 1.0,
+            debounce_ms: DEFAULT_DEBOUNCE_MS,
+            gpio_pin: 0,
+            edge: 1, // switches pull the pin low
+            rate_period: 2,
+            _empty: [b'\0'; 23],
+        };
+        special_config.update_from_values(value)?;
+        Ok(special_config)
+    }
+
+    pub fn update_from_values(&mut self, values: serde_json::Value) -> Result<(), &'static str> {
+        match &values["gpio_pin"] {
+            serde_json::Value::Number(number) => {
+                // only some of the gpios have an interrupt line of their own
+                match number.as_u64() {
+                    Some(number) if rriv_board::GPIO_INTERRUPT_CAPABLE_PINS.iter().any(|pin| *pin as u64 == number) => {
+                        self.gpio_pin = number as u8
+                    }
+                    _ => return Err("gpio_pin must be 2, 5, 6, 7 or 8"),
+                }
+            }
+            serde_json::Value::Null => {}
+            _ => return Err("gpio_pin must be 2, 5, 6, 7 or 8"),
+        }
+
+        match &values["edge"] {
+            serde_json::Value::String(edge) => {
+                match trigger_service::edge_from_str(edge.as_str()) {
+                    Some(edge) => self.edge = edge,
+                    None => return Err("edge must be rising, falling or both"),
+                }
+            }
+            serde_json::Value::Null => {}
+            _ => return Err("edge must be rising, falling or both"),
+        }
+
+        match &values["debounce_ms"] {
+            serde_json::Value::Number(number) => {
+                match number.as_u64() {
+                    Some(number) if number <= MAX_DEBOUNCE_MS as u64 => self.debounce_ms = number as u16,
+                    _ => return Err("debounce_ms must be 0 to 1000"),
+                }
+            }
+            serde_json::Value::Null => {}
+            _ => return Err("debounce_ms must be 0 to 1000"),
+        }
+
+        match &values["multiplier"] {
+            serde_json::Value::Number(number) => {
+                match number.as_f64() {
+                    Some(number) if number > 0.0 => self.multiplier = number as f32,
+                    _ => return Err("multiplier must be greater than 0"),
+                }
+            }
+            serde_json::Value::Null => {}
+            _ => return Err("multiplier must be greater than 0"),
+        }
+
+        match &values["rate_period"] {
+            serde_json::Value::String(rate_period) => {
+                match rate_period_from_str(rate_period.as_str()) {
+                    Some(rate_period) => self.rate_period = rate_period,
+                    None => return Err("rate_period must be second, minute or hour"),
+                }
+            }
+            serde_json::Value::Null => {}
+            _ => return Err("rate_period must be second, minute or hour"),
+        }
+
+        Ok(())
+    }
+}
+
+pub struct PulseCounter {
+    general_config: SensorDriverGeneralConfiguration,
+    special_config: PulseCounterSpecialConfiguration,
+    interrupt_enabled: bool,
+    last_reading_time: i64,
+    count: u32,       // pulses since the previous reading
+    total_count: u64, // pulses since the sensor was set, kept in backup registers
+    rate: Option<f64>,
+}
+
+impl PulseCounter {
+    pub fn new(
+        general_config: SensorDriverGeneralConfiguration,
+        special_config: PulseCounterSpecialConfiguration,
+    ) -> Self {
+        PulseCounter {
+            general_config,
+            special_config,
+            interrupt_enabled: false,
+            last_reading_time: 0,
+            count: 0,
+            total_count: 0,
+            rate: None,
+        }
+    }
+
+    fn pin_index(&self) -> Option<usize> {
+        let pin = self.special_config.gpio_pin as usize;
+        if (1..=rriv_board::GPIO_INTERRUPT_PINS).contains(&pin) {
+            Some(pin - 1)
+        } else {
+            None
+        }
+    }
+
+    // the total takes two backup values per pin
+    fn retrieve_total(&self, board: &mut dyn rriv_board::RRIVBoard, index: usize) -> u64 {
+        let high = board.retrieve_backup_value(index * 2) as u64;
+        let low = board.retrieve_backup_value(index * 2 + 1) as u64;
+        high << 32 | low
+    }
+
+    fn store_total(&self, board: &mut dyn rriv_board::RRIVBoard, index: usize, total: u64) {
+        board.store_backup_value(index * 2, (total >> 32) as u32);
+        board.store_backup_value(index * 2 + 1, total as u32);
+    }
+}
+
+impl SensorDriver for PulseCounter {
+    fn setup(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        let index = match self.pin_index() {
+            Some(index) => index,
+            None => return,
+        };
+        let pin = self.special_config.gpio_pin;
+
+        // tipping bucket and flow meter reed switches pull the pin low
+        board.set_gpio_pin_mode(pin, GpioMode::PullUpInput);
+
+        // compared in cpu cycles, which wrap at u32::MAX so wrapping_sub stays correct across the wrap,
+        // an edge more than a wrap (89 s at 48 MHz) after the last one is only dropped if it lands within the debounce of a wrap
+        let debounce_cycles = self.special_config.debounce_ms as u32 * 1000 * board.get_cycles_per_microsecond();
+        let last_edge: Cell<Option<u32>> = Cell::new(None);
+        rriv_board::configure_gpio_interrupt_function(pin, move |cycles, _state| {
+            if let Some(last_edge_cycles) = last_edge.get() {
+                if cycles.wrapping_sub(last_edge_cycles) < debounce_cycles {
+                    return;
+                }
+            }
+            last_edge.set(Some(cycles));
+            PULSE_COUNTS[index].fetch_add(1, Ordering::Relaxed);
+        });
+
+        PULSE_COUNTS[index].store(0, Ordering::Relaxed);
+        // counting lines keep counting through sleep without waking the logger up
+        match board.enable_gpio_interrupt(pin, trigger_service::interrupt_edge(self.special_config.edge), false) {
+            Ok(_) => self.interrupt_enabled = true,
+            Err(_) => {
+                rriv_board::remove_gpio_interrupt_function(pin);
+                self.interrupt_enabled = false;
+                defmt::println!("pulse counter interrupts not supported on gpio{}", pin);
+            }
+        }
+
+        self.last_reading_time = board.timestamp();
+        self.count = 0;
+        self.total_count = self.retrieve_total(board, index);
+        self.rate = None;
+    }
+
+    fn teardown(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        if self.interrupt_enabled {
+            board.disable_gpio_interrupt(self.special_config.gpio_pin);
+            rriv_board::remove_gpio_interrupt_function(self.special_config.gpio_pin);
+            self.interrupt_enabled = false;
+        }
+        if let Some(index) = self.pin_index() {
+            self.store_total(board, index, 0);
+        }
+    }
+
+    fn get_requested_gpios(&self) -> super::resources::gpio::GpioRequest {
+        let mut gpio_request = super::resources::gpio::GpioRequest::none();
+        gpio_request.use_pin(self.special_config.gpio_pin);
+        gpio_request
+    }
+
+    // pulses keep arriving while the logger sleeps
+    fn needs_power_while_sleeping(&self) -> bool {
+        true
+    }
+
+    getters!();
+
+    fn get_measured_parameter_count(&mut self) -> usize {
+        3
+    }
+
+    fn get_measured_parameter_value(&mut self, index: usize) -> Result<f64, ()> {
+        if !self.interrupt_enabled {
+            return Err(());
+        }
+        let multiplier = self.special_config.multiplier as f64;
+        match index {
+            0 => Ok(self.count as f64 * multiplier),
+            1 => Ok(self.total_count as f64 * multiplier),
+            2 => self.rate.ok_or(()),
+            _ => Err(()),
+        }
+    }
+
+    fn get_measured_parameter_identifier(&mut self, index: usize) -> [u8; 16] {
+        let identifier = match index {
+            0 => "count",
+            1 => "total",
+            2 => "rate",
+            _ => "invalid",
+        };
+        let mut buf = [0u8; 16];
+        buf[..identifier.len()].copy_from_slice(identifier.as_bytes());
+        buf
+    }
+
+    fn take_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        let index = match self.pin_index() {
+            Some(index) => index,
+            None => return,
+        };
+
+        let now = board.timestamp();
+        self.count = PULSE_COUNTS[index].swap(0, Ordering::Relaxed);
+        self.total_count += self.count as u64;
+        self.store_total(board, index, self.total_count);
+
+        let elapsed = now - self.last_reading_time;
+        self.rate = if elapsed > 0 {
+            let per_second = self.count as f64 * self.special_config.multiplier as f64 / elapsed as f64;
+            Some(per_second * rate_period_seconds(self.special_config.rate_period))
+        } else {
+            None
+        };
+        self.last_reading_time = now;
+    }
+
+    fn get_configuration_json(&mut self) -> serde_json::Value {
+        let mut sensor_id = self.get_id();
+        let sensor_id = util::str_from_utf8(&mut sensor_id).unwrap_or("Invalid");
+
+        let mut sensor_name = sensor_name_from_type_id(self.get_type_id().into());
+        let sensor_name = util::str_from_utf8(&mut sensor_name).unwrap_or("Invalid");
+
+        json!({
+            "id": sensor_id,
+            "type": sensor_name,
+            "gpio_pin": self.special_config.gpio_pin,
+            "edge": trigger_service::edge_text(self.special_config.edge),
+            "debounce_ms": self.special_config.debounce_ms,
+            "multiplier": self.special_config.multiplier,
+            "rate_period": rate_period_text(self.special_config.rate_period),
+        })
+    }
+
+    fn update(&mut self, values: serde_json::Value) -> Result<(), &'static str> {
+        self.special_config.update_from_values(values)
+    }
+}
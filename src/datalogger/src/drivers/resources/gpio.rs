@@ -47,7 +47,8 @@ pub struct GpioRequest {
     gpio7 : bool,
     gpio8 : bool,
     usart : bool,
-    usart_count : u8
+    usart_count : u8,
+    power_switch_users : [u8; 8] // sensors sharing a gpio that switches their power domain
 }
 
 
@@ -63,7 +64,8 @@ impl GpioRequest {
             gpio7: false, 
             gpio8: false, 
             usart: false,
-            usart_count : 0        }
+            usart_count : 0,
+            power_switch_users: [0; 8]        }
     }
 
     #[allow(unused)]
@@ -128,6 +130,59 @@ impl GpioRequest {
         Ok(())
     }
 
+    // a gpio switching a power domain is shared by the sensors in that domain,
+    // but conflicts with any other use of the pin
+    pub fn update_or_conflict_with_power_switch(&mut self, request: GpioRequest, power_switch: Option<u8>) -> Result<(), &'static str> {
+        if let Some(pin) = power_switch {
+            if pin == 3 || pin == 4 {
+                return Err("pin3 and pin4 are not supported");
+            }
+            if request.pin(pin) {
+                return Err("power switch gpio is also used by the sensor");
+            }
+            if self.pin(pin) && self.power_switch_users[pin as usize - 1] == 0 {
+                return Err("power switch gpio already requested");
+            }
+        }
+
+        self.update_or_conflict(request)?;
+
+        if let Some(pin) = power_switch {
+            self.power_switch_users[pin as usize - 1] += 1;
+            self.use_pin(pin);
+        }
+        Ok(())
+    }
+
+    pub fn release_with_power_switch(&mut self, request: GpioRequest, power_switch: Option<u8>) {
+        self.release(request);
+        if let Some(pin) = power_switch {
+            let users = &mut self.power_switch_users[pin as usize - 1];
+            if *users > 0 {
+                *users -= 1;
+                if *users == 0 {
+                    let mut switch_request = GpioRequest::none();
+                    switch_request.use_pin(pin);
+                    self.release(switch_request);
+                }
+            }
+        }
+    }
+
+    fn pin(&self, pin: u8) -> bool {
+        match pin {
+            1 => self.gpio1,
+            2 => self.gpio2,
+            3 => self.gpio3,
+            4 => self.gpio4,
+            5 => self.gpio5,
+            6 => self.gpio6,
+            7 => self.gpio7,
+            8 => self.gpio8,
+            _ => false,
+        }
+    }
+
     pub fn gpio1(&self) -> bool {
         return self.gpio1
     }
@@ -209,3 +264,38 @@ impl GpioRequest {
 }
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(pin: u8) -> GpioRequest {
+        let mut request = GpioRequest::none();
+        request.use_pin(pin);
+        request
+    }
+
+    #[test]
+    fn test_power_switch_is_shared() {
+        let mut assigned = GpioRequest::none();
+        assert!(assigned.update_or_conflict_with_power_switch(request(1), Some(5)).is_ok());
+        assert!(assigned.update_or_conflict_with_power_switch(request(2), Some(5)).is_ok());
+        assert!(assigned.update_or_conflict(request(5)).is_err());
+
+        assigned.release_with_power_switch(request(1), Some(5));
+        assert!(assigned.gpio5());
+        assigned.release_with_power_switch(request(2), Some(5));
+        assert!(!assigned.gpio5());
+        assert!(assigned.update_or_conflict(request(5)).is_ok());
+    }
+
+    #[test]
+    fn test_power_switch_conflicts() {
+        let mut assigned = GpioRequest::none();
+        assert!(assigned.update_or_conflict(request(6)).is_ok());
+        assert!(assigned.update_or_conflict_with_power_switch(GpioRequest::none(), Some(6)).is_err());
+        assert!(assigned.update_or_conflict_with_power_switch(request(1), Some(1)).is_err());
+        assert!(assigned.update_or_conflict_with_power_switch(GpioRequest::none(), Some(3)).is_err());
+        assert!(!assigned.gpio1());
+    }
+}
@@ -1 +1,2 @@
-pub mod gpio;
\ No newline at end of file
+pub mod gpio;
+pub mod power;
\ No newline at end of file
@@ -0,0 +1,153 @@
+use rriv_board::gpio::GpioMode;
+use rriv_board::{PowerRail, RRIVBoard};
+
+const GPIO_SWITCH_OFFSET: u8 = 10;
+
+// the power a sensor needs switched on while it measures
+#[derive(Clone, Copy, PartialEq)]
+pub enum PowerDomain {
+    AlwaysOn,
+    Rail3v,
+    Rail5v,
+    GpioSwitch(u8), // a load switch driven high by a dynamic gpio
+}
+
+impl PowerDomain {
+    pub fn from_u8(value: u8) -> PowerDomain {
+        match value {
+            1 => PowerDomain::Rail3v,
+            2 => PowerDomain::Rail5v,
+            11..=18 => PowerDomain::GpioSwitch(value - GPIO_SWITCH_OFFSET),
+            _ => PowerDomain::AlwaysOn,
+        }
+    }
+
+    pub fn to_u8(self) -> u8 {
+        match self {
+            PowerDomain::AlwaysOn => 0,
+            PowerDomain::Rail3v => 1,
+            PowerDomain::Rail5v => 2,
+            PowerDomain::GpioSwitch(pin) => pin + GPIO_SWITCH_OFFSET,
+        }
+    }
+
+    pub fn from_str(value: &str) -> Option<PowerDomain> {
+        match value {
+            "always_on" => Some(PowerDomain::AlwaysOn),
+            "3v" => Some(PowerDomain::Rail3v),
+            "5v" => Some(PowerDomain::Rail5v),
+            _ => {
+                // pin3 and pin4 are not supported, see GpioRequest
+                let pin = value.strip_prefix("gpio")?.parse::<u8>().ok()?;
+                if (1..=8).contains(&pin) && pin != 3 && pin != 4 {
+                    Some(PowerDomain::GpioSwitch(pin))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    pub fn text(&self) -> &'static str {
+        match self {
+            PowerDomain::AlwaysOn => "always_on",
+            PowerDomain::Rail3v => "3v",
+            PowerDomain::Rail5v => "5v",
+            PowerDomain::GpioSwitch(pin) => {
+                let names = ["gpio1", "gpio2", "gpio3", "gpio4", "gpio5", "gpio6", "gpio7", "gpio8"];
+                names[(*pin as usize - 1) % names.len()]
+            }
+        }
+    }
+
+    pub fn gpio_switch(&self) -> Option<u8> {
+        match self {
+            PowerDomain::GpioSwitch(pin) => Some(*pin),
+            _ => None,
+        }
+    }
+}
+
+// reference counts for the switched power domains, shared by the sensors using them
+// a domain is switched on by its first user and off again when its last user releases it
+pub struct PowerDomains {
+    rail_3v: u8,
+    rail_5v: u8,
+    gpio_switches: [u8; 8],
+}
+
+impl PowerDomains {
+    pub fn new() -> PowerDomains {
+        PowerDomains {
+            rail_3v: 0,
+            rail_5v: 0,
+            gpio_switches: [0; 8],
+        }
+    }
+
+    pub fn is_on(&self, domain: PowerDomain) -> bool {
+        match domain {
+            PowerDomain::AlwaysOn => true,
+            PowerDomain::Rail3v => self.rail_3v > 0,
+            PowerDomain::Rail5v => self.rail_5v > 0,
+            PowerDomain::GpioSwitch(pin) => self.gpio_switches[pin as usize - 1] > 0,
+        }
+    }
+
+    pub fn acquire(&mut self, board: &mut dyn RRIVBoard, domain: PowerDomain) {
+        match domain {
+            PowerDomain::AlwaysOn => {}
+            PowerDomain::Rail3v => {
+                self.rail_3v += 1;
+                if self.rail_3v == 1 {
+                    board.set_power_rail(PowerRail::Switched3v, true);
+                }
+            }
+            PowerDomain::Rail5v => {
+                self.rail_5v += 1;
+                if self.rail_5v == 1 {
+                    board.set_power_rail(PowerRail::Switched5v, true);
+                }
+            }
+            PowerDomain::GpioSwitch(pin) => {
+                let count = &mut self.gpio_switches[pin as usize - 1];
+                *count += 1;
+                if *count == 1 {
+                    board.set_gpio_pin_mode(pin, GpioMode::PushPullOutput);
+                    board.write_gpio_pin(pin, true);
+                }
+            }
+        }
+    }
+
+    pub fn release(&mut self, board: &mut dyn RRIVBoard, domain: PowerDomain) {
+        match domain {
+            PowerDomain::AlwaysOn => {}
+            PowerDomain::Rail3v => {
+                if self.rail_3v > 0 {
+                    self.rail_3v -= 1;
+                    if self.rail_3v == 0 {
+                        board.set_power_rail(PowerRail::Switched3v, false);
+                    }
+                }
+            }
+            PowerDomain::Rail5v => {
+                if self.rail_5v > 0 {
+                    self.rail_5v -= 1;
+                    if self.rail_5v == 0 {
+                        board.set_power_rail(PowerRail::Switched5v, false);
+                    }
+                }
+            }
+            PowerDomain::GpioSwitch(pin) => {
+                let count = &mut self.gpio_switches[pin as usize - 1];
+                if *count > 0 {
+                    *count -= 1;
+                    if *count == 0 {
+                        board.write_gpio_pin(pin, false);
+                    }
+                }
+            }
+        }
+    }
+}
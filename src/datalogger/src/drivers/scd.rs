@@ -0,0 +1,603 @@
+use serde_json::json;
+
+use crate::sensor_name_from_type_id;
+use crate::services::barometer_service;
+
+use super::sht::crc8;
+use super::types::*;
+
+// Sensirion SCD30 and SCD40/SCD41 CO2, temperature and humidity sensors on i2c
+// every 16 bit word to and from the sensor is followed by a CRC-8 and readings that fail the check are dropped
+// pressure compensation uses a fixed pressure or the latest reading from a barometer driver such as the bme280
+// forced recalibration goes through sensor calibrate point/fit with the reference CO2 in ppm,
+// the sensor should run in the reference air for a few minutes first and the recalibration is sent on the next loop
+// the SCD41 can measure in single shot mode, which leaves the sensor idle between readings
+
+const CHIP_SCD30: u8 = 0;
+const CHIP_SCD40: u8 = 1;
+const CHIP_SCD41: u8 = 2;
+
+const SCD30_ADDRESS: u8 = 0x61;
+const SCD4X_ADDRESS: u8 = 0x62;
+
+const MODE_PERIODIC: u8 = 0;
+const MODE_SINGLE_SHOT: u8 = 1; // SCD41
+
+const PRESSURE_OFF: u8 = 0;
+const PRESSURE_BAROMETER: u8 = 1;
+const PRESSURE_FIXED: u8 = 2;
+
+const MIN_PRESSURE: u16 = 700; // hPa, the range both sensors accept
+const MAX_PRESSURE: u16 = 1400;
+const PRESSURE_TOLERANCE: u16 = 2; // hPa, an SCD30 restarts its measurement on every pressure change
+const MIN_FRC_REFERENCE: f64 = 400.0; // ppm
+const MAX_FRC_REFERENCE: f64 = 2000.0;
+
+const FRC_NONE: u8 = 0;
+const FRC_PENDING: u8 = 1;
+const FRC_APPLIED: u8 = 2;
+const FRC_FAILED: u8 = 3;
+
+const SCD30_START_CONTINUOUS: u16 = 0x0010; // argument is the pressure in mbar, 0 for none
+const SCD30_STOP_CONTINUOUS: u16 = 0x0104;
+const SCD30_DATA_READY: u16 = 0x0202;
+const SCD30_READ_MEASUREMENT: u16 = 0x0300;
+const SCD30_SET_ASC: u16 = 0x5306;
+const SCD30_SET_FRC: u16 = 0x5204;
+const SCD30_READ_DELAY_MS: u16 = 3;
+
+const SCD4X_START_PERIODIC: u16 = 0x21B1;
+const SCD4X_STOP_PERIODIC: u16 = 0x3F86;
+const SCD4X_READ_MEASUREMENT: u16 = 0xEC05;
+const SCD4X_DATA_READY: u16 = 0xE4B8;
+const SCD4X_SET_AMBIENT_PRESSURE: u16 = 0xE000; // argument in hPa
+const SCD4X_SET_ASC: u16 = 0x2416;
+const SCD4X_PERFORM_FRC: u16 = 0x362F;
+const SCD4X_PERSIST_SETTINGS: u16 = 0x3615;
+const SCD4X_MEASURE_SINGLE_SHOT: u16 = 0x219D;
+const SCD4X_READ_DELAY_MS: u16 = 1;
+const SCD4X_STOP_PERIODIC_MS: u16 = 500;
+const SCD4X_FRC_MS: u16 = 400;
+const SCD4X_PERSIST_SETTINGS_MS: u16 = 800;
+const SCD4X_SINGLE_SHOT_MS: u32 = 5000;
+
+fn chip_from_str(value: &str) -> Option<u8> {
+    match value {
+        "scd30" => Some(CHIP_SCD30),
+        "scd40" => Some(CHIP_SCD40),
+        "scd41" => Some(CHIP_SCD41),
+        _ => None,
+    }
+}
+
+fn chip_text(chip: u8) -> &'static str {
+    match chip {
+        CHIP_SCD40 => "scd40",
+        CHIP_SCD41 => "scd41",
+        _ => "scd30",
+    }
+}
+
+fn mode_from_str(value: &str) -> Option<u8> {
+    match value {
+        "periodic" => Some(MODE_PERIODIC),
+        "single_shot" => Some(MODE_SINGLE_SHOT),
+        _ => None,
+    }
+}
+
+fn mode_text(mode: u8) -> &'static str {
+    match mode {
+        MODE_SINGLE_SHOT => "single_shot",
+        _ => "periodic",
+    }
+}
+
+fn frc_text(frc: u8) -> &'static str {
+    match frc {
+        FRC_PENDING => "pending",
+        FRC_APPLIED => "applied",
+        FRC_FAILED => "failed",
+        _ => "none",
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct SCDSpecialConfiguration {
+    chip: u8,
+    mode: u8,
+    asc: u8, // automatic self calibration, 0 or 1
+    pressure_source: u8,
+    pressure: u16, // hPa, for PRESSURE_FIXED
+    _empty: [u8; 26],
+}
+
+impl SCDSpecialConfiguration {
+    pub fn new_from_bytes(
+        bytes: [u8; SENSOR_SETTINGS_PARTITION_SIZE],
+    ) -> SCDSpecialConfiguration {
+        let settings = bytes.as_ptr().cast::<SCDSpecialConfiguration>();
+        unsafe { *settings }
+    }
+
+    pub fn parse_from_values(value: serde_json::Value) -> Result<SCDSpecialConfiguration, &'static str> {
+        let chip = match &value["chip"] {
+            serde_json::Value::String(chip) => match chip_from_str(chip.as_str()) {
+                Some(chip) => chip,
+                None => return Err("chip must be scd30, scd40 or scd41"),
+            },
+            _ => return Err("chip must be scd30, scd40 or scd41"),
+        };
+
+        let mut special_config = Self {
+            chip,
+            mode: MODE_PERIODIC,
+            asc: 1, // the sensors' default
+            pressure_source: PRESSURE_BAROMETER,
+            pressure: 0,
+            _empty: [b'\0'; 26],
+        };
+        special_config.update_from_values(value)?;
+        Ok(special_config)
+    }
+
+    pub fn update_from_values(&mut self, values: serde_json::Value) -> Result<(), &'static str> {
+        match &values["chip"] {
+            serde_json::Value::String(chip) if chip_from_str(chip.as_str()) == Some(self.chip) => {}
+            serde_json::Value::Null => {}
+            _ => return Err("the chip can't be changed, remove and add the sensor"),
+        }
+
+        match &values["mode"] {
+            serde_json::Value::String(mode) => {
+                match mode_from_str(mode.as_str()) {
+                    Some(MODE_SINGLE_SHOT) if self.chip != CHIP_SCD41 => return Err("single_shot mode needs an scd41"),
+                    Some(mode) => self.mode = mode,
+                    None => return Err("mode must be periodic or single_shot"),
+                }
+            }
+            serde_json::Value::Null => {}
+            _ => return Err("mode must be periodic or single_shot"),
+        }
+
+        match &values["asc"] {
+            serde_json::Value::Bool(asc) => self.asc = *asc as u8,
+            serde_json::Value::Null => {}
+            _ => return Err("asc must be true or false"),
+        }
+
+        match &values["pressure"] {
+            serde_json::Value::String(source) if source == "off" => self.pressure_source = PRESSURE_OFF,
+            serde_json::Value::String(source) if source == "barometer" => self.pressure_source = PRESSURE_BAROMETER,
+            serde_json::Value::Number(number) => {
+                match number.as_u64() {
+                    Some(number) if number >= MIN_PRESSURE as u64 && number <= MAX_PRESSURE as u64 => {
+                        self.pressure_source = PRESSURE_FIXED;
+                        self.pressure = number as u16;
+                    }
+                    _ => return Err("pressure must be off, barometer or 700 to 1400 hPa"),
+                }
+            }
+            serde_json::Value::Null => {}
+            _ => return Err("pressure must be off, barometer or 700 to 1400 hPa"),
+        }
+
+        Ok(())
+    }
+}
+
+pub struct Scd {
+    general_config: SensorDriverGeneralConfiguration,
+    special_config: SCDSpecialConfiguration,
+    measurement_requested: bool,
+    measurement_start: u32,
+    applied_pressure: u16, // hPa, 0 when the sensor isn't compensating
+    frc_reference: u16, // ppm, waiting to be sent to the sensor
+    frc: u8,
+    co2: Option<f64>,
+    temperature: Option<f64>,
+    humidity: Option<f64>,
+}
+
+impl Scd {
+    pub fn new(
+        general_config: SensorDriverGeneralConfiguration,
+        special_config: SCDSpecialConfiguration,
+    ) -> Self {
+        Scd {
+            general_config,
+            special_config,
+            measurement_requested: false,
+            measurement_start: 0,
+            applied_pressure: 0,
+            frc_reference: 0,
+            frc: FRC_NONE,
+            co2: None,
+            temperature: None,
+            humidity: None,
+        }
+    }
+
+    fn is_scd30(&self) -> bool {
+        self.special_config.chip == CHIP_SCD30
+    }
+
+    fn single_shot(&self) -> bool {
+        self.special_config.mode == MODE_SINGLE_SHOT
+    }
+
+    fn address(&self) -> u8 {
+        if self.is_scd30() {
+            SCD30_ADDRESS
+        } else {
+            SCD4X_ADDRESS
+        }
+    }
+
+    fn write_command(&mut self, board: &mut dyn rriv_board::RRIVBoard, command: u16) -> Result<(), ()> {
+        board.ic2_write(self.address(), &command.to_be_bytes())
+    }
+
+    fn write_command_with_argument(
+        &mut self,
+        board: &mut dyn rriv_board::RRIVBoard,
+        command: u16,
+        argument: u16,
+    ) -> Result<(), ()> {
+        let command = command.to_be_bytes();
+        let argument = argument.to_be_bytes();
+        let message = [command[0], command[1], argument[0], argument[1], crc8(&argument)];
+        board.ic2_write(self.address(), &message)
+    }
+
+    // reads the words that follow a command, failing if any of them fails the crc
+    fn read_words(&mut self, board: &mut dyn rriv_board::RRIVBoard, command: u16, words: &mut [u16]) -> Result<(), ()> {
+        self.write_command(board, command)?;
+        board.delay_ms(if self.is_scd30() { SCD30_READ_DELAY_MS } else { SCD4X_READ_DELAY_MS });
+
+        let mut data = [0u8; 18];
+        let data = &mut data[..words.len() * 3];
+        board.ic2_read(self.address(), data)?;
+        for i in 0..words.len() {
+            let word = &data[i * 3..i * 3 + 3];
+            if crc8(&word[0..2]) != word[2] {
+                defmt::println!("{} crc error", chip_text(self.special_config.chip));
+                return Err(());
+            }
+            words[i] = u16::from_be_bytes([word[0], word[1]]);
+        }
+        Ok(())
+    }
+
+    // hPa, None when the sensor shouldn't compensate
+    fn ambient_pressure(&self) -> Option<u16> {
+        match self.special_config.pressure_source {
+            PRESSURE_FIXED => Some(self.special_config.pressure),
+            PRESSURE_BAROMETER => {
+                let kpa = barometer_service::barometric_pressure()?;
+                let hpa = (kpa * 10.0 + 0.5) as u16;
+                if (MIN_PRESSURE..=MAX_PRESSURE).contains(&hpa) {
+                    Some(hpa)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+
+    // the SCD30 takes the pressure when continuous measurement starts, the SCD4x takes it at any time
+    fn apply_pressure(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        let pressure = self.ambient_pressure().unwrap_or(0);
+        if pressure == 0 || self.applied_pressure == 0 {
+            if pressure == self.applied_pressure {
+                return;
+            }
+        } else if pressure.abs_diff(self.applied_pressure) < PRESSURE_TOLERANCE {
+            return;
+        }
+
+        let result = if self.is_scd30() {
+            self.write_command_with_argument(board, SCD30_START_CONTINUOUS, pressure)
+        } else if pressure != 0 {
+            self.write_command_with_argument(board, SCD4X_SET_AMBIENT_PRESSURE, pressure)
+        } else {
+            Ok(()) // the SCD4x can't switch compensation off without a reinit, leave the last pressure
+        };
+        match result {
+            Ok(_) => self.applied_pressure = pressure,
+            Err(_) => defmt::println!("failed to set {} pressure", chip_text(self.special_config.chip)),
+        }
+    }
+
+    fn stop_periodic_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) -> Result<(), ()> {
+        if self.is_scd30() {
+            self.write_command(board, SCD30_STOP_CONTINUOUS)
+        } else {
+            let result = self.write_command(board, SCD4X_STOP_PERIODIC);
+            board.delay_ms(SCD4X_STOP_PERIODIC_MS);
+            result
+        }
+    }
+
+    fn start_periodic_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) -> Result<(), ()> {
+        if self.is_scd30() {
+            let pressure = self.ambient_pressure().unwrap_or(0);
+            self.write_command_with_argument(board, SCD30_START_CONTINUOUS, pressure)?;
+            self.applied_pressure = pressure;
+            Ok(())
+        } else {
+            self.write_command(board, SCD4X_START_PERIODIC)
+        }
+    }
+
+    fn data_ready(&mut self, board: &mut dyn rriv_board::RRIVBoard) -> Result<bool, ()> {
+        let mut words = [0u16; 1];
+        if self.is_scd30() {
+            self.read_words(board, SCD30_DATA_READY, &mut words)?;
+            Ok(words[0] == 1)
+        } else {
+            self.read_words(board, SCD4X_DATA_READY, &mut words)?;
+            Ok(words[0] & 0x07FF != 0)
+        }
+    }
+
+    fn read_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        if self.is_scd30() {
+            // three big endian floats, each split over two words
+            let mut words = [0u16; 6];
+            if self.read_words(board, SCD30_READ_MEASUREMENT, &mut words).is_err() {
+                return;
+            }
+            let float = |high: u16, low: u16| f32::from_bits(((high as u32) << 16) | low as u32) as f64;
+            self.co2 = Some(float(words[0], words[1]));
+            self.temperature = Some(float(words[2], words[3]));
+            self.humidity = Some(float(words[4], words[5]));
+        } else {
+            let mut words = [0u16; 3];
+            if self.read_words(board, SCD4X_READ_MEASUREMENT, &mut words).is_err() {
+                return;
+            }
+            self.co2 = Some(words[0] as f64);
+            self.temperature = Some(-45.0 + 175.0 * words[1] as f64 / 65535.0);
+            self.humidity = Some(100.0 * words[2] as f64 / 65535.0);
+        }
+    }
+
+    fn forced_recalibration(&mut self, board: &mut dyn rriv_board::RRIVBoard, reference: u16) -> Result<(), ()> {
+        if self.is_scd30() {
+            return self.write_command_with_argument(board, SCD30_SET_FRC, reference);
+        }
+
+        // the SCD4x only recalibrates when idle
+        if !self.single_shot() {
+            self.stop_periodic_measurement(board)?;
+        }
+
+        let result = self.scd4x_forced_recalibration(board, reference);
+
+        if !self.single_shot() && self.start_periodic_measurement(board).is_err() {
+            defmt::println!("failed to restart scd4x measurement");
+        }
+        result
+    }
+
+    fn scd4x_forced_recalibration(&mut self, board: &mut dyn rriv_board::RRIVBoard, reference: u16) -> Result<(), ()> {
+        self.write_command_with_argument(board, SCD4X_PERFORM_FRC, reference)?;
+        board.delay_ms(SCD4X_FRC_MS);
+
+        let mut data = [0u8; 3];
+        board.ic2_read(SCD4X_ADDRESS, &mut data)?;
+        if crc8(&data[0..2]) != data[2] {
+            return Err(());
+        }
+        let correction = u16::from_be_bytes([data[0], data[1]]);
+        if correction == 0xFFFF {
+            return Err(()); // the sensor rejected the recalibration
+        }
+        defmt::println!("scd4x frc correction {} ppm", correction as i32 - 0x8000);
+
+        // otherwise the correction is lost at power off
+        self.write_command(board, SCD4X_PERSIST_SETTINGS)?;
+        board.delay_ms(SCD4X_PERSIST_SETTINGS_MS);
+        Ok(())
+    }
+}
+
+impl SensorDriver for Scd {
+    getters!();
+
+    fn get_configuration_json(&mut self) -> serde_json::Value {
+        let mut sensor_id = self.get_id();
+        let sensor_id = util::str_from_utf8(&mut sensor_id).unwrap_or("Invalid");
+
+        let mut sensor_name = sensor_name_from_type_id(self.get_type_id().into());
+        let sensor_name = util::str_from_utf8(&mut sensor_name).unwrap_or("Invalid");
+
+        let pressure = match self.special_config.pressure_source {
+            PRESSURE_FIXED => json!(self.special_config.pressure),
+            PRESSURE_BAROMETER => json!("barometer"),
+            _ => json!("off"),
+        };
+
+        json!({
+            "id": sensor_id,
+            "type": sensor_name,
+            "chip": chip_text(self.special_config.chip),
+            "mode": mode_text(self.special_config.mode),
+            "asc": self.special_config.asc == 1,
+            "pressure": pressure,
+            "frc": frc_text(self.frc),
+        })
+    }
+
+    fn setup(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        self.measurement_requested = false;
+        self.applied_pressure = 0;
+
+        // the sensor may still be measuring from before a restart, and the SCD4x ignores settings until it stops
+        if self.stop_periodic_measurement(board).is_err() {
+            defmt::println!("{} not found at 0x{:x}", chip_text(self.special_config.chip), self.address());
+            return;
+        }
+
+        let asc = self.special_config.asc as u16;
+        let command = if self.is_scd30() { SCD30_SET_ASC } else { SCD4X_SET_ASC };
+        if self.write_command_with_argument(board, command, asc).is_err() {
+            defmt::println!("failed to set {} asc", chip_text(self.special_config.chip));
+        }
+
+        if self.single_shot() {
+            self.apply_pressure(board);
+            return;
+        }
+
+        if !self.is_scd30() {
+            self.apply_pressure(board);
+        }
+        if self.start_periodic_measurement(board).is_err() {
+            defmt::println!("failed to start {} measurement", chip_text(self.special_config.chip));
+        }
+    }
+
+    fn teardown(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        if !self.single_shot() {
+            let _ = self.stop_periodic_measurement(board);
+        }
+        self.measurement_requested = false;
+    }
+
+    fn get_measured_parameter_count(&mut self) -> usize {
+        3
+    }
+
+    fn get_measured_parameter_value(&mut self, index: usize) -> Result<f64, ()> {
+        let value = match index {
+            0 => self.co2,
+            1 => self.temperature,
+            2 => self.humidity,
+            _ => None,
+        };
+        value.ok_or(())
+    }
+
+    fn get_measured_parameter_identifier(&mut self, index: usize) -> [u8; 16] {
+        let identifier = match index {
+            0 => "co2",
+            1 => "temperature",
+            2 => "humidity",
+            _ => "invalid",
+        };
+        let mut buf = [0u8; 16];
+        buf[..identifier.len()].copy_from_slice(identifier.as_bytes());
+        buf
+    }
+
+    fn get_measurement_timeout(&self) -> Option<i64> {
+        if self.single_shot() {
+            Some(SCD4X_SINGLE_SHOT_MS as i64 / 1000 + 2)
+        } else {
+            None
+        }
+    }
+
+    fn take_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        self.start_measurement(board);
+        // long enough for a single shot, or for the next periodic reading
+        for _ in 0..700 {
+            if self.poll_measurement(board) {
+                return;
+            }
+            board.run_loop_iteration(); // feeds the watchdog, the wait is longer than the watchdog period
+            board.delay_ms(10);
+        }
+        self.measurement_requested = false;
+    }
+
+    fn start_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        self.co2 = None;
+        self.temperature = None;
+        self.humidity = None;
+        self.apply_pressure(board);
+
+        if self.single_shot()
+            && self.write_command(board, SCD4X_MEASURE_SINGLE_SHOT).is_err()
+        {
+            defmt::println!("failed to start scd41 single shot");
+            return;
+        }
+        self.measurement_requested = true;
+        self.measurement_start = board.millis();
+    }
+
+    fn poll_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) -> bool {
+        if !self.measurement_requested {
+            return true;
+        }
+
+        if self.single_shot() && board.millis().wrapping_sub(self.measurement_start) < SCD4X_SINGLE_SHOT_MS {
+            return false;
+        }
+
+        match self.data_ready(board) {
+            Ok(true) => {}
+            Ok(false) => return false,
+            Err(_) => {
+                defmt::println!("failed to check {} data ready", chip_text(self.special_config.chip));
+                self.measurement_requested = false;
+                return true;
+            }
+        }
+
+        self.measurement_requested = false;
+        self.read_measurement(board);
+        true
+    }
+
+    // sends a forced recalibration queued by fit once no measurement is running
+    fn update_actuators(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        if self.frc != FRC_PENDING || self.measurement_requested {
+            return;
+        }
+
+        let reference = self.frc_reference;
+        match self.forced_recalibration(board, reference) {
+            Ok(_) => {
+                defmt::println!("{} recalibrated to {} ppm", chip_text(self.special_config.chip), reference);
+                self.frc = FRC_APPLIED;
+            }
+            Err(_) => {
+                defmt::println!("{} recalibration failed", chip_text(self.special_config.chip));
+                self.frc = FRC_FAILED;
+            }
+        }
+    }
+
+    // forced recalibration to the reference point, the sensor keeps its own calibration
+    fn fit(&mut self, pairs: &[CalibrationPair]) -> Result<(), ()> {
+        if pairs.len() != 1 {
+            return Err(());
+        }
+
+        let reference = pairs[0].point;
+        if !(MIN_FRC_REFERENCE..=MAX_FRC_REFERENCE).contains(&reference) {
+            defmt::println!("frc reference must be 400 to 2000 ppm");
+            return Err(());
+        }
+
+        self.frc_reference = (reference + 0.5) as u16;
+        self.frc = FRC_PENDING;
+        Ok(())
+    }
+
+    fn clear_calibration(&mut self) {
+        // cancels a recalibration that hasn't been sent yet
+        if self.frc == FRC_PENDING {
+            self.frc = FRC_NONE;
+        }
+    }
+
+    fn update(&mut self, values: serde_json::Value) -> Result<(), &'static str> {
+        self.special_config.update_from_values(values)
+    }
+}
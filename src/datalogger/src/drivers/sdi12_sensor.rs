@@ -0,0 +1,422 @@
+use rriv_board::gpio::GpioMode;
+use sdi12::SDI12_BUFFER_SIZE;
+use serde_json::json;
+
+use crate::sensor_name_from_type_id;
+use crate::services::sdi12_service::{Sdi12Command, Sdi12TxProcessor};
+
+use super::types::*;
+
+// reads any SDI-12 sensor on a gpio, with the logger acting as the recorder
+// the measurement command is acknowledged with the time the sensor needs, then the values are collected with aD0! to aD9!
+
+const MAX_VALUES: usize = 20;
+const MAX_DATA_COMMANDS: u8 = 10; // aD0! to aD9!
+const MAX_RETRIES: u8 = 3;
+const TIMEOUT_MARGIN_SECONDS: i64 = 10; // time for the data commands after the sensor's measurement time
+const PARAMETER_NAMES_SIZE: usize = 27;
+
+const COMMAND_M: u8 = b'M';
+const COMMAND_C: u8 = b'C';
+const COMMAND_R: u8 = b'R';
+const COMMAND_HA: u8 = b'H';
+
+// M, M1-M9, C, C1-C9, R0-R9 or HA
+fn command_from_str(value: &str) -> Option<(u8, u8)> {
+    let bytes = value.as_bytes();
+    match bytes {
+        [b'H', b'A'] => Some((COMMAND_HA, 0)),
+        [command @ (b'M' | b'C')] => Some((*command, 0)),
+        [command @ (b'M' | b'C'), digit @ b'1'..=b'9'] => Some((*command, *digit)),
+        [b'R', digit @ b'0'..=b'9'] => Some((COMMAND_R, *digit)),
+        _ => None,
+    }
+}
+
+fn valid_address(address: char) -> bool {
+    address.is_ascii_alphanumeric()
+}
+
+#[derive(Copy, Clone)]
+pub struct Sdi12SensorSpecialConfiguration {
+    gpio_pin: u8,
+    address: u8,
+    command: u8,       // M, C, R or H for HA
+    command_digit: u8, // the n of aMn!, aCn! and aRn!, 0 when there isn't one
+    value_count: u8,
+    parameter_names: [u8; PARAMETER_NAMES_SIZE], // comma separated
+}
+
+impl Sdi12SensorSpecialConfiguration {
+    pub fn new_from_bytes(
+        bytes: [u8; SENSOR_SETTINGS_PARTITION_SIZE],
+    ) -> Sdi12SensorSpecialConfiguration {
+        let settings = bytes.as_ptr().cast::<Sdi12SensorSpecialConfiguration>();
+        unsafe { *settings }
+    }
+
+    pub fn parse_from_values(value: serde_json::Value) -> Result<Sdi12SensorSpecialConfiguration, &'static str> {
+        if !value["gpio_pin"].is_number() {
+            return Err("gpio pin is required");
+        }
+        if !value["value_count"].is_number() {
+            return Err("value_count is required");
+        }
+
+        let mut special_config = Self {
+            gpio_pin: 0,
+            address: b'0',
+            command: COMMAND_M,
+            command_digit: 0,
+            value_count: 0,
+            parameter_names: [b'\0'; PARAMETER_NAMES_SIZE],
+        };
+        special_config.update_from_values(value)?;
+        Ok(special_config)
+    }
+
+    pub fn update_from_values(&mut self, values: serde_json::Value) -> Result<(), &'static str> {
+        match &values["gpio_pin"] {
+            serde_json::Value::Number(number) => {
+                match number.as_u64() {
+                    Some(number) if (1..=8).contains(&number) => self.gpio_pin = number as u8,
+                    _ => return Err("invalid pin"),
+                }
+            }
+            serde_json::Value::Null => {}
+            _ => return Err("invalid pin"),
+        }
+
+        match &values["sensor_address"] {
+            serde_json::Value::String(address) => {
+                let mut chars = address.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(address), None) if valid_address(address) => self.address = address as u8,
+                    _ => return Err("sensor_address must be a single character, 0-9, a-z or A-Z"),
+                }
+            }
+            serde_json::Value::Null => {}
+            _ => return Err("sensor_address must be a single character, 0-9, a-z or A-Z"),
+        }
+
+        match &values["command"] {
+            serde_json::Value::String(command) => {
+                match command_from_str(command.as_str()) {
+                    Some((command, digit)) => {
+                        self.command = command;
+                        self.command_digit = digit;
+                    }
+                    None => return Err("command must be M, M1-M9, C, C1-C9, R0-R9 or HA"),
+                }
+            }
+            serde_json::Value::Null => {}
+            _ => return Err("command must be M, M1-M9, C, C1-C9, R0-R9 or HA"),
+        }
+
+        match &values["value_count"] {
+            serde_json::Value::Number(number) => {
+                match number.as_u64() {
+                    Some(number) if number >= 1 && number <= MAX_VALUES as u64 => self.value_count = number as u8,
+                    _ => return Err("value_count must be 1 to 20"),
+                }
+            }
+            serde_json::Value::Null => {}
+            _ => return Err("value_count must be 1 to 20"),
+        }
+
+        match &values["parameter_names"] {
+            serde_json::Value::Array(names) => {
+                let mut parameter_names = [b'\0'; PARAMETER_NAMES_SIZE];
+                let mut cursor = 0;
+                for (i, name) in names.iter().enumerate() {
+                    let name = match name.as_str() {
+                        Some(name) if !name.is_empty() && name.len() < 16 && name.is_ascii() && !name.contains(',') => name,
+                        _ => return Err("parameter names must be 1 to 15 ascii characters"),
+                    };
+                    let separator = if i > 0 { 1 } else { 0 };
+                    if cursor + separator + name.len() > PARAMETER_NAMES_SIZE {
+                        return Err("parameter names must fit in 27 characters including commas");
+                    }
+                    if separator > 0 {
+                        parameter_names[cursor] = b',';
+                        cursor += 1;
+                    }
+                    parameter_names[cursor..cursor + name.len()].copy_from_slice(name.as_bytes());
+                    cursor += name.len();
+                }
+                self.parameter_names = parameter_names;
+            }
+            serde_json::Value::Null => {}
+            _ => return Err("parameter_names must be a list of names"),
+        }
+
+        Ok(())
+    }
+
+    fn parameter_names(&self) -> impl Iterator<Item = &str> {
+        let length = self.parameter_names.iter().position(|&b| b == b'\0').unwrap_or(PARAMETER_NAMES_SIZE);
+        let names = core::str::from_utf8(&self.parameter_names[..length]).unwrap_or("");
+        names.split(',').filter(|name| !name.is_empty())
+    }
+
+    fn command_text(&self) -> [u8; 2] {
+        match self.command {
+            COMMAND_HA => [b'H', b'A'],
+            command => [command, self.command_digit],
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum MeasurementState {
+    Idle,
+    Waiting, // for the sensor's measurement time or a service request
+    Reading(u8), // the next aDn! to send
+}
+
+pub struct Sdi12Sensor {
+    general_config: SensorDriverGeneralConfiguration,
+    special_config: Sdi12SensorSpecialConfiguration,
+    values: [Option<f64>; MAX_VALUES],
+    received: usize,
+    expected: usize,
+    state: MeasurementState,
+    wait_start: u32,
+    wait_ms: u32,
+    retries: u8,
+    measurement_timeout: Option<i64>,
+}
+
+impl Sdi12Sensor {
+    pub fn new(
+        general_config: SensorDriverGeneralConfiguration,
+        special_config: Sdi12SensorSpecialConfiguration,
+    ) -> Self {
+        Sdi12Sensor {
+            general_config,
+            special_config,
+            values: [None; MAX_VALUES],
+            received: 0,
+            expected: 0,
+            state: MeasurementState::Idle,
+            wait_start: 0,
+            wait_ms: 0,
+            retries: 0,
+            measurement_timeout: None,
+        }
+    }
+
+    fn processor(&self) -> Sdi12TxProcessor {
+        Sdi12TxProcessor::new(self.special_config.gpio_pin, self.special_config.address as char)
+    }
+
+    fn command(&self) -> Sdi12Command {
+        let digit = self.special_config.command_digit as char;
+        match (self.special_config.command, self.special_config.command_digit) {
+            (COMMAND_HA, _) => Sdi12Command::HA,
+            (COMMAND_R, _) => Sdi12Command::R(digit),
+            (COMMAND_C, 0) => Sdi12Command::C,
+            (COMMAND_C, _) => Sdi12Command::Cc(digit),
+            (_, 0) => Sdi12Command::M,
+            (_, _) => Sdi12Command::Mc(digit),
+        }
+    }
+
+    fn store_values(&mut self, sdi12: &mut Sdi12TxProcessor, board: &mut dyn rriv_board::RRIVBoard, response: [char; SDI12_BUFFER_SIZE]) -> bool {
+        let d_response = match sdi12.parse_d_command(board, response) {
+            Some(d_response) => d_response,
+            None => return false,
+        };
+        for i in 0..d_response.count as usize {
+            if self.received >= self.expected {
+                break;
+            }
+            self.values[self.received] = Some(d_response.data[i] as f64);
+            self.received += 1;
+        }
+        true
+    }
+
+    fn finish(&mut self) -> bool {
+        self.state = MeasurementState::Idle;
+        self.measurement_timeout = None;
+        true
+    }
+}
+
+impl SensorDriver for Sdi12Sensor {
+    fn setup(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        board.set_gpio_pin_mode(self.special_config.gpio_pin, GpioMode::PullDownInput);
+        self.processor().setup(board);
+    }
+
+    fn teardown(&mut self, _board: &mut dyn rriv_board::RRIVBoard) {
+        rriv_board::remove_gpio_interrupt_function(self.special_config.gpio_pin);
+    }
+
+    fn get_requested_gpios(&self) -> super::resources::gpio::GpioRequest {
+        let mut gpio_request = super::resources::gpio::GpioRequest::none();
+        gpio_request.use_pin(self.special_config.gpio_pin);
+        gpio_request
+    }
+
+    getters!();
+
+    fn get_measured_parameter_count(&mut self) -> usize {
+        self.special_config.value_count as usize
+    }
+
+    fn get_measured_parameter_value(&mut self, index: usize) -> Result<f64, ()> {
+        match self.values.get(index) {
+            Some(Some(value)) => Ok(*value),
+            _ => Err(()),
+        }
+    }
+
+    fn get_measured_parameter_identifier(&mut self, index: usize) -> [u8; 16] {
+        let mut buf = [0u8; 16];
+        match self.special_config.parameter_names().nth(index) {
+            Some(name) => buf[..name.len()].copy_from_slice(name.as_bytes()),
+            None => {
+                let mut identifier = [0u8; 16];
+                if let Ok(identifier) = format_no_std::show(&mut identifier, format_args!("value_{}", index)) {
+                    buf[..identifier.len()].copy_from_slice(identifier.as_bytes());
+                }
+            }
+        }
+        buf
+    }
+
+    fn take_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        self.start_measurement(board);
+        while !self.poll_measurement(board) {
+            board.run_loop_iteration(); // feeds the watchdog while the sensor measures
+            board.delay_ms(5);
+        }
+    }
+
+    fn start_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        self.values = [None; MAX_VALUES];
+        self.received = 0;
+        self.expected = self.special_config.value_count as usize;
+        self.retries = 0;
+        self.state = MeasurementState::Idle;
+        self.measurement_timeout = None;
+
+        let mut sdi12 = self.processor();
+        sdi12.send_break(board);
+        sdi12.send_command(board, self.command());
+        let response = match sdi12.read_response(board) {
+            Ok(response) => response,
+            Err(_) => {
+                defmt::println!("SDI12: no response from sensor {}", self.special_config.address as char);
+                return;
+            }
+        };
+
+        if self.special_config.command == COMMAND_R {
+            // continuous measurements respond with the values straight away
+            self.store_values(&mut sdi12, board, response);
+            return;
+        }
+
+        let (ttt, n) = if self.special_config.command == COMMAND_HA {
+            match sdi12.parse_ha_command(response) {
+                Some(ack) => (ack.ttt, ack.nnn as usize),
+                None => (0, 0),
+            }
+        } else {
+            match sdi12.parse_m_command(response) {
+                Some(ack) => (ack.ttt, ack.n as usize),
+                None => (0, 0),
+            }
+        };
+        if n == 0 {
+            defmt::println!("SDI12: invalid acknowledgement from sensor {}", self.special_config.address as char);
+            return;
+        }
+        if n < self.expected {
+            self.expected = n;
+        }
+
+        self.wait_start = board.millis();
+        self.wait_ms = ttt * 1000;
+        self.measurement_timeout = Some(ttt as i64 + TIMEOUT_MARGIN_SECONDS);
+        self.state = MeasurementState::Waiting;
+    }
+
+    fn poll_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) -> bool {
+        let mut sdi12 = self.processor();
+        match self.state {
+            MeasurementState::Idle => true,
+            MeasurementState::Waiting => {
+                // only M measurements end early with a service request
+                let service_request = self.special_config.command == COMMAND_M && sdi12.service_request_received(board);
+                if service_request || board.millis().wrapping_sub(self.wait_start) >= self.wait_ms {
+                    self.state = MeasurementState::Reading(0);
+                }
+                false
+            }
+            MeasurementState::Reading(index) => {
+                sdi12.send_break(board);
+                sdi12.send_command(board, Sdi12Command::D((b'0' + index) as char));
+                let stored = match sdi12.read_response(board) {
+                    Ok(response) => self.store_values(&mut sdi12, board, response),
+                    Err(_) => false,
+                };
+
+                if !stored {
+                    self.retries += 1;
+                    if self.retries >= MAX_RETRIES {
+                        defmt::println!("SDI12: no data from sensor {}", self.special_config.address as char);
+                        return self.finish();
+                    }
+                    return false;
+                }
+
+                self.retries = 0;
+                if self.received >= self.expected || index + 1 >= MAX_DATA_COMMANDS {
+                    return self.finish();
+                }
+                self.state = MeasurementState::Reading(index + 1);
+                false
+            }
+        }
+    }
+
+    fn get_measurement_timeout(&self) -> Option<i64> {
+        self.measurement_timeout
+    }
+
+    fn get_configuration_json(&mut self) -> serde_json::Value {
+        let mut sensor_id = self.get_id();
+        let sensor_id = util::str_from_utf8(&mut sensor_id).unwrap_or("Invalid");
+
+        let mut sensor_name = sensor_name_from_type_id(self.get_type_id().into());
+        let sensor_name = util::str_from_utf8(&mut sensor_name).unwrap_or("Invalid");
+
+        let command = self.special_config.command_text();
+        let command_length = if command[1] == 0 { 1 } else { 2 };
+        let command = core::str::from_utf8(&command[..command_length]).unwrap_or("Invalid");
+
+        let address = [self.special_config.address];
+        let address = core::str::from_utf8(&address).unwrap_or("Invalid");
+
+        let parameter_names: alloc::vec::Vec<&str> = self.special_config.parameter_names().collect();
+
+        json!({
+            "id": sensor_id,
+            "type": sensor_name,
+            "gpio_pin": self.special_config.gpio_pin,
+            "sensor_address": address,
+            "command": command,
+            "value_count": self.special_config.value_count,
+            "parameter_names": parameter_names,
+        })
+    }
+
+    fn update(&mut self, values: serde_json::Value) -> Result<(), &'static str> {
+        self.special_config.update_from_values(values)
+    }
+}
+
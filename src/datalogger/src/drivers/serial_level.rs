@@ -0,0 +1,443 @@
+use serde_json::json;
+
+use crate::sensor_name_from_type_id;
+use crate::services::usart_service;
+
+use super::types::*;
+
+// non-contact water level from a serial rangefinder on the usart, MaxBotix style ultrasonic sensors and radar level sensors
+// the sensors stream readings continuously, a measurement collects a burst of readings and reports
+// the median distance, the stage as mount_height - distance, and how many readings in the burst were valid
+// the usart is switched to the sensor's baud_rate while the sensor holds it, MaxBotix sensors send at 9600
+
+const MAX_READINGS: usize = 15;
+const DEFAULT_READINGS: u8 = 5;
+const READING_TIMEOUT_MS: u32 = 1000; // the burst ends early if the sensor goes quiet
+const MAX_FRAME_LENGTH: usize = 12;
+const CAPTURE_LIMIT: usize = 200; // clear the capture before it fills the usart raw buffer
+
+// MaxBotix sensors send R followed by the distance and a carriage return, e.g. R1234\r
+const PROTOCOL_MAXBOTIX: u8 = 0;
+// radar level sensors and others that send a decimal distance per line
+const PROTOCOL_ASCII: u8 = 1;
+
+const UNITS_MM: u8 = 0;
+const UNITS_CM: u8 = 1;
+const UNITS_INCHES: u8 = 2;
+const UNITS_M: u8 = 3;
+
+fn protocol_from_str(value: &str) -> Option<u8> {
+    match value {
+        "maxbotix" => Some(PROTOCOL_MAXBOTIX),
+        "ascii" => Some(PROTOCOL_ASCII),
+        _ => None,
+    }
+}
+
+fn protocol_text(protocol: u8) -> &'static str {
+    match protocol {
+        PROTOCOL_ASCII => "ascii",
+        _ => "maxbotix",
+    }
+}
+
+fn units_from_str(value: &str) -> Option<u8> {
+    match value {
+        "mm" => Some(UNITS_MM),
+        "cm" => Some(UNITS_CM),
+        "in" => Some(UNITS_INCHES),
+        "m" => Some(UNITS_M),
+        _ => None,
+    }
+}
+
+fn units_text(units: u8) -> &'static str {
+    match units {
+        UNITS_CM => "cm",
+        UNITS_INCHES => "in",
+        UNITS_M => "m",
+        _ => "mm",
+    }
+}
+
+// meters per unit of the sensor's readings
+fn units_scale(units: u8) -> f64 {
+    match units {
+        UNITS_CM => 0.01,
+        UNITS_INCHES => 0.0254,
+        UNITS_M => 1.0,
+        _ => 0.001,
+    }
+}
+
+fn median(values: &mut [f64]) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+
+    // insertion sort, bursts are short
+    for i in 1..values.len() {
+        let mut j = i;
+        while j > 0 && values[j - 1] > values[j] {
+            values.swap(j - 1, j);
+            j -= 1;
+        }
+    }
+
+    let middle = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        Some((values[middle - 1] + values[middle]) / 2.0)
+    } else {
+        Some(values[middle])
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct SerialLevelSpecialConfiguration {
+    mount_height: f32, // m, from the sensor face to the stage datum
+    min_distance: f32, // m, readings outside the range are not valid returns
+    max_distance: f32, // m, MaxBotix sensors report their maximum range when there is no target
+    protocol: u8,
+    units: u8,
+    readings: u8, // readings per burst
+    baud_rate: u8, // index into the usart baud rates
+}
+
+impl SerialLevelSpecialConfiguration {
+    pub fn new_from_bytes(
+        bytes: [u8; SENSOR_SETTINGS_PARTITION_SIZE],
+    ) -> SerialLevelSpecialConfiguration {
+        let settings = bytes.as_ptr().cast::<SerialLevelSpecialConfiguration>();
+        unsafe { *settings }
+    }
+
+    pub fn parse_from_values(value: serde_json::Value) -> Result<SerialLevelSpecialConfiguration, &'static str> {
+        if !value["mount_height"].is_number() {
+            return Err("mount_height is required");
+        }
+
+        let mut special_config = Self {
+            mount_height: 0.0,
+            min_distance: 0.0,
+            max_distance: 9.998, // below the 9999 mm no target reading of the 10 m MaxBotix sensors
+            protocol: PROTOCOL_MAXBOTIX,
+            units: UNITS_MM,
+            readings: DEFAULT_READINGS,
+            baud_rate: usart_service::BAUD_RATE_9600,
+        };
+        special_config.update_from_values(value)?;
+        Ok(special_config)
+    }
+
+    pub fn update_from_values(&mut self, values: serde_json::Value) -> Result<(), &'static str> {
+        match &values["protocol"] {
+            serde_json::Value::String(protocol) => match protocol_from_str(protocol.as_str()) {
+                Some(protocol) => self.protocol = protocol,
+                None => return Err("protocol must be maxbotix or ascii"),
+            },
+            serde_json::Value::Null => {}
+            _ => return Err("protocol must be maxbotix or ascii"),
+        }
+
+        match &values["units"] {
+            serde_json::Value::String(units) => match units_from_str(units.as_str()) {
+                Some(units) => self.units = units,
+                None => return Err("units must be mm, cm, in or m"),
+            },
+            serde_json::Value::Null => {}
+            _ => return Err("units must be mm, cm, in or m"),
+        }
+
+        match &values["readings"] {
+            serde_json::Value::Number(number) => match number.as_u64() {
+                Some(number) if number >= 1 && number <= MAX_READINGS as u64 => self.readings = number as u8,
+                _ => return Err("readings must be 1 to 15"),
+            },
+            serde_json::Value::Null => {}
+            _ => return Err("readings must be 1 to 15"),
+        }
+
+        match &values["baud_rate"] {
+            serde_json::Value::Number(number) => match number.as_u64().and_then(usart_service::baud_rate_index) {
+                Some(baud_rate) => self.baud_rate = baud_rate,
+                None => return Err("baud_rate must be 9600, 19200, 38400, 57600 or 115200"),
+            },
+            serde_json::Value::Null => {}
+            _ => return Err("baud_rate must be 9600, 19200, 38400, 57600 or 115200"),
+        }
+
+        let distances = [
+            ("mount_height", &mut self.mount_height, "mount_height must be a distance in m"),
+            ("min_distance", &mut self.min_distance, "min_distance must be a distance in m"),
+            ("max_distance", &mut self.max_distance, "max_distance must be a distance in m"),
+        ];
+        for (key, distance, message) in distances {
+            match &values[key] {
+                serde_json::Value::Number(number) => match number.as_f64() {
+                    Some(number) if number >= 0.0 => *distance = number as f32,
+                    _ => return Err(message),
+                },
+                serde_json::Value::Null => {}
+                _ => return Err(message),
+            }
+        }
+
+        if self.min_distance >= self.max_distance {
+            return Err("min_distance must be less than max_distance");
+        }
+
+        Ok(())
+    }
+}
+
+pub struct SerialLevel {
+    general_config: SensorDriverGeneralConfiguration,
+    special_config: SerialLevelSpecialConfiguration,
+    distance: Option<f64>, // m
+    valid_count: Option<usize>,
+    measuring: bool,
+    bus_owned: bool, // the usart is captured for our readings
+    readings: [f64; MAX_READINGS],
+    received_count: usize, // frames received this burst, valid or not
+    reading_count: usize,  // valid readings this burst
+    parsed: usize,         // captured bytes already parsed
+    synced: bool,          // a frame boundary has been seen, so the frame buffer holds a whole frame
+    frame: [u8; MAX_FRAME_LENGTH],
+    frame_length: usize,
+    last_reading_time: u32,
+}
+
+impl SerialLevel {
+    pub fn new(
+        general_config: SensorDriverGeneralConfiguration,
+        special_config: SerialLevelSpecialConfiguration,
+    ) -> Self {
+        SerialLevel {
+            general_config,
+            special_config,
+            distance: None,
+            valid_count: None,
+            measuring: false,
+            bus_owned: false,
+            readings: [0.0; MAX_READINGS],
+            received_count: 0,
+            reading_count: 0,
+            parsed: 0,
+            synced: false,
+            frame: [0u8; MAX_FRAME_LENGTH],
+            frame_length: 0,
+            last_reading_time: 0,
+        }
+    }
+
+    fn release_bus(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        if self.bus_owned {
+            usart_service::stop_raw_capture(board);
+            board.set_usart_baud_rate(rriv_board::USART_BAUD_RATE);
+            self.bus_owned = false;
+        }
+    }
+
+    // parse the bytes received since the last poll, a frame at the start of the capture may be partial so it is dropped
+    fn receive_readings(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        let mut buffer = [0u8; CAPTURE_LIMIT + 56];
+        let length = usart_service::read_raw_bytes(&mut buffer);
+
+        for &byte in &buffer[self.parsed..length] {
+            match byte {
+                b'R' if self.special_config.protocol == PROTOCOL_MAXBOTIX => {
+                    self.synced = true;
+                    self.frame_length = 0;
+                }
+                b'\r' | b'\n' => {
+                    if self.synced && self.frame_length > 0 {
+                        self.finish_frame(board);
+                    }
+                    self.synced = self.special_config.protocol == PROTOCOL_ASCII;
+                    self.frame_length = 0;
+                }
+                _ => {
+                    if self.frame_length < MAX_FRAME_LENGTH {
+                        self.frame[self.frame_length] = byte;
+                        self.frame_length += 1;
+                    } else {
+                        self.synced = false;
+                    }
+                }
+            }
+        }
+        self.parsed = length;
+
+        if length >= CAPTURE_LIMIT {
+            // bytes arriving while clearing can be lost, so resync on the next frame
+            usart_service::clear_raw_bytes(board);
+            self.parsed = 0;
+            self.synced = false;
+            self.frame_length = 0;
+        }
+    }
+
+    fn finish_frame(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        if self.received_count >= self.special_config.readings as usize {
+            return;
+        }
+        self.received_count += 1;
+        self.last_reading_time = board.millis();
+
+        // radar sensors may append units or spaces to the number
+        let frame = &self.frame[..self.frame_length];
+        let end = frame
+            .iter()
+            .position(|byte| !(byte.is_ascii_digit() || *byte == b'.' || *byte == b'-' || *byte == b' '))
+            .unwrap_or(frame.len());
+        let distance = match core::str::from_utf8(&frame[..end]) {
+            Ok(text) => match text.trim().parse::<f64>() {
+                Ok(distance) => distance * units_scale(self.special_config.units),
+                Err(_) => return,
+            },
+            Err(_) => return,
+        };
+
+        if distance < self.special_config.min_distance as f64 || distance > self.special_config.max_distance as f64 {
+            return;
+        }
+        self.readings[self.reading_count] = distance;
+        self.reading_count += 1;
+    }
+
+    fn finish_burst(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        self.release_bus(board);
+        self.measuring = false;
+        self.distance = median(&mut self.readings[..self.reading_count]);
+        self.valid_count = Some(self.reading_count);
+    }
+}
+
+impl SensorDriver for SerialLevel {
+    getters!();
+
+    fn get_configuration_json(&mut self) -> serde_json::Value {
+        let mut sensor_id = self.get_id();
+        let sensor_id = util::str_from_utf8(&mut sensor_id).unwrap_or("Invalid");
+
+        let mut sensor_name = sensor_name_from_type_id(self.get_type_id().into());
+        let sensor_name = util::str_from_utf8(&mut sensor_name).unwrap_or("Invalid");
+
+        json!({
+            "id": sensor_id,
+            "type": sensor_name,
+            "protocol": protocol_text(self.special_config.protocol),
+            "units": units_text(self.special_config.units),
+            "readings": self.special_config.readings,
+            "baud_rate": usart_service::baud_rate(self.special_config.baud_rate),
+            "mount_height": self.special_config.mount_height,
+            "min_distance": self.special_config.min_distance,
+            "max_distance": self.special_config.max_distance,
+        })
+    }
+
+    fn setup(&mut self, _board: &mut dyn rriv_board::RRIVBoard) {}
+
+    fn teardown(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        self.release_bus(board);
+        self.measuring = false;
+    }
+
+    fn get_requested_gpios(&self) -> super::resources::gpio::GpioRequest {
+        let mut gpio_request = super::resources::gpio::GpioRequest::none();
+        gpio_request.use_usart();
+        gpio_request
+    }
+
+    fn get_measured_parameter_count(&mut self) -> usize {
+        3
+    }
+
+    fn get_measured_parameter_value(&mut self, index: usize) -> Result<f64, ()> {
+        match index {
+            0 => self.distance.ok_or(()),
+            1 => self
+                .distance
+                .map(|distance| self.special_config.mount_height as f64 - distance)
+                .ok_or(()),
+            2 => self.valid_count.map(|count| count as f64).ok_or(()),
+            _ => Err(()),
+        }
+    }
+
+    fn get_measured_parameter_identifier(&mut self, index: usize) -> [u8; 16] {
+        let identifier = match index {
+            0 => "distance_m",
+            1 => "stage_m",
+            2 => "valid_count",
+            _ => "invalid",
+        };
+        let mut buf = [0u8; 16];
+        buf[..identifier.len()].copy_from_slice(identifier.as_bytes());
+        buf
+    }
+
+    fn get_measurement_timeout(&self) -> Option<i64> {
+        Some(self.special_config.readings as i64 * READING_TIMEOUT_MS as i64 / 1000 + 5)
+    }
+
+    fn take_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        self.start_measurement(board);
+        let attempts = (self.special_config.readings as u32 + 2) * READING_TIMEOUT_MS / 10;
+        for _ in 0..attempts {
+            if self.poll_measurement(board) {
+                return;
+            }
+            board.run_loop_iteration(); // feeds the watchdog while the sensor measures
+            board.delay_ms(10);
+        }
+        self.teardown(board);
+    }
+
+    fn start_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        self.release_bus(board);
+        self.distance = None;
+        self.valid_count = None;
+        self.received_count = 0;
+        self.reading_count = 0;
+        self.measuring = true;
+    }
+
+    fn poll_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) -> bool {
+        if !self.measuring {
+            return true;
+        }
+
+        if !self.bus_owned {
+            // wait for any other usart user to finish with the bus
+            if usart_service::start_raw_capture(board) {
+                board.set_usart_baud_rate(usart_service::baud_rate(self.special_config.baud_rate));
+                self.bus_owned = true;
+                self.parsed = 0;
+                self.synced = false;
+                self.frame_length = 0;
+                self.last_reading_time = board.millis();
+            }
+            return false;
+        }
+
+        self.receive_readings(board);
+
+        if self.received_count >= self.special_config.readings as usize {
+            self.finish_burst(board);
+            return true;
+        }
+
+        if board.millis().wrapping_sub(self.last_reading_time) > READING_TIMEOUT_MS {
+            defmt::println!("serial level sensor stopped sending after {} readings", self.received_count);
+            self.finish_burst(board);
+            return true;
+        }
+
+        false
+    }
+
+    fn update(&mut self, values: serde_json::Value) -> Result<(), &'static str> {
+        self.special_config.update_from_values(values)
+    }
+}
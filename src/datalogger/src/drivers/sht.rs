@@ -0,0 +1,523 @@
+use serde_json::json;
+
+use crate::sensor_name_from_type_id;
+
+use super::types::*;
+
+// Sensirion SHT3x (SHT31, SHT35) and SHT4x (SHT40, SHT45) humidity and temperature sensors on i2c
+// every 16 bit word from the sensor is followed by a CRC-8 and readings that fail the check are dropped
+// the heater drives off condensation: after a reading at or above heater_threshold %RH it runs for heater_seconds,
+// and it is switched off before the next measurement starts
+
+const CHIP_SHT3X: u8 = 0;
+const CHIP_SHT4X: u8 = 1;
+
+const DEFAULT_ADDRESS: u8 = 0x44;
+const DEFAULT_HEATER_THRESHOLD: u8 = 95; // %RH
+const DEFAULT_HEATER_SECONDS: u8 = 1;
+const MAX_HEATER_SECONDS: u8 = 30;
+
+// the SHT3x calls precision repeatability
+const PRECISION_HIGH: u8 = 0;
+const PRECISION_MEDIUM: u8 = 1;
+const PRECISION_LOW: u8 = 2;
+
+const HEATER_OFF: u8 = 0;
+const HEATER_LOW: u8 = 1; // 20 mW on the SHT4x
+const HEATER_MEDIUM: u8 = 2; // 110 mW on the SHT4x
+const HEATER_HIGH: u8 = 3; // 200 mW on the SHT4x, the SHT3x heater has a single power
+
+// SHT3x single shot measurements without clock stretching
+const SHT3X_MEASURE_HIGH: [u8; 2] = [0x24, 0x00];
+const SHT3X_MEASURE_MEDIUM: [u8; 2] = [0x24, 0x0B];
+const SHT3X_MEASURE_LOW: [u8; 2] = [0x24, 0x16];
+const SHT3X_HEATER_ON: [u8; 2] = [0x30, 0x6D];
+const SHT3X_HEATER_OFF: [u8; 2] = [0x30, 0x66];
+const SHT3X_SOFT_RESET: [u8; 2] = [0x30, 0xA2];
+
+const SHT4X_MEASURE_HIGH: u8 = 0xFD;
+const SHT4X_MEASURE_MEDIUM: u8 = 0xF6;
+const SHT4X_MEASURE_LOW: u8 = 0xE0;
+const SHT4X_HEATER_LOW_1S: u8 = 0x1E;
+const SHT4X_HEATER_MEDIUM_1S: u8 = 0x2F;
+const SHT4X_HEATER_HIGH_1S: u8 = 0x39;
+const SHT4X_SOFT_RESET: u8 = 0x94;
+const SHT4X_HEATER_PULSE_MS: u32 = 1100; // 1 s pulse followed by a measurement
+
+fn chip_from_str(value: &str) -> Option<u8> {
+    match value {
+        "sht3x" => Some(CHIP_SHT3X),
+        "sht4x" => Some(CHIP_SHT4X),
+        _ => None,
+    }
+}
+
+fn chip_text(chip: u8) -> &'static str {
+    match chip {
+        CHIP_SHT4X => "sht4x",
+        _ => "sht3x",
+    }
+}
+
+fn precision_from_str(value: &str) -> Option<u8> {
+    match value {
+        "high" => Some(PRECISION_HIGH),
+        "medium" => Some(PRECISION_MEDIUM),
+        "low" => Some(PRECISION_LOW),
+        _ => None,
+    }
+}
+
+fn precision_text(precision: u8) -> &'static str {
+    match precision {
+        PRECISION_MEDIUM => "medium",
+        PRECISION_LOW => "low",
+        _ => "high",
+    }
+}
+
+fn heater_from_str(value: &str) -> Option<u8> {
+    match value {
+        "off" => Some(HEATER_OFF),
+        "low" => Some(HEATER_LOW),
+        "medium" => Some(HEATER_MEDIUM),
+        "high" => Some(HEATER_HIGH),
+        _ => None,
+    }
+}
+
+fn heater_text(heater: u8) -> &'static str {
+    match heater {
+        HEATER_LOW => "low",
+        HEATER_MEDIUM => "medium",
+        HEATER_HIGH => "high",
+        _ => "off",
+    }
+}
+
+// CRC-8 with polynomial 0x31 and initial value 0xFF, as in the Sensirion datasheets, shared with the SCD driver
+pub(crate) fn crc8(data: &[u8]) -> u8 {
+    let mut crc: u8 = 0xFF;
+    for byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x31 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+// the two words of a measurement, temperature then humidity, None if either fails the crc
+fn checked_words(data: &[u8; 6]) -> Option<(u16, u16)> {
+    if crc8(&data[0..2]) != data[2] || crc8(&data[3..5]) != data[5] {
+        return None;
+    }
+    Some((u16::from_be_bytes([data[0], data[1]]), u16::from_be_bytes([data[3], data[4]])))
+}
+
+#[derive(Copy, Clone)]
+pub struct SHTSpecialConfiguration {
+    chip: u8,
+    address: u8,
+    precision: u8,
+    heater: u8,
+    heater_threshold: u8, // %RH
+    heater_seconds: u8,
+    _empty: [u8; 26],
+}
+
+impl SHTSpecialConfiguration {
+    pub fn new_from_bytes(
+        bytes: [u8; SENSOR_SETTINGS_PARTITION_SIZE],
+    ) -> SHTSpecialConfiguration {
+        let settings = bytes.as_ptr().cast::<SHTSpecialConfiguration>();
+        unsafe { *settings }
+    }
+
+    pub fn parse_from_values(value: serde_json::Value) -> Result<SHTSpecialConfiguration, &'static str> {
+        let chip = match &value["chip"] {
+            serde_json::Value::String(chip) => match chip_from_str(chip.as_str()) {
+                Some(chip) => chip,
+                None => return Err("chip must be sht3x or sht4x"),
+            },
+            _ => return Err("chip must be sht3x or sht4x"),
+        };
+
+        let mut special_config = Self {
+            chip,
+            address: DEFAULT_ADDRESS,
+            precision: PRECISION_HIGH,
+            heater: HEATER_OFF,
+            heater_threshold: DEFAULT_HEATER_THRESHOLD,
+            heater_seconds: DEFAULT_HEATER_SECONDS,
+            _empty: [b'\0'; 26],
+        };
+        special_config.update_from_values(value)?;
+        Ok(special_config)
+    }
+
+    pub fn update_from_values(&mut self, values: serde_json::Value) -> Result<(), &'static str> {
+        match &values["chip"] {
+            serde_json::Value::String(chip) if chip_from_str(chip.as_str()) == Some(self.chip) => {}
+            serde_json::Value::Null => {}
+            _ => return Err("the chip can't be changed, remove and add the sensor"),
+        }
+
+        match &values["address"] {
+            serde_json::Value::Number(number) => {
+                match number.as_u64() {
+                    Some(number) if (0x44..=0x46).contains(&number) => self.address = number as u8,
+                    _ => return Err("address must be 0x44, 0x45 or 0x46"),
+                }
+            }
+            serde_json::Value::Null => {}
+            _ => return Err("address must be 0x44, 0x45 or 0x46"),
+        }
+
+        match &values["precision"] {
+            serde_json::Value::String(precision) => {
+                match precision_from_str(precision.as_str()) {
+                    Some(precision) => self.precision = precision,
+                    None => return Err("precision must be high, medium or low"),
+                }
+            }
+            serde_json::Value::Null => {}
+            _ => return Err("precision must be high, medium or low"),
+        }
+
+        match &values["heater"] {
+            serde_json::Value::String(heater) => {
+                match heater_from_str(heater.as_str()) {
+                    Some(heater) => self.heater = heater,
+                    None => return Err("heater must be off, low, medium or high"),
+                }
+            }
+            serde_json::Value::Null => {}
+            _ => return Err("heater must be off, low, medium or high"),
+        }
+
+        match &values["heater_threshold"] {
+            serde_json::Value::Number(number) => {
+                match number.as_u64() {
+                    Some(number) if number <= 100 => self.heater_threshold = number as u8,
+                    _ => return Err("heater_threshold must be 0 to 100 %RH"),
+                }
+            }
+            serde_json::Value::Null => {}
+            _ => return Err("heater_threshold must be 0 to 100 %RH"),
+        }
+
+        match &values["heater_seconds"] {
+            serde_json::Value::Number(number) => {
+                match number.as_u64() {
+                    Some(number) if number >= 1 && number <= MAX_HEATER_SECONDS as u64 => self.heater_seconds = number as u8,
+                    _ => return Err("heater_seconds must be 1 to 30"),
+                }
+            }
+            serde_json::Value::Null => {}
+            _ => return Err("heater_seconds must be 1 to 30"),
+        }
+
+        Ok(())
+    }
+}
+
+pub struct Sht {
+    general_config: SensorDriverGeneralConfiguration,
+    special_config: SHTSpecialConfiguration,
+    measurement_requested: bool,
+    measurement_start: Option<u32>, // None until the measurement command has been accepted
+    heater_start: Option<u32>,
+    last_heater_pulse: Option<u32>, // SHT4x
+    temperature: Option<f64>,
+    humidity: Option<f64>,
+}
+
+impl Sht {
+    pub fn new(
+        general_config: SensorDriverGeneralConfiguration,
+        special_config: SHTSpecialConfiguration,
+    ) -> Self {
+        Sht {
+            general_config,
+            special_config,
+            measurement_requested: false,
+            measurement_start: None,
+            heater_start: None,
+            last_heater_pulse: None,
+            temperature: None,
+            humidity: None,
+        }
+    }
+
+    fn write_command(&mut self, board: &mut dyn rriv_board::RRIVBoard, command: &[u8]) -> Result<(), ()> {
+        board.ic2_write(self.special_config.address, command)
+    }
+
+    fn send_measure_command(&mut self, board: &mut dyn rriv_board::RRIVBoard) -> Result<(), ()> {
+        if self.special_config.chip == CHIP_SHT3X {
+            let command = match self.special_config.precision {
+                PRECISION_MEDIUM => SHT3X_MEASURE_MEDIUM,
+                PRECISION_LOW => SHT3X_MEASURE_LOW,
+                _ => SHT3X_MEASURE_HIGH,
+            };
+            self.write_command(board, &command)
+        } else {
+            let command = match self.special_config.precision {
+                PRECISION_MEDIUM => SHT4X_MEASURE_MEDIUM,
+                PRECISION_LOW => SHT4X_MEASURE_LOW,
+                _ => SHT4X_MEASURE_HIGH,
+            };
+            self.write_command(board, &[command])
+        }
+    }
+
+    // maximum conversion time from the datasheets
+    fn measurement_time_ms(&self) -> u32 {
+        match (self.special_config.chip, self.special_config.precision) {
+            (CHIP_SHT3X, PRECISION_MEDIUM) => 7,
+            (CHIP_SHT3X, PRECISION_LOW) => 5,
+            (CHIP_SHT3X, _) => 16,
+            (_, PRECISION_MEDIUM) => 5,
+            (_, PRECISION_LOW) => 2,
+            (_, _) => 9,
+        }
+    }
+
+    fn start_heater(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        let now = board.millis();
+        let result = if self.special_config.chip == CHIP_SHT3X {
+            self.write_command(board, &SHT3X_HEATER_ON)
+        } else {
+            self.send_heater_pulse(board)
+        };
+        match result {
+            Ok(_) => {
+                self.heater_start = Some(now);
+                if self.special_config.chip == CHIP_SHT4X {
+                    self.last_heater_pulse = Some(now);
+                }
+            }
+            Err(_) => defmt::println!("failed to start {} heater", chip_text(self.special_config.chip)),
+        }
+    }
+
+    fn send_heater_pulse(&mut self, board: &mut dyn rriv_board::RRIVBoard) -> Result<(), ()> {
+        let command = match self.special_config.heater {
+            HEATER_LOW => SHT4X_HEATER_LOW_1S,
+            HEATER_MEDIUM => SHT4X_HEATER_MEDIUM_1S,
+            _ => SHT4X_HEATER_HIGH_1S,
+        };
+        self.write_command(board, &[command])
+    }
+
+    fn stop_heater(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        if self.heater_start.take().is_some() && self.special_config.chip == CHIP_SHT3X
+            && self.write_command(board, &SHT3X_HEATER_OFF).is_err()
+        {
+            defmt::println!("failed to stop sht3x heater");
+        }
+        // an SHT4x pulse ends by itself
+    }
+
+    fn heater_pulse_running(&self, now: u32) -> bool {
+        match self.last_heater_pulse {
+            Some(last_heater_pulse) => now.wrapping_sub(last_heater_pulse) < SHT4X_HEATER_PULSE_MS,
+            None => false,
+        }
+    }
+}
+
+impl SensorDriver for Sht {
+    getters!();
+
+    fn get_configuration_json(&mut self) -> serde_json::Value {
+        let mut sensor_id = self.get_id();
+        let sensor_id = util::str_from_utf8(&mut sensor_id).unwrap_or("Invalid");
+
+        let mut sensor_name = sensor_name_from_type_id(self.get_type_id().into());
+        let sensor_name = util::str_from_utf8(&mut sensor_name).unwrap_or("Invalid");
+
+        json!({
+            "id": sensor_id,
+            "type": sensor_name,
+            "chip": chip_text(self.special_config.chip),
+            "address": self.special_config.address,
+            "precision": precision_text(self.special_config.precision),
+            "heater": heater_text(self.special_config.heater),
+            "heater_threshold": self.special_config.heater_threshold,
+            "heater_seconds": self.special_config.heater_seconds,
+        })
+    }
+
+    fn setup(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        let result = if self.special_config.chip == CHIP_SHT3X {
+            self.write_command(board, &SHT3X_SOFT_RESET)
+        } else {
+            self.write_command(board, &[SHT4X_SOFT_RESET])
+        };
+        if result.is_err() {
+            defmt::println!("{} not found at 0x{:x}", chip_text(self.special_config.chip), self.special_config.address);
+        }
+        board.delay_ms(2); // soft reset time
+        self.heater_start = None;
+        self.last_heater_pulse = None;
+        self.measurement_requested = false;
+    }
+
+    fn teardown(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        self.stop_heater(board);
+    }
+
+    fn get_measured_parameter_count(&mut self) -> usize {
+        2
+    }
+
+    fn get_measured_parameter_value(&mut self, index: usize) -> Result<f64, ()> {
+        let value = match index {
+            0 => self.temperature,
+            1 => self.humidity,
+            _ => None,
+        };
+        value.ok_or(())
+    }
+
+    fn get_measured_parameter_identifier(&mut self, index: usize) -> [u8; 16] {
+        let identifier = match index {
+            0 => "temperature",
+            1 => "humidity",
+            _ => "invalid",
+        };
+        let mut buf = [0u8; 16];
+        buf[..identifier.len()].copy_from_slice(identifier.as_bytes());
+        buf
+    }
+
+    fn take_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        self.start_measurement(board);
+        // long enough for an SHT4x heater pulse to finish
+        for _ in 0..300 {
+            if self.poll_measurement(board) {
+                return;
+            }
+            board.delay_ms(5);
+        }
+    }
+
+    fn start_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        self.temperature = None;
+        self.humidity = None;
+        if self.special_config.chip == CHIP_SHT3X {
+            self.stop_heater(board);
+        }
+        self.measurement_requested = true;
+        self.measurement_start = None;
+    }
+
+    fn poll_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) -> bool {
+        if !self.measurement_requested {
+            return true;
+        }
+
+        let now = board.millis();
+        let measurement_start = match self.measurement_start {
+            Some(measurement_start) => measurement_start,
+            None => {
+                // an SHT4x doesn't accept commands during a heater pulse
+                if self.heater_pulse_running(now) {
+                    return false;
+                }
+                self.heater_start = None;
+                if self.send_measure_command(board).is_err() {
+                    defmt::println!("failed to start {} measurement", chip_text(self.special_config.chip));
+                    self.measurement_requested = false;
+                    return true;
+                }
+                self.measurement_start = Some(now);
+                return false;
+            }
+        };
+
+        if now.wrapping_sub(measurement_start) < self.measurement_time_ms() {
+            return false;
+        }
+
+        let mut data = [0u8; 6];
+        if board.ic2_read(self.special_config.address, &mut data).is_err() {
+            // the sensor doesn't acknowledge the read until the measurement is done
+            return false;
+        }
+        self.measurement_requested = false;
+
+        let (raw_temperature, raw_humidity) = match checked_words(&data) {
+            Some(words) => words,
+            None => {
+                defmt::println!("{} crc error", chip_text(self.special_config.chip));
+                return true;
+            }
+        };
+
+        let raw_temperature = raw_temperature as f64 / 65535.0;
+        let raw_humidity = raw_humidity as f64 / 65535.0;
+        self.temperature = Some(-45.0 + 175.0 * raw_temperature);
+        let humidity = if self.special_config.chip == CHIP_SHT3X {
+            100.0 * raw_humidity
+        } else {
+            -6.0 + 125.0 * raw_humidity
+        };
+        let humidity = humidity.clamp(0.0, 100.0);
+        self.humidity = Some(humidity);
+
+        if self.special_config.heater != HEATER_OFF && humidity >= self.special_config.heater_threshold as f64 {
+            self.start_heater(board);
+        }
+        true
+    }
+
+    // keeps the heater running for heater_seconds after a humid reading
+    fn update_actuators(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        let heater_start = match self.heater_start {
+            Some(heater_start) => heater_start,
+            None => return,
+        };
+        if self.measurement_requested {
+            return;
+        }
+
+        let now = board.millis();
+        if now.wrapping_sub(heater_start) >= self.special_config.heater_seconds as u32 * 1000 {
+            self.stop_heater(board);
+            return;
+        }
+
+        if self.special_config.chip == CHIP_SHT4X && !self.heater_pulse_running(now)
+            && self.send_heater_pulse(board).is_ok()
+        {
+            self.last_heater_pulse = Some(now);
+        }
+    }
+
+    fn update(&mut self, values: serde_json::Value) -> Result<(), &'static str> {
+        self.special_config.update_from_values(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc8() {
+        // example from the Sensirion datasheets
+        assert_eq!(0x92, crc8(&[0xBE, 0xEF]));
+        assert_eq!(0xFF, crc8(&[]));
+        assert_eq!(0x81, crc8(&[0x00, 0x00]));
+    }
+
+    #[test]
+    fn test_checked_words() {
+        assert_eq!(Some((0xBEEF, 0x0000)), checked_words(&[0xBE, 0xEF, 0x92, 0x00, 0x00, 0x81]));
+        assert_eq!(None, checked_words(&[0xBE, 0xEF, 0x93, 0x00, 0x00, 0x81]));
+        assert_eq!(None, checked_words(&[0xBE, 0xEF, 0x92, 0x00, 0x01, 0x81]));
+    }
+}
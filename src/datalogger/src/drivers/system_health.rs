@@ -0,0 +1,144 @@
+use serde_json::json;
+
+use crate::sensor_name_from_type_id;
+use crate::services::system_health_service;
+
+use super::types::*;
+
+// the logger's own vital signs, logged alongside the sensor values
+// read_errors counts driver timeouts and error values since the last system_health measurement
+
+const NUMBER_OF_MEASURED_PARAMETERS: usize = 8;
+
+#[derive(Copy, Clone)]
+pub struct SystemHealthSpecialConfiguration {
+    _empty: [u8; 32], // must add to 32
+}
+
+impl SystemHealthSpecialConfiguration {
+    pub fn parse_from_values(
+        value: serde_json::Value,
+    ) -> Result<SystemHealthSpecialConfiguration, &'static str> {
+        let _ = value;
+        Ok(Self { _empty: [b'\0'; 32] })
+    }
+
+    pub fn new_from_bytes(
+        bytes: [u8; SENSOR_SETTINGS_PARTITION_SIZE],
+    ) -> SystemHealthSpecialConfiguration {
+        let settings = bytes.as_ptr().cast::<SystemHealthSpecialConfiguration>();
+        unsafe { *settings }
+    }
+}
+
+pub struct SystemHealth {
+    general_config: SensorDriverGeneralConfiguration,
+    special_config: SystemHealthSpecialConfiguration,
+    battery: Option<f64>,
+    mcu_temperature: Option<f64>,
+    free_heap: Option<f64>,
+    uptime: Option<f64>,
+    boot_count: Option<f64>,
+    lorawan_joined: Option<f64>,
+    sd_free_kb: Option<f64>,
+    read_errors: Option<f64>,
+    setup_time: Option<i64>, // board timestamp when the sensor was set up
+}
+
+impl SensorDriver for SystemHealth {
+    getters!();
+
+    fn get_configuration_json(&mut self) -> serde_json::Value {
+        let mut sensor_id = self.get_id();
+        let sensor_id = util::str_from_utf8(&mut sensor_id).unwrap_or("Invalid");
+
+        let mut sensor_name = sensor_name_from_type_id(self.get_type_id().into());
+        let sensor_name = util::str_from_utf8(&mut sensor_name).unwrap_or("Invalid");
+
+        json!({
+            "id": sensor_id,
+            "type": sensor_name,
+        })
+    }
+
+    fn setup(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        // setup runs again when the sensor's power domain comes back, keep the first time
+        if self.setup_time.is_none() {
+            self.setup_time = Some(board.timestamp());
+        }
+    }
+
+    fn get_measured_parameter_count(&mut self) -> usize {
+        NUMBER_OF_MEASURED_PARAMETERS
+    }
+
+    fn get_measured_parameter_value(&mut self, index: usize) -> Result<f64, ()> {
+        match index {
+            0 => self.battery.ok_or(()),
+            1 => self.mcu_temperature.ok_or(()),
+            2 => self.free_heap.ok_or(()),
+            3 => self.uptime.ok_or(()),
+            4 => self.boot_count.ok_or(()),
+            5 => self.lorawan_joined.ok_or(()),
+            6 => self.sd_free_kb.ok_or(()),
+            7 => self.read_errors.ok_or(()),
+            _ => Err(()),
+        }
+    }
+
+    fn get_measured_parameter_identifier(&mut self, index: usize) -> [u8; 16] {
+        let identifier = match index {
+            0 => "battery",
+            1 => "mcu_temp",
+            2 => "free_heap",
+            3 => "uptime_s",
+            4 => "boot_count",
+            5 => "lorawan_joined",
+            6 => "sd_free_kb",
+            7 => "read_errors",
+            _ => "invalid",
+        };
+        let mut buf = [0u8; 16];
+        buf[..identifier.len()].copy_from_slice(identifier.as_bytes());
+        buf
+    }
+
+    fn take_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        // the board reports -1 when the battery can't be read
+        let battery = board.get_battery_level();
+        self.battery = if battery < 0 { None } else { Some(battery as f64) };
+        self.mcu_temperature = Some(board.read_temp_adc() as f64);
+        self.free_heap = system_health_service::free_heap().map(|bytes| bytes as f64);
+        self.uptime = self.setup_time.map(|setup_time| (board.timestamp() - setup_time) as f64);
+        self.boot_count = Some(board.get_boot_count() as f64);
+        self.lorawan_joined = Some(if system_health_service::lorawan_joined() { 1.0 } else { 0.0 });
+        self.sd_free_kb = board.get_sd_free_space_kb().map(|kb| kb as f64);
+        self.read_errors = Some(system_health_service::take_read_errors() as f64);
+    }
+
+    fn update(&mut self, values: serde_json::Value) -> Result<(), &'static str> {
+        let _ = values;
+        Ok(())
+    }
+}
+
+impl SystemHealth {
+    pub fn new(
+        general_config: SensorDriverGeneralConfiguration,
+        special_config: SystemHealthSpecialConfiguration,
+    ) -> Self {
+        SystemHealth {
+            general_config,
+            special_config,
+            battery: None,
+            mcu_temperature: None,
+            free_heap: None,
+            uptime: None,
+            boot_count: None,
+            lorawan_joined: None,
+            sd_free_kb: None,
+            read_errors: None,
+            setup_time: None,
+        }
+    }
+}
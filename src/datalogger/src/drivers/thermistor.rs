@@ -0,0 +1,397 @@
+use crate::sensor_name_from_type_id;
+
+use super::types::*;
+use bitfield_struct::bitfield;
+use serde_json::json;
+
+// NTC thermistor in a voltage divider with a series resistor, read from the internal or external adc
+// temperature comes from the beta model or from Steinhart-Hart coefficients, and a fit to three
+// calibration points sets the Steinhart-Hart coefficients
+// 1/T = a + b ln(R) + c ln(R)^3, with T in kelvin
+
+const KELVIN: f64 = 273.15;
+
+const ADC_SELECT_INTERNAL: usize = 0;
+const ADC_SELECT_EXTERNAL: usize = 1;
+
+const MODEL_BETA: usize = 0;
+const MODEL_STEINHART_HART: usize = 1;
+
+// the thermistor is between the adc input and ground, with the series resistor to the excitation voltage
+const POSITION_LOW: usize = 0;
+// the thermistor is between the excitation voltage and the adc input
+const POSITION_HIGH: usize = 1;
+
+const DEFAULT_ADC_BITS: u8 = 12;
+const DEFAULT_VOLTAGE: f32 = 3.3;
+const DEFAULT_T0: f32 = 25.0;
+
+#[bitfield(u8)]
+struct ThermistorBitfield {
+    #[bits(2)]
+    adc_select: usize,
+    #[bits(1)]
+    model: usize,
+    #[bits(1)]
+    position: usize,
+    #[bits(4)]
+    _unused: usize,
+}
+
+#[derive(Copy, Clone)]
+pub struct ThermistorSpecialConfiguration {
+    coefficients: [f32; 3], // beta, R0 and T0 for the beta model, or a, b and c
+    series_resistor: f32,   // ohms
+    excitation_voltage: f32,
+    adc_reference: f32, // volts at full scale
+    sensor_port: u8,
+    adc_bits: u8,
+    settings: ThermistorBitfield,
+    _empty: [u8; 5],
+}
+
+impl ThermistorSpecialConfiguration {
+    pub fn new_from_bytes(
+        bytes: [u8; SENSOR_SETTINGS_PARTITION_SIZE],
+    ) -> ThermistorSpecialConfiguration {
+        let settings = bytes.as_ptr().cast::<ThermistorSpecialConfiguration>();
+        unsafe { *settings }
+    }
+
+    pub fn parse_from_values(value: serde_json::Value) -> Result<ThermistorSpecialConfiguration, &'static str> {
+        if !value["sensor_port"].is_number() {
+            return Err("sensor_port is required");
+        }
+        if !value["series_resistor"].is_number() {
+            return Err("series_resistor is required");
+        }
+        if !value["beta"].is_number() && !value["a"].is_number() {
+            return Err("beta and r0, or Steinhart-Hart a, b and c are required");
+        }
+
+        let mut special_config = Self {
+            coefficients: [0.0, 0.0, DEFAULT_T0],
+            series_resistor: 0.0,
+            excitation_voltage: DEFAULT_VOLTAGE,
+            adc_reference: DEFAULT_VOLTAGE,
+            sensor_port: 0,
+            adc_bits: DEFAULT_ADC_BITS,
+            settings: ThermistorBitfield::new(),
+            _empty: [b'\0'; 5],
+        };
+        special_config.update_from_values(value)?;
+        Ok(special_config)
+    }
+
+    pub fn update_from_values(&mut self, values: serde_json::Value) -> Result<(), &'static str> {
+        match &values["sensor_port"] {
+            serde_json::Value::Number(number) => match number.as_u64() {
+                Some(number) if number <= u8::MAX as u64 => self.sensor_port = number as u8,
+                _ => return Err("invalid sensor port"),
+            },
+            serde_json::Value::Null => {}
+            _ => return Err("invalid sensor port"),
+        }
+
+        match &values["adc_select"] {
+            serde_json::Value::String(adc_select) => match adc_select.as_str() {
+                "internal" => self.settings.set_adc_select(ADC_SELECT_INTERNAL),
+                "external" => self.settings.set_adc_select(ADC_SELECT_EXTERNAL),
+                _ => return Err("adc_select must be internal or external"),
+            },
+            serde_json::Value::Null => {}
+            _ => return Err("adc_select must be internal or external"),
+        }
+
+        match &values["adc_bits"] {
+            serde_json::Value::Number(number) => match number.as_u64() {
+                Some(number) if (8..=24).contains(&number) => self.adc_bits = number as u8,
+                _ => return Err("adc_bits must be 8 to 24"),
+            },
+            serde_json::Value::Null => {}
+            _ => return Err("adc_bits must be 8 to 24"),
+        }
+
+        match &values["position"] {
+            serde_json::Value::String(position) => match position.as_str() {
+                "low" => self.settings.set_position(POSITION_LOW),
+                "high" => self.settings.set_position(POSITION_HIGH),
+                _ => return Err("position must be low or high"),
+            },
+            serde_json::Value::Null => {}
+            _ => return Err("position must be low or high"),
+        }
+
+        let divider = [
+            ("series_resistor", &mut self.series_resistor, "series_resistor must be a positive number of ohms"),
+            ("excitation_voltage", &mut self.excitation_voltage, "excitation_voltage must be a positive number"),
+            ("adc_reference", &mut self.adc_reference, "adc_reference must be a positive number"),
+        ];
+        for (key, setting, message) in divider {
+            match &values[key] {
+                serde_json::Value::Number(number) => match number.as_f64() {
+                    Some(number) if number > 0.0 => *setting = number as f32,
+                    _ => return Err(message),
+                },
+                serde_json::Value::Null => {}
+                _ => return Err(message),
+            }
+        }
+
+        if values["beta"].is_number() {
+            let beta = values["beta"].as_f64().unwrap_or(0.0);
+            let r0 = match values["r0"].as_f64() {
+                Some(r0) if r0 > 0.0 => r0,
+                _ => return Err("r0 must be the thermistor's resistance at t0, in ohms"),
+            };
+            let t0 = match &values["t0"] {
+                serde_json::Value::Number(number) => number.as_f64().unwrap_or(DEFAULT_T0 as f64),
+                serde_json::Value::Null => DEFAULT_T0 as f64,
+                _ => return Err("t0 must be degrees C"),
+            };
+            if beta <= 0.0 {
+                return Err("beta must be positive");
+            }
+            self.coefficients = [beta as f32, r0 as f32, t0 as f32];
+            self.settings.set_model(MODEL_BETA);
+        } else if values["a"].is_number() || values["b"].is_number() || values["c"].is_number() {
+            match (values["a"].as_f64(), values["b"].as_f64(), values["c"].as_f64()) {
+                (Some(a), Some(b), Some(c)) => {
+                    self.coefficients = [a as f32, b as f32, c as f32];
+                    self.settings.set_model(MODEL_STEINHART_HART);
+                }
+                _ => return Err("Steinhart-Hart needs all of a, b and c"),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub struct Thermistor {
+    general_config: SensorDriverGeneralConfiguration,
+    special_config: ThermistorSpecialConfiguration,
+    resistance: Option<f64>,
+    temperature: Option<f64>,
+}
+
+impl Thermistor {
+    pub fn new(
+        general_config: SensorDriverGeneralConfiguration,
+        special_config: ThermistorSpecialConfiguration,
+    ) -> Self {
+        Thermistor {
+            general_config,
+            special_config,
+            resistance: None,
+            temperature: None,
+        }
+    }
+
+    fn resistance_from_counts(&self, counts: f64) -> Option<f64> {
+        let config = &self.special_config;
+        let full_scale = ((1u32 << config.adc_bits) - 1) as f64;
+        let voltage = counts / full_scale * config.adc_reference as f64;
+        let excitation = config.excitation_voltage as f64;
+        let series = config.series_resistor as f64;
+
+        // an open or shorted thermistor puts the input at a rail
+        if voltage <= 0.0 || voltage >= excitation {
+            return None;
+        }
+        match config.settings.position() {
+            POSITION_HIGH => Some(series * (excitation - voltage) / voltage),
+            _ => Some(series * voltage / (excitation - voltage)),
+        }
+    }
+
+    fn temperature_from_resistance(&self, resistance: f64) -> f64 {
+        let [first, second, third] = self.special_config.coefficients;
+        let ln_r = util::ln(resistance);
+        let inverse_kelvin = match self.special_config.settings.model() {
+            MODEL_STEINHART_HART => first as f64 + second as f64 * ln_r + third as f64 * ln_r * ln_r * ln_r,
+            _ => {
+                // 1/T = 1/T0 + ln(R/R0)/beta
+                let (beta, r0, t0) = (first as f64, second as f64, third as f64);
+                1.0 / (t0 + KELVIN) + (ln_r - util::ln(r0)) / beta
+            }
+        };
+        1.0 / inverse_kelvin - KELVIN
+    }
+}
+
+impl SensorDriver for Thermistor {
+    getters!();
+
+    fn get_configuration_json(&mut self) -> serde_json::Value {
+        let mut sensor_id = self.get_id();
+        let sensor_id = util::str_from_utf8(&mut sensor_id).unwrap_or("Invalid");
+
+        let mut sensor_name = sensor_name_from_type_id(self.get_type_id().into());
+        let sensor_name = util::str_from_utf8(&mut sensor_name).unwrap_or("Invalid");
+
+        let config = &self.special_config;
+        let mut json = json!({
+            "id": sensor_id,
+            "type": sensor_name,
+            "sensor_port": config.sensor_port,
+            "adc_select": if config.settings.adc_select() == ADC_SELECT_EXTERNAL { "external" } else { "internal" },
+            "adc_bits": config.adc_bits,
+            "adc_reference": config.adc_reference,
+            "excitation_voltage": config.excitation_voltage,
+            "series_resistor": config.series_resistor,
+            "position": if config.settings.position() == POSITION_HIGH { "high" } else { "low" },
+        });
+        let [first, second, third] = config.coefficients;
+        if config.settings.model() == MODEL_STEINHART_HART {
+            json["a"] = first.into();
+            json["b"] = second.into();
+            json["c"] = third.into();
+        } else {
+            json["beta"] = first.into();
+            json["r0"] = second.into();
+            json["t0"] = third.into();
+        }
+        json
+    }
+
+    fn setup(&mut self, _board: &mut dyn rriv_board::RRIVBoard) {}
+
+    fn get_measured_parameter_count(&mut self) -> usize {
+        2
+    }
+
+    fn get_measured_parameter_value(&mut self, index: usize) -> Result<f64, ()> {
+        match index {
+            0 => self.resistance.ok_or(()),
+            1 => self.temperature.ok_or(()),
+            _ => Err(()),
+        }
+    }
+
+    fn get_measured_parameter_identifier(&mut self, index: usize) -> [u8; 16] {
+        let identifier = match index {
+            0 => "ohms",
+            1 => "temperature",
+            _ => "invalid",
+        };
+        let mut buf = [0u8; 16];
+        buf[..identifier.len()].copy_from_slice(identifier.as_bytes());
+        buf
+    }
+
+    fn take_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        let counts = match self.special_config.settings.adc_select() {
+            ADC_SELECT_EXTERNAL => board.query_external_adc(self.special_config.sensor_port),
+            _ => board.query_internal_adc(self.special_config.sensor_port),
+        };
+
+        self.resistance = self.resistance_from_counts(counts as f64);
+        self.temperature = self.resistance.map(|resistance| self.temperature_from_resistance(resistance));
+    }
+
+    fn get_required_calibration_point_count(&self) -> usize {
+        3
+    }
+
+    // fit Steinhart-Hart a, b and c to the three calibration points, each a temperature in C and the measured resistance
+    fn fit(&mut self, pairs: &[CalibrationPair]) -> Result<(), ()> {
+        if pairs.len() != 3 {
+            return Err(());
+        }
+
+        // a + b L + c L^3 = 1/T for each point, solved with Cramer's rule
+        let mut rows = [[0_f64; 4]; 3];
+        for i in 0..3 {
+            if pairs[i].values[0] <= 0.0 {
+                return Err(());
+            }
+            let ln_r = util::ln(pairs[i].values[0]);
+            rows[i] = [1.0, ln_r, ln_r * ln_r * ln_r, 1.0 / (pairs[i].point + KELVIN)];
+        }
+
+        let determinant = |columns: [usize; 3]| {
+            let m = |row: usize, column: usize| rows[row][columns[column]];
+            m(0, 0) * (m(1, 1) * m(2, 2) - m(1, 2) * m(2, 1))
+                - m(0, 1) * (m(1, 0) * m(2, 2) - m(1, 2) * m(2, 0))
+                + m(0, 2) * (m(1, 0) * m(2, 1) - m(1, 1) * m(2, 0))
+        };
+
+        let d = determinant([0, 1, 2]);
+        if d == 0.0 {
+            return Err(()); // points at the same resistance
+        }
+        let a = determinant([3, 1, 2]) / d;
+        let b = determinant([0, 3, 2]) / d;
+        let c = determinant([0, 1, 3]) / d;
+
+        self.special_config.coefficients = [a as f32, b as f32, c as f32];
+        self.special_config.settings.set_model(MODEL_STEINHART_HART);
+        Ok(())
+    }
+
+    fn update(&mut self, values: serde_json::Value) -> Result<(), &'static str> {
+        self.special_config.update_from_values(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a common 10k NTC
+    const A: f64 = 1.009249522e-3;
+    const B: f64 = 2.378405444e-4;
+    const C: f64 = 2.019202697e-7;
+
+    fn thermistor() -> Thermistor {
+        Thermistor::new(
+            SensorDriverGeneralConfiguration::new(*b"therm1", 0),
+            ThermistorSpecialConfiguration::new_from_bytes([0; SENSOR_SETTINGS_PARTITION_SIZE]),
+        )
+    }
+
+    fn pair(resistance: f64) -> CalibrationPair {
+        let ln_r = util::ln(resistance);
+        let temperature = 1.0 / (A + B * ln_r + C * ln_r * ln_r * ln_r) - KELVIN;
+        CalibrationPair { point: temperature, values: Box::new([resistance]) }
+    }
+
+    #[test]
+    fn test_steinhart_hart_fit() {
+        let mut thermistor = thermistor();
+        thermistor.fit(&[pair(32650.0), pair(10000.0), pair(3603.0)]).unwrap();
+
+        let [a, b, c] = thermistor.special_config.coefficients;
+        assert!((a as f64 - A).abs() < A * 1e-4);
+        assert!((b as f64 - B).abs() < B * 1e-4);
+        assert!((c as f64 - C).abs() < C * 1e-3);
+
+        for resistance in [32650.0, 10000.0, 5000.0, 3603.0] {
+            let expected = pair(resistance).point;
+            assert!((thermistor.temperature_from_resistance(resistance) - expected).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn test_steinhart_hart_fit_rejects_bad_points() {
+        let mut thermistor = thermistor();
+        assert!(thermistor.fit(&[pair(32650.0), pair(10000.0)]).is_err());
+        assert!(thermistor.fit(&[pair(32650.0), pair(10000.0), pair(10000.0)]).is_err());
+        let mut shorted = pair(3603.0);
+        shorted.values[0] = 0.0;
+        assert!(thermistor.fit(&[pair(32650.0), pair(10000.0), shorted]).is_err());
+        assert_eq!(MODEL_BETA, thermistor.special_config.settings.model());
+    }
+
+    #[test]
+    fn test_beta_model() {
+        let mut thermistor = thermistor();
+        thermistor.special_config.coefficients = [3950.0, 10000.0, 25.0];
+        assert!((thermistor.temperature_from_resistance(10000.0) - 25.0).abs() < 1e-6);
+        // 1/T = 1/T0 + ln(R/R0)/beta
+        let expected = 1.0 / (1.0 / (25.0 + KELVIN) + util::ln(2.0) / 3950.0) - KELVIN;
+        assert!((thermistor.temperature_from_resistance(20000.0) - expected).abs() < 1e-6);
+        assert!(thermistor.temperature_from_resistance(20000.0) < 25.0);
+    }
+}
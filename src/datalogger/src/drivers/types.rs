@@ -1,6 +1,7 @@
 use alloc::boxed::Box;
 
 use super::resources::gpio::*;
+use super::resources::power::PowerDomain;
 
 
 pub const SENSOR_SETTINGS_PARTITION_SIZE: usize = 32; // partitioning is part of the driver implemention, and not meaningful at the EEPROM level
@@ -8,14 +9,28 @@ pub type SensorGeneralSettingsSlice = [u8; SENSOR_SETTINGS_PARTITION_SIZE];
 #[allow(dead_code)]
 pub type SensorSpecialSettingsSlice = [u8; SENSOR_SETTINGS_PARTITION_SIZE];
 
+// stored in eeprom, so fields keep their offsets and new fields go in bytes that were zero or unused
 #[derive(Copy, Clone)]
+#[repr(C)]
 pub struct SensorDriverGeneralConfiguration {
     pub id: [u8; 6],
     pub sensor_type_id: u16,
-    pub warmup: u16,
+    pub warmup: u16, // seconds
     pub readings_per_burst: u8,
+    _reserved: u8, // padding in earlier firmware, so its contents are unknown
+    pub sampling_interval: u16, // seconds, 0 means sample in every measurement, otherwise at least a measurement cycle
+    pub adaptive_parameter: u8, // index of the measured parameter that drives adaptive sampling
+    pub adaptive_level_enabled: u8, // 1 means crossing adaptive_level starts fast sampling
+    pub adaptive_interval: u16, // seconds between measurement cycles during an event, 0 means no adaptive sampling
+    pub adaptive_duration: u16, // minutes of fast sampling after the last event
+    pub adaptive_change: f32, // change between consecutive readings that starts fast sampling, 0 means unused
+    pub adaptive_level: f32,
+    pub power_domain: u8, // see PowerDomain, 0 means always on
+    _unused: [u8; 3],
 }
 
+const _: () = assert!(core::mem::size_of::<SensorDriverGeneralConfiguration>() <= SENSOR_SETTINGS_PARTITION_SIZE);
+
 #[derive(Copy, Clone)]
 #[allow(dead_code)]
 pub struct EmptySpecialConfiguration {}
@@ -27,14 +42,25 @@ impl SensorDriverGeneralConfiguration {
             sensor_type_id: sensor_type_id,
             warmup: 0,
             readings_per_burst: 1,
+            _reserved: 0,
+            sampling_interval: 0,
+            adaptive_parameter: 0,
+            adaptive_level_enabled: 0,
+            adaptive_interval: 0,
+            adaptive_duration: 0,
+            adaptive_change: 0.0,
+            adaptive_level: 0.0,
+            power_domain: 0,
+            _unused: [0; 3],
         }
     }
 
     pub fn new_from_bytes(
         bytes: &SensorGeneralSettingsSlice,
     ) -> SensorDriverGeneralConfiguration {
+        // the byte slice isn't aligned for the f32 fields
         let settings = bytes.as_ptr().cast::<SensorDriverGeneralConfiguration>();
-        unsafe { *settings }
+        unsafe { settings.read_unaligned() }
     }
 
     pub fn empty() -> SensorDriverGeneralConfiguration {
@@ -43,6 +69,16 @@ impl SensorDriverGeneralConfiguration {
             sensor_type_id: 0,
             warmup: 0,
             readings_per_burst: 0,
+            _reserved: 0,
+            sampling_interval: 0,
+            adaptive_parameter: 0,
+            adaptive_level_enabled: 0,
+            adaptive_interval: 0,
+            adaptive_duration: 0,
+            adaptive_change: 0.0,
+            adaptive_level: 0.0,
+            power_domain: 0,
+            _unused: [0; 3],
        }
     }
 }
@@ -70,16 +106,44 @@ pub trait SensorDriver {
     fn setup(&mut self, board: &mut dyn rriv_board::RRIVBoard);
     fn get_id(&self) -> [u8; 6];
     fn get_type_id(&self) -> u16;
+    fn get_general_configuration(&self) -> SensorDriverGeneralConfiguration;
+    fn set_general_configuration(&mut self, general_config: SensorDriverGeneralConfiguration);
 
     fn get_measured_parameter_count(&mut self) -> usize;
     fn get_measured_parameter_value(&mut self, index: usize) -> Result<f64, ()>;
     fn get_measured_parameter_identifier(&mut self, index: usize) -> [u8; 16];
 
     fn take_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard);
+
+    // two phase measurement so that sensors convert concurrently without blocking the run loop
+    // start_measurement begins a conversion and poll_measurement returns true once the values are ready
+    // drivers that don't implement these take the whole measurement in start_measurement
+    fn start_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) {
+        self.take_measurement(board);
+    }
+
+    #[allow(unused)]
+    fn poll_measurement(&mut self, board: &mut dyn rriv_board::RRIVBoard) -> bool {
+        true
+    }
+
+    // seconds the measurement engine waits for poll_measurement before giving up, None for the default
+    fn get_measurement_timeout(&self) -> Option<i64> {
+        None
+    }
+
+    // wake or enable the sensor, sensors without an enable input are switched on by their power domain beforehand
+    // the measurement engine then waits the configured warmup before measuring, in a measurement cycle or in interactive mode
+    #[allow(unused)]
+    fn begin_warmup(&mut self, board: &mut dyn rriv_board::RRIVBoard) {}
     
     #[allow(unused)]
     fn update_actuators(&mut self, board: &mut dyn rriv_board::RRIVBoard) {}
 
+    // release board resources set up in setup, such as gpio interrupts, before the driver is removed or reconfigured
+    #[allow(unused)]
+    fn teardown(&mut self, board: &mut dyn rriv_board::RRIVBoard) {}
+
     // for fitting calibrations, for drivers that implement a calibration
     #[allow(unused)]
     fn fit(&mut self, pairs: &[CalibrationPair]) -> Result<(), ()> { 
@@ -87,12 +151,34 @@ pub trait SensorDriver {
         Err(()) 
     }
     fn clear_calibration(&mut self) {}
-    // fn get_required_calibration_point_count(&self) -> usize;  // TODO
+    // the number of calibration points fit takes, the datalogger keeps this many of the newest points
+    fn get_required_calibration_point_count(&self) -> usize {
+        1
+    }
 
     fn get_requested_gpios(&self) -> GpioRequest {
         GpioRequest::none()
     }
 
+    // the switched rail or load switch the sensor needs powered while it measures
+    fn get_requested_power(&self) -> PowerDomain {
+        PowerDomain::from_u8(self.get_general_configuration().power_domain)
+    }
+
+    // sensors that work between measurement cycles, such as counters, keep their power domain on while the logger sleeps
+    fn needs_power_while_sleeping(&self) -> bool {
+        false
+    }
+
+    // the gpios the driver uses, including a gpio that switches its power
+    fn get_requested_gpios_with_power(&self) -> GpioRequest {
+        let mut gpios = self.get_requested_gpios();
+        if let Some(pin) = self.get_requested_power().gpio_switch() {
+            gpios.use_pin(pin);
+        }
+        gpios
+    }
+
     fn update(&mut self, values: serde_json::Value) -> Result<(),&'static str>;
 
 }
@@ -109,6 +195,14 @@ macro_rules! getters {
             self.general_config.sensor_type_id.clone()
         }
 
+        fn get_general_configuration(&self) -> SensorDriverGeneralConfiguration {
+            self.general_config
+        }
+
+        fn set_general_configuration(&mut self, general_config: SensorDriverGeneralConfiguration) {
+            self.general_config = general_config;
+        }
+
         fn get_configuration_bytes(&self, storage: &mut [u8; rriv_board::EEPROM_SENSOR_SETTINGS_SIZE]) {
             let generic_settings_bytes: &[u8] = unsafe { util::any_as_u8_slice(&self.general_config) };
             let special_settings_bytes: &[u8] = unsafe { util::any_as_u8_slice(&self.special_config) };
@@ -150,4 +244,48 @@ pub fn copy_config_into_partition(
     };
     let offset = SENSOR_SETTINGS_PARTITION_SIZE * partition;
     storage[offset..offset + copy_size].copy_from_slice(&bytes[0..copy_size]);
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use core::mem::offset_of;
+
+    #[test]
+    fn test_general_configuration_keeps_stored_offsets() {
+        assert_eq!(0, offset_of!(SensorDriverGeneralConfiguration, id));
+        assert_eq!(6, offset_of!(SensorDriverGeneralConfiguration, sensor_type_id));
+        assert_eq!(8, offset_of!(SensorDriverGeneralConfiguration, warmup));
+        assert_eq!(10, offset_of!(SensorDriverGeneralConfiguration, readings_per_burst));
+        assert_eq!(12, offset_of!(SensorDriverGeneralConfiguration, sampling_interval));
+        assert_eq!(14, offset_of!(SensorDriverGeneralConfiguration, adaptive_parameter));
+        assert_eq!(15, offset_of!(SensorDriverGeneralConfiguration, adaptive_level_enabled));
+        assert_eq!(16, offset_of!(SensorDriverGeneralConfiguration, adaptive_interval));
+        assert_eq!(18, offset_of!(SensorDriverGeneralConfiguration, adaptive_duration));
+        assert_eq!(20, offset_of!(SensorDriverGeneralConfiguration, adaptive_change));
+        assert_eq!(24, offset_of!(SensorDriverGeneralConfiguration, adaptive_level));
+        assert_eq!(28, offset_of!(SensorDriverGeneralConfiguration, power_domain));
+        assert_eq!(SENSOR_SETTINGS_PARTITION_SIZE, core::mem::size_of::<SensorDriverGeneralConfiguration>());
+    }
+
+    #[test]
+    fn test_general_configuration_from_earlier_firmware() {
+        // id, type, warmup and readings per burst as saved before sampling_interval was added
+        let mut bytes = [0u8; SENSOR_SETTINGS_PARTITION_SIZE];
+        bytes[0..6].copy_from_slice(b"abcdef");
+        bytes[6..8].copy_from_slice(&7u16.to_ne_bytes());
+        bytes[8..10].copy_from_slice(&30u16.to_ne_bytes());
+        bytes[10] = 5;
+        bytes[11] = 0xAA; // padding
+
+        let config = SensorDriverGeneralConfiguration::new_from_bytes(&bytes);
+        assert_eq!(*b"abcdef", config.id);
+        assert_eq!(7, config.sensor_type_id);
+        assert_eq!(30, config.warmup);
+        assert_eq!(5, config.readings_per_burst);
+        assert_eq!(0, config.sampling_interval);
+        assert_eq!(0, config.adaptive_interval); // no adaptive sampling
+        assert_eq!(0.0, config.adaptive_change);
+        assert_eq!(0, config.power_domain); // always on
+    }
+}
@@ -3,6 +3,8 @@
 mod datalogger;
 mod services;
 
+pub use services::system_health_service::publish_free_heap;
+
 use core::f64;
 use core::i16::MAX;
 use core::cmp::min;
@@ -27,7 +29,7 @@ use crate::{protocol::responses, services::*, telemetry::telemeters::{Telemeter}
 use alloc::boxed::Box;
 
 mod drivers;
-use drivers::{resources::gpio::*, types::*, *};
+use drivers::{resources::gpio::*, resources::power::{PowerDomain, PowerDomains}, types::*, *};
 
 mod protocol;
 mod registry;
@@ -43,6 +45,9 @@ pub struct DataLogger {
     last_interactive_log_time: i64,
 
     assigned_gpios: GpioRequest,
+    power_domains: PowerDomains,
+    sensor_powered: [bool; EEPROM_TOTAL_SENSOR_SLOTS], // holds a reference on the sensor's power domain
+    sensor_needs_setup: [bool; EEPROM_TOTAL_SENSOR_SLOTS], // powered up again since setup, which the sensor forgot
 
     mode: DataLoggerMode,
     serial_tx_mode: DataLoggerSerialTxMode,
@@ -53,12 +58,36 @@ pub struct DataLogger {
 
     lorawan_telemeter: Option<telemetry::telemeters::lorawan::RakWireless3172>,
     sdi12_service: Option<sdi12_service::Sdi12RxProcessor>,
+    modbus_service: Option<modbus_service::ModbusSlaveService>,
 
     // measurement cycle
     completed_bursts: u8,
     readings_completed_in_current_burst: u8,
+    next_burst_time: i64, // board timestamp before which the next burst must not start
+    sensors_warmed_up: bool,
+
+    // per sensor sampling
+    last_sample_times: [Option<i64>; EEPROM_TOTAL_SENSOR_SLOTS],
+    sampled_in_current_row: [bool; EEPROM_TOTAL_SENSOR_SLOTS],
+    measurement_pending: [bool; EEPROM_TOTAL_SENSOR_SLOTS], // started but not yet collected
+    measurement_start_time: Option<i64>, // board timestamp when the sensors were started, None when no measurement is running
+
+    // adaptive sampling
+    adaptive_previous_values: [Option<f64>; EEPROM_TOTAL_SENSOR_SLOTS], // last value of each sensor's adaptive parameter
+    adaptive_interval: Option<i64>, // seconds between measurement cycles while sampling fast, None at the normal rate
+    adaptive_hold_time: i64, // board timestamp until which the fast interval is held before decaying
+
+    sdi12_measurement_time: Option<i64>, // board timestamp at which the pending SDI-12 measurement is taken
+    next_cycle_time: Option<i64>, // board timestamp at which the next measurement cycle starts, None while a cycle is running
+    hibernate_wake_time: Option<i64>, // board timestamp at which hibernate mode ends
+    last_command_time: i64, // board timestamp of the last command, the logger stays awake for a while after commands
+    trigger_source: Option<u8>, // gpio whose trigger started the current measurement, rows are tagged with it
 }
 
+const COMMAND_WINDOW_SECONDS: i64 = 30;
+const SDI12_GPIO: u8 = 5; // data pin when the logger answers as an sdi12 sensor
+const MEASUREMENT_TIMEOUT_SECONDS: i64 = 10; // give up on sensors that haven't finished by then, unless the driver asks for longer
+
 const SENSOR_DRIVER_INIT_VALUE: core::option::Option<Box<dyn drivers::types::SensorDriver>> = None;
 const CALIBRATION_INIT_VALUE: core::option::Option<Box<[types::CalibrationPair]>> = None;
 
@@ -69,13 +98,31 @@ impl DataLogger {
             sensor_drivers: [SENSOR_DRIVER_INIT_VALUE; rriv_board::EEPROM_TOTAL_SENSOR_SLOTS],
             last_interactive_log_time: 0,
             assigned_gpios: GpioRequest::none(),
+            power_domains: PowerDomains::new(),
+            sensor_powered: [false; EEPROM_TOTAL_SENSOR_SLOTS],
+            sensor_needs_setup: [false; EEPROM_TOTAL_SENSOR_SLOTS],
             mode: DataLoggerMode::Interactive,
             serial_tx_mode: DataLoggerSerialTxMode::Normal,
             calibration_point_values: [CALIBRATION_INIT_VALUE; EEPROM_TOTAL_SENSOR_SLOTS],
             lorawan_telemeter: None,
             completed_bursts: 0,
             readings_completed_in_current_burst: 0,
+            next_burst_time: 0,
+            sensors_warmed_up: false,
+            last_sample_times: [None; EEPROM_TOTAL_SENSOR_SLOTS],
+            sampled_in_current_row: [false; EEPROM_TOTAL_SENSOR_SLOTS],
+            measurement_pending: [false; EEPROM_TOTAL_SENSOR_SLOTS],
+            measurement_start_time: None,
+            adaptive_previous_values: [None; EEPROM_TOTAL_SENSOR_SLOTS],
+            adaptive_interval: None,
+            adaptive_hold_time: 0,
+            sdi12_measurement_time: None,
+            next_cycle_time: None,
+            hibernate_wake_time: None,
+            last_command_time: 0,
+            trigger_source: None,
             sdi12_service: None,
+            modbus_service: None,
         }
     }
 
@@ -86,7 +133,7 @@ impl DataLogger {
         defmt::println!("retrieved {:?}", bytes);
         let settings: DataloggerSettings = DataloggerSettings::new_from_bytes(bytes); // convert the bytes pack into a DataloggerSettings
 
-        let settings = settings.configure_defaults();
+        let settings = settings.migrate().configure_defaults();
 
         settings
     }
@@ -135,8 +182,7 @@ impl DataLogger {
         command_service::setup(board);
         usart_service::setup(board);
 
-        let sdi12_gpio = 5;
-        self.sdi12_service = Some(sdi12_service::Sdi12RxProcessor::new(sdi12_gpio));
+        self.sdi12_service = Some(sdi12_service::Sdi12RxProcessor::new(SDI12_GPIO));
 
         // read all the sensors from EEPROM
         let registry = get_registry();
@@ -168,7 +214,7 @@ impl DataLogger {
                 // check for dedicated resources
                 match self
                     .assigned_gpios
-                    .update_or_conflict(driver.get_requested_gpios())
+                    .update_or_conflict_with_power_switch(driver.get_requested_gpios(), driver.get_requested_power().gpio_switch())
                 {
                     Ok(_) => {}
                     Err(message) => {
@@ -180,6 +226,10 @@ impl DataLogger {
                     }
                 };
 
+                // the rails are on after start up, sensors are set up powered
+                self.power_domains.acquire(board, driver.get_requested_power());
+                self.sensor_powered[i] = true;
+
                 driver.setup(board);
                 self.sensor_drivers[i] = Some(driver);
             }
@@ -191,13 +241,32 @@ impl DataLogger {
             Err(err) => defmt::println!("{}", err),
         }
 
+        match self.set_up_modbus_rtu(board, self.settings.toggles.enable_modbus_rtu()) {
+            Ok(_) => {},
+            Err(err) => defmt::println!("{}", err),
+        }
+
+        // sdi12 mode claims its pin before the trigger can
+        if let DataLoggerMode::SDI12 = self.mode {
+            if let Err(err) = self.assign_sdi12_gpio() {
+                defmt::println!("{}", err);
+                self.mode = DataLoggerMode::Interactive;
+            }
+        }
+
+        match self.set_up_trigger(board, self.settings.trigger.pin(), self.settings.trigger.edge()) {
+            Ok(_) => {},
+            Err(err) => defmt::println!("{}", err),
+        }
+
         match self.mode {
             DataLoggerMode::Field => {
                 // if we are launching into field mode, write column headers to a new file
                 self.write_column_headers_to_storage(board);
+                self.start_field_mode_schedule(board);
             },
             DataLoggerMode::SDI12 => {
-                sdi12_service::setup(board, 5);
+                sdi12_service::setup(board, SDI12_GPIO);
             }
             _ => {
                 if self.settings.toggles.enable_interactive_logging() {
@@ -206,8 +275,13 @@ impl DataLogger {
                 // otherwise we are not logging to storage by default, so don't write any file yet
             }
         }
+        if !self.sensors_need_power() {
+            self.power_off_sensors(board);
+        }
+
         defmt::println!("done with setup");
 
+        self.last_command_time = board.timestamp(); // stay awake for the command window after start up
         protocol::status::send_ready_status(board);
     }
 
@@ -236,12 +310,300 @@ impl DataLogger {
                 self.assigned_gpios.release(requested_gpios);
             }
             self.lorawan_telemeter = None;
+            system_health_service::publish_lorawan_joined(false);
             return Ok(());
         }
 
     }
 
 
+    pub fn set_up_modbus_rtu(&mut self, board: &mut impl RRIVBoard, enable: bool) -> Result<(), &'static str> {
+        let mut requested_gpios = GpioRequest::none();
+        requested_gpios.use_usart();
+
+        if enable {
+            if self.modbus_service.is_some() {
+                return Ok(());
+            }
+
+            // the slave needs the usart to itself
+            if self.assigned_gpios.update_or_conflict(requested_gpios).is_err() {
+                return Err("usart pin conflict");
+            }
+            match modbus_service::ModbusSlaveService::new(board, self.settings.options.modbus_slave_id()) {
+                Ok(service) => self.modbus_service = Some(service),
+                Err(error) => {
+                    let mut requested_gpios = GpioRequest::none();
+                    requested_gpios.use_usart();
+                    self.assigned_gpios.release(requested_gpios);
+                    return Err(error);
+                }
+            }
+        } else if let Some(mut service) = self.modbus_service.take() {
+            service.stop(board);
+            self.assigned_gpios.release(requested_gpios);
+        }
+        Ok(())
+    }
+
+    fn process_modbus_request(&mut self, board: &mut impl RRIVBoard) {
+        let mut frame = [0u8; modbus_service::FRAME_SIZE];
+        let length = match &mut self.modbus_service {
+            Some(service) => match service.take_message(board, &mut frame) {
+                Some(length) => length,
+                None => return,
+            },
+            None => return,
+        };
+
+        let adu = match modbus_core::rtu::server::decode_request(&frame[..length]) {
+            Ok(Some(adu)) => adu,
+            _ => {
+                defmt::println!("invalid modbus request");
+                return;
+            }
+        };
+
+        let request = adu.pdu.0;
+        let mut words = [0u16; modbus_service::MAX_READ_QUANTITY as usize];
+        let mut bytes = [0u8; modbus_service::MAX_READ_QUANTITY as usize * 2];
+        let result = match request {
+            modbus_core::Request::ReadInputRegisters(address, quantity) => {
+                self.read_modbus_registers(board, true, address, quantity, &mut words)
+                    .and_then(|_| modbus_core::Data::from_words(&words[..quantity as usize], &mut bytes)
+                        .map_err(|_| modbus_core::Exception::ServerDeviceFailure))
+                    .map(modbus_core::Response::ReadInputRegisters)
+            }
+            modbus_core::Request::ReadHoldingRegisters(address, quantity) => {
+                self.read_modbus_registers(board, false, address, quantity, &mut words)
+                    .and_then(|_| modbus_core::Data::from_words(&words[..quantity as usize], &mut bytes)
+                        .map_err(|_| modbus_core::Exception::ServerDeviceFailure))
+                    .map(modbus_core::Response::ReadHoldingRegisters)
+            }
+            modbus_core::Request::WriteSingleRegister(address, value) => {
+                self.write_modbus_holding_registers(board, address, &[value])
+                    .map(|_| modbus_core::Response::WriteSingleRegister(address, value))
+            }
+            modbus_core::Request::WriteMultipleRegisters(address, data) => {
+                let quantity = data.len();
+                for (i, word) in words.iter_mut().enumerate().take(quantity) {
+                    *word = data.get(i).unwrap_or_default();
+                }
+                self.write_modbus_holding_registers(board, address, &words[..quantity])
+                    .map(|_| modbus_core::Response::WriteMultipleRegisters(address, quantity as u16))
+            }
+            _ => Err(modbus_core::Exception::IllegalFunction),
+        };
+
+        if adu.hdr.slave == modbus_service::BROADCAST_SLAVE_ID {
+            // broadcasts are not answered
+            return;
+        }
+
+        let pdu = match result {
+            Ok(response) => modbus_core::ResponsePdu(Ok(response)),
+            Err(exception) => modbus_core::ResponsePdu(Err(modbus_core::ExceptionResponse {
+                function: modbus_core::FunctionCode::from(request),
+                exception,
+            })),
+        };
+        if let Some(service) = &mut self.modbus_service {
+            service.send_response(board, pdu);
+        }
+    }
+
+    fn read_modbus_registers(
+        &mut self,
+        board: &mut impl RRIVBoard,
+        input: bool,
+        address: u16,
+        quantity: u16,
+        words: &mut [u16],
+    ) -> Result<(), modbus_core::Exception> {
+        if quantity == 0 || quantity as usize > words.len() {
+            return Err(modbus_core::Exception::IllegalDataValue);
+        }
+
+        let epoch = if input { board.epoch_timestamp() } else { 0 };
+        for i in 0..quantity {
+            let register = address.checked_add(i).ok_or(modbus_core::Exception::IllegalDataAddress)?;
+            let value = if input {
+                self.modbus_input_register(epoch, register)
+            } else {
+                self.modbus_holding_register(register)
+            };
+            words[i as usize] = value.ok_or(modbus_core::Exception::IllegalDataAddress)?;
+        }
+        Ok(())
+    }
+
+    fn modbus_input_register(&mut self, epoch: i64, register: u16) -> Option<u16> {
+        let sensor_registers_end = modbus_service::INPUT_REGISTER_SENSOR_BASE
+            + EEPROM_TOTAL_SENSOR_SLOTS as u16 * modbus_service::REGISTERS_PER_SENSOR;
+        match register {
+            modbus_service::INPUT_REGISTER_EPOCH => Some((epoch as u32 >> 16) as u16),
+            modbus_service::INPUT_REGISTER_EPOCH_LOW => Some(epoch as u32 as u16),
+            modbus_service::INPUT_REGISTER_SENSOR_COUNT => {
+                Some(self.sensor_drivers.iter().filter(|driver| driver.is_some()).count() as u16)
+            }
+            modbus_service::INPUT_REGISTER_MODE => Some(self.mode.to_u8() as u16),
+            register if register >= modbus_service::INPUT_REGISTER_SENSOR_BASE && register < sensor_registers_end => {
+                let offset = register - modbus_service::INPUT_REGISTER_SENSOR_BASE;
+                let slot = (offset / modbus_service::REGISTERS_PER_SENSOR) as usize;
+                let parameter = ((offset % modbus_service::REGISTERS_PER_SENSOR) / 2) as usize;
+                let value = match &mut self.sensor_drivers[slot] {
+                    Some(driver) => {
+                        if parameter < driver.get_measured_parameter_count() {
+                            driver.get_measured_parameter_value(parameter).ok()
+                        } else {
+                            None
+                        }
+                    }
+                    None => None,
+                };
+                Some(modbus_service::float_registers(value)[(offset % 2) as usize])
+            }
+            _ => None,
+        }
+    }
+
+    fn modbus_holding_register(&self, register: u16) -> Option<u16> {
+        match register {
+            0..=3 => Some(modbus_service::text_register(
+                &self.settings.logger_name,
+                (register - modbus_service::HOLDING_REGISTER_LOGGER_NAME) as usize,
+            )),
+            modbus_service::HOLDING_REGISTER_INTERACTIVE_LOGGING_INTERVAL => Some(self.settings.interactive_logging_interval),
+            modbus_service::HOLDING_REGISTER_SLEEP_INTERVAL => Some(self.settings.sleep_interval),
+            modbus_service::HOLDING_REGISTER_MODE => Some(self.mode.to_u8() as u16),
+            _ => None,
+        }
+    }
+
+    // apply holding register writes through the same path as the datalogger set command
+    fn write_modbus_holding_registers(
+        &mut self,
+        board: &mut impl RRIVBoard,
+        address: u16,
+        values: &[u16],
+    ) -> Result<(), modbus_core::Exception> {
+        const REGISTER_COUNT: usize = modbus_service::HOLDING_REGISTER_COUNT as usize;
+        let mut registers = [0u16; REGISTER_COUNT];
+        for (i, register) in registers.iter_mut().enumerate() {
+            *register = self.modbus_holding_register(i as u16).unwrap_or_default();
+        }
+
+        let mut written = [false; REGISTER_COUNT];
+        for (i, value) in values.iter().enumerate() {
+            let register = address as usize + i;
+            if register >= REGISTER_COUNT {
+                return Err(modbus_core::Exception::IllegalDataAddress);
+            }
+            registers[register] = *value;
+            written[register] = true;
+        }
+
+        let mut payload = json!({ "object": "datalogger", "action": "set" });
+        if written[0..4].contains(&true) {
+            let mut logger_name = [0u8; 8];
+            for (register, value) in registers.iter().enumerate().take(4) {
+                modbus_service::set_text_register(&mut logger_name, register, *value);
+            }
+            let length = logger_name.iter().position(|c| *c == 0).unwrap_or(logger_name.len());
+            let logger_name = core::str::from_utf8(&logger_name[..length])
+                .map_err(|_| modbus_core::Exception::IllegalDataValue)?;
+            payload["logger_name"] = json!(logger_name);
+        }
+        if written[modbus_service::HOLDING_REGISTER_INTERACTIVE_LOGGING_INTERVAL as usize] {
+            payload["interactive_logging_interval"] =
+                json!(registers[modbus_service::HOLDING_REGISTER_INTERACTIVE_LOGGING_INTERVAL as usize]);
+        }
+        if written[modbus_service::HOLDING_REGISTER_SLEEP_INTERVAL as usize] {
+            payload["sleep_interval"] = json!(registers[modbus_service::HOLDING_REGISTER_SLEEP_INTERVAL as usize]);
+        }
+        if written[modbus_service::HOLDING_REGISTER_MODE as usize] {
+            let mode = registers[modbus_service::HOLDING_REGISTER_MODE as usize];
+            if !(1..=4).contains(&mode) {
+                return Err(modbus_core::Exception::IllegalDataValue);
+            }
+            payload["mode"] = json!(datalogger::modes::mode_text(&DataLoggerMode::from_u8(mode as u8)));
+        }
+
+        let payload: DataloggerSetPayload =
+            serde_json::from_value(payload).map_err(|_| modbus_core::Exception::IllegalDataValue)?;
+        match self.update_datalogger_settings(board, payload) {
+            Ok(_) => Ok(()),
+            Err(error) => {
+                defmt::println!("modbus write rejected: {}", error);
+                Err(modbus_core::Exception::IllegalDataValue)
+            }
+        }
+    }
+
+    pub fn set_up_trigger(&mut self, board: &mut impl RRIVBoard, pin: u8, edge: u8) -> Result<(), &'static str> {
+        if pin == 0 {
+            return Ok(());
+        }
+
+        let mut requested_gpios = GpioRequest::none();
+        requested_gpios.use_pin(pin);
+        self.assigned_gpios.update_or_conflict(requested_gpios)?;
+
+        if let Err(error) = trigger_service::setup(board, pin, edge) {
+            let mut requested_gpios = GpioRequest::none();
+            requested_gpios.use_pin(pin);
+            self.assigned_gpios.release(requested_gpios);
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    fn assign_sdi12_gpio(&mut self) -> Result<(), &'static str> {
+        let mut requested_gpios = GpioRequest::none();
+        requested_gpios.use_pin(SDI12_GPIO);
+        self.assigned_gpios.update_or_conflict(requested_gpios)
+    }
+
+    fn release_sdi12_gpio(&mut self) {
+        let mut requested_gpios = GpioRequest::none();
+        requested_gpios.use_pin(SDI12_GPIO);
+        self.assigned_gpios.release(requested_gpios);
+    }
+
+    fn tear_down_trigger(&mut self, board: &mut impl RRIVBoard) {
+        let pin = self.settings.trigger.pin();
+        if pin == 0 {
+            return;
+        }
+
+        trigger_service::teardown(board, pin);
+        let mut requested_gpios = GpioRequest::none();
+        requested_gpios.use_pin(pin);
+        self.assigned_gpios.release(requested_gpios);
+    }
+
+    fn process_trigger(&mut self, board: &mut impl RRIVBoard, pin: u8) {
+        if pin != self.settings.trigger.pin() {
+            return; // left over from a trigger that has been changed
+        }
+
+        match self.mode {
+            DataLoggerMode::Interactive => {
+                // measure on the next pass through interactive mode
+                self.trigger_source = Some(pin);
+                self.last_interactive_log_time = 0;
+            }
+            // otherwise a measurement cycle is already running
+            DataLoggerMode::Field if self.next_cycle_time.is_some() => {
+                defmt::println!("measurement cycle triggered by gpio{}", pin);
+                self.next_cycle_time = None;
+                self.initialize_measurement_cycle(board);
+                self.trigger_source = Some(pin);
+            }
+            _ => {}
+        }
+    }
+
     pub fn run_loop_iteration(&mut self, board: &mut impl RRIVBoard) {
         //
         // Process incoming commands
@@ -251,6 +613,7 @@ impl DataLogger {
             match get_command_result {
                 Ok(command_payload) => {
                     self.execute_command(board, command_payload);
+                    self.last_command_time = board.timestamp();
                 }
                 Err(error) => {
 
@@ -277,21 +640,20 @@ impl DataLogger {
             }        
         }
 
-        // //
-        // // receive serialb (modbus) message
-        // //
-
-        // if let Some(modbus_service) = &mut self.modbus_service {
-        //     match modbus_service.take_message(board) {
-        //         Ok(adu) => {
-        //             // relay this to the modbus driver, if configured
-        //         },
-        //         Err(_) => {},
-        //     }
-        // }
+        //
+        // Answer modbus rtu requests when acting as a slave
+        //
+        self.process_modbus_request(board);
 
         self.update_actuators(board);
 
+        //
+        // Start a measurement when the trigger pin fires, this also ends any sleep
+        //
+        if let Some(pin) = trigger_service::take_trigger() {
+            self.process_trigger(board, pin);
+        }
+
         //
         // Do the measurement cycle
         //
@@ -304,17 +666,31 @@ impl DataLogger {
                 // process telemetry
                 // process actuators
 
-                if board.timestamp()
+                // warm the sensors up after they are powered on, as before the first burst of a measurement cycle
+                if !self.sensors_warmed_up {
+                    let warmup = self.begin_sensor_warmup(board);
+                    self.sensors_warmed_up = true;
+                    self.next_burst_time = board.timestamp() + warmup as i64;
+                    if warmup > 0 {
+                        defmt::println!("warming up sensors for {}s", warmup);
+                    }
+                }
+                let warmed_up = board.timestamp() >= self.next_burst_time;
+
+                let measurement_running = self.measurement_start_time.is_some();
+                let measurement_due = measurement_running || (warmed_up && board.timestamp()
                     >= self.last_interactive_log_time
-                        + self.settings.interactive_logging_interval as i64
-                {
+                        + self.settings.interactive_logging_interval as i64);
+
+                if measurement_due && !measurement_running {
                     // process a single measurement
                     // is this called a 'single measurement cycle' ?
 
                     self.process_errors(board);
+                }
 
-                    self.measure_sensor_values(board); // measureSensorValues(false);
-
+                // the sensors measure concurrently, output the row once they have all finished
+                if measurement_due && self.measure_sensor_values(board) { // measureSensorValues(false);
 
                     self.write_last_measurement_to_serial(board); //outputLastMeasurement();
                                                                   // Serial2.print(F("CMD >> "));
@@ -324,6 +700,7 @@ impl DataLogger {
                         self.write_raw_measurement_to_storage(board);
                     }                    
 
+                    self.trigger_source = None;
                     self.last_interactive_log_time = board.timestamp();
                 }
                 
@@ -336,33 +713,55 @@ impl DataLogger {
                 // maybe we have sleep_interval (minutes) and interactive_logged_interval (seconds)
 
 
-                self.process_errors(board);
-                self.run_measurement_cycle(board);
-                if self.measurement_cycle_completed() {
-                    defmt::println!("Measurement cycle completed");
+                if let Some(next_cycle_time) = self.next_cycle_time {
+                    let now = board.timestamp();
+                    if now >= next_cycle_time {
+                        self.next_cycle_time = None;
+                        // start the next measurement cycle
+                        self.initialize_measurement_cycle(board);
+                    } else if self.sleep_allowed(board) {
+                        // go to sleep until the next cycle is due
+                        let wake_epoch = board.epoch_timestamp() + (next_cycle_time - now);
+                        board.sleep_until(wake_epoch);
+                    }
+                    // otherwise keep processing commands while waiting for the next cycle
+                } else {
+                    self.process_errors(board);
+                    self.run_measurement_cycle(board);
+                    if self.measurement_cycle_completed() {
+                        defmt::println!("Measurement cycle completed");
 
-                    self.process_telemetry(board);
+                        self.process_telemetry(board);
 
-                    //     // go to sleep until the next in interval (in minutes)
-                    let mut slept = 0u64;
-                    while slept < (self.settings.sleep_interval as u64) * 1000u64 * 60u64 {
-                        // TODO: allow using interactive_logging_interval here for testing
-                        board.delay_ms(2000);
-                        board.sleep(); // TODO: this just feeds the watchdog for now
-                        slept = slept + 2000;
+                        // wait until the next cycle is due
+                        self.schedule_next_measurement_cycle(board);
+                        self.power_off_sensors(board);
                     }
-
-                    //     // start the next measurement cycle
-                    self.initialize_measurement_cycle();
                 }
             }
             DataLoggerMode::HibernateUntil => {
                 // this block is processed when we start up, don't get an interactive mode interrupt, and are in HibernateUntil mode
                 // or when we have just entered this mode
-                // TODO: hibernate until the requested wake time
+                if self.hibernate_wake_time.is_none() {
+                    self.schedule_hibernate_wake(board);
+                }
+
+                if let Some(hibernate_wake_time) = self.hibernate_wake_time {
+                    if board.timestamp() >= hibernate_wake_time {
+                        // the internal clock can drift from the RTC, so check against the RTC before waking
+                        if board.epoch_timestamp() >= self.settings.hibernate_until as i64 {
+                            self.wake_from_hibernate(board);
+                        } else {
+                            self.hibernate_wake_time = None;
+                        }
+                    } else if self.sleep_allowed(board) {
+                        board.sleep_until(self.settings.hibernate_until as i64);
+                    }
+                }
             }
             DataLoggerMode::SDI12 => {
                 let mut take_measurement = false;
+                let warmup = self.max_sensor_warmup();
                 if let Some(sdi12_service) = &mut self.sdi12_service {
                     // if sdi12_service.is_awake() == false {
                     //     sdi12_service.wake_up(board);
@@ -386,7 +785,7 @@ impl DataLogger {
                                             }
                                         }
 
-                                        let measurement_time = 5; // 5 seconds approximate for now
+                                        let measurement_time = sdi12_service::measurement_time(warmup);
                                         defmt::println!("total_measurements: {}", total_measurements_count);
                                         sdi12_service.send_ha_ack(board, '0', measurement_time, total_measurements_count);
                                         sdi12_service.set_total_measurements(total_measurements_count);
@@ -408,7 +807,7 @@ impl DataLogger {
                                             }
 
 
-                                            let measurement_time = 5; // 5 seconds approximate for now
+                                            let measurement_time = sdi12_service::measurement_time(warmup);
                                             defmt::println!("total_measurements: {}", total_measurements_count);
                                             let n = if total_measurements_count > 9 {9_u8} else {total_measurements_count as u8};
                                             sdi12_service.send_m_ack(board, '0', measurement_time, n);
@@ -420,6 +819,11 @@ impl DataLogger {
                                         defmt::println!("Sent Ack to M");
                                     }
 
+                                    Sdi12Command::C | Sdi12Command::Cc(_) | Sdi12Command::R(_) => {
+                                        defmt::println!("SDI12: command not supported");
+                                        sdi12_service.sleep(board);
+                                    }
+
                                     Sdi12Command::D(digit) => {
                                        
                                             let mut data_send: [f64; MEASUREMENTS_IN_PAYLOAD as usize] = [f64::MAX; MEASUREMENTS_IN_PAYLOAD as usize];
@@ -471,9 +875,23 @@ impl DataLogger {
                 }
 
                 if take_measurement {
+                    // measure once the sensors have warmed up, the recorder waits ttt before requesting data
+                    let warmup = self.begin_sensor_warmup(board);
+                    self.sdi12_measurement_time = Some(board.timestamp() + warmup as i64);
+                }
+
+                let measurement_due = match self.sdi12_measurement_time {
+                    Some(measurement_time) => board.timestamp() >= measurement_time,
+                    None => false,
+                };
+
+                if measurement_due && self.measurement_start_time.is_none() {
                     board.usb_serial_send(format_args!("SDI12: taking measurement\n"));
+                }
 
-                    self.measure_sensor_values(board);
+                // the sensors measure concurrently, fill the data once they have all finished
+                if measurement_due && self.measure_sensor_values(board) {
+                    self.sdi12_measurement_time = None;
 
                     let sdi12_service =  self.sdi12_service.as_mut().unwrap();
 
@@ -500,24 +918,277 @@ impl DataLogger {
         }
     }
 
-    fn initialize_measurement_cycle(&mut self) {
+    fn initialize_measurement_cycle(&mut self, board: &mut impl rriv_board::RRIVBoard) {
         self.completed_bursts = 0;
         self.readings_completed_in_current_burst = 0;
+        // give the sensors start_up_delay seconds after wake before the first burst
+        self.next_burst_time = board.timestamp() + self.settings.start_up_delay as i64;
+        self.sensors_warmed_up = false;
+        self.power_on_sensors(board);
+        self.trigger_source = None;
+        self.measurement_start_time = None;
+    }
+
+    // stay awake for a while after start up and after each command so the logger can be
+    // reconfigured over USB, which doesn't work while the MCU is sleeping
+    // the modbus rtu slave can't receive requests while sleeping either
+    fn sleep_allowed(&mut self, board: &mut impl rriv_board::RRIVBoard) -> bool {
+        board.timestamp() - self.last_command_time >= COMMAND_WINDOW_SECONDS && self.modbus_service.is_none()
+    }
+
+    fn schedule_hibernate_wake(&mut self, board: &mut impl rriv_board::RRIVBoard) {
+        let now = board.epoch_timestamp();
+        let wait = self.settings.hibernate_until as i64 - now;
+        let wait = if wait > 0 { wait } else { 0 };
+        defmt::println!("hibernating for {}s", wait);
+        self.hibernate_wake_time = Some(board.timestamp() + wait);
+    }
+
+    fn wake_from_hibernate(&mut self, board: &mut impl rriv_board::RRIVBoard) {
+        defmt::println!("hibernate complete, switching to field mode");
+        self.hibernate_wake_time = None;
+        self.mode = DataLoggerMode::Field;
+        self.settings.mode = self.mode.to_u8();
+        self.store_settings(board);
+
+        // the deployment starts with a fresh log file
+        board.create_log_file();
+        self.write_column_headers_to_storage(board);
+        self.start_field_mode_schedule(board);
+    }
+
+    fn start_field_mode_schedule(&mut self, board: &mut impl rriv_board::RRIVBoard) {
+        if self.settings.options.clock_aligned() {
+            // wait for the first aligned time slot
+            self.schedule_next_measurement_cycle(board);
+        } else {
+            self.next_cycle_time = None;
+            self.initialize_measurement_cycle(board);
+        }
+    }
+
+    fn schedule_next_measurement_cycle(&mut self, board: &mut impl rriv_board::RRIVBoard) {
+        let wait: i64 = if let Some(adaptive_wait) = self.adaptive_wait(board) {
+            adaptive_wait
+        } else if self.settings.options.clock_aligned() {
+            // wake early enough that the first burst lands on the aligned time
+            let lead = self.settings.start_up_delay as i64 + self.max_sensor_warmup() as i64;
+            let now = board.epoch_timestamp();
+            let next_cycle_epoch = datalogger::schedule::next_cycle_epoch(&self.settings, now + lead);
+            next_cycle_epoch - lead - now
+        } else {
+            self.settings.sleep_interval as i64 * 60
+        };
+
+        defmt::println!("next measurement cycle in {}s", wait);
+        self.next_cycle_time = Some(board.timestamp() + wait);
+    }
+
+    // the wait before the next cycle while sampling fast, None when back at the normal sleep_interval
+    fn adaptive_wait(&mut self, board: &mut impl rriv_board::RRIVBoard) -> Option<i64> {
+        let interval = self.adaptive_interval?;
+        if board.timestamp() < self.adaptive_hold_time {
+            return Some(interval);
+        }
+
+        // the event is over, decay back to the normal interval by doubling the wait each cycle
+        let normal_interval = self.settings.sleep_interval as i64 * 60;
+        let decayed_interval = interval * 2;
+        if decayed_interval >= normal_interval {
+            defmt::println!("adaptive sampling ended");
+            self.adaptive_interval = None;
+            return None;
+        }
+        self.adaptive_interval = Some(decayed_interval);
+        Some(decayed_interval)
+    }
+
+    // start fast sampling when a sensor's adaptive parameter changes by more than its threshold
+    // or crosses its level between consecutive readings
+    fn check_adaptive_rules(&mut self, board: &mut impl rriv_board::RRIVBoard) {
+        let now = board.timestamp();
+        for i in 0..self.sensor_drivers.len() {
+            if !self.sampled_in_current_row[i] {
+                continue;
+            }
+            if let Some(ref mut driver) = self.sensor_drivers[i] {
+                let config = driver.get_general_configuration();
+                if config.adaptive_interval == 0 {
+                    continue;
+                }
+
+                let value = match driver.get_measured_parameter_value(config.adaptive_parameter as usize) {
+                    Ok(value) => value,
+                    Err(_) => continue,
+                };
+                let previous = match self.adaptive_previous_values[i].replace(value) {
+                    Some(previous) => previous,
+                    None => continue,
+                };
+
+                let change = if value > previous { value - previous } else { previous - value };
+                let changed = config.adaptive_change > 0.0 && change >= config.adaptive_change as f64;
+                let level = config.adaptive_level as f64;
+                let crossed = config.adaptive_level_enabled == 1 && ((previous < level) != (value < level));
+                if !changed && !crossed {
+                    continue;
+                }
+
+                // the fastest rule wins and each event extends the hold
+                let interval = config.adaptive_interval as i64;
+                defmt::println!("adaptive sampling event on slot {}, sampling every {}s", i, interval);
+                self.adaptive_interval = match self.adaptive_interval {
+                    Some(current) if current < interval => Some(current),
+                    _ => Some(interval),
+                };
+                let hold_time = now + config.adaptive_duration as i64 * 60;
+                if hold_time > self.adaptive_hold_time {
+                    self.adaptive_hold_time = hold_time;
+                }
+            }
+        }
+    }
+
+    // sensors are powered all the time outside of field mode, in field mode only during measurement cycles
+    // unless they need power while sleeping
+    fn sensors_need_power(&self) -> bool {
+        match self.mode {
+            DataLoggerMode::Field => self.next_cycle_time.is_none(),
+            DataLoggerMode::HibernateUntil => false,
+            _ => true,
+        }
+    }
+
+    fn power_on_sensors(&mut self, board: &mut impl rriv_board::RRIVBoard) {
+        for i in 0..self.sensor_drivers.len() {
+            if self.sensor_powered[i] {
+                continue;
+            }
+            if let Some(ref driver) = self.sensor_drivers[i] {
+                // sensors lose their configuration when their domain was switched off,
+                // they are set up again once they have started up
+                let domain = driver.get_requested_power();
+                if domain != PowerDomain::AlwaysOn && !self.power_domains.is_on(domain) {
+                    self.sensor_needs_setup[i] = true;
+                }
+            }
+        }
+        for i in 0..self.sensor_drivers.len() {
+            if self.sensor_powered[i] {
+                continue;
+            }
+            if let Some(ref driver) = self.sensor_drivers[i] {
+                self.power_domains.acquire(board, driver.get_requested_power());
+                self.sensor_powered[i] = true;
+            }
+        }
+    }
+
+    // set up the sensors that were powered up again
+    fn set_up_powered_sensors(&mut self, board: &mut impl rriv_board::RRIVBoard) {
+        for i in 0..self.sensor_drivers.len() {
+            if !self.sensor_needs_setup[i] || !self.sensor_powered[i] {
+                continue;
+            }
+            if let Some(ref mut driver) = self.sensor_drivers[i] {
+                driver.setup(board);
+            }
+            self.sensor_needs_setup[i] = false;
+        }
+    }
+
+    fn power_off_sensors(&mut self, board: &mut impl rriv_board::RRIVBoard) {
+        for i in 0..self.sensor_drivers.len() {
+            if !self.sensor_powered[i] {
+                continue;
+            }
+            if let Some(ref driver) = self.sensor_drivers[i] {
+                if driver.needs_power_while_sleeping() {
+                    continue;
+                }
+                self.power_domains.release(board, driver.get_requested_power());
+            }
+            self.sensor_powered[i] = false;
+        }
+    }
+
+    fn max_sensor_warmup(&self) -> u16 {
+        let mut warmup = 0;
+        for i in 0..self.sensor_drivers.len() {
+            if let Some(driver) = &self.sensor_drivers[i] {
+                let requested = driver.get_general_configuration().warmup;
+                if requested > warmup {
+                    warmup = requested;
+                }
+            }
+        }
+        warmup
+    }
+
+    // switch all the sensors on together so their warmups overlap
+    // returns the number of seconds until the slowest sensor is warmed up
+    fn begin_sensor_warmup(&mut self, board: &mut impl rriv_board::RRIVBoard) -> u16 {
+        self.set_up_powered_sensors(board);
+        for i in 0..self.sensor_drivers.len() {
+            if let Some(ref mut driver) = self.sensor_drivers[i] {
+                driver.begin_warmup(board);
+            }
+        }
+        self.max_sensor_warmup()
     }
 
     fn measurement_cycle_completed(&self) -> bool {
         self.completed_bursts >= self.settings.bursts_per_measurement_cycle
     }
 
+    fn readings_per_burst(&self) -> u8 {
+        // the datalogger setting overrides the sensors when it is set
+        if self.settings.readings_per_burst > 0 {
+            return self.settings.readings_per_burst;
+        }
+
+        // otherwise the burst is as long as the longest burst requested by any sensor
+        let mut readings_per_burst = 1;
+        for i in 0..self.sensor_drivers.len() {
+            if let Some(driver) = &self.sensor_drivers[i] {
+                let requested = driver.get_general_configuration().readings_per_burst;
+                if requested > readings_per_burst {
+                    readings_per_burst = requested;
+                }
+            }
+        }
+        readings_per_burst
+    }
+
     fn run_measurement_cycle(&mut self, board: &mut impl rriv_board::RRIVBoard) {
-        // calculate the total number of readings per burst, max of readings requests on each sensor.
-        // allow burst length to be set per sensors OR overwridden
+        // wait out start_up_delay or delay_between_bursts without blocking the run loop,
+        // so that commands continue to be processed
+        if board.timestamp() < self.next_burst_time {
+            return;
+        }
+
+        // warm the sensors up once per cycle, before the first burst
+        if !self.sensors_warmed_up {
+            let warmup = self.begin_sensor_warmup(board);
+            self.sensors_warmed_up = true;
+            if warmup > 0 {
+                defmt::println!("warming up sensors for {}s", warmup);
+                self.next_burst_time = board.timestamp() + warmup as i64;
+                return;
+            }
+        }
 
-        let readings_per_burst: u8 = 10; // TODO get it from settings (need to be added there)
+        let readings_per_burst = self.readings_per_burst();
 
         // get next raw reading
-        defmt::println!("measuring sensor values in cycle");
-        self.measure_sensor_values(board);
+        // the sensors measure concurrently and commands keep being processed until they have all finished
+        if self.measurement_start_time.is_none() {
+            defmt::println!("measuring sensor values in cycle");
+        }
+        if !self.measure_sensor_values(board) {
+            return;
+        }
+        self.check_adaptive_rules(board);
         self.readings_completed_in_current_burst = self.readings_completed_in_current_burst + 1;
         defmt::println!(
             "completed reading {}",
@@ -538,6 +1209,8 @@ impl DataLogger {
         if self.readings_completed_in_current_burst >= readings_per_burst {
             defmt::println!("completed burst {}", self.completed_bursts);
             self.completed_bursts = self.completed_bursts + 1;
+            self.readings_completed_in_current_burst = 0;
+            self.next_burst_time = board.timestamp() + self.settings.delay_between_bursts as i64;
         }
         defmt::println!("run_measurement_cycle done");
     }
@@ -611,12 +1284,78 @@ impl DataLogger {
         }
     }
 
-    fn measure_sensor_values(&mut self, board: &mut impl rriv_board::RRIVBoard) {
+    // start a measurement on every sensor that is due, returns without waiting for them
+    fn start_sensor_measurements(&mut self, board: &mut impl rriv_board::RRIVBoard) {
+        // outside of field mode there is no warmup to set the sensors up in
+        self.set_up_powered_sensors(board);
+
+        let now = board.timestamp();
+        for i in 0..self.sensor_drivers.len() {
+            self.sampled_in_current_row[i] = false;
+            self.measurement_pending[i] = false;
+            if let Some(ref mut driver) = self.sensor_drivers[i] {
+                // sensors with a sampling interval are only measured when they are due
+                // and otherwise keep their last values for telemetry
+                // the interval is at least a cycle long, see schedule::sampling_interval_allowed
+                let sampling_interval = driver.get_general_configuration().sampling_interval as i64;
+                let due = match self.last_sample_times[i] {
+                    Some(last_sample_time) => now - last_sample_time >= sampling_interval,
+                    None => true,
+                };
+                if !due {
+                    continue;
+                }
+
+                driver.start_measurement(board);
+                self.last_sample_times[i] = Some(now);
+                self.sampled_in_current_row[i] = true;
+                self.measurement_pending[i] = true;
+            }
+        }
+        self.measurement_start_time = Some(now);
+    }
+
+    // collect the sensors that have finished, returns true once all of them have
+    fn poll_sensor_measurements(&mut self, board: &mut impl rriv_board::RRIVBoard) -> bool {
+        let elapsed = self.measurement_start_time.map(|start_time| board.timestamp() - start_time);
+
+        let mut complete = true;
         for i in 0..self.sensor_drivers.len() {
+            if !self.measurement_pending[i] {
+                continue;
+            }
             if let Some(ref mut driver) = self.sensor_drivers[i] {
-                driver.take_measurement(board);
+                let timeout = driver.get_measurement_timeout().unwrap_or(MEASUREMENT_TIMEOUT_SECONDS);
+                let timed_out = match elapsed {
+                    Some(elapsed) => elapsed >= timeout,
+                    None => true,
+                };
+                if driver.poll_measurement(board) {
+                    self.measurement_pending[i] = false;
+                } else if timed_out {
+                    defmt::println!("measurement timed out on slot {}", i);
+                    system_health_service::record_read_error();
+                    self.measurement_pending[i] = false;
+                } else {
+                    complete = false;
+                }
+            } else {
+                self.measurement_pending[i] = false;
             }
         }
+
+        if complete {
+            self.measurement_start_time = None;
+        }
+        complete
+    }
+
+    // returns true once the values of every sensor in the row have been measured
+    fn measure_sensor_values(&mut self, board: &mut impl rriv_board::RRIVBoard) -> bool {
+        if self.measurement_start_time.is_none() {
+            self.start_sensor_measurements(board);
+        }
+        self.poll_sensor_measurements(board)
     }
 
     fn update_actuators(&mut self, board: &mut impl rriv_board::RRIVBoard) {
@@ -697,6 +1436,12 @@ impl DataLogger {
                 }
 
                 for j in 0..driver.get_measured_parameter_count() {
+                    if !self.sampled_in_current_row[i] {
+                        // not sampled in this row, leave the cell empty
+                        board.usb_serial_send(format_args!("{}",","));
+                        continue;
+                    }
+
                     match driver.get_measured_parameter_value(j) {
                         Ok(value) => {
                             let value = (value * 1000f64) as i32;
@@ -729,7 +1474,10 @@ impl DataLogger {
                 hardware_error_text(errors[0])
             }
         };
-        board.usb_serial_send(format_args!("{}\n", error_text));
+        match self.trigger_source {
+            Some(pin) if error_text.is_empty() => board.usb_serial_send(format_args!("trigger_gpio{}\n", pin)),
+            _ => board.usb_serial_send(format_args!("{}\n", error_text)),
+        }
     }
 
     fn write_raw_measurement_to_storage(&mut self, board: &mut impl rriv_board::RRIVBoard) {
@@ -739,8 +1487,15 @@ impl DataLogger {
 
         // TODO: find a better way to print this uid, or generate and use a UUID that doesn't come from the MCU's uid
         let uid = board.get_uid();
+
+        // rows measured because of a trigger are tagged with the trigger's pin
+        match self.trigger_source {
+            Some(pin) => board.write_log_file(format_args!("trigger_gpio{},", pin)),
+            None => board.write_log_file(format_args!("raw,")),
+        }
+
         let output = format_args!(
-            "raw,{},{},{},-,{:X?}{:X?}{:X?}{:X?}{:X?}{:X?}{:X?}{:X?}{:X?}{:X?}{:X?}{:X?},{}.{},{},",
+            "{},{},{},-,{:X?}{:X?}{:X?}{:X?}{:X?}{:X?}{:X?}{:X?}{:X?}{:X?}{:X?}{:X?},{}.{},{},",
             util::str_from_utf8(&mut self.settings.site_name).unwrap_or_default(),
             util::str_from_utf8(&mut self.settings.logger_name).unwrap_or_default(),
             util::str_from_utf8(&mut self.settings.deployment_identifier).unwrap_or_default(),
@@ -773,6 +1528,14 @@ impl DataLogger {
 
 
                 for j in 0..driver.get_measured_parameter_count() {
+                    if !self.sampled_in_current_row[i] {
+                        // not sampled in this row, leave the cell empty
+                        if j != driver.get_measured_parameter_count() - 1 {
+                            board.write_log_file(format_args!(","));
+                        }
+                        continue;
+                    }
+
                     match driver.get_measured_parameter_value(j) {
                         Ok(value) => {
                             let value = (value * 1000f64) as i32;
@@ -793,6 +1556,7 @@ impl DataLogger {
                         Err(_) => {
                             defmt::println!("{}", "Error getting measurement value");
                             board.write_log_file(format_args!("Error"));
+                            system_health_service::record_read_error();
                         }
                     }
 
@@ -855,17 +1619,34 @@ impl DataLogger {
             // persistant mode is locked, do nothing.
             return false;
         }
-        
+
+        let in_sdi12_mode = matches!(self.mode, DataLoggerMode::SDI12);
+        if mode == "sdi12" && !in_sdi12_mode {
+            if let Err(error) = self.assign_sdi12_gpio() {
+                defmt::println!("{}", error);
+                return false;
+            }
+        } else if mode != "sdi12" && in_sdi12_mode {
+            self.release_sdi12_gpio();
+        }
+
         match mode {
             "field" => {
                 self.mode = DataLoggerMode::Field;
                 // switching into field mode should create a new file
                 self.write_column_headers_to_storage(board);
+                self.start_field_mode_schedule(board);
+            }
+            "hibernate" => {
+                self.mode = DataLoggerMode::HibernateUntil;
+                self.serial_tx_mode = DataLoggerSerialTxMode::Quiet;
+                board.set_debug(false);
+                self.schedule_hibernate_wake(board);
             }
             "sdi12" => {
                 self.mode = DataLoggerMode::SDI12;
                 self.serial_tx_mode = DataLoggerSerialTxMode::Quiet;
-                sdi12_service::setup(board, 5);
+                sdi12_service::setup(board, SDI12_GPIO);
                 board.set_debug(false);
                 defmt::println!("In SDI12 mode!");
             }
@@ -877,6 +1658,13 @@ impl DataLogger {
             }
         };
         self.settings.mode = self.mode.to_u8();
+
+        if self.sensors_need_power() {
+            self.power_on_sensors(board);
+        } else {
+            self.power_off_sensors(board);
+        }
+        self.sensors_warmed_up = false;
         return true;
 
     }
@@ -920,6 +1708,13 @@ impl DataLogger {
                     }
                 };
 
+                if let Some(sampling_interval) = payload_values.sampling_interval {
+                    if !datalogger::schedule::sampling_interval_allowed(&self.settings, sampling_interval) {
+                        responses::send_command_response_error(board, "sampling_interval must be 0 or at least the time between measurement cycles", "");
+                        return;
+                    }
+                }
+
                 // check if we have an existing sensor with this id
                 let mut slot = None;
                 if let Some(sensor_id) = &mut payload_values.sensor_id {
@@ -929,6 +1724,8 @@ impl DataLogger {
                     }
                 }
 
+                let sensors_need_power = self.sensors_need_power();
+
                 let mut driver: Option<&mut Box<dyn SensorDriver>> = None;
                 if slot.is_none() {
                     slot = find_empty_slot(&mut self.sensor_drivers);
@@ -972,8 +1769,18 @@ impl DataLogger {
                         }
                         
                         // release bound resources so they can be checked and rebound or changed in next step
+                        driver.teardown(board);
                         self.assigned_gpios
-                            .release(driver.get_requested_gpios());
+                            .release_with_power_switch(driver.get_requested_gpios(), driver.get_requested_power().gpio_switch());
+                        if self.sensor_powered[slot] {
+                            self.power_domains.release(board, driver.get_requested_power());
+                            self.sensor_powered[slot] = false;
+                        }
+
+                        // update the general configuration
+                        let mut general_config = driver.get_general_configuration();
+                        datalogger::commands::update_general_configuration(&mut general_config, &payload_values);
+                        driver.set_general_configuration(general_config);
 
                         // update the driver
                         match driver.update(raw_values) {
@@ -991,7 +1798,7 @@ impl DataLogger {
                 // check for dedicated resources
                 match self
                     .assigned_gpios
-                    .update_or_conflict(driver.get_requested_gpios())
+                    .update_or_conflict_with_power_switch(driver.get_requested_gpios(), driver.get_requested_power().gpio_switch())
                 {
                     Ok(_) => {}
                     Err(message) => {
@@ -1000,7 +1807,10 @@ impl DataLogger {
                     }
                 };
 
-             
+                if sensors_need_power {
+                    self.power_domains.acquire(board, driver.get_requested_power());
+                    self.sensor_powered[slot] = true;
+                }
 
                 driver.setup(board);
 
@@ -1014,9 +1824,11 @@ impl DataLogger {
                     // existing driver will have already been updated in place
                     self.sensor_drivers[slot] = Some(new_driver); // put the new or updated driver into place
                 }
+                self.last_sample_times[slot] = None; // sample the new configuration right away
+                self.adaptive_previous_values[slot] = None;
 
                 if let Some(driver) = &mut self.sensor_drivers[slot] {
-                    responses::send_json(board, driver.get_configuration_json());
+                    responses::send_json(board, sensor_configuration_json(driver));
                     return;
                 } else {
                     responses::send_command_response_error(board, "sensor not configured", "");
@@ -1025,7 +1837,7 @@ impl DataLogger {
             CommandPayload::SensorGet(payload) => {
                 if let Some(index) = self.get_driver_index_by_id_value(payload.id) {
                     if let Some(driver) = &mut self.sensor_drivers[index] {
-                        responses::send_json(board, driver.get_configuration_json());
+                        responses::send_json(board, sensor_configuration_json(driver));
                         return;
                     }
                 }
@@ -1050,9 +1862,15 @@ impl DataLogger {
                 }
                 let slot = slot.unwrap();
 
-                let driver = &self.sensor_drivers[slot];
+                let driver = &mut self.sensor_drivers[slot];
                 if let Some(driver) = driver {
-                    self.assigned_gpios.release(driver.get_requested_gpios());
+                    driver.teardown(board);
+                    self.assigned_gpios.release_with_power_switch(driver.get_requested_gpios(), driver.get_requested_power().gpio_switch());
+                    if self.sensor_powered[slot] {
+                        self.power_domains.release(board, driver.get_requested_power());
+                        self.sensor_powered[slot] = false;
+                    }
+                    self.sensor_needs_setup[slot] = false;
                 } else {
                     responses::send_command_response_error(board, "sensor not found", "");
                     return;
@@ -1061,6 +1879,8 @@ impl DataLogger {
                 let bytes = bytes::empty_sensor_settings();
                 board.store_sensor_settings(slot as u8, &bytes);
                 self.sensor_drivers[slot] = None;
+                self.last_sample_times[slot] = None;
+                self.adaptive_previous_values[slot] = None;
                 responses::send_command_response_message(board, "sensor removed");
             }
             CommandPayload::SensorList(_) => {
@@ -1107,7 +1927,7 @@ impl DataLogger {
                         driver.take_measurement(board);
 
                         let count = driver.get_measured_parameter_count() / 2; // TODO: get_measured_parameter_count, vs get_output_parameter_count
-                        let mut values = Box::new([0_f64; 10]); // TODO: max of 10, should we make this dynamic?
+                        let mut values = alloc::vec![0_f64; count].into_boxed_slice(); // a value for each raw output
                         for j in 0..count {
                             defmt::println!("{:?}", j);
                             let value = match driver.get_measured_parameter_value(j * 2) {
@@ -1122,8 +1942,8 @@ impl DataLogger {
 
                         // TODO: datalogger can own a calibration object that tracks the calibrations and does the fit
 
-                        // store the point
-                        // TODO: we are only storing one point for now
+                        // store the point, newest first, keeping the number of points the driver fits
+                        let point_count = driver.get_required_calibration_point_count();
                         let calibration_pair = CalibrationPair {
                             point: args.1,
                             values: values,
@@ -1132,9 +1952,11 @@ impl DataLogger {
                             let pairs: &Option<Box<[CalibrationPair]>> =
                                 &self.calibration_point_values[index];
                             if let Some(pairs) = pairs {
-                                let arr = [calibration_pair, pairs[0].clone()];
-                                let arr_box = Box::new(arr);
-                                self.calibration_point_values[index] = Some(arr_box);
+                                let mut arr = alloc::vec![calibration_pair];
+                                for pair in pairs.iter().take(point_count.saturating_sub(1)) {
+                                    arr.push(pair.clone());
+                                }
+                                self.calibration_point_values[index] = Some(arr.into_boxed_slice());
                             }
                         } else {
                             let arr = [calibration_pair];
@@ -1217,19 +2039,7 @@ impl DataLogger {
                     if let Some(driver) = &mut self.sensor_drivers[index] {
                         let pairs: &Option<Box<[CalibrationPair]>> =
                             &self.calibration_point_values[index];
-                        // if let Some(pairs) = pairs {
-                        //     for i in 0..pairs.len() {
-                        //         let pair = &pairs[i];
-                        //         defmt::println!("calib pair{:?} {} {}", i, pair.point, pair.values[i]);
-                        //     }
-                        // }
-
                         if let Some(pairs) = pairs {
-                            for i in 0..pairs.len() {
-                                let pair = &pairs[i];
-                                defmt::println!("calib pair{:?} {} {}", i, pair.point, pair.values[0]);
-                            }
-
                             driver.clear_calibration();
                             match driver.fit(pairs) {
                                 Ok(_) => {
@@ -1387,8 +2197,20 @@ impl DataLogger {
         set_command_payload: DataloggerSetPayload,
     ) -> Result<(), &'static str> {
         let mode = set_command_payload.mode.clone(); // TODO: clean this up
-        let values = set_command_payload.values();
-        
+        let values = set_command_payload.values()?;
+
+        // the sensors' sampling intervals must stay at least as long as the measurement cycles
+        let mut schedule = self.settings;
+        schedule.sleep_interval = values.sleep_interval.unwrap_or(schedule.sleep_interval);
+        schedule.window_start = values.window_start.unwrap_or(schedule.window_start);
+        schedule.window_end = values.window_end.unwrap_or(schedule.window_end);
+        schedule.window_interval = values.window_interval.unwrap_or(schedule.window_interval);
+        for driver in self.sensor_drivers.iter().flatten() {
+            if !datalogger::schedule::sampling_interval_allowed(&schedule, driver.get_general_configuration().sampling_interval) {
+                return Err("measurement cycles must not be longer than a sensor's sampling_interval");
+            }
+        }
+
 
         if let Some(enable_lorawan_telemetry) = &values.enable_lorawan_telemetry {
             if self.settings.toggles.enable_lorawan_telemetry() != *enable_lorawan_telemetry {
@@ -1399,20 +2221,28 @@ impl DataLogger {
             }
         }
 
+        let trigger_pin = values.trigger_pin.unwrap_or(self.settings.trigger.pin());
+        let trigger_edge = values.trigger_edge.unwrap_or(self.settings.trigger.edge());
+        if trigger_pin != self.settings.trigger.pin() || trigger_edge != self.settings.trigger.edge() {
+            self.tear_down_trigger(board);
+            if let Err(error) = self.set_up_trigger(board, trigger_pin, trigger_edge) {
+                // put the previous trigger back
+                let _ = self.set_up_trigger(board, self.settings.trigger.pin(), self.settings.trigger.edge());
+                return Err(error);
+            }
+        }
+
         if let Some(enable_interactive_logging) = &values.interactive_logging {
             if *enable_interactive_logging {
                 self.write_column_headers_to_storage(board);
             }
         }
 
-        // if let Some(enable_sdi12) = &values.enable_sdi12 {
-        //     if self.settings.toggles.enable_sdi12() != *enable_sdi12 {
-        //        match self.set_up_modbus_rtu(*enable_sdi12) {
-        //             Ok(_) => {},
-        //             Err(error) => {return Err(error);}, 
-        //         }
-        //     }
-        // }
+        if let Some(enable_modbus_rtu) = &values.enable_modbus_rtu {
+            if self.settings.toggles.enable_modbus_rtu() != *enable_modbus_rtu {
+                self.set_up_modbus_rtu(board, *enable_modbus_rtu)?;
+            }
+        }
 
         let new_settings: DataloggerSettings = self.settings.with_values(values);
         let mut old_settings: DataloggerSettings = self.settings.clone();
@@ -1449,6 +2279,12 @@ impl DataLogger {
 
 
 
+        if let Some(service) = &mut self.modbus_service {
+            if self.settings.options.modbus_slave_id() != old_settings.options.modbus_slave_id() {
+                service.set_slave_id(self.settings.options.modbus_slave_id());
+            }
+        }
+
         if self.settings.get_bytes() != old_settings.get_bytes() {
             self.store_settings(board);
         }
@@ -1456,6 +2292,8 @@ impl DataLogger {
     }
 
     fn datalogger_settings_payload(&mut self) -> Value {
+        let mut window_start = [0u8; 5];
+        let mut window_end = [0u8; 5];
         json!({
            "site_name": util::str_from_utf8(&mut self.settings.site_name).unwrap_or_default(),
            "logger_name" : util::str_from_utf8(&mut self.settings.logger_name).unwrap_or_default(),
@@ -1466,11 +2304,20 @@ impl DataLogger {
            "start_up_delay" : self.settings.start_up_delay,
            "delay_between_bursts" : self.settings.delay_between_bursts,
            "bursts_per_measurement_cycle" : self.settings.bursts_per_measurement_cycle,
+           "readings_per_burst" : self.settings.readings_per_burst,
+           "clock_aligned" : self.settings.options.clock_aligned(),
+           "window_start" : datalogger::schedule::format_time_of_day(self.settings.window_start, &mut window_start),
+           "window_end" : datalogger::schedule::format_time_of_day(self.settings.window_end, &mut window_end),
+           "window_interval" : self.settings.window_interval,
+           "hibernate_until" : self.settings.hibernate_until,
+           "trigger_pin" : self.settings.trigger.pin(),
+           "trigger_edge" : trigger_service::edge_text(self.settings.trigger.edge()),
            "mode" : datalogger::modes::mode_text(&self.mode),
            "lock_mode" : self.settings.toggles.lock_mode(),
            "interactive_logging": self.settings.toggles.enable_interactive_logging(),
            "enable_lorawan_telemetry" : self.settings.toggles.enable_lorawan_telemetry(),
            "enable_modbus_rtu" : self.settings.toggles.enable_modbus_rtu(),
+           "modbus_slave_id" : modbus_service::slave_id_or_default(self.settings.options.modbus_slave_id()),
            "enable_sdi12" : self.settings.toggles.enable_sdi12(),
         })
     }
@@ -1482,7 +2329,7 @@ impl DataLogger {
         let mut assignments: [[u8; 6]; 9] = [[b'\0'; 6]; 9];
         for i in 0..self.sensor_drivers.len() {
             if let Some(driver) = &self.sensor_drivers[i] {
-                let gpios = driver.get_requested_gpios();
+                let gpios = driver.get_requested_gpios_with_power();
                 if gpios.gpio1() {
                     assignments[0] = driver.get_id()
                 }
@@ -1518,6 +2365,12 @@ impl DataLogger {
             assignments[7].clone_from_slice(id);
             assignments[8].clone_from_slice(id);
         }
+        if self.modbus_service.is_some() {
+            let id = b"modbus";
+            assignments[6].clone_from_slice(id);
+            assignments[7].clone_from_slice(id);
+            assignments[8].clone_from_slice(id);
+        }
         match responses::device_get(board, serial_number, uid, assignments){
             Ok(_) => {},
             Err(_) => {
@@ -29,7 +29,6 @@ pub fn calibration_point_list(board: &mut impl RRIVBoard, pairs: &Option<Box<[Ca
 
     if let Some(pairs) = pairs {
         for i in 0..pairs.len() {
-            defmt::println!("calib pair{:?}", i);
             let pair = &pairs[i];
             board.usb_serial_send(format_args!("{{'point': {}, 'values': [", pair.point));
             for i in 0..pair.values.len() {
@@ -2,7 +2,7 @@ use alloc::boxed::Box;
 use crate::drivers::{ types::{SensorDriver, SensorDriverGeneralConfiguration, SENSOR_SETTINGS_PARTITION_SIZE}};
 
 
-const SENSOR_NAMES: [&str; 16] = [
+const SENSOR_NAMES: [&str; 30] = [
     "no_match",
     "generic_analog",
     "atlas_ec",
@@ -19,6 +19,20 @@ const SENSOR_NAMES: [&str; 16] = [
     "mhz9041a",
     "gndwater_sdi12",
     "ring_w_mux_sim",
+    "pulse_counter",
+    "sdi12_sensor",
+    "atlas_ph",
+    "atlas_orp",
+    "atlas_do",
+    "atlas_rtd",
+    "bme280",
+    "sht",
+    "serial_level",
+    "nmea_gps",
+    "generic_i2c",
+    "thermistor",
+    "system_health",
+    "scd",
 ];
 
 pub fn sensor_type_id_from_name(name: &str) -> Result<u16, ()> {
@@ -89,8 +103,8 @@ pub fn get_registry() -> [DriverCreateFunctions; 256] {
         crate::drivers::generic_analog::GenericAnalogSpecialConfiguration
     )); 
     driver_create_functions[2] = Some(driver_create_functions!(
-        crate::drivers::atlas_ec::AtlasEC,
-        crate::drivers::atlas_ec::AtlasECSpecialConfiguration
+        crate::drivers::atlas::AtlasEC,
+        crate::drivers::atlas::AtlasECSpecialConfiguration
     ));
     driver_create_functions[3] = Some(driver_create_functions!(
         crate::drivers::aht20::AHT20,
@@ -129,7 +143,10 @@ pub fn get_registry() -> [DriverCreateFunctions; 256] {
         crate::drivers::ring_temperature_sim::RingTemperatureDriver,
         crate::drivers::ring_temperature_sim::RingTemperatureDriverSpecialConfiguration
     ));
-    driver_create_functions[12] = None; // modbus placeholder
+    driver_create_functions[12] = Some(driver_create_functions!(
+        crate::drivers::modbus_rtu::ModbusRtu,
+        crate::drivers::modbus_rtu::ModbusRtuSpecialConfiguration
+    ));
     driver_create_functions[13] = Some(driver_create_functions!(
         crate::drivers::mhz9041a::MHZ9041ADriver,
         crate::drivers::mhz9041a::MHZ9041ADriverSpecialConfiguration
@@ -142,6 +159,62 @@ pub fn get_registry() -> [DriverCreateFunctions; 256] {
         crate::drivers::ring_w_mux_sim::RingMuxTemperatureDriver,
         crate::drivers::ring_w_mux_sim::RingMuxTemperatureDriverSpecialConfiguration
     ));
+    driver_create_functions[16] = Some(driver_create_functions!(
+        crate::drivers::pulse_counter::PulseCounter,
+        crate::drivers::pulse_counter::PulseCounterSpecialConfiguration
+    ));
+    driver_create_functions[17] = Some(driver_create_functions!(
+        crate::drivers::sdi12_sensor::Sdi12Sensor,
+        crate::drivers::sdi12_sensor::Sdi12SensorSpecialConfiguration
+    ));
+    driver_create_functions[18] = Some(driver_create_functions!(
+        crate::drivers::atlas::AtlasPH,
+        crate::drivers::atlas::AtlasPHSpecialConfiguration
+    ));
+    driver_create_functions[19] = Some(driver_create_functions!(
+        crate::drivers::atlas::AtlasORP,
+        crate::drivers::atlas::AtlasORPSpecialConfiguration
+    ));
+    driver_create_functions[20] = Some(driver_create_functions!(
+        crate::drivers::atlas::AtlasDO,
+        crate::drivers::atlas::AtlasDOSpecialConfiguration
+    ));
+    driver_create_functions[21] = Some(driver_create_functions!(
+        crate::drivers::atlas::AtlasRTD,
+        crate::drivers::atlas::AtlasRTDSpecialConfiguration
+    ));
+    driver_create_functions[22] = Some(driver_create_functions!(
+        crate::drivers::bme280::BME280,
+        crate::drivers::bme280::BME280SpecialConfiguration
+    ));
+    driver_create_functions[23] = Some(driver_create_functions!(
+        crate::drivers::sht::Sht,
+        crate::drivers::sht::SHTSpecialConfiguration
+    ));
+    driver_create_functions[24] = Some(driver_create_functions!(
+        crate::drivers::serial_level::SerialLevel,
+        crate::drivers::serial_level::SerialLevelSpecialConfiguration
+    ));
+    driver_create_functions[25] = Some(driver_create_functions!(
+        crate::drivers::nmea_gps::NmeaGps,
+        crate::drivers::nmea_gps::NmeaGpsSpecialConfiguration
+    ));
+    driver_create_functions[26] = Some(driver_create_functions!(
+        crate::drivers::generic_i2c::GenericI2C,
+        crate::drivers::generic_i2c::GenericI2CSpecialConfiguration
+    ));
+    driver_create_functions[27] = Some(driver_create_functions!(
+        crate::drivers::thermistor::Thermistor,
+        crate::drivers::thermistor::ThermistorSpecialConfiguration
+    ));
+    driver_create_functions[28] = Some(driver_create_functions!(
+        crate::drivers::system_health::SystemHealth,
+        crate::drivers::system_health::SystemHealthSpecialConfiguration
+    ));
+    driver_create_functions[29] = Some(driver_create_functions!(
+        crate::drivers::scd::Scd,
+        crate::drivers::scd::SCDSpecialConfiguration
+    ));
     driver_create_functions
 }
 
@@ -0,0 +1,27 @@
+// The latest barometric pressure measured by a barometer driver,
+// for drivers that compensate for air pressure, such as dissolved oxygen and unvented pressure transducers
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+const NO_PRESSURE: u32 = 0x7FC0_0000; // f32 NaN bits
+
+// kPa, stored as f32 bits
+static BAROMETRIC_PRESSURE: AtomicU32 = AtomicU32::new(NO_PRESSURE);
+
+pub fn publish_pressure(kpa: f32) {
+    BAROMETRIC_PRESSURE.store(kpa.to_bits(), Ordering::Relaxed);
+}
+
+pub fn clear_pressure() {
+    BAROMETRIC_PRESSURE.store(NO_PRESSURE, Ordering::Relaxed);
+}
+
+// kPa, None until a barometer has been read
+pub fn barometric_pressure() -> Option<f32> {
+    let kpa = f32::from_bits(BAROMETRIC_PRESSURE.load(Ordering::Relaxed));
+    if kpa.is_nan() {
+        None
+    } else {
+        Some(kpa)
+    }
+}
@@ -1,3 +1,7 @@
 pub mod command_service;
 pub mod usart_service;
-pub mod sdi12_service;
\ No newline at end of file
+pub mod sdi12_service;
+pub mod trigger_service;
+pub mod modbus_service;
+pub mod barometer_service;
+pub mod system_health_service;
@@ -0,0 +1,143 @@
+// Modbus RTU slave on the usart, so SCADA systems and PLCs can poll the logger like any other field instrument
+// requests are answered from the run loop, so the logger doesn't sleep while the slave is enabled,
+// which costs battery life in field mode
+// there is no direction gpio setting, so the rs485 transceiver must switch direction automatically
+//
+// register map, 32 bit values are sent high word first
+//
+// input registers (function 4)
+//   0-1    current UTC epoch, u32
+//   2      number of configured sensors
+//   3      mode, 1 interactive, 2 field, 3 hibernate, 4 sdi12
+//   100-   sensor values as f32, 64 registers for each sensor slot starting at 100 + slot * 64
+//          parameter n of a slot is at 100 + slot * 64 + n * 2, for up to 32 parameters,
+//          which covers a full ds18b20 chain, only a groundwater_flow_sdi12 sensor can measure more
+//          NaN when the slot is empty or the value is missing
+//
+// holding registers (function 3 to read, 6 and 16 to write)
+//   0-3    logger name, 8 ascii characters, 2 per register with the first in the high byte
+//   4      interactive logging interval, seconds
+//   5      sleep interval, minutes
+//   6      mode, as in input register 3
+
+use modbus_core::rtu::{server, Header, ResponseAdu};
+use modbus_core::ResponsePdu;
+use rriv_board::RRIVBoard;
+
+use crate::services::usart_service;
+
+pub const DEFAULT_SLAVE_ID: u8 = 1;
+pub const BROADCAST_SLAVE_ID: u8 = 0;
+pub const FRAME_SIZE: usize = 256;
+
+pub const INPUT_REGISTER_EPOCH: u16 = 0;
+pub const INPUT_REGISTER_EPOCH_LOW: u16 = 1;
+pub const INPUT_REGISTER_SENSOR_COUNT: u16 = 2;
+pub const INPUT_REGISTER_MODE: u16 = 3;
+pub const INPUT_REGISTER_SENSOR_BASE: u16 = 100;
+pub const REGISTERS_PER_SENSOR: u16 = 64;
+
+pub const HOLDING_REGISTER_LOGGER_NAME: u16 = 0;
+pub const HOLDING_REGISTER_INTERACTIVE_LOGGING_INTERVAL: u16 = 4;
+pub const HOLDING_REGISTER_SLEEP_INTERVAL: u16 = 5;
+pub const HOLDING_REGISTER_MODE: u16 = 6;
+pub const HOLDING_REGISTER_COUNT: u16 = 7;
+
+pub const MAX_READ_QUANTITY: u16 = 125;
+
+const FRAME_SILENCE_MS: u32 = 5; // a frame is complete once the line has been quiet this long
+
+pub struct ModbusSlaveService {
+    slave_id: u8,
+    received_length: usize,
+    last_receive_time: u32,
+}
+
+impl ModbusSlaveService {
+    // take over the usart for modbus frames
+    pub fn new(board: &mut impl RRIVBoard, slave_id: u8) -> Result<Self, &'static str> {
+        if !usart_service::start_raw_capture(board) {
+            return Err("usart is busy");
+        }
+
+        let slave_id = slave_id_or_default(slave_id);
+        defmt::println!("modbus rtu slave {} listening", slave_id);
+        Ok(ModbusSlaveService {
+            slave_id,
+            received_length: 0,
+            last_receive_time: board.millis(),
+        })
+    }
+
+    pub fn stop(&mut self, board: &mut impl RRIVBoard) {
+        usart_service::stop_raw_capture(board);
+    }
+
+    pub fn set_slave_id(&mut self, slave_id: u8) {
+        self.slave_id = slave_id_or_default(slave_id);
+    }
+
+    // take a complete frame addressed to us or broadcast, returns the frame length
+    pub fn take_message(&mut self, board: &mut impl RRIVBoard, frame: &mut [u8; FRAME_SIZE]) -> Option<usize> {
+        let length = usart_service::read_raw_bytes(frame);
+        let now = board.millis();
+        if length != self.received_length {
+            self.received_length = length;
+            self.last_receive_time = now;
+            return None;
+        }
+        if length == 0 || now.wrapping_sub(self.last_receive_time) < FRAME_SILENCE_MS {
+            return None;
+        }
+
+        usart_service::clear_raw_bytes(board);
+        self.received_length = 0;
+
+        if frame[0] != self.slave_id && frame[0] != BROADCAST_SLAVE_ID {
+            // a frame for another slave on the bus
+            return None;
+        }
+        Some(length)
+    }
+
+    pub fn send_response(&mut self, board: &mut impl RRIVBoard, pdu: ResponsePdu) {
+        let adu = ResponseAdu {
+            hdr: Header { slave: self.slave_id },
+            pdu,
+        };
+        let mut frame = [0u8; FRAME_SIZE];
+        match server::encode_response(adu, &mut frame) {
+            Ok(length) => board.usart_send(&frame[..length]),
+            Err(_) => defmt::println!("failed to encode modbus response"),
+        }
+    }
+}
+
+pub fn slave_id_or_default(slave_id: u8) -> u8 {
+    if slave_id == 0 { DEFAULT_SLAVE_ID } else { slave_id }
+}
+
+// registers for an f32 value, NaN when there isn't a value
+pub fn float_registers(value: Option<f64>) -> [u16; 2] {
+    let bits = match value {
+        Some(value) => (value as f32).to_bits(),
+        None => f32::NAN.to_bits(),
+    };
+    [(bits >> 16) as u16, bits as u16]
+}
+
+// registers for an ascii string, 2 characters per register with the first in the high byte
+pub fn text_register(text: &[u8], register: usize) -> u16 {
+    let high = text.get(register * 2).copied().unwrap_or(0);
+    let low = text.get(register * 2 + 1).copied().unwrap_or(0);
+    ((high as u16) << 8) | low as u16
+}
+
+pub fn set_text_register(text: &mut [u8], register: usize, value: u16) {
+    if let Some(high) = text.get_mut(register * 2) {
+        *high = (value >> 8) as u8;
+    }
+    if let Some(low) = text.get_mut(register * 2 + 1) {
+        *low = value as u8;
+    }
+}
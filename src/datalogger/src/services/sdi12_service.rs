@@ -3,11 +3,27 @@ use sdi12::*;
 use core::fmt::{self, Write};
 
 pub const MEASUREMENTS_IN_PAYLOAD: u8 = 4;
+pub const MAX_VALUES_IN_RESPONSE: usize = 9; // values parse_data can return from one data response
+const MEASUREMENT_DURATION: u32 = 5; // seconds, approximate time to measure all sensors
+const MAX_MEASUREMENT_TIME: u32 = 999; // ttt is three digits
+
+// the ttt reported to the recorder, covering the sensor warmup and the measurement itself
+pub fn measurement_time(warmup: u16) -> u32 {
+    let ttt = warmup as u32 + MEASUREMENT_DURATION;
+    if ttt > MAX_MEASUREMENT_TIME {
+        MAX_MEASUREMENT_TIME
+    } else {
+        ttt
+    }
+}
 
 pub enum Sdi12Command {
     M,
     Mc(char),
+    C,
+    Cc(char),
     D(char),
+    R(char),
     HA,
 }
 
@@ -26,7 +42,7 @@ pub struct SDI12_HAResponse {
 #[allow(non_camel_case_types, unused)]
 pub struct SDI12_Dresponse {
     pub address: char,
-    pub data: [f32; MEASUREMENTS_IN_PAYLOAD as usize],
+    pub data: [f32; MAX_VALUES_IN_RESPONSE],
     pub count: u8,
 }
 
@@ -107,7 +123,10 @@ impl<'a> BoardForSDI12 for Sdi12Board<'a> {
 
 
 pub fn setup(board: &mut dyn RRIVBoard, gpio: u8) {
-    rriv_board::configure_gpio_interrupt_function(sdi12::probe_interrupt_handler);
+    let cycles_per_us = board.get_cycles_per_microsecond();
+    rriv_board::configure_gpio_interrupt_function(gpio, move |cycles, gpio_state| {
+        sdi12::probe_interrupt_handler(cycles / cycles_per_us, gpio_state) // sdi12 times in microseconds
+    });
     let my_board = Sdi12Board::new(gpio, board);
     let mut sdi12 = SDI12::new(my_board);
     sdi12.sleep();
@@ -365,8 +384,11 @@ impl<'a> Sdi12TxProcessor {
         }
     }
 
-    pub fn setup(&mut self) {
-        rriv_board::configure_gpio_interrupt_function(sdi12::datalogger_interrupt_handler);
+    pub fn setup(&mut self, board: &mut dyn RRIVBoard) {
+        let cycles_per_us = board.get_cycles_per_microsecond();
+        rriv_board::configure_gpio_interrupt_function(self.gpio, move |cycles, gpio_state| {
+            sdi12::datalogger_interrupt_handler(cycles / cycles_per_us, gpio_state) // sdi12 times in microseconds
+        });
     }
 
     pub fn send_break(&mut self, board: &mut dyn RRIVBoard) {
@@ -398,14 +420,24 @@ impl<'a> Sdi12TxProcessor {
                 command[2] = id;
                 command[3] = '!';
             }
+            Sdi12Command::C => {
+                command[1] = 'C';
+                command[2] = '!';
+            }
+            Sdi12Command::Cc(id) => {
+                command[1] = 'C';
+                command[2] = id;
+                command[3] = '!';
+            }
             Sdi12Command::D(id) => {
                 command[1] = 'D';
                 command[2] = id;
                 command[3] = '!';
             }
-            _ => {
-                defmt::println!("Wrong command");
-                return;
+            Sdi12Command::R(id) => {
+                command[1] = 'R';
+                command[2] = id;
+                command[3] = '!';
             }
         }
         sdi12.send_command(command);
@@ -447,6 +479,48 @@ impl<'a> Sdi12TxProcessor {
         Ok(response)
     }
 
+    // parse the atttn acknowledgement to M commands, or atttnn to C commands
+    pub fn parse_m_command(&mut self, response: [char; SDI12_BUFFER_SIZE]) -> Option<SDI12_MResponse> {
+        if response[0] != self.address {
+            return None;
+        }
+
+        let mut ttt: u32 = 0;
+        for &c in &response[1..4] {
+            ttt = ttt * 10 + c.to_digit(10)?;
+        }
+
+        let mut n: u8 = 0;
+        let mut digits = 0;
+        for &c in &response[4..] {
+            match c.to_digit(10) {
+                Some(d) if digits < 2 => {
+                    n = n * 10 + d as u8;
+                    digits += 1;
+                }
+                _ => break,
+            }
+        }
+        if digits == 0 {
+            return None;
+        }
+
+        Some(SDI12_MResponse { ttt, n })
+    }
+
+    // a sensor that finishes an M measurement early sends a service request, a<CR><LF>
+    pub fn service_request_received(&mut self, board: &mut dyn RRIVBoard) -> bool {
+        let my_board = Sdi12Board::new(self.gpio, board);
+        let mut sdi12 = SDI12::new(my_board);
+        if sdi12.available() == 0 {
+            return false;
+        }
+        match self.read_response(board) {
+            Ok(response) => response[0] == self.address && response[1] == '\r',
+            Err(_) => false,
+        }
+    }
+
     #[allow(unused)]
     pub fn parse_ha_command(&mut self, response: [char; SDI12_BUFFER_SIZE]) -> Option<SDI12_HAResponse> {
         let address_r = response[0];
@@ -489,7 +563,7 @@ impl<'a> Sdi12TxProcessor {
         
         let mut resp : SDI12_Dresponse = SDI12_Dresponse {
             address: '\0',
-            data: [0.0; MEASUREMENTS_IN_PAYLOAD as usize],
+            data: [0.0; MAX_VALUES_IN_RESPONSE],
             count: 0,
         };
 
@@ -505,7 +579,7 @@ impl<'a> Sdi12TxProcessor {
         let (parsed_data, count) = sdi12.parse_data(response);
 
         resp.count = count;
-        for i in 0..MEASUREMENTS_IN_PAYLOAD as usize {
+        for i in 0..MAX_VALUES_IN_RESPONSE {
             resp.data[i] = parsed_data[i];
         }
         
@@ -664,7 +738,7 @@ impl<'a> Sdi12TxProcessor {
         command[3] = '!';
         let mut resp : SDI12_Dresponse = SDI12_Dresponse {
             address: '\0',
-            data: [0.0; MEASUREMENTS_IN_PAYLOAD as usize],
+            data: [0.0; MAX_VALUES_IN_RESPONSE],
             count: 0,
         };
         
@@ -689,7 +763,7 @@ impl<'a> Sdi12TxProcessor {
         let (parsed_data, count) = sdi12.parse_data(response);
 
         resp.count = count;
-        for i in 0..MEASUREMENTS_IN_PAYLOAD as usize {
+        for i in 0..MAX_VALUES_IN_RESPONSE {
             resp.data[i] = parsed_data[i];
         }
         
@@ -0,0 +1,41 @@
+// Logger vital signs that aren't available from the board,
+// collected here for the system_health driver
+
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+const NO_FREE_HEAP: u32 = u32::MAX;
+
+// bytes, published by the application since the allocator lives there
+static FREE_HEAP: AtomicU32 = AtomicU32::new(NO_FREE_HEAP);
+static LORAWAN_JOINED: AtomicBool = AtomicBool::new(false);
+static READ_ERRORS: AtomicU32 = AtomicU32::new(0);
+
+pub fn publish_free_heap(bytes: usize) {
+    FREE_HEAP.store(bytes as u32, Ordering::Relaxed);
+}
+
+// None until the application has published it
+pub fn free_heap() -> Option<u32> {
+    match FREE_HEAP.load(Ordering::Relaxed) {
+        NO_FREE_HEAP => None,
+        bytes => Some(bytes),
+    }
+}
+
+pub fn publish_lorawan_joined(joined: bool) {
+    LORAWAN_JOINED.store(joined, Ordering::Relaxed);
+}
+
+pub fn lorawan_joined() -> bool {
+    LORAWAN_JOINED.load(Ordering::Relaxed)
+}
+
+// a driver timed out or returned an error for one of its values
+pub fn record_read_error() {
+    READ_ERRORS.fetch_add(1, Ordering::Relaxed);
+}
+
+// the errors recorded since the last call
+pub fn take_read_errors() -> u32 {
+    READ_ERRORS.swap(0, Ordering::Relaxed)
+}
@@ -0,0 +1,67 @@
+// Measurements triggered by an edge on a dynamic gpio,
+// such as a float switch, a door sensor or the sync pulse of another logger
+// the trigger device must be powered from an always on supply, the switched rails are off while the logger sleeps
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use rriv_board::gpio::{GpioInterruptEdge, GpioMode};
+use rriv_board::RRIVBoard;
+
+// pin of a trigger that hasn't been handled yet, 0 means none
+static PENDING_TRIGGER: AtomicU8 = AtomicU8::new(0);
+
+pub fn edge_from_str(value: &str) -> Option<u8> {
+    match value {
+        "rising" => Some(0),
+        "falling" => Some(1),
+        "both" => Some(2),
+        _ => None,
+    }
+}
+
+pub fn edge_text(edge: u8) -> &'static str {
+    match edge {
+        1 => "falling",
+        2 => "both",
+        _ => "rising",
+    }
+}
+
+pub fn interrupt_edge(edge: u8) -> GpioInterruptEdge {
+    match edge {
+        1 => GpioInterruptEdge::Falling,
+        2 => GpioInterruptEdge::RisingFalling,
+        _ => GpioInterruptEdge::Rising,
+    }
+}
+
+pub fn setup(board: &mut impl RRIVBoard, pin: u8, edge: u8) -> Result<(), &'static str> {
+    // switches and open collector outputs only pull the pin low
+    board.set_gpio_pin_mode(pin, GpioMode::PullUpInput);
+
+    rriv_board::configure_gpio_interrupt_function(pin, move |_time, _state| {
+        PENDING_TRIGGER.store(pin, Ordering::Relaxed);
+    });
+
+    match board.enable_gpio_interrupt(pin, interrupt_edge(edge), true) {
+        Ok(_) => Ok(()),
+        Err(_) => {
+            rriv_board::remove_gpio_interrupt_function(pin);
+            Err("trigger interrupts not supported on this pin")
+        }
+    }
+}
+
+pub fn teardown(board: &mut impl RRIVBoard, pin: u8) {
+    board.disable_gpio_interrupt(pin);
+    rriv_board::remove_gpio_interrupt_function(pin);
+    PENDING_TRIGGER.store(0, Ordering::Relaxed);
+}
+
+// take the pin of the pending trigger, if any
+pub fn take_trigger() -> Option<u8> {
+    match PENDING_TRIGGER.swap(0, Ordering::Relaxed) {
+        0 => None,
+        pin => Some(pin),
+    }
+}
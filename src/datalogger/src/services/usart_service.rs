@@ -11,6 +11,13 @@ static mut COMMAND: [u8; USART_BUFFER_SIZE] = [0u8; USART_BUFFER_SIZE];
 
 static mut MESSAGE_DATA: MessageData = MessageData::default();  // TODO: This can be owned by USARTCharacterProcessor, doesn't need to be static
 
+// raw capture for binary protocols like modbus rtu, bytes bypass the line buffers while capturing
+const RAW_BUFFER_SIZE: usize = 256; // the maximum modbus rtu frame length
+static mut RAW_CAPTURE: bool = false;
+static mut RAW_BYTES: [u8; RAW_BUFFER_SIZE] = [0u8; RAW_BUFFER_SIZE];
+static mut RAW_LENGTH: usize = 0;
+static mut RAW_CAPTURE_STARTED: bool = false;
+
 pub struct MessageData {
     buffer: [[u8; USART_BUFFER_SIZE]; USART_BUFFER_NUM],  // The buffer can just be contiguous memory, we can raise command avialable by detected \r
     cur: usize,
@@ -40,6 +47,13 @@ impl<'a> USARTCharacterProcessor {
 impl<'a, 'b> RXProcessor for USARTCharacterProcessor {
     fn process_byte(&mut self, character: u8) {
         unsafe {
+            if RAW_CAPTURE {
+                if RAW_LENGTH < RAW_BUFFER_SIZE {
+                    RAW_BYTES[RAW_LENGTH] = character;
+                    RAW_LENGTH += 1;
+                }
+                return;
+            }
             #[allow(static_mut_refs)]
             let message_data = MESSAGE_DATA.borrow_mut();
             process_character(message_data, character);
@@ -154,6 +168,66 @@ pub fn take_command(board: &dyn RRIVBoard) -> Result<[u8; USART_BUFFER_SIZE], ()
 }
 
 
+// start capturing raw bytes, returns false if another user is already capturing
+pub fn start_raw_capture(board: &dyn RRIVBoard) -> bool {
+    let do_start_raw_capture = || unsafe {
+        RAW_CAPTURE_STARTED = !RAW_CAPTURE;
+        if RAW_CAPTURE_STARTED {
+            RAW_CAPTURE = true;
+            RAW_LENGTH = 0;
+        }
+    };
+
+    board.critical_section(do_start_raw_capture);
+    unsafe { RAW_CAPTURE_STARTED }
+}
+
+pub fn stop_raw_capture(board: &dyn RRIVBoard) {
+    let do_stop_raw_capture = || unsafe {
+        RAW_CAPTURE = false;
+        RAW_LENGTH = 0;
+    };
+
+    board.critical_section(do_stop_raw_capture);
+}
+
+// discard bytes received so far, such as a partial frame
+pub fn clear_raw_bytes(board: &dyn RRIVBoard) {
+    let do_clear_raw_bytes = || unsafe {
+        RAW_LENGTH = 0;
+    };
+
+    board.critical_section(do_clear_raw_bytes);
+}
+
+// copy the bytes received since the capture started or was cleared, returns the number of bytes
+pub fn read_raw_bytes(output: &mut [u8]) -> usize {
+    // bytes are only appended while capturing, so those below the length are stable
+    let length = unsafe { RAW_LENGTH };
+    let length = if length > output.len() { output.len() } else { length };
+    unsafe {
+        #[allow(static_mut_refs)]
+        output[..length].copy_from_slice(&RAW_BYTES[..length]);
+    }
+    length
+}
+
+// baud rates sensors on the usart can use, stored in their configuration as an index
+// index 0 is the board's baud rate, so configurations stored before the setting existed keep it
+const BAUD_RATES: [u32; 5] = [rriv_board::USART_BAUD_RATE, 9600, 19200, 38400, 57600];
+pub const BAUD_RATE_9600: u8 = 1;
+
+pub fn baud_rate_index(baud_rate: u64) -> Option<u8> {
+    BAUD_RATES.iter().position(|rate| *rate as u64 == baud_rate).map(|index| index as u8)
+}
+
+pub fn baud_rate(index: u8) -> u32 {
+    match BAUD_RATES.get(index as usize) {
+        Some(rate) => *rate,
+        None => rriv_board::USART_BAUD_RATE,
+    }
+}
+
 pub fn format_and_send(board: &mut dyn RRIVBoard, args: fmt::Arguments){
      let mut buf = [0u8;200];
         match format_no_std::show(
@@ -6,7 +6,7 @@ use util::str_from_utf8;
 
 use crate::telemetry::codecs::naive_codec;
 use crate::{drivers::resources::gpio::GpioRequest, telemetry::telemeters::Telemeter};
-use crate::services::usart_service;
+use crate::services::{system_health_service, usart_service};
 use alloc::string::{String,ToString};
 
 #[derive(Clone, Copy)]
@@ -291,6 +291,9 @@ impl Telemeter for RakWireless3172 {
             _ => {}
         }
 
+        let joined = matches!(self.telemetry_step, RakWireless3172Step::Joined);
+        system_health_service::publish_lorawan_joined(joined);
+
         // defmt::println!("done setting up lorawan")
     }
 
@@ -83,4 +83,72 @@ pub fn format_decimal(value: i32) -> Result<([u8;20], usize), ()> {
             Err(())
         },
     }
-}
\ No newline at end of file
+}
+
+// natural log and exponential for drivers, without a float math library
+pub fn ln(x: f64) -> f64 {
+    // x = m * 2^k with m in [1, 2)
+    let bits = x.to_bits();
+    let k = ((bits >> 52) & 0x7FF) as i64 - 1023;
+    let m = f64::from_bits((bits & 0x000F_FFFF_FFFF_FFFF) | 0x3FF0_0000_0000_0000);
+
+    // ln(m) = 2 atanh((m - 1) / (m + 1))
+    let z = (m - 1.0) / (m + 1.0);
+    let z2 = z * z;
+    let mut term = z;
+    let mut sum = 0.0;
+    for n in 0..12 {
+        sum += term / (2 * n + 1) as f64;
+        term *= z2;
+    }
+    2.0 * sum + k as f64 * core::f64::consts::LN_2
+}
+
+pub fn exp(x: f64) -> f64 {
+    // x = n ln2 + r with |r| <= ln2 / 2
+    let n = (x / core::f64::consts::LN_2 + if x < 0.0 { -0.5 } else { 0.5 }) as i64;
+    let r = x - n as f64 * core::f64::consts::LN_2;
+    let mut term = 1.0;
+    let mut sum = 1.0;
+    for i in 1..14 {
+        term *= r / i as f64;
+        sum += term;
+    }
+    sum * f64::from_bits(((n + 1023) as u64) << 52)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::f64::consts::{E, LN_10, LN_2};
+
+    fn assert_close(expected: f64, value: f64) {
+        assert!((expected - value).abs() <= expected.abs() * 1e-12 + 1e-12, "{} != {}", value, expected);
+    }
+
+    #[test]
+    fn test_ln() {
+        assert_close(0.0, ln(1.0));
+        assert_close(1.0, ln(E));
+        assert_close(LN_2, ln(2.0));
+        assert_close(LN_10, ln(10.0));
+        assert_close(-LN_10, ln(0.1));
+        assert_close(4.0 * LN_10, ln(10000.0));
+    }
+
+    #[test]
+    fn test_exp() {
+        assert_close(1.0, exp(0.0));
+        assert_close(E, exp(1.0));
+        assert_close(1.0 / E, exp(-1.0));
+        assert_close(10.0, exp(LN_10));
+        assert_close(1e-6, exp(-6.0 * LN_10));
+    }
+
+    #[test]
+    fn test_exp_inverts_ln() {
+        for x in [1e-3, 0.5, 1.5, 3.0, 298.15, 32650.0] {
+            assert_close(x, exp(ln(x)));
+        }
+    }
+}